@@ -0,0 +1,42 @@
+// Runs a ROM headless for a few emulated seconds and saves a screenshot, using nothing but the core API:
+// cargo run --example minimal -- rom.gb screenshot.png
+use ayyboy::gameboy::GameBoy;
+use ayyboy::system::{Button, System};
+use std::fs::File;
+use std::time::Duration;
+
+fn main() {
+    let args = std::env::args().collect::<Vec<String>>();
+    let [_, rom_path, screenshot_path] = args.as_slice() else {
+        eprintln!("Usage: minimal <ROM> <SCREENSHOT.png>");
+        return;
+    };
+
+    // Without a boot ROM the built-in one for the cartridge's hardware is used
+    let rom = std::fs::read(rom_path).expect("Failed to read ROM file");
+    let mut gameboy = GameBoy::new(None, rom);
+
+    // Nobody is listening, so don't wait for the audio device either
+    gameboy.set_muted(true);
+
+    while gameboy.emulated_time() < Duration::from_secs(5) {
+        // Mash start to get past the title screen
+        let start = gameboy.frame_count() % 30 < 15;
+        gameboy.update_button(Button::Start, start);
+        gameboy.run_frame();
+    }
+
+    let (width, height) = gameboy.screen_size();
+    let pixels = gameboy.framebuffer().concat();
+
+    let file = File::create(screenshot_path).expect("Failed to create screenshot");
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&pixels))
+        .expect("Failed to write screenshot");
+
+    println!("Saved frame {} to {}", gameboy.frame_count(), screenshot_path);
+}
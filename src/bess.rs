@@ -0,0 +1,289 @@
+// Imports save states in the "Best Effort Save State" format (BESS), which SameBoy appends to its
+// own states and other emulators can write too. Only what ayyboy can map is taken over: CPU
+// registers, IO registers, RAM, VRAM, OAM, HRAM, palettes, cartridge RAM and the mapper registers.
+// The PPU, APU and timer pick up from the registers, their internal counters start over (DIV keeps its value).
+// See https://github.com/LIJI32/SameBoy/blob/master/BESS.md for the format.
+use crate::error::AyyError;
+use crate::gameboy::{GameBoy, Mode};
+use crate::lr35902::sm83::Register;
+use crate::memory::addressable::Addressable;
+use crate::memory::{
+    BACKGROUND_PALETTE_DATA_REGISTER, BACKGROUND_PALETTE_INDEX_REGISTER, BOOTROM_MAPPER_REGISTER, DIV_REGISTER,
+    DOUBLE_SPEED_SWITCH_REGISTER, HDMA_LENGTH_MODE_START_REGISTER, HRAM_START, INTERRUPT_ENABLE_REGISTER,
+    INTERRUPT_FLAGS_REGISTER, IO_START, OAM_DMA_REGISTER, OAM_START, OBJECT_PALETTE_DATA_REGISTER,
+    OBJECT_PALETTE_INDEX_REGISTER, VRAM_BANK_SELECT_REGISTER, VRAM_START, WRAM_BANK0_START, WRAM_BANK1_START,
+    WRAM_BANK_SELECT_REGISTER,
+};
+use crate::serial::SERIAL_CONTROL_REGISTER;
+use crate::sound::{NR14, NR24, NR34, NR44, NR52, WAVE_PATTERN_RAM_END, WAVE_PATTERN_RAM_START};
+
+const FOOTER_MAGIC: &[u8; 4] = b"BESS";
+const CORE_MAJOR_VERSION: u16 = 1;
+const CORE_IO_SIZE: usize = 0x80;
+// Model, registers, IME/IE/state, reserved and the IO registers, then the buffer table
+const CORE_BUFFERS_OFFSET: usize = 4 + 4 + 12 + 4 + CORE_IO_SIZE;
+const CORE_BUFFER_COUNT: usize = 7;
+// Title and global checksum from the cartridge header
+const INFO_SIZE: usize = 0x12;
+
+// Where in the state file the CORE block's memory dumps are
+#[derive(Clone, Copy)]
+struct Buffer {
+    size: usize,
+    offset: usize,
+}
+
+// The 4 character id of a block and its body
+type Block<'a> = (&'a [u8], &'a [u8]);
+
+struct Core<'a> {
+    model: [u8; 4],
+    registers: [u16; 6],
+    ime: bool,
+    ie: u8,
+    halted: bool,
+    io: &'a [u8],
+    // RAM, VRAM, cartridge RAM, OAM, HRAM, background and object palettes
+    buffers: [Buffer; CORE_BUFFER_COUNT],
+}
+
+fn invalid<T>(reason: impl Into<String>) -> Result<T, AyyError> {
+    Err(AyyError::InvalidSaveState { reason: reason.into() })
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, AyyError> {
+    match data.get(offset..offset + 2) {
+        Some(bytes) => Ok(u16::from_le_bytes([bytes[0], bytes[1]])),
+        None => invalid("truncated block"),
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<usize, AyyError> {
+    match data.get(offset..offset + 4) {
+        Some(bytes) => Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize),
+        None => invalid("truncated block"),
+    }
+}
+
+// The blocks of the state by their 4 character id, in file order
+fn blocks(data: &[u8]) -> Result<Vec<Block<'_>>, AyyError> {
+    if data.len() < 8 || &data[data.len() - 4..] != FOOTER_MAGIC {
+        return invalid("not a BESS save state (no footer), only SameBoy style states can be imported");
+    }
+
+    let mut offset = u32_at(data, data.len() - 8)?;
+    let mut blocks = Vec::new();
+    loop {
+        let Some(id) = data.get(offset..offset + 4) else {
+            return invalid("block list runs past the end of the file");
+        };
+        let length = u32_at(data, offset + 4)?;
+        let Some(body) = data.get(offset + 8..offset + 8 + length) else {
+            return invalid(format!(
+                "{} block runs past the end of the file",
+                String::from_utf8_lossy(id)
+            ));
+        };
+
+        if id == b"END " {
+            return Ok(blocks);
+        }
+        blocks.push((id, body));
+        offset += 8 + length;
+    }
+}
+
+fn parse_core(body: &[u8]) -> Result<Core<'_>, AyyError> {
+    let major = u16_at(body, 0)?;
+    if major != CORE_MAJOR_VERSION {
+        return invalid(format!("unsupported CORE block version {}", major));
+    }
+    if body.len() < CORE_BUFFERS_OFFSET + CORE_BUFFER_COUNT * 8 {
+        return invalid("CORE block is too short");
+    }
+
+    let mut registers = [0; 6];
+    for (idx, register) in registers.iter_mut().enumerate() {
+        *register = u16_at(body, 8 + idx * 2)?;
+    }
+
+    let mut buffers = [Buffer { size: 0, offset: 0 }; CORE_BUFFER_COUNT];
+    for (idx, buffer) in buffers.iter_mut().enumerate() {
+        *buffer = Buffer {
+            size: u32_at(body, CORE_BUFFERS_OFFSET + idx * 8)?,
+            offset: u32_at(body, CORE_BUFFERS_OFFSET + idx * 8 + 4)?,
+        };
+    }
+
+    Ok(Core {
+        model: body[4..8].try_into().unwrap(),
+        registers,
+        ime: body[20] != 0,
+        ie: body[21],
+        halted: body[22] == 1,
+        io: &body[24..24 + CORE_IO_SIZE],
+        buffers,
+    })
+}
+
+// A memory dump of the CORE block, at most `max` bytes of it are used
+fn buffer<'a>(data: &'a [u8], buffer: Buffer, name: &str, max: usize) -> Result<&'a [u8], AyyError> {
+    match data.get(buffer.offset..buffer.offset + buffer.size) {
+        Some(bytes) => Ok(&bytes[..bytes.len().min(max)]),
+        None => invalid(format!("{} dump runs past the end of the file", name)),
+    }
+}
+
+// Builds a copy of `gb` with the state applied, `gb` has to run the same game the state was made with
+pub fn import(gb: &GameBoy, data: &[u8]) -> Result<GameBoy, AyyError> {
+    let blocks = blocks(data)?;
+    let Some(core) = blocks.iter().find(|(id, _)| *id == b"CORE") else {
+        return invalid("no CORE block");
+    };
+    let core = parse_core(core.1)?;
+
+    let mode = match core.model[0] {
+        b'G' | b'S' => Mode::Dmg,
+        b'C' => Mode::Cgb,
+        _ => return invalid(format!("unsupported model {}", String::from_utf8_lossy(&core.model))),
+    };
+    if mode != gb.mode {
+        return invalid("the state was made on different hardware (DMG vs. CGB)");
+    }
+
+    // The title and checksum are all there is to tell whether the state belongs to this ROM
+    if let Some((_, info)) = blocks.iter().find(|(id, _)| *id == b"INFO") {
+        let header = (0x134..0x144)
+            .chain(0x14e..0x150)
+            .map(|addr| gb.mmu.cartridge.read(addr))
+            .collect::<Result<Vec<u8>, AyyError>>()?;
+        if info.get(..INFO_SIZE) != Some(&header[..]) {
+            return invalid("the state was made with a different ROM");
+        }
+    }
+
+    let mut state = gb.clone();
+    let cpu = &mut state.cpu;
+    for (register, value) in [
+        Register::PC,
+        Register::AF,
+        Register::BC,
+        Register::DE,
+        Register::HL,
+        Register::SP,
+    ]
+    .iter()
+    .zip(core.registers)
+    {
+        cpu.write_register16(register, value);
+    }
+    match core.ime {
+        true => cpu.enable_interrupts(false),
+        false => cpu.disable_interrupts(),
+    }
+    cpu.halted = core.halted;
+
+    let mmu = &mut state.mmu;
+    let [ram, vram, cartridge_ram, oam, hram, background_palettes, object_palettes] = core.buffers;
+
+    // Banked memory goes in through the bank registers, they are restored with the other IO registers below
+    let ram = buffer(data, ram, "RAM", 0x8000)?;
+    for (bank, bytes) in ram.chunks(0x1000).enumerate() {
+        let start = match bank {
+            0 => WRAM_BANK0_START,
+            _ => {
+                mmu.write_unchecked(WRAM_BANK_SELECT_REGISTER, bank as u8);
+                WRAM_BANK1_START
+            }
+        };
+        bytes
+            .iter()
+            .enumerate()
+            .for_each(|(offset, byte)| mmu.write_unchecked(start + offset as u16, *byte));
+    }
+
+    let vram = buffer(data, vram, "VRAM", 0x4000)?;
+    for (bank, bytes) in vram.chunks(0x2000).enumerate() {
+        mmu.write_unchecked(VRAM_BANK_SELECT_REGISTER, bank as u8);
+        bytes
+            .iter()
+            .enumerate()
+            .for_each(|(offset, byte)| mmu.write_unchecked(VRAM_START + offset as u16, *byte));
+    }
+
+    for (start, dump, name, max) in [(OAM_START, oam, "OAM", 0xa0), (HRAM_START, hram, "HRAM", 0x7f)] {
+        for (offset, byte) in buffer(data, dump, name, max)?.iter().enumerate() {
+            mmu.write_unchecked(start + offset as u16, *byte);
+        }
+    }
+
+    if mode == Mode::Cgb {
+        for (index, data_register, dump, name) in [
+            (
+                BACKGROUND_PALETTE_INDEX_REGISTER,
+                BACKGROUND_PALETTE_DATA_REGISTER,
+                background_palettes,
+                "background palette",
+            ),
+            (
+                OBJECT_PALETTE_INDEX_REGISTER,
+                OBJECT_PALETTE_DATA_REGISTER,
+                object_palettes,
+                "object palette",
+            ),
+        ] {
+            mmu.cgb_cram.write(index, 0b1000_0000);
+            for byte in buffer(data, dump, name, 0x40)? {
+                mmu.cgb_cram.write(data_register, *byte);
+            }
+        }
+    }
+
+    let cartridge_ram = buffer(data, cartridge_ram, "cartridge RAM", usize::MAX)?;
+    if !cartridge_ram.is_empty() {
+        mmu.cartridge.load_ram(cartridge_ram.to_vec());
+    }
+
+    // The mapper registers, as the writes that would set them
+    if let Some((_, writes)) = blocks.iter().find(|(id, _)| *id == b"MBC ") {
+        for write in writes.chunks_exact(3) {
+            mmu.cartridge
+                .write(u16::from_le_bytes([write[0], write[1]]), write[2])?;
+        }
+    }
+
+    // The APU is powered up first so that the other sound registers stick. Nothing is started again,
+    // i.e. DMAs, serial transfers and sound channel triggers. The palettes were loaded above, only their
+    // index registers are restored, and the boot ROM stays unmapped. Wave RAM goes straight into the channel,
+    // a write would end up at the byte being played if channel 3 is on.
+    mmu.write_unchecked(NR52, core.io[(NR52 - IO_START) as usize]);
+    for (offset, value) in core.io.iter().enumerate() {
+        let address = IO_START + offset as u16;
+        match address {
+            NR52
+            | OAM_DMA_REGISTER
+            | HDMA_LENGTH_MODE_START_REGISTER
+            | BOOTROM_MAPPER_REGISTER
+            | BACKGROUND_PALETTE_DATA_REGISTER
+            | OBJECT_PALETTE_DATA_REGISTER
+            | WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END => {}
+            DOUBLE_SPEED_SWITCH_REGISTER => mmu.cgb_double_speed = mode == Mode::Cgb && value & 0b1000_0000 != 0,
+            SERIAL_CONTROL_REGISTER => mmu.write_unchecked(address, value & 0b0111_1111),
+            // Writing DIV would clear it
+            DIV_REGISTER => mmu.timer.set_counter((*value as u16) << 8),
+            NR14 | NR24 | NR34 | NR44 => mmu.write_unchecked(address, value & 0b0111_1111),
+            _ => mmu.write_unchecked(address, *value),
+        }
+    }
+    mmu.apu.load_wave_ram(
+        &core.io[(WAVE_PATTERN_RAM_START - IO_START) as usize..=(WAVE_PATTERN_RAM_END - IO_START) as usize],
+    );
+    // Writing STAT/LYC can request a STAT interrupt, the flags as saved win
+    mmu.write_unchecked(
+        INTERRUPT_FLAGS_REGISTER,
+        core.io[(INTERRUPT_FLAGS_REGISTER - IO_START) as usize],
+    );
+    mmu.write_unchecked(INTERRUPT_ENABLE_REGISTER, core.ie);
+
+    Ok(state)
+}
@@ -0,0 +1,59 @@
+use std::ops::AddAssign;
+
+// Cycles each component has to advance by after the CPU executed some
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ComponentCycles {
+    // The timer is driven by DIV, which counts CPU cycles and thus follows the speed mode
+    pub timer: usize,
+    // These always run at the normal rate, in double speed they only see every other CPU cycle
+    pub ppu: usize,
+    pub apu: usize,
+    pub cartridge: usize,
+}
+
+impl AddAssign for ComponentCycles {
+    fn add_assign(&mut self, other: ComponentCycles) {
+        self.timer += other.timer;
+        self.ppu += other.ppu;
+        self.apu += other.apu;
+        self.cartridge += other.cartridge;
+    }
+}
+
+// Central place for CGB double speed: turns CPU T-cycles into component cycles
+#[derive(Debug, Clone)]
+pub struct ClockDivider {
+    // An odd cycle left over in double speed, e.g. from overclocking, is handed out next time
+    remainder: usize,
+}
+
+impl ClockDivider {
+    pub fn new() -> ClockDivider {
+        ClockDivider { remainder: 0 }
+    }
+
+    // Taken from a smarter person: https://github.com/NightShade256/Argentum/blob/1be04a77c4a13f5134952f78cf4c3c5b355fe12d/crates/argentum/src/bus.rs#L274
+    pub fn split(&mut self, cpu_cycles: usize, double_speed: bool) -> ComponentCycles {
+        let normal = match double_speed {
+            true => {
+                let cycles = cpu_cycles + self.remainder;
+                self.remainder = cycles & 1;
+                cycles >> 1
+            }
+            false => cpu_cycles + std::mem::take(&mut self.remainder),
+        };
+
+        ComponentCycles {
+            timer: cpu_cycles,
+            ppu: normal,
+            apu: normal,
+            cartridge: normal,
+        }
+    }
+}
+
+impl Default for ClockDivider {
+    fn default() -> ClockDivider {
+        ClockDivider::new()
+    }
+}
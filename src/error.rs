@@ -1,3 +1,4 @@
+use crate::lr35902::diagnostics::Diagnostics;
 use crate::lr35902::sm83::{Instruction, Operand};
 use snafu::prelude::*;
 
@@ -23,6 +24,31 @@ pub enum AyyError {
     WriteToReadOnlyMemory { address: u16, data: u8 },
     #[snafu(display("Write to disabled external RAM at address: {:04x} with data: {:02x}", address, data))]
     WriteToDisabledExternalRam { address: u16, data: u8 },
+    #[snafu(display("Failed to assemble instruction: {}", line))]
+    AssemblerFailure { line: String },
+    #[snafu(display("Invalid expression: {}", reason))]
+    InvalidExpression { reason: String },
+    #[snafu(display("Save state belongs to a different system"))]
+    IncompatibleSaveState,
+    #[snafu(display("Save state was made with a different ROM (SHA-1 {}, running {})", found, expected))]
+    SaveStateRomMismatch { expected: String, found: String },
+    #[snafu(display("Can't import save state: {}", reason))]
+    InvalidSaveState { reason: String },
+    #[snafu(display("Invalid cartridge: {}", reason))]
+    InvalidCartridge { reason: String },
+    #[snafu(display("Invalid ROM patch: {}", reason))]
+    InvalidPatch { reason: String },
+    #[snafu(display("Frame capture error: {}", reason))]
+    Capture { reason: String },
+    #[snafu(display("Failed to open audio device: {}", reason))]
+    Audio { reason: String },
+    #[snafu(display("Netplay error: {}", reason))]
+    Netplay { reason: String },
     #[snafu(display("Out of bounds memory access at address: {:04x}", address))]
     OutOfBoundsMemoryAccess { address: u16 },
+    #[snafu(display("{}\n{}", error, diagnostics))]
+    Diagnosed {
+        error: Box<AyyError>,
+        diagnostics: Box<Diagnostics>,
+    },
 }
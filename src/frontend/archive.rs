@@ -0,0 +1,162 @@
+use crate::memory::patch::{apply_patch, crc32};
+use log::{info, warn};
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use zip::ZipArchive;
+
+// Files next to a ROM that belong to it, moved along when zipped ROMs got their own name
+const ROM_FILE_SUFFIXES: [&str; 7] = [
+    ".sav",
+    ".sav.sha1",
+    ".sav.info",
+    ".sav.png",
+    ".rtc",
+    ".cfg",
+    ".last.png",
+];
+
+// Cache directories unpacked into during this run, removed again when the last ExtractionGuard is dropped
+static EXTRACTED: Mutex<Extracted> = Mutex::new(Extracted {
+    directories: Vec::new(),
+    guards: 0,
+});
+
+struct Extracted {
+    directories: Vec<PathBuf>,
+    guards: usize,
+}
+
+pub struct LoadedRom {
+    // Saves, states and settings are named after this. For zipped ROMs it's the ROM inside the zip,
+    // placed next to the zip, so that zipping or unzipping a game keeps its save.
+    pub path: String,
+    pub rom: Vec<u8>,
+}
+
+// Removes everything unpacked once the last guard is gone, the binary holds one until it exits
+pub struct ExtractionGuard {
+    _private: (),
+}
+
+impl ExtractionGuard {
+    pub fn acquire() -> ExtractionGuard {
+        EXTRACTED.lock().unwrap().guards += 1;
+        ExtractionGuard { _private: () }
+    }
+}
+
+impl Drop for ExtractionGuard {
+    fn drop(&mut self) {
+        let mut extracted = EXTRACTED.lock().unwrap();
+        extracted.guards -= 1;
+        if extracted.guards > 0 {
+            return;
+        }
+
+        for directory in extracted.directories.drain(..) {
+            if let Err(e) = std::fs::remove_dir_all(&directory) {
+                warn!("Failed to remove unpacked ROM {}: {}", directory.display(), e);
+            }
+        }
+        // Only goes away if no other instance is still using it
+        let _ = std::fs::remove_dir(cache_root());
+    }
+}
+
+// Reads the ROM and applies the patch given, or one named like the ROM if there is one. Zipped ROMs come back
+// with the path of the ROM inside.
+pub fn read_rom(rom_path: &str, patch_path: Option<String>) -> Result<LoadedRom, String> {
+    let LoadedRom { path, mut rom } = load_rom(rom_path)?;
+    if let Some(patch_path) = patch_path.or_else(|| find_patch(&path)) {
+        let patch = std::fs::read(&patch_path).map_err(|e| format!("Failed to read patch {}: {}", patch_path, e))?;
+        rom = apply_patch(&rom, &patch).map_err(|e| format!("Failed to apply patch {}: {}", patch_path, e))?;
+        info!("Applied patch {}", patch_path);
+    }
+    Ok(LoadedRom { path, rom })
+}
+
+fn find_patch(rom_path: &str) -> Option<String> {
+    ["ips", "bps"]
+        .iter()
+        .map(|extension| Path::new(rom_path).with_extension(extension))
+        .find(|path| path.exists())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+// Reads a ROM, unpacking the first file in it if it's a zip
+pub fn load_rom(path: &str) -> Result<LoadedRom, String> {
+    if !path.to_lowercase().ends_with(".zip") {
+        return std::fs::read(path)
+            .map(|rom| LoadedRom {
+                path: path.to_owned(),
+                rom,
+            })
+            .map_err(|e| format!("Failed to read ROM file {}: {}", path, e));
+    }
+
+    let zip_error = |e: zip::result::ZipError| format!("Failed to unzip {}: {}", path, e);
+    let file = File::open(path).map_err(|e| format!("Failed to read ROM file {}: {}", path, e))?;
+    let mut archive = ZipArchive::new(file).map_err(zip_error)?;
+    let index = (0..archive.len())
+        .find(|index| archive.by_index(*index).is_ok_and(|entry| entry.is_file()))
+        .ok_or_else(|| format!("No ROM found in {}", path))?;
+    let mut entry = archive.by_index(index).map_err(zip_error)?;
+    let name = entry
+        .enclosed_name()
+        .and_then(|name| name.file_name().map(|name| name.to_owned()))
+        .ok_or_else(|| format!("No ROM found in {}", path))?;
+
+    // Keyed by the entry's checksum, so the same game always unpacks to the same place. A copy already
+    // there, e.g. when the ROM is opened again from the launcher, is used instead of unzipping again.
+    let directory = cache_root().join(format!("{:08x}", entry.crc32()));
+    let extracted = directory.join(&name);
+    let cached = std::fs::read(&extracted).ok().filter(|rom| crc32(rom) == entry.crc32());
+    let rom = match cached {
+        Some(rom) => {
+            info!("Using {} unpacked to {}", path, extracted.display());
+            rom
+        }
+        None => {
+            let mut rom = Vec::with_capacity(entry.size() as usize);
+            entry
+                .read_to_end(&mut rom)
+                .map_err(|e| format!("Failed to unzip {}: {}", path, e))?;
+            match std::fs::create_dir_all(&directory).and_then(|_| std::fs::write(&extracted, &rom)) {
+                Ok(_) => info!("Unzipped {} to {}", path, extracted.display()),
+                // The ROM is already in memory, the copy on disk is only a convenience
+                Err(e) => warn!("Failed to unpack {} to {}: {}", path, extracted.display(), e),
+            }
+            rom
+        }
+    };
+    if extracted.exists() {
+        let directories = &mut EXTRACTED.lock().unwrap().directories;
+        if !directories.contains(&directory) {
+            directories.push(directory);
+        }
+    }
+
+    let rom_path = Path::new(path).with_file_name(&name).to_string_lossy().into_owned();
+    adopt_zip_files(path, &rom_path);
+    Ok(LoadedRom { path: rom_path, rom })
+}
+
+// Saves used to be named after the zip, they move over unless the ROM already has its own
+fn adopt_zip_files(zip_path: &str, rom_path: &str) {
+    for suffix in ROM_FILE_SUFFIXES {
+        let (old, new) = (format!("{}{}", zip_path, suffix), format!("{}{}", rom_path, suffix));
+        if !Path::new(&old).exists() || Path::new(&new).exists() {
+            continue;
+        }
+        match std::fs::rename(&old, &new) {
+            Ok(_) => info!("Moved {} to {}", old, new),
+            Err(e) => warn!("Failed to move {} to {}: {}", old, new, e),
+        }
+    }
+}
+
+fn cache_root() -> PathBuf {
+    std::env::temp_dir().join("ayyboy")
+}
@@ -0,0 +1,92 @@
+use crate::error::AyyError;
+use crate::video::palette::Color;
+use log::{info, warn};
+use std::io::{ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{sync_channel, SyncSender, TrySendError};
+use std::sync::Arc;
+
+// Frames a slow client may lag behind before frames get dropped for it
+const CLIENT_BACKLOG: usize = 2;
+
+// Receives every frame the emulator presents, e.g. to hand clean game video to streaming software
+pub trait FrameSink {
+    fn push_frame(&mut self, width: usize, height: usize, framebuffer: &[Color]);
+}
+
+// Serves the screen as raw RGB24 video over TCP, one frame after another without any framing.
+// Any number of clients can connect, e.g. `ffmpeg -f rawvideo -pix_fmt rgb24 -s 160x144 -i tcp://127.0.0.1:<port>`
+pub struct FrameStream {
+    listener: TcpListener,
+    clients: Vec<SyncSender<Arc<Vec<u8>>>>,
+}
+
+impl FrameStream {
+    pub fn bind(port: u16) -> Result<FrameStream, AyyError> {
+        let listener = TcpListener::bind(("127.0.0.1", port)).map_err(capture_error)?;
+        FrameStream::new(listener)
+    }
+
+    pub fn new(listener: TcpListener) -> Result<FrameStream, AyyError> {
+        listener.set_nonblocking(true).map_err(capture_error)?;
+        info!("Streaming frames on {}", listener.local_addr().map_err(capture_error)?);
+
+        Ok(FrameStream {
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    fn accept_clients(&mut self) {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, peer)) => {
+                    info!("Frame stream client connected from {}", peer);
+                    self.clients.push(FrameStream::spawn_client(stream));
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Failed to accept frame stream client: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    // Each client gets its own writer so that a slow one never stalls the emulation
+    fn spawn_client(mut stream: TcpStream) -> SyncSender<Arc<Vec<u8>>> {
+        let (sender, receiver) = sync_channel::<Arc<Vec<u8>>>(CLIENT_BACKLOG);
+        std::thread::spawn(move || {
+            let _ = stream.set_nonblocking(false);
+            let _ = stream.set_nodelay(true);
+            while let Ok(frame) = receiver.recv() {
+                if stream.write_all(&frame).is_err() {
+                    break;
+                }
+            }
+        });
+        sender
+    }
+}
+
+impl FrameSink for FrameStream {
+    fn push_frame(&mut self, _width: usize, _height: usize, framebuffer: &[Color]) {
+        self.accept_clients();
+        if self.clients.is_empty() {
+            return;
+        }
+
+        let frame = Arc::new(framebuffer.iter().flatten().copied().collect::<Vec<u8>>());
+        self.clients.retain(|client| match client.try_send(frame.clone()) {
+            Ok(_) | Err(TrySendError::Full(_)) => true,
+            Err(TrySendError::Disconnected(_)) => {
+                info!("Frame stream client disconnected");
+                false
+            }
+        });
+    }
+}
+
+fn capture_error(e: std::io::Error) -> AyyError {
+    AyyError::Capture { reason: e.to_string() }
+}
@@ -0,0 +1,64 @@
+use crate::memory::WRAM_BANK0_START;
+
+// How a candidate's current value is compared against the previous snapshot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    EqualTo(u8),
+    Greater,
+    Less,
+    Changed,
+    Unchanged,
+    // Wrapping difference, so lives going from 0 to 255 count as "changed by -1"
+    ChangedBy(i16),
+}
+
+impl Filter {
+    fn matches(&self, previous: u8, current: u8) -> bool {
+        match self {
+            Filter::EqualTo(value) => current == *value,
+            Filter::Greater => current > previous,
+            Filter::Less => current < previous,
+            Filter::Changed => current != previous,
+            Filter::Unchanged => current == previous,
+            Filter::ChangedBy(delta) => current == previous.wrapping_add(*delta as u8),
+        }
+    }
+}
+
+// Iteratively narrows WRAM addresses down to the ones holding a value of interest
+pub struct CheatSearch {
+    previous: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl CheatSearch {
+    // Starts with every byte of the snapshot as a candidate
+    pub fn new(snapshot: Vec<u8>) -> CheatSearch {
+        let candidates = (0..snapshot.len())
+            .map(|offset| WRAM_BANK0_START + offset as u16)
+            .collect();
+        CheatSearch {
+            previous: snapshot,
+            candidates,
+        }
+    }
+
+    // Keeps the candidates that pass the filter and compares against this snapshot next time
+    pub fn filter(&mut self, snapshot: Vec<u8>, filter: Filter) {
+        let previous = &self.previous;
+        self.candidates.retain(|addr| {
+            let offset = (addr - WRAM_BANK0_START) as usize;
+            filter.matches(previous[offset], snapshot[offset])
+        });
+        self.previous = snapshot;
+    }
+
+    pub fn candidates(&self) -> &[u16] {
+        &self.candidates
+    }
+
+    // Value of the candidate at the last snapshot
+    pub fn value(&self, addr: u16) -> u8 {
+        self.previous[(addr - WRAM_BANK0_START) as usize]
+    }
+}
@@ -1,14 +1,62 @@
 use eframe::egui::{
-    self, vec2, Color32, ColorImage, Image, RichText, TextStyle, TextureHandle, TextureOptions, Window,
+    self, vec2, CentralPanel, Color32, ColorImage, Image, RichText, TextStyle, TextureHandle, TextureOptions, Ui,
+    ViewportBuilder, ViewportClass, ViewportId, Window,
 };
 use egui::Context;
 
+use crate::error::AyyError;
 use crate::gameboy::{GameBoy, Mode};
-use crate::video::palette::Color;
+use crate::lr35902::asm::Assembler;
+use crate::lr35902::disasm::disassemble_source;
+use crate::lr35902::expr::{BreakCondition, Expr};
+use crate::lr35902::sm83::Register;
+use crate::memory::banked::{BankedAddress, SWITCHABLE_ROM_START};
+use crate::memory::heatmap::{AccessHeatmap, PAGE_COUNT, PAGE_SIZE};
+use crate::memory::io_dispatch::{register_doc, register_docs};
+use crate::memory::io_registers::{register_address, register_name, Access, IoBreakpoint};
+use crate::memory::{JOYPAD_REGISTER, ROM_END};
+use crate::sound::note::Note;
+use crate::system::{SaveState, System};
 use crate::video::tile::Tile;
-use crate::video::{BACKGROUND_HEIGHT, BACKGROUND_WIDTH, TILESET_HEIGHT, TILESET_WIDTH};
+use crate::video::{
+    BACKGROUND_HEIGHT, BACKGROUND_WIDTH, BG_PALETTE_REGISTER, LCD_CONTROL_REGISTER, OBJ0_PALETTE_REGISTER,
+    OBJ1_PALETTE_REGISTER, TILESET_HEIGHT, TILESET_WIDTH,
+};
+use std::path::Path;
 
+use super::cheat_search::{CheatSearch, Filter};
 use super::renderer::SCALE;
+use super::state_diff::{diff_states, Change};
+use super::vram_export::{layout_tiles, palettes, save_tiles_png, to_pal, vram_tiles};
+use super::watch::{load_watches, save_watches, Watch, WatchType};
+
+// The heatmap is a square of 16x16 pages
+const HEATMAP_SIZE: usize = 16;
+// Cheat search hits listed at once, the rest only show up in the count
+const CHEAT_SEARCH_LISTED: usize = 32;
+// Most recent IO writes listed in the log viewer
+const IO_LOG_LISTED: usize = 64;
+const CHEAT_SEARCH_FILTERS: [&str; 6] = ["Equal to", "Greater", "Less", "Changed", "Unchanged", "Changed by"];
+// Every panel that can be popped out, some only show up with their check enabled
+const PANELS: [&str; 17] = [
+    "Tileset 0",
+    "Tileset 1",
+    "Background Tilemap",
+    "Window Tilemap",
+    "Timing",
+    "Cartridge",
+    "APU",
+    "Memory Heatmap",
+    "Disassembly",
+    "Breakpoints",
+    "Stack",
+    "Uninitialized Reads",
+    "RAM Watch",
+    "Cheat Search",
+    "IO Log",
+    "Palettes",
+    "State Diff",
+];
 
 pub struct Debugger {
     pub window_open: bool,
@@ -16,6 +64,44 @@ pub struct Debugger {
     vram1_tileset_texture: TextureHandle,
     backgroundmap_texture: TextureHandle,
     windowmap_texture: TextureHandle,
+    heatmap_texture: TextureHandle,
+    // VRAM generation and LCDC the tilesets 0 and 1, background and window textures were drawn at
+    vram_views_drawn: [Option<(u64, u8)>; 4],
+    assembler: Assembler,
+    disasm_address: String,
+    assemble_address: String,
+    assemble_input: String,
+    assemble_status: String,
+    watches: Vec<Watch>,
+    watch_address: String,
+    watch_type: WatchType,
+    watch_status: String,
+    // Watches that evaluate an expression instead of reading an address, with the text they were typed as
+    expression_watches: Vec<(String, Expr)>,
+    watch_expression: String,
+    cheat_search: Option<CheatSearch>,
+    cheat_filter: usize,
+    cheat_value: String,
+    cheat_status: String,
+    breakpoint_address: String,
+    breakpoint_condition: String,
+    breakpoint_status: String,
+    io_log_register: String,
+    io_log_status: String,
+    // Address of one of the documented registers
+    io_breakpoint_register: u16,
+    io_breakpoint_access: Access,
+    // Stack warnings and uninitialized reads the user was already shown
+    stack_warnings_seen: usize,
+    uninit_reads_seen: usize,
+    // Panels popped out into their own OS window, e.g. to keep them on another monitor
+    detached: Vec<&'static str>,
+    // Outcome of the last export, shown in the panel it was started from
+    export_status: Option<(&'static str, String)>,
+    // Snapshot taken in the State Diff panel, and what it found last
+    diff_snapshot: Option<SaveState>,
+    diff_changes: Vec<Change>,
+    diff_status: String,
 }
 
 impl Debugger {
@@ -44,29 +130,130 @@ impl Debugger {
             TextureOptions::NEAREST,
         );
 
+        let heatmap_texture = ctx.load_texture(
+            "heatmap_texture",
+            ColorImage::new([HEATMAP_SIZE, HEATMAP_SIZE], Color32::BLACK),
+            TextureOptions::NEAREST,
+        );
+
         Self {
             window_open: false,
             vram0_tileset_texture,
             vram1_tileset_texture,
             backgroundmap_texture,
             windowmap_texture,
+            heatmap_texture,
+            vram_views_drawn: [None; 4],
+            assembler: Assembler::new(),
+            disasm_address: String::new(),
+            assemble_address: String::new(),
+            assemble_input: String::new(),
+            assemble_status: String::new(),
+            watches: Vec::new(),
+            watch_address: String::new(),
+            watch_type: WatchType::U8,
+            watch_status: String::new(),
+            expression_watches: Vec::new(),
+            watch_expression: String::new(),
+            cheat_search: None,
+            cheat_filter: 0,
+            cheat_value: String::new(),
+            cheat_status: String::new(),
+            breakpoint_address: String::new(),
+            breakpoint_condition: String::new(),
+            breakpoint_status: String::new(),
+            io_log_register: String::new(),
+            io_log_status: String::new(),
+            io_breakpoint_register: JOYPAD_REGISTER,
+            io_breakpoint_access: Access::Write,
+            stack_warnings_seen: 0,
+            uninit_reads_seen: 0,
+            detached: Vec::new(),
+            export_status: None,
+            diff_snapshot: None,
+            diff_changes: Vec::new(),
+            diff_status: String::new(),
+        }
+    }
+
+    // Frozen RAM watches are applied every frame, even with the debugger closed
+    pub fn apply_frozen_watches(&self, system: &mut dyn System) {
+        if let Some(gb) = system.as_any_mut().downcast_mut::<GameBoy>() {
+            self.watches.iter().for_each(|watch| watch.apply(&mut gb.mmu));
+        }
+    }
+
+    // Brings up the debugger when a breakpoint ended the last frame, returns whether to pause
+    pub fn check_breakpoint(&mut self, system: &mut dyn System) -> bool {
+        let Some(gb) = system.as_any_mut().downcast_mut::<GameBoy>() else {
+            return false;
+        };
+        let Some(hit) = gb.take_breakpoint_hit() else {
+            return false;
+        };
+
+        self.breakpoint_status = match (gb.take_io_breakpoint_hit(), gb.take_condition_hit()) {
+            (Some(access), _) => format!("Hit breakpoint on {} at {}, press Space to continue", access, hit),
+            (_, Some(condition)) => format!("{} turned true at {}, press Space to continue", condition, hit),
+            (None, None) => format!("Hit breakpoint at {}, press Space to continue", hit),
+        };
+        self.window_open = true;
+        true
+    }
+
+    // Brings up the debugger when the stack check or dev mode caught something new, the game keeps running
+    pub fn check_warnings(&mut self, system: &mut dyn System) {
+        let Some(gb) = system.as_any_mut().downcast_mut::<GameBoy>() else {
+            return;
+        };
+
+        let stack_warnings = gb.cpu.stack_warnings().len();
+        let uninit_reads = gb.mmu.uninit.reads().len();
+        if stack_warnings > self.stack_warnings_seen || uninit_reads > self.uninit_reads_seen {
+            self.window_open = true;
         }
+        self.stack_warnings_seen = stack_warnings;
+        self.uninit_reads_seen = uninit_reads;
     }
 
-    pub fn update_ui(&mut self, ctx: &Context, gb: &mut GameBoy) {
+    pub fn update_ui(
+        &mut self, ctx: &Context, system: &mut dyn System, rom_path: &str, quick_save: Option<&SaveState>,
+    ) {
+        // Only the Game Boy has debugging views so far
+        let Some(gb) = system.as_any_mut().downcast_mut::<GameBoy>() else {
+            return;
+        };
+
+        gb.breakpoints.debugger_attached = self.window_open;
+
         if !self.window_open {
             return;
         }
 
-        Window::new("Tileset 0").resizable(false).show(ctx, |ui| {
-            let tileset = gb.dbg_render_tileset(0);
-            Debugger::render_into_texture(
-                &tileset,
-                &mut self.vram0_tileset_texture,
-                16,
-                TILESET_WIDTH,
-                TILESET_HEIGHT,
-            );
+        Window::new("Windows").resizable(false).show(ctx, |ui| {
+            ui.label("Pop out into a separate window, closing it docks the panel again");
+            for panel in PANELS {
+                let mut detached = self.detached.contains(&panel);
+                if ui.checkbox(&mut detached, panel).changed() {
+                    match detached {
+                        true => self.detached.push(panel),
+                        false => self.detached.retain(|detached| *detached != panel),
+                    }
+                }
+            }
+        });
+
+        show_panel(ctx, &mut self.detached, "Tileset 0", |ui| {
+            if vram_view_stale(&mut self.vram_views_drawn[0], gb) {
+                let tileset = gb.dbg_render_tileset(0);
+                Debugger::render_into_texture(
+                    &tileset,
+                    &mut self.vram0_tileset_texture,
+                    16,
+                    TILESET_WIDTH,
+                    TILESET_HEIGHT,
+                );
+            }
 
             let image = Image::new(&self.vram0_tileset_texture);
             let image = image.fit_to_exact_size(vec2(
@@ -74,17 +261,27 @@ impl Debugger {
                 (TILESET_HEIGHT * (SCALE / 4)) as f32,
             ));
             ui.add(image);
+
+            // Raw 2bpp tiles for tile editors
+            if ui.button("Export VRAM bank 0").clicked() {
+                let path = format!("{}.vram0.chr", rom_path);
+                let result = std::fs::write(&path, vram_tiles(gb, 0));
+                self.export_status = Some(("Tileset 0", export_outcome(&path, result)));
+            }
+            show_export_status(ui, &self.export_status, "Tileset 0");
         });
 
-        Window::new("Tileset 1").resizable(false).show(ctx, |ui| {
-            let tileset = gb.dbg_render_tileset(1);
-            Debugger::render_into_texture(
-                &tileset,
-                &mut self.vram1_tileset_texture,
-                16,
-                TILESET_WIDTH,
-                TILESET_HEIGHT,
-            );
+        show_panel(ctx, &mut self.detached, "Tileset 1", |ui| {
+            if vram_view_stale(&mut self.vram_views_drawn[1], gb) {
+                let tileset = gb.dbg_render_tileset(1);
+                Debugger::render_into_texture(
+                    &tileset,
+                    &mut self.vram1_tileset_texture,
+                    16,
+                    TILESET_WIDTH,
+                    TILESET_HEIGHT,
+                );
+            }
 
             let image = Image::new(&self.vram1_tileset_texture);
             let image = image.fit_to_exact_size(vec2(
@@ -92,17 +289,26 @@ impl Debugger {
                 (TILESET_HEIGHT * (SCALE / 4)) as f32,
             ));
             ui.add(image);
+
+            // Raw 2bpp tiles for tile editors
+            if ui.button("Export VRAM bank 1").clicked() {
+                let path = format!("{}.vram1.chr", rom_path);
+                let result = std::fs::write(&path, vram_tiles(gb, 1));
+                self.export_status = Some(("Tileset 1", export_outcome(&path, result)));
+            }
+            show_export_status(ui, &self.export_status, "Tileset 1");
         });
 
-        Window::new("Background Tilemap").resizable(false).show(ctx, |ui| {
-            let backgroundmap = gb.dbg_render_background_tilemap();
-            Debugger::render_into_texture(
-                &backgroundmap,
-                &mut self.backgroundmap_texture,
-                32,
-                BACKGROUND_WIDTH,
-                BACKGROUND_HEIGHT,
-            );
+        show_panel(ctx, &mut self.detached, "Background Tilemap", |ui| {
+            if vram_view_stale(&mut self.vram_views_drawn[2], gb) {
+                Debugger::render_into_texture(
+                    &gb.dbg_render_background_tilemap(),
+                    &mut self.backgroundmap_texture,
+                    32,
+                    BACKGROUND_WIDTH,
+                    BACKGROUND_HEIGHT,
+                );
+            }
 
             let image = Image::new(&self.backgroundmap_texture);
             let image = image.fit_to_exact_size(vec2(
@@ -110,17 +316,25 @@ impl Debugger {
                 (BACKGROUND_HEIGHT * (SCALE / 4)) as f32,
             ));
             ui.add(image);
+
+            if ui.button("Export tilemap as PNG").clicked() {
+                let path = format!("{}.background.png", rom_path);
+                let result = save_tiles_png(&gb.dbg_render_background_tilemap(), 32, Path::new(&path));
+                self.export_status = Some(("Background Tilemap", export_outcome(&path, result)));
+            }
+            show_export_status(ui, &self.export_status, "Background Tilemap");
         });
 
-        Window::new("Window Tilemap").resizable(false).show(ctx, |ui| {
-            let windowmap = gb.dbg_render_window_tilemap();
-            Debugger::render_into_texture(
-                &windowmap,
-                &mut self.windowmap_texture,
-                32,
-                BACKGROUND_WIDTH,
-                BACKGROUND_HEIGHT,
-            );
+        show_panel(ctx, &mut self.detached, "Window Tilemap", |ui| {
+            if vram_view_stale(&mut self.vram_views_drawn[3], gb) {
+                Debugger::render_into_texture(
+                    &gb.dbg_render_window_tilemap(),
+                    &mut self.windowmap_texture,
+                    32,
+                    BACKGROUND_WIDTH,
+                    BACKGROUND_HEIGHT,
+                );
+            }
 
             let image = Image::new(&self.windowmap_texture);
             let image = image.fit_to_exact_size(vec2(
@@ -128,10 +342,631 @@ impl Debugger {
                 (BACKGROUND_HEIGHT * (SCALE / 4)) as f32,
             ));
             ui.add(image);
+
+            if ui.button("Export tilemap as PNG").clicked() {
+                let path = format!("{}.window.png", rom_path);
+                let result = save_tiles_png(&gb.dbg_render_window_tilemap(), 32, Path::new(&path));
+                self.export_status = Some(("Window Tilemap", export_outcome(&path, result)));
+            }
+            show_export_status(ui, &self.export_status, "Window Tilemap");
+        });
+
+        show_panel(ctx, &mut self.detached, "Timing", |ui| {
+            ui.label(RichText::new(format!("Frame:  {}", gb.frame_count())).text_style(TextStyle::Monospace));
+            ui.label(RichText::new(format!("Cycles: {}", gb.cycle_count())).text_style(TextStyle::Monospace));
+            ui.label(
+                RichText::new(format!("Time:   {:.3}s", gb.emulated_time().as_secs_f64()))
+                    .text_style(TextStyle::Monospace),
+            );
+            ui.label(RichText::new(format!("LY:     {}", gb.scanline())).text_style(TextStyle::Monospace));
+            ui.label(RichText::new(format!("Mode:   {:?}", gb.ppu_mode())).text_style(TextStyle::Monospace));
+
+            let channels = gb.mmu.apu.channels_enabled();
+            ui.label(
+                RichText::new(format!(
+                    "APU:    {}",
+                    (1..=4)
+                        .zip(channels)
+                        .map(|(channel, enabled)| if enabled { channel.to_string() } else { "-".to_string() })
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                ))
+                .text_style(TextStyle::Monospace),
+            );
+        });
+
+        show_panel(ctx, &mut self.detached, "Cartridge", |ui| {
+            let cartridge = &gb.mmu.cartridge;
+            let banks = |size: usize, count: usize| match size {
+                0 => String::from("none"),
+                _ => format!("{} ({} banks)", format_size(size), count),
+            };
+
+            ui.label(RichText::new(format!("Mapper:   {}", cartridge.name())).text_style(TextStyle::Monospace));
+            ui.label(
+                RichText::new(format!(
+                    "ROM:      {}",
+                    banks(cartridge.rom_size(), cartridge.rom_bank_count())
+                ))
+                .text_style(TextStyle::Monospace),
+            );
+            ui.label(
+                RichText::new(format!(
+                    "RAM:      {}",
+                    banks(cartridge.ram_size(), cartridge.ram_bank_count())
+                ))
+                .text_style(TextStyle::Monospace),
+            );
+            ui.label(
+                RichText::new(format!("ROM bank: {}", cartridge.current_rom_bank())).text_style(TextStyle::Monospace),
+            );
+            if cartridge.ram_size() > 0 {
+                ui.label(
+                    RichText::new(format!("RAM bank: {}", cartridge.current_ram_bank()))
+                        .text_style(TextStyle::Monospace),
+                );
+            }
+            if let Some(rtc) = cartridge.rtc() {
+                let mut time = format!(
+                    "RTC:      day {} {:02}:{:02}:{:02}",
+                    rtc.days, rtc.hours, rtc.minutes, rtc.seconds
+                );
+                if rtc.halted {
+                    time.push_str(", halted");
+                }
+                if rtc.day_carry {
+                    time.push_str(", day overflow");
+                }
+                ui.label(RichText::new(time).text_style(TextStyle::Monospace));
+            }
+
+            // Only battery backed RAM ends up in the .sav
+            let battery = match (cartridge.has_battery(), cartridge.ram_dirty()) {
+                (false, _) => "none",
+                (true, true) => "yes, unsaved changes (F5 saves)",
+                (true, false) => "yes, saved",
+            };
+            ui.label(RichText::new(format!("Battery:  {}", battery)).text_style(TextStyle::Monospace));
+        });
+
+        show_panel(ctx, &mut self.detached, "APU", |ui| {
+            ui.label(RichText::new("CH  State  Frequency  Note      Vol  Envelope").text_style(TextStyle::Monospace));
+            for (channel, state) in (1..=4).zip(gb.mmu.apu.channel_states()) {
+                // The noise channel has no pitch, its LFSR clock is shown instead
+                let note = if channel == 4 {
+                    None
+                } else {
+                    Note::nearest(state.frequency)
+                };
+                let envelope = match state.envelope {
+                    Some(envelope) if envelope.period == 0 => format!("{:>2} hold", envelope.initial_volume),
+                    Some(envelope) => format!(
+                        "{:>2} {}{}",
+                        envelope.initial_volume,
+                        if envelope.incrementing { "+" } else { "-" },
+                        envelope.period
+                    ),
+                    None => "-".to_string(),
+                };
+                ui.label(
+                    RichText::new(format!(
+                        "{}   {:<5}  {:>7.1}Hz  {:<8}  {:>3}  {}",
+                        channel,
+                        if state.enabled { "on" } else { "off" },
+                        state.frequency,
+                        note.map(|note| note.to_string()).unwrap_or("-".to_string()),
+                        state.volume,
+                        envelope
+                    ))
+                    .text_style(TextStyle::Monospace),
+                );
+            }
+        });
+
+        show_panel(ctx, &mut self.detached, "Memory Heatmap", |ui| {
+            Debugger::render_heatmap(&gb.mmu.heatmap, &mut self.heatmap_texture);
+
+            let size = (HEATMAP_SIZE * 16) as f32;
+            let response = ui.add(Image::new(&self.heatmap_texture).fit_to_exact_size(vec2(size, size)));
+
+            // One cell per 256 byte page, each row covers $1000 bytes
+            if let Some(pos) = response.hover_pos() {
+                let cell = (pos - response.rect.min) / 16.0;
+                let page =
+                    (cell.y as usize).min(HEATMAP_SIZE - 1) * HEATMAP_SIZE + (cell.x as usize).min(HEATMAP_SIZE - 1);
+                response.on_hover_text(format!(
+                    "${:04x}: {} reads, {} writes",
+                    page * PAGE_SIZE,
+                    gb.mmu.heatmap.reads(page),
+                    gb.mmu.heatmap.writes(page)
+                ));
+            }
+
+            ui.label("Green: reads, red: writes (last frame)");
+        });
+
+        show_panel(ctx, &mut self.detached, "Disassembly", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Address (empty for PC):");
+                ui.text_edit_singleline(&mut self.disasm_address);
+            });
+
+            let pc = gb.cpu.read_register16(&Register::PC);
+            let addr = Debugger::parse_address(&self.disasm_address).unwrap_or(pc);
+            let bank = if (SWITCHABLE_ROM_START..=ROM_END).contains(&addr) {
+                gb.mmu.cartridge.current_rom_bank() as usize
+            } else {
+                0
+            };
+
+            if let Ok(instructions) = disassemble_source(&gb.mmu, bank, addr, 16) {
+                for instruction in instructions {
+                    let marker = if instruction.address == pc { ">" } else { " " };
+                    let banked = BankedAddress::new(instruction.bank as u16, instruction.address);
+                    let breakpoint = if gb.breakpoints.addresses.contains(&banked) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    ui.label(
+                        RichText::new(format!("{}{} {}", marker, breakpoint, instruction))
+                            .text_style(TextStyle::Monospace),
+                    );
+                }
+            }
+
+            ui.separator();
+
+            // Patches RAM as usual and the loaded image for ROM addresses, the cartridge file is left untouched
+            ui.horizontal(|ui| {
+                ui.label("Assemble at:");
+                ui.add(egui::TextEdit::singleline(&mut self.assemble_address).desired_width(48.0));
+                ui.text_edit_singleline(&mut self.assemble_input);
+
+                if ui.button("Assemble").clicked() {
+                    self.assemble_status = match Debugger::parse_address(&self.assemble_address) {
+                        Some(addr) => match Debugger::assemble_at(&self.assembler, gb, addr, &self.assemble_input) {
+                            Ok(length) => format!("Wrote {} byte(s) to {:04x}", length, addr),
+                            Err(e) => e.to_string(),
+                        },
+                        None => format!("Invalid address: {}", self.assemble_address),
+                    };
+                }
+            });
+
+            if !self.assemble_status.is_empty() {
+                ui.label(&self.assemble_status);
+            }
+        });
+
+        show_panel(ctx, &mut self.detached, "Breakpoints", |ui| {
+            // A plain address in $4000-$7fff only breaks in the bank mapped right now
+            ui.horizontal(|ui| {
+                ui.label("Address (bank:addr):");
+                ui.add(egui::TextEdit::singleline(&mut self.breakpoint_address).desired_width(64.0));
+
+                if ui.button("Add").clicked() {
+                    match BankedAddress::parse(&self.breakpoint_address, gb.mmu.cartridge.as_ref()) {
+                        Some(addr) if !gb.breakpoints.addresses.contains(&addr) => gb.breakpoints.addresses.push(addr),
+                        Some(_) => {}
+                        None => self.breakpoint_status = format!("Invalid address: {}", self.breakpoint_address),
+                    }
+                }
+            });
+
+            ui.separator();
+
+            let mut removed = None;
+            for (idx, addr) in gb.breakpoints.addresses.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(addr.to_string()).text_style(TextStyle::Monospace));
+                    if ui.button("Remove").clicked() {
+                        removed = Some(idx);
+                    }
+                });
+            }
+            if let Some(idx) = removed {
+                gb.breakpoints.addresses.remove(idx);
+            }
+
+            ui.separator();
+
+            // Stops right before an instruction accesses the register through one of its operands
+            ui.horizontal(|ui| {
+                ui.label("IO register:");
+                let selected = self.io_breakpoint_register;
+                let picker = egui::ComboBox::from_id_source("io_breakpoint_register")
+                    .selected_text(Debugger::register_label(selected))
+                    .show_ui(ui, |ui| {
+                        for (address, doc) in register_docs() {
+                            ui.selectable_value(
+                                &mut self.io_breakpoint_register,
+                                address,
+                                Debugger::register_label(address),
+                            )
+                            .on_hover_text(doc.summary);
+                        }
+                    });
+                if let Some(tooltip) = Debugger::register_tooltip(gb, selected) {
+                    picker.response.on_hover_text(tooltip);
+                }
+                egui::ComboBox::from_id_source("io_breakpoint_access")
+                    .selected_text(self.io_breakpoint_access.name())
+                    .show_ui(ui, |ui| {
+                        for access in Access::ALL {
+                            ui.selectable_value(&mut self.io_breakpoint_access, access, access.name());
+                        }
+                    });
+
+                if ui.button("Add").clicked() {
+                    let breakpoint = IoBreakpoint {
+                        address: self.io_breakpoint_register,
+                        access: self.io_breakpoint_access,
+                    };
+                    if !gb.breakpoints.io.contains(&breakpoint) {
+                        gb.breakpoints.io.push(breakpoint);
+                    }
+                }
+            });
+
+            let mut removed = None;
+            for (idx, breakpoint) in gb.breakpoints.io.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(breakpoint.to_string()).text_style(TextStyle::Monospace));
+                    if ui.button("Remove").clicked() {
+                        removed = Some(idx);
+                    }
+                });
+            }
+            if let Some(idx) = removed {
+                gb.breakpoints.io.remove(idx);
+            }
+
+            ui.separator();
+
+            // Stops once the expression turns true, e.g. [0xc345] > 3 && A == 0xff
+            ui.horizontal(|ui| {
+                ui.label("Condition:");
+                ui.add(egui::TextEdit::singleline(&mut self.breakpoint_condition).desired_width(160.0));
+
+                if ui.button("Add").clicked() {
+                    match BreakCondition::parse(&self.breakpoint_condition) {
+                        Ok(condition) => gb.breakpoints.conditions.push(condition),
+                        Err(e) => self.breakpoint_status = e.to_string(),
+                    }
+                }
+            });
+
+            let mut removed = None;
+            for (idx, condition) in gb.breakpoints.conditions.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    ui.label(RichText::new(&condition.source).text_style(TextStyle::Monospace));
+                    if ui.button("Remove").clicked() {
+                        removed = Some(idx);
+                    }
+                });
+            }
+            if let Some(idx) = removed {
+                gb.breakpoints.conditions.remove(idx);
+            }
+
+            if !self.breakpoint_status.is_empty() {
+                ui.label(&self.breakpoint_status);
+            }
+        });
+
+        if gb.cpu.stack_check_enabled() {
+            show_panel(ctx, &mut self.detached, "Stack", |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(
+                        RichText::new(format!("SP: ${:04x}", gb.cpu.read_register16(&Register::SP)))
+                            .text_style(TextStyle::Monospace),
+                    );
+                    if ui.button("Clear").clicked() {
+                        gb.cpu.clear_stack_warnings();
+                        self.stack_warnings_seen = 0;
+                    }
+                });
+
+                ui.separator();
+
+                if gb.cpu.stack_warnings().is_empty() {
+                    ui.label("No stack problems so far");
+                }
+                for warning in gb.cpu.stack_warnings() {
+                    ui.label(RichText::new(warning.to_string()).text_style(TextStyle::Monospace));
+                }
+            });
+        }
+
+        if gb.mmu.uninit.is_enabled() {
+            show_panel(ctx, &mut self.detached, "Uninitialized Reads", |ui| {
+                if ui.button("Clear").clicked() {
+                    gb.mmu.uninit.clear();
+                    self.uninit_reads_seen = 0;
+                }
+
+                ui.separator();
+
+                let reads = gb.mmu.uninit.reads();
+                if reads.is_empty() {
+                    ui.label("Every WRAM/HRAM read so far was written first");
+                }
+                egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                    for read in reads {
+                        ui.label(RichText::new(read.to_string()).text_style(TextStyle::Monospace));
+                    }
+                });
+            });
+        }
+
+        show_panel(ctx, &mut self.detached, "RAM Watch", |ui| {
+            ui.horizontal(|ui| {
+                ui.label("Address:");
+                ui.add(egui::TextEdit::singleline(&mut self.watch_address).desired_width(48.0));
+                egui::ComboBox::from_id_source("watch_type")
+                    .selected_text(self.watch_type.name())
+                    .show_ui(ui, |ui| {
+                        for kind in WatchType::ALL {
+                            ui.selectable_value(&mut self.watch_type, kind, kind.name());
+                        }
+                    });
+
+                if ui.button("Add").clicked() {
+                    match Debugger::parse_address(&self.watch_address) {
+                        Some(addr) => self.watches.push(Watch::new(addr, self.watch_type)),
+                        None => self.watch_status = format!("Invalid address: {}", self.watch_address),
+                    }
+                }
+            });
+
+            ui.separator();
+
+            let mut removed = None;
+            for (idx, watch) in self.watches.iter_mut().enumerate() {
+                ui.horizontal(|ui| {
+                    let value = watch.format(&watch.frozen.clone().unwrap_or_else(|| watch.read(&gb.mmu)));
+                    let label = ui.label(
+                        RichText::new(format!("${:04x} {:<4} {:>14}", watch.address, watch.kind.name(), value))
+                            .text_style(TextStyle::Monospace),
+                    );
+                    if let Some(tooltip) = Debugger::register_tooltip(gb, watch.address) {
+                        label.on_hover_text(tooltip);
+                    }
+
+                    let mut frozen = watch.frozen.is_some();
+                    if ui.checkbox(&mut frozen, "Freeze").changed() {
+                        match frozen {
+                            true => watch.freeze(&gb.mmu),
+                            false => watch.frozen = None,
+                        }
+                    }
+
+                    if ui.button("Remove").clicked() {
+                        removed = Some(idx);
+                    }
+                });
+            }
+            if let Some(idx) = removed {
+                self.watches.remove(idx);
+            }
+
+            ui.separator();
+
+            ui.horizontal(|ui| {
+                ui.label("Expression:");
+                ui.add(egui::TextEdit::singleline(&mut self.watch_expression).desired_width(160.0));
+
+                if ui.button("Add").clicked() {
+                    match Expr::parse(&self.watch_expression) {
+                        Ok(expr) => self
+                            .expression_watches
+                            .push((self.watch_expression.trim().to_owned(), expr)),
+                        Err(e) => self.watch_status = e.to_string(),
+                    }
+                }
+            });
+
+            let mut removed = None;
+            for (idx, (source, expr)) in self.expression_watches.iter().enumerate() {
+                ui.horizontal(|ui| {
+                    let value = expr.eval(&gb.cpu, &gb.mmu);
+                    ui.label(
+                        RichText::new(format!("{} = {} (${:x})", source, value, value))
+                            .text_style(TextStyle::Monospace),
+                    );
+                    if ui.button("Remove").clicked() {
+                        removed = Some(idx);
+                    }
+                });
+            }
+            if let Some(idx) = removed {
+                self.expression_watches.remove(idx);
+            }
+
+            ui.separator();
+
+            // Watch lists live next to the ROM, so every game keeps its own
+            let watch_path = format!("{}.watch", rom_path);
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    self.watch_status = match std::fs::write(&watch_path, save_watches(&self.watches)) {
+                        Ok(_) => format!("Saved {} watch(es) to {}", self.watches.len(), watch_path),
+                        Err(e) => format!("Failed to save {}: {}", watch_path, e),
+                    };
+                }
+
+                if ui.button("Load").clicked() {
+                    self.watch_status = match std::fs::read_to_string(&watch_path) {
+                        Ok(data) => {
+                            self.watches = load_watches(&data);
+                            format!("Loaded {} watch(es) from {}", self.watches.len(), watch_path)
+                        }
+                        Err(e) => format!("Failed to load {}: {}", watch_path, e),
+                    };
+                }
+            });
+
+            if !self.watch_status.is_empty() {
+                ui.label(&self.watch_status);
+            }
+        });
+
+        show_panel(ctx, &mut self.detached, "Cheat Search", |ui| {
+            ui.horizontal(|ui| {
+                if ui.button("New search").clicked() {
+                    self.cheat_search = Some(CheatSearch::new(gb.mmu.dump_wram()));
+                    self.cheat_status.clear();
+                }
+
+                egui::ComboBox::from_id_source("cheat_filter")
+                    .selected_text(CHEAT_SEARCH_FILTERS[self.cheat_filter])
+                    .show_ui(ui, |ui| {
+                        for (idx, name) in CHEAT_SEARCH_FILTERS.iter().enumerate() {
+                            ui.selectable_value(&mut self.cheat_filter, idx, *name);
+                        }
+                    });
+
+                if self.cheat_filter == 0 || self.cheat_filter == 5 {
+                    ui.add(egui::TextEdit::singleline(&mut self.cheat_value).desired_width(48.0));
+                }
+
+                if let Some(search) = &mut self.cheat_search
+                    && ui.button("Filter").clicked()
+                {
+                    match Debugger::parse_filter(self.cheat_filter, &self.cheat_value) {
+                        Some(filter) => {
+                            search.filter(gb.mmu.dump_wram(), filter);
+                            self.cheat_status.clear();
+                        }
+                        None => self.cheat_status = format!("Invalid value: {}", self.cheat_value),
+                    }
+                }
+            });
+
+            let Some(search) = &self.cheat_search else {
+                ui.label("Start a new search to snapshot WRAM");
+                return;
+            };
+
+            ui.label(format!("{} candidate(s)", search.candidates().len()));
+            ui.separator();
+
+            // Hits turn into RAM watches, or into frozen watches to act as cheats
+            for addr in search.candidates().iter().take(CHEAT_SEARCH_LISTED) {
+                ui.horizontal(|ui| {
+                    let value = search.value(*addr);
+                    ui.label(
+                        RichText::new(format!("${:04x} {:>3} (${:02x})", addr, value, value))
+                            .text_style(TextStyle::Monospace),
+                    );
+
+                    if ui.button("Watch").clicked() {
+                        self.watches.push(Watch::new(*addr, WatchType::U8));
+                    }
+
+                    if ui.button("Freeze").clicked() {
+                        let mut watch = Watch::new(*addr, WatchType::U8);
+                        watch.freeze(&gb.mmu);
+                        self.watches.push(watch);
+                    }
+                });
+            }
+
+            if !self.cheat_status.is_empty() {
+                ui.label(&self.cheat_status);
+            }
+        });
+
+        show_panel(ctx, &mut self.detached, "IO Log", |ui| {
+            ui.horizontal(|ui| {
+                if gb.mmu.io_log.is_recording() {
+                    if ui.button("Stop").clicked() {
+                        gb.mmu.io_log.stop();
+                    }
+                } else if ui.button("Record").clicked() {
+                    gb.mmu.io_log.start(gb.frame_count());
+                }
+
+                if ui.button("Clear").clicked() {
+                    gb.mmu.io_log.clear();
+                }
+
+                ui.label("Register:");
+                ui.add(egui::TextEdit::singleline(&mut self.io_log_register).desired_width(48.0));
+            });
+
+            let writes = gb.mmu.io_log.writes();
+            let full = if gb.mmu.io_log.is_full() { ", full" } else { "" };
+            ui.label(format!("{} write(s){}", writes.len(), full));
+            ui.separator();
+
+            // Newest first, empty or unparsable filters show every register
+            let register =
+                register_address(&self.io_log_register).or_else(|| Debugger::parse_address(&self.io_log_register));
+            let listed = writes
+                .iter()
+                .rev()
+                .filter(|write| register.is_none_or(|register| write.address == register))
+                .take(IO_LOG_LISTED);
+            egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                for write in listed {
+                    let label = ui.label(
+                        RichText::new(format!(
+                            "{:>8} LY {:>3}  {:<12} = ${:02x}",
+                            write.frame,
+                            write.scanline,
+                            Debugger::register_label(write.address),
+                            write.value
+                        ))
+                        .text_style(TextStyle::Monospace),
+                    );
+                    // Decodes the value that was written, not what the register holds now
+                    if let Some(doc) = register_doc(write.address) {
+                        label.on_hover_text(doc.describe(write.value));
+                    }
+                }
+            });
+
+            ui.separator();
+
+            // Traces live next to the ROM, CSV for reading and binary for tools
+            ui.horizontal(|ui| {
+                let csv_path = format!("{}.iolog.csv", rom_path);
+                if ui.button("Save CSV").clicked() {
+                    self.io_log_status = match std::fs::write(&csv_path, gb.mmu.io_log.to_csv()) {
+                        Ok(_) => format!("Saved to {}", csv_path),
+                        Err(e) => format!("Failed to save {}: {}", csv_path, e),
+                    };
+                }
+
+                let binary_path = format!("{}.iolog", rom_path);
+                if ui.button("Save binary").clicked() {
+                    self.io_log_status = match std::fs::write(&binary_path, gb.mmu.io_log.to_binary()) {
+                        Ok(_) => format!("Saved to {}", binary_path),
+                        Err(e) => format!("Failed to save {}: {}", binary_path, e),
+                    };
+                }
+            });
+
+            if !self.io_log_status.is_empty() {
+                ui.label(&self.io_log_status);
+            }
         });
 
-        if gb.mode == Mode::Cgb {
-            Window::new("Palettes").resizable(false).show(ctx, |ui| {
+        show_panel(ctx, &mut self.detached, "Palettes", |ui| {
+            if gb.mode == Mode::Dmg {
+                for (name, register) in [
+                    ("BGP ", BG_PALETTE_REGISTER),
+                    ("OBP0", OBJ0_PALETTE_REGISTER),
+                    ("OBP1", OBJ1_PALETTE_REGISTER),
+                ] {
+                    ui.label(
+                        RichText::new(format!("{}: {:08b}", name, gb.mmu.read_unchecked(register)))
+                            .text_style(TextStyle::Monospace),
+                    );
+                }
+            } else {
                 ui.heading("Background Palette");
 
                 for slot in 0..8 {
@@ -160,33 +995,172 @@ impl Debugger {
                         }
                     });
                 }
+            }
+
+            ui.separator();
+
+            // Background palettes first, then the object ones, as the PPU draws them
+            if ui.button("Export palettes as .pal").clicked() {
+                let path = format!("{}.pal", rom_path);
+                let result = std::fs::write(&path, to_pal(&palettes(gb)));
+                self.export_status = Some(("Palettes", export_outcome(&path, result)));
+            }
+            show_export_status(ui, &self.export_status, "Palettes");
+        });
+
+        show_panel(ctx, &mut self.detached, "State Diff", |ui| {
+            // Snapshot against the running game, or the quick save against either of them
+            let snapshot = self
+                .diff_snapshot
+                .as_ref()
+                .and_then(|state| state.downcast_ref::<GameBoy>());
+            let quick_save = quick_save.and_then(|state| state.downcast_ref::<GameBoy>());
+            let mut compared = None;
+            let mut take_snapshot = false;
+            ui.horizontal(|ui| {
+                take_snapshot = ui.button("Take snapshot").clicked();
+                if let Some(snapshot) = snapshot
+                    && ui.button("Snapshot vs live").clicked()
+                {
+                    compared = Some(("snapshot", "live", diff_states(snapshot, gb)));
+                }
+                if let Some(quick_save) = quick_save
+                    && ui.button("Quick save vs live").clicked()
+                {
+                    compared = Some(("quick save", "live", diff_states(quick_save, gb)));
+                }
+                if let (Some(snapshot), Some(quick_save)) = (snapshot, quick_save)
+                    && ui.button("Snapshot vs quick save").clicked()
+                {
+                    compared = Some(("snapshot", "quick save", diff_states(snapshot, quick_save)));
+                }
             });
-        }
+            if take_snapshot {
+                self.diff_snapshot = Some(gb.save_state());
+                self.diff_status = format!("Took snapshot at frame {}", gb.frame_count());
+            }
+            if let Some((before, after, changes)) = compared {
+                self.diff_status = format!("{} change(s) from {} to {}", changes.len(), before, after);
+                self.diff_changes = changes;
+            }
+            ui.label(&self.diff_status);
+            ui.separator();
+
+            egui::ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+                for group in self.diff_changes.chunk_by(|a, b| a.subsystem == b.subsystem) {
+                    egui::CollapsingHeader::new(format!("{} ({})", group[0].subsystem, group.len()))
+                        .default_open(true)
+                        .show(ui, |ui| {
+                            for change in group {
+                                ui.label(RichText::new(change.to_string()).text_style(TextStyle::Monospace));
+                            }
+                        });
+                }
+            });
+        });
     }
 
     pub fn toggle_window(&mut self) {
         self.window_open = !self.window_open;
     }
 
-    fn render_into_texture(
-        tiles: &Vec<Tile>, texture: &mut TextureHandle, boundary: usize, width: usize, height: usize,
-    ) {
-        let mut pixels = vec![Color32::BLACK; width * height];
+    fn assemble_at(assembler: &Assembler, gb: &mut GameBoy, addr: u16, line: &str) -> Result<usize, AyyError> {
+        let bytes = assembler.assemble(line)?;
+        for (idx, byte) in bytes.iter().enumerate() {
+            gb.mmu.patch(addr.wrapping_add(idx as u16), *byte)?;
+        }
 
-        for (idx, tile) in tiles.iter().enumerate() {
-            for y in 0..8 {
-                for x in 0..8 {
-                    // 16 tiles per row
-                    let color: Color = tile.pixels[y][x].into();
-                    let color32 = Color32::from_rgba_premultiplied(color[0], color[1], color[2], 255);
+        Ok(bytes.len())
+    }
 
-                    let tile_x = (idx % boundary) * 8 + x;
-                    let tile_y = (idx / boundary) * 8 + y;
+    // Values are decimal, or hex with a "$" or "0x" prefix
+    fn parse_filter(filter: usize, value: &str) -> Option<Filter> {
+        let value = value.trim();
+        let parse = |value: &str| match value.strip_prefix("0x").or_else(|| value.strip_prefix('$')) {
+            Some(digits) => i16::from_str_radix(digits, 16).ok(),
+            None => value.parse::<i16>().ok(),
+        };
 
-                    pixels[tile_y * 8 * boundary + tile_x] = color32;
-                }
-            }
+        match filter {
+            0 => parse(value)
+                .and_then(|value| u8::try_from(value).ok())
+                .map(Filter::EqualTo),
+            1 => Some(Filter::Greater),
+            2 => Some(Filter::Less),
+            3 => Some(Filter::Changed),
+            4 => Some(Filter::Unchanged),
+            _ => parse(value).map(Filter::ChangedBy),
         }
+    }
+
+    // e.g. "LCDC $ff40", unnamed registers only show the address
+    fn register_label(address: u16) -> String {
+        match register_name(address) {
+            Some(name) => format!("{:<5} ${:04x}", name, address),
+            None => format!("${:04x}", address),
+        }
+    }
+
+    // Documentation of an IO register with its bits decoded from the current value
+    fn register_tooltip(gb: &GameBoy, address: u16) -> Option<String> {
+        let doc = register_doc(address)?;
+        let value = gb.mmu.peek(address).ok()?;
+        Some(format!(
+            "{} = ${:02x}\n{}",
+            Debugger::register_label(address),
+            value,
+            doc.describe(value)
+        ))
+    }
+
+    fn parse_address(value: &str) -> Option<u16> {
+        let value = value.trim();
+        let digits = value
+            .strip_prefix("0x")
+            .or_else(|| value.strip_prefix('$'))
+            .unwrap_or(value);
+
+        u16::from_str_radix(digits, 16).ok()
+    }
+
+    fn render_heatmap(heatmap: &AccessHeatmap, texture: &mut TextureHandle) {
+        let max_reads = (0..PAGE_COUNT).map(|page| heatmap.reads(page)).max().unwrap_or(0);
+        let max_writes = (0..PAGE_COUNT).map(|page| heatmap.writes(page)).max().unwrap_or(0);
+
+        // Logarithmic, a handful of accesses should still be visible next to a busy page
+        let intensity = |count: u32, max: u32| {
+            if max == 0 {
+                0
+            } else {
+                ((count as f32).ln_1p() / (max as f32).ln_1p() * 255.0) as u8
+            }
+        };
+
+        let pixels = (0..PAGE_COUNT)
+            .map(|page| {
+                Color32::from_rgb(
+                    intensity(heatmap.writes(page), max_writes),
+                    intensity(heatmap.reads(page), max_reads),
+                    0,
+                )
+            })
+            .collect();
+
+        let image = ColorImage {
+            size: [HEATMAP_SIZE, HEATMAP_SIZE],
+            pixels,
+        };
+
+        texture.set(image, TextureOptions::NEAREST);
+    }
+
+    fn render_into_texture(
+        tiles: &Vec<Tile>, texture: &mut TextureHandle, boundary: usize, width: usize, height: usize,
+    ) {
+        let pixels = layout_tiles(tiles, boundary)
+            .iter()
+            .map(|color| Color32::from_rgba_premultiplied(color[0], color[1], color[2], 255))
+            .collect();
 
         let image = ColorImage {
             size: [width, height],
@@ -196,3 +1170,60 @@ impl Debugger {
         texture.set(image, TextureOptions::NEAREST);
     }
 }
+
+// Whether a view of VRAM needs drawing again, which it then counts as drawn. Tilemaps and tile addressing
+// follow LCDC, so that counts as well.
+fn vram_view_stale(drawn: &mut Option<(u64, u8)>, gb: &GameBoy) -> bool {
+    let current = Some((gb.mmu.vram_generation(), gb.mmu.read_unchecked(LCD_CONTROL_REGISTER)));
+    std::mem::replace(drawn, current) != current
+}
+
+// Cartridge sizes are powers of two, from 2 KiB RAM up to 8 MiB ROM
+fn format_size(bytes: usize) -> String {
+    match bytes {
+        0..0x400 => format!("{} bytes", bytes),
+        0x400..0x100000 => format!("{} KiB", bytes / 0x400),
+        _ => format!("{} MiB", bytes / 0x100000),
+    }
+}
+
+fn export_outcome(path: &str, result: std::io::Result<()>) -> String {
+    match result {
+        Ok(_) => format!("Saved to {}", path),
+        Err(e) => format!("Failed to save {}: {}", path, e),
+    }
+}
+
+fn show_export_status(ui: &mut Ui, status: &Option<(&'static str, String)>, panel: &'static str) {
+    if let Some((_, status)) = status.as_ref().filter(|(exported_from, _)| *exported_from == panel) {
+        ui.label(status);
+    }
+}
+
+// Shows a debugger panel as a window inside the main one, or in its own OS window while detached
+fn show_panel(
+    ctx: &Context, detached: &mut Vec<&'static str>, title: &'static str, add_contents: impl FnOnce(&mut Ui),
+) {
+    if !detached.contains(&title) {
+        Window::new(title).resizable(false).show(ctx, add_contents);
+        return;
+    }
+
+    let closed = ctx.show_viewport_immediate(
+        ViewportId::from_hash_of(title),
+        ViewportBuilder::default().with_title(title),
+        |ctx, class| {
+            // Backends that can't open more windows embed it instead
+            if class == ViewportClass::Embedded {
+                Window::new(title).resizable(false).show(ctx, add_contents);
+                return false;
+            }
+
+            CentralPanel::default().show(ctx, add_contents);
+            ctx.input(|i| i.viewport().close_requested())
+        },
+    );
+    if closed {
+        detached.retain(|panel| *panel != title);
+    }
+}
@@ -0,0 +1,140 @@
+use eframe::egui::{PaintCallback, Rect};
+use eframe::egui_glow::{self, CallbackFn, ShaderVersion};
+use eframe::glow::{self, HasContext};
+use std::sync::{Arc, Mutex};
+
+// Covers whatever the viewport is with one triangle strip, no vertex buffer needed
+const VERTEX_SHADER: &str = r#"
+    const vec2 corners[4] = vec2[4](vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(-1.0, 1.0), vec2(1.0, 1.0));
+    out vec2 v_uv;
+    void main() {
+        vec2 corner = corners[gl_VertexID];
+        v_uv = vec2(corner.x * 0.5 + 0.5, 0.5 - corner.y * 0.5);
+        gl_Position = vec4(corner, 0.0, 1.0);
+    }
+"#;
+
+const FRAGMENT_SHADER: &str = r#"
+    precision mediump float;
+    uniform sampler2D u_screen;
+    in vec2 v_uv;
+    out vec4 out_color;
+    void main() {
+        out_color = texture(u_screen, v_uv);
+    }
+"#;
+
+// Draws the screen straight into the window with OpenGL, in a paint callback, instead of handing egui a new
+// image every frame to tessellate and upload along with the rest of the UI. Frames are only copied into
+// a texture of its own when egui gets around to painting.
+pub struct DirectScreen {
+    program: glow::Program,
+    vertex_array: glow::VertexArray,
+    texture: glow::Texture,
+    // Latest frame as RGBA, older ones that never made it to the screen are dropped
+    pending: Option<(usize, usize, Vec<u8>)>,
+}
+
+impl DirectScreen {
+    pub fn new(gl: &glow::Context) -> Result<DirectScreen, String> {
+        let version = ShaderVersion::get(gl);
+        if !version.is_new_shader_interface() {
+            return Err(format!("{:?} is too old to draw the screen directly", version));
+        }
+
+        unsafe {
+            let program = gl.create_program()?;
+            let mut shaders = Vec::new();
+            for (kind, source) in [
+                (glow::VERTEX_SHADER, VERTEX_SHADER),
+                (glow::FRAGMENT_SHADER, FRAGMENT_SHADER),
+            ] {
+                let shader = gl.create_shader(kind)?;
+                gl.shader_source(shader, &format!("{}\n{}", version.version_declaration(), source));
+                gl.compile_shader(shader);
+                if !gl.get_shader_compile_status(shader) {
+                    return Err(format!("Failed to compile shader: {}", gl.get_shader_info_log(shader)));
+                }
+                gl.attach_shader(program, shader);
+                shaders.push(shader);
+            }
+            gl.link_program(program);
+            if !gl.get_program_link_status(program) {
+                return Err(format!("Failed to link shaders: {}", gl.get_program_info_log(program)));
+            }
+            for shader in shaders {
+                gl.detach_shader(program, shader);
+                gl.delete_shader(shader);
+            }
+
+            let vertex_array = gl.create_vertex_array()?;
+            let texture = gl.create_texture()?;
+            gl.bind_texture(glow::TEXTURE_2D, Some(texture));
+            // Pixels stay sharp however large the window is, like the NEAREST texture egui would get
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MIN_FILTER, glow::NEAREST as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_MAG_FILTER, glow::NEAREST as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_S, glow::CLAMP_TO_EDGE as i32);
+            gl.tex_parameter_i32(glow::TEXTURE_2D, glow::TEXTURE_WRAP_T, glow::CLAMP_TO_EDGE as i32);
+            gl.bind_texture(glow::TEXTURE_2D, None);
+
+            Ok(DirectScreen {
+                program,
+                vertex_array,
+                texture,
+                pending: None,
+            })
+        }
+    }
+
+    pub fn set_frame(&mut self, width: usize, height: usize, pixels: Vec<u8>) {
+        self.pending = Some((width, height, pixels));
+    }
+
+    // egui puts its own state back after a callback, so nothing needs restoring here
+    fn paint(&mut self, gl: &glow::Context) {
+        unsafe {
+            gl.active_texture(glow::TEXTURE0);
+            gl.bind_texture(glow::TEXTURE_2D, Some(self.texture));
+            if let Some((width, height, pixels)) = self.pending.take() {
+                gl.pixel_store_i32(glow::UNPACK_ALIGNMENT, 1);
+                gl.tex_image_2d(
+                    glow::TEXTURE_2D,
+                    0,
+                    glow::RGBA8 as i32,
+                    width as i32,
+                    height as i32,
+                    0,
+                    glow::RGBA,
+                    glow::UNSIGNED_BYTE,
+                    Some(&pixels),
+                );
+            }
+
+            gl.disable(glow::BLEND);
+            gl.use_program(Some(self.program));
+            gl.uniform_1_i32(gl.get_uniform_location(self.program, "u_screen").as_ref(), 0);
+            gl.bind_vertex_array(Some(self.vertex_array));
+            gl.draw_arrays(glow::TRIANGLE_STRIP, 0, 4);
+        }
+    }
+
+    // The GL objects outlive the screen otherwise, only possible while the context is still around
+    pub fn destroy(&self, gl: &glow::Context) {
+        unsafe {
+            gl.delete_program(self.program);
+            gl.delete_vertex_array(self.vertex_array);
+            gl.delete_texture(self.texture);
+        }
+    }
+}
+
+// Paints the screen over the rect when egui draws this frame, the viewport is set to the rect by then
+pub fn paint_callback(screen: &Arc<Mutex<DirectScreen>>, rect: Rect) -> PaintCallback {
+    let screen = screen.clone();
+    PaintCallback {
+        rect,
+        callback: Arc::new(CallbackFn::new(move |_info, painter: &egui_glow::Painter| {
+            screen.lock().unwrap().paint(painter.gl());
+        })),
+    }
+}
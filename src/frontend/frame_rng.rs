@@ -0,0 +1,52 @@
+use crate::memory::ram_init::XorShift;
+use crate::system::Button;
+
+// Random numbers for tools that drive a game, like button mashing or fuzzing the input. Every frame the tool
+// runs gets its own stream derived from the seed and the frame's number in the run, so a run repeated with
+// the same seed sees the same numbers, no matter how fast the host is or how many numbers earlier frames
+// took. That's the number of run_frame calls, the system's frame count stands still while the LCD is off.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameRng {
+    seed: u64,
+}
+
+impl FrameRng {
+    pub fn new(seed: u64) -> FrameRng {
+        FrameRng { seed }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // The numbers of a frame, counted from the start of the run
+    pub fn frame(&self, frame: u64) -> XorShift {
+        XorShift::new(mix(self.seed ^ mix(frame)))
+    }
+
+    // Random input for a frame, as a bitmask of Button::mask. Opposite directions are never held together,
+    // a real d-pad can't do that and some games misbehave when it happens.
+    pub fn buttons(&self, frame: u64) -> u8 {
+        let mut rng = self.frame(frame);
+        let mut input = rng.next_u64() as u8;
+        for (first, second) in [(Button::Up, Button::Down), (Button::Left, Button::Right)] {
+            let both = first.mask() | second.mask();
+            if input & both == both {
+                input &= !if rng.next_u64() & 1 == 0 {
+                    first.mask()
+                } else {
+                    second.mask()
+                };
+            }
+        }
+        input
+    }
+}
+
+// splitmix64's finalizer, spreads consecutive frame numbers over the whole state
+fn mix(value: u64) -> u64 {
+    let mut x = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
@@ -0,0 +1,82 @@
+use crate::frontend::frame_rng::FrameRng;
+use crate::frontend::screenshot::{frame_hash, save_png};
+use crate::gameboy::GameBoy;
+use crate::system::System;
+use crate::video::SCREEN_WIDTH;
+use std::path::{Path, PathBuf};
+
+// One "<frame> <hash>" line per frame
+pub const HASHES_FILE: &str = "frames.txt";
+// Every distinct frame as <hash>.png, most frames repeat so this stays small
+const FRAMES_DIRECTORY: &str = "frames";
+
+// Runs the game for the given number of frames and records a hash of every one of them into the
+// directory, along with the frames themselves. Two builds running the same ROM should end up with
+// the same hashes, the first one that differs is where they diverged. With an RNG the buttons are mashed at
+// random, the same way for the same seed, so that traces also cover what the game does with input.
+pub fn record(gb: &mut GameBoy, frames: u64, dir: &Path, mash: Option<&FrameRng>) -> std::io::Result<Vec<String>> {
+    std::fs::create_dir_all(dir.join(FRAMES_DIRECTORY))?;
+
+    let mut hashes = Vec::new();
+    for frame in 0..frames {
+        if let Some(rng) = mash {
+            gb.update_buttons(rng.buttons(frame));
+        }
+        gb.run_frame();
+
+        let pixels = gb.framebuffer();
+        let hash = frame_hash(pixels.as_flattened());
+        let png = frame_path(dir, &hash);
+        if !png.exists() {
+            save_png(&png, SCREEN_WIDTH, &pixels)?;
+        }
+        hashes.push(hash);
+    }
+
+    let lines = hashes
+        .iter()
+        .enumerate()
+        .map(|(frame, hash)| format!("{} {}\n", frame, hash))
+        .collect::<String>();
+    std::fs::write(dir.join(HASHES_FILE), lines)?;
+    Ok(hashes)
+}
+
+pub fn load_hashes(dir: &Path) -> std::io::Result<Vec<String>> {
+    let lines = std::fs::read_to_string(dir.join(HASHES_FILE))?;
+    Ok(lines
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(str::to_owned)
+        .collect())
+}
+
+// First frame the two runs disagree on, a run that is shorter than the other diverges where it ends
+pub fn first_divergence(this: &[String], other: &[String]) -> Option<usize> {
+    match this.iter().zip(other).position(|(this, other)| this != other) {
+        Some(frame) => Some(frame),
+        None if this.len() != other.len() => Some(this.len().min(other.len())),
+        None => None,
+    }
+}
+
+// Copies both versions of a diverged frame next to each other into this run's directory, for
+// comparing them side by side. Returns where they went, a run without the frame has no file.
+pub fn dump_divergence(
+    this_dir: &Path, other_dir: &Path, frame: usize, this: &[String], other: &[String],
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut dumped = Vec::new();
+    for (name, dir, hashes) in [("this", this_dir, this), ("other", other_dir, other)] {
+        let Some(hash) = hashes.get(frame) else {
+            continue;
+        };
+        let dump = this_dir.join(format!("divergence-{}-{}.png", frame, name));
+        std::fs::copy(frame_path(dir, hash), &dump)?;
+        dumped.push(dump);
+    }
+    Ok(dumped)
+}
+
+fn frame_path(dir: &Path, hash: &str) -> PathBuf {
+    dir.join(FRAMES_DIRECTORY).join(format!("{}.png", hash))
+}
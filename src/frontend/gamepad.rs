@@ -0,0 +1,256 @@
+#[cfg(feature = "gamepad")]
+use log::warn;
+use std::time::{Duration, Instant};
+
+pub const MAX_RUMBLE_STRENGTH: u32 = 100;
+
+// Least time between two commands to the gamepad. Games switch the motor far more often than that, and
+// drivers queue up what they can't keep up with, which would leave the rumble lagging behind the game.
+pub const RUMBLE_UPDATE_INTERVAL: Duration = Duration::from_millis(16);
+// Time the motors take to spin down to a tenth once the game stops them, like the cartridge motor does
+const RUMBLE_DECAY: Duration = Duration::from_millis(60);
+// Below this the motors are switched off rather than kept barely spinning
+const RUMBLE_CUTOFF: f32 = 0.05;
+// How often the worker looks for gamepads being plugged in or picked up while the level doesn't change
+#[cfg(feature = "gamepad")]
+const RUMBLE_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+// Turns the on/off motor of the cartridge into a motor level (0.0 to 1.0) for the gamepad: on right away
+// at the chosen strength, off with a decay, and no more than one change per update interval. A change
+// that comes too early is held back until the next update, so the gamepad lags by one interval at most.
+pub struct RumbleEnvelope {
+    strength: f32,
+    level: f32,
+    updated: Instant,
+    // Last level handed out, and when
+    sent: f32,
+    sent_at: Option<Instant>,
+}
+
+impl RumbleEnvelope {
+    // Strength is in percent of MAX_RUMBLE_STRENGTH
+    pub fn new(strength: u32) -> RumbleEnvelope {
+        let mut envelope = RumbleEnvelope {
+            strength: 0.0,
+            level: 0.0,
+            updated: Instant::now(),
+            sent: 0.0,
+            sent_at: None,
+        };
+        envelope.set_strength(strength);
+        envelope
+    }
+
+    pub fn strength(&self) -> u32 {
+        (self.strength * MAX_RUMBLE_STRENGTH as f32).round() as u32
+    }
+
+    pub fn set_strength(&mut self, strength: u32) {
+        self.strength = strength.min(MAX_RUMBLE_STRENGTH) as f32 / MAX_RUMBLE_STRENGTH as f32;
+    }
+
+    // The new motor level if it's due to be sent
+    pub fn update(&mut self, rumble: bool, now: Instant) -> Option<f32> {
+        let elapsed = now.saturating_duration_since(self.updated);
+        self.updated = now;
+
+        self.level = if rumble {
+            self.strength
+        } else {
+            self.level * 0.1f32.powf(elapsed.as_secs_f32() / RUMBLE_DECAY.as_secs_f32())
+        };
+        if self.level < RUMBLE_CUTOFF {
+            self.level = 0.0;
+        }
+
+        if self.level == self.sent {
+            return None;
+        }
+        if let Some(sent_at) = self.sent_at
+            && now.saturating_duration_since(sent_at) < RUMBLE_UPDATE_INTERVAL
+        {
+            return None;
+        }
+
+        self.sent = self.level;
+        self.sent_at = Some(now);
+        Some(self.level)
+    }
+}
+
+// Forwards the rumble of the cartridge to the gamepad last used. Setting up the motors can block, so that
+// happens on a worker thread, which only gets started once there is something to rumble.
+pub struct GamepadRumble {
+    envelope: RumbleEnvelope,
+    #[cfg(feature = "gamepad")]
+    worker: Option<std::sync::mpsc::Sender<f32>>,
+}
+
+impl GamepadRumble {
+    pub fn new(strength: u32) -> GamepadRumble {
+        GamepadRumble {
+            envelope: RumbleEnvelope::new(strength),
+            #[cfg(feature = "gamepad")]
+            worker: None,
+        }
+    }
+
+    pub fn strength(&self) -> u32 {
+        self.envelope.strength()
+    }
+
+    pub fn set_strength(&mut self, strength: u32) {
+        self.envelope.set_strength(strength);
+    }
+
+    // Called every frame the window draws, also while paused so that the motors come to a stop
+    pub fn update(&mut self, rumble: bool) {
+        if let Some(level) = self.envelope.update(rumble, Instant::now()) {
+            self.send(level);
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn send(&mut self, level: f32) {
+        if self.worker.is_none() {
+            if level == 0.0 {
+                return;
+            }
+            self.worker = GamepadRumble::spawn();
+        }
+
+        if let Some(worker) = &self.worker
+            && worker.send(level).is_err()
+        {
+            warn!("Gamepad rumble worker exited, disabling rumble");
+            self.worker = None;
+        }
+    }
+
+    #[cfg(not(feature = "gamepad"))]
+    fn send(&mut self, _level: f32) {}
+
+    #[cfg(feature = "gamepad")]
+    fn spawn() -> Option<std::sync::mpsc::Sender<f32>> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        match std::thread::Builder::new()
+            .name(String::from("gamepad rumble"))
+            .spawn(move || GamepadRumble::worker(receiver))
+        {
+            Ok(_) => Some(sender),
+            Err(e) => {
+                warn!("Failed to spawn gamepad rumble worker: {}", e);
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn worker(receiver: std::sync::mpsc::Receiver<f32>) {
+        use gilrs::ff::{BaseEffect, BaseEffectType, Effect, EffectBuilder, Repeat, Replay, Ticks};
+        use gilrs::{EventType, GamepadId, Gilrs};
+        use std::sync::mpsc::RecvTimeoutError;
+
+        let mut gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(e) => {
+                warn!("Failed to set up gamepads: {}", e);
+                return;
+            }
+        };
+
+        // Both motors at full strength, the level is applied as the gain
+        let create_effect = |gilrs: &mut Gilrs, id: GamepadId| -> Option<Effect> {
+            let scheduling = Replay {
+                play_for: Ticks::from_ms(RUMBLE_POLL_INTERVAL.as_millis() as u32),
+                ..Default::default()
+            };
+            let result = EffectBuilder::new()
+                .add_effect(BaseEffect {
+                    kind: BaseEffectType::Strong { magnitude: u16::MAX },
+                    scheduling,
+                    ..Default::default()
+                })
+                .add_effect(BaseEffect {
+                    kind: BaseEffectType::Weak { magnitude: u16::MAX },
+                    scheduling,
+                    ..Default::default()
+                })
+                .repeat(Repeat::Infinitely)
+                .gamepads(&[id])
+                .finish(gilrs);
+            match result {
+                Ok(effect) => Some(effect),
+                Err(e) => {
+                    warn!("Failed to set up rumble on gamepad {}: {}", id, e);
+                    None
+                }
+            }
+        };
+
+        let mut level = 0.0;
+        let mut active: Option<GamepadId> = None;
+        let mut effect: Option<Effect> = None;
+        let mut playing = false;
+
+        loop {
+            match receiver.recv_timeout(RUMBLE_POLL_INTERVAL) {
+                Ok(update) => level = update,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            // Only the latest level matters
+            while let Ok(newer) = receiver.try_recv() {
+                level = newer;
+            }
+
+            // The gamepad last used is the one in the player's hands
+            let mut picked = active;
+            while let Some(event) = gilrs.next_event() {
+                if event.event == EventType::Disconnected {
+                    if picked == Some(event.id) {
+                        picked = None;
+                    }
+                } else if gilrs.gamepad(event.id).is_ff_supported() {
+                    picked = Some(event.id);
+                }
+            }
+            if picked.is_none() {
+                picked = gilrs
+                    .gamepads()
+                    .find(|(_, gamepad)| gamepad.is_ff_supported())
+                    .map(|(id, _)| id);
+            }
+            if picked != active {
+                if let Some(effect) = &effect {
+                    let _ = effect.stop();
+                }
+                active = picked;
+                effect = active.and_then(|id| create_effect(&mut gilrs, id));
+                playing = false;
+            }
+
+            let Some(effect) = &effect else {
+                continue;
+            };
+            let result = if level > 0.0 {
+                effect
+                    .set_master_gain(level)
+                    .and_then(|_| if playing { Ok(()) } else { effect.play() })
+            } else if playing {
+                effect.stop()
+            } else {
+                Ok(())
+            };
+            match result {
+                Ok(_) => playing = level > 0.0,
+                Err(e) => warn!("Failed to update gamepad rumble: {}", e),
+            }
+        }
+
+        // The emulator is gone, make sure the motors don't keep running
+        if let Some(effect) = &effect {
+            let _ = effect.stop();
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use crate::frontend::capture::FrameSink;
+use crate::frontend::library::{
+    entries, format_last_played, format_play_time, screenshot_path, unix_time, LibraryEntry, LIBRARY_PATH,
+};
+use crate::frontend::renderer::{show_key_help, Renderer};
+use crate::frontend::screenshot::load_png;
+use crate::frontend::settings::Settings;
+use crate::system::System;
+use eframe::egui::{
+    vec2, Align2, CentralPanel, Color32, ColorImage, Context, Image, Key, ScrollArea, Sense, TextEdit, TextureHandle,
+    TextureOptions, Ui, Window,
+};
+use eframe::{glow, App, Frame};
+use log::{error, info};
+use std::path::Path;
+
+// Size the screenshots of the last session are shown at in the library
+const THUMBNAIL_SIZE: [f32; 2] = [80.0, 72.0];
+
+// Builds the system and its settings for a ROM path, or explains why it can't
+pub type SystemLoader = Box<dyn Fn(&str) -> Result<(Box<dyn System>, Settings), String>>;
+
+// What the window shows when started without a ROM. Once one is opened it hands over to the renderer.
+pub struct Launcher {
+    loader: SystemLoader,
+    renderer: Option<Renderer>,
+    // Given to the renderer, which is only created later
+    frame_sink: Option<Box<dyn FrameSink>>,
+    rom_path: String,
+    error: Option<String>,
+    // Recently played games, and the last frame of each once it's loaded into a texture
+    library: Vec<LibraryEntry>,
+    thumbnails: Option<Vec<Option<TextureHandle>>>,
+}
+
+impl Launcher {
+    pub fn new(loader: SystemLoader, frame_sink: Option<Box<dyn FrameSink>>) -> Launcher {
+        Launcher {
+            loader,
+            renderer: None,
+            frame_sink,
+            rom_path: String::new(),
+            error: None,
+            library: entries(Path::new(LIBRARY_PATH)),
+            thumbnails: None,
+        }
+    }
+
+    // e.g. when the ROM given on the command line turned out to be broken
+    pub fn show_error(&mut self, rom_path: &str, error: String) {
+        self.rom_path = rom_path.to_owned();
+        self.error = Some(error);
+    }
+
+    fn open(&mut self, ctx: &Context, rom_path: &str) {
+        match (self.loader)(rom_path) {
+            Ok((system, settings)) => {
+                info!("Opened {}", rom_path);
+                self.renderer = Some(Renderer::new(ctx, system, settings, None, self.frame_sink.take()));
+            }
+            Err(e) => {
+                error!("{}", e);
+                self.error = Some(e);
+            }
+        }
+    }
+
+    // Returns the game picked to play, if any
+    fn show_library(&mut self, ctx: &Context, ui: &mut Ui) -> Option<String> {
+        let thumbnails = self.thumbnails.get_or_insert_with(|| {
+            self.library
+                .iter()
+                .map(|entry| {
+                    let path = screenshot_path(&entry.rom_path);
+                    let (width, height, pixels) = load_png(Path::new(&path)).ok()?;
+                    let image = ColorImage::from_rgb([width, height], &pixels);
+                    Some(ctx.load_texture(path, image, TextureOptions::NEAREST))
+                })
+                .collect()
+        });
+
+        let now = unix_time();
+        let mut picked = None;
+        ScrollArea::vertical().max_height(320.0).show(ui, |ui| {
+            for (entry, thumbnail) in self.library.iter().zip(thumbnails.iter()) {
+                ui.horizontal(|ui| {
+                    match thumbnail {
+                        Some(texture) => ui.add(Image::new(texture).fit_to_exact_size(THUMBNAIL_SIZE.into())),
+                        None => ui.allocate_exact_size(THUMBNAIL_SIZE.into(), Sense::hover()).1,
+                    };
+                    ui.vertical(|ui| {
+                        let name = Path::new(&entry.rom_path)
+                            .file_name()
+                            .map(|name| name.to_string_lossy().into_owned())
+                            .unwrap_or_else(|| entry.rom_path.clone());
+                        ui.strong(name);
+                        let stats = &entry.stats;
+                        ui.label(format!(
+                            "Played {} ({} frames), last {}",
+                            format_play_time(stats.play_time),
+                            stats.frames,
+                            format_last_played(stats.last_played, now)
+                        ));
+                        ui.label(format!(
+                            "{} state(s) saved, {} loaded",
+                            stats.states_saved, stats.states_loaded
+                        ));
+                        if ui.button("Play").clicked() {
+                            picked = Some(entry.path.clone());
+                        }
+                    });
+                });
+            }
+        });
+        picked
+    }
+}
+
+impl App for Launcher {
+    fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+        if let Some(renderer) = &mut self.renderer {
+            renderer.update(ctx, frame);
+            return;
+        }
+
+        let dropped_rom = ctx.input(|i| i.raw.dropped_files.iter().find_map(|file| file.path.clone()));
+        if let Some(path) = dropped_rom {
+            self.open(ctx, &path.to_string_lossy());
+        }
+
+        CentralPanel::default().show(ctx, |_| {});
+        Window::new("ayyboy")
+            .anchor(Align2::CENTER_CENTER, vec2(0.0, 0.0))
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                show_key_help(ui);
+                ui.separator();
+
+                ui.label("Drop a ROM onto the window or enter its path:");
+                let mut open = false;
+                ui.horizontal(|ui| {
+                    let path = ui.add(TextEdit::singleline(&mut self.rom_path).hint_text("rom.gb"));
+                    open = path.lost_focus() && ui.input(|i| i.key_pressed(Key::Enter));
+                    open |= ui.button("Open ROM").clicked();
+                });
+                if open && !self.rom_path.is_empty() {
+                    let rom_path = self.rom_path.clone();
+                    self.open(ctx, &rom_path);
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(Color32::RED, error);
+                }
+
+                if !self.library.is_empty() {
+                    ui.separator();
+                    ui.heading("Library");
+                    if let Some(rom_path) = self.show_library(ctx, ui) {
+                        self.rom_path = rom_path.clone();
+                        self.open(ctx, &rom_path);
+                    }
+                }
+            });
+    }
+
+    fn on_exit(&mut self, gl: Option<&glow::Context>) {
+        if let Some(renderer) = &mut self.renderer {
+            renderer.on_exit(gl);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use crate::frontend::rom_settings::{PlayStats, RomSettings};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// The games played, most recent first with one game per line, in the working directory like the log. Each
+// line is the path that was opened and the ROM's path, separated by a tab.
+pub const LIBRARY_PATH: &str = "./ayyboy_library.txt";
+// Older games drop off the list
+const MAX_GAMES: usize = 20;
+
+// A game in the library with what's known about it
+pub struct LibraryEntry {
+    // What to open to play it again, the zip for zipped ROMs
+    pub path: String,
+    // Where the files of the ROM are kept, see Settings::rom_path
+    pub rom_path: String,
+    pub stats: PlayStats,
+}
+
+// The last frame of the most recent session, next to the ROM
+pub fn screenshot_path(rom_path: &str) -> String {
+    format!("{}.last.png", rom_path)
+}
+
+// The opened path and ROM path of every game
+pub fn recent_games(library_path: &Path) -> Vec<(String, String)> {
+    std::fs::read_to_string(library_path)
+        .map(|data| {
+            data.lines()
+                .filter(|line| !line.is_empty())
+                .map(|line| match line.split_once('\t') {
+                    Some((path, rom_path)) => (path.to_owned(), rom_path.to_owned()),
+                    None => (line.to_owned(), line.to_owned()),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Moves the game to the top of the list. The path is what the user opened, which for zipped ROMs isn't
+// where the ROM's files are.
+pub fn record_played(library_path: &Path, path: &str, rom_path: &str) -> std::io::Result<()> {
+    let mut games = recent_games(library_path);
+    games.retain(|(game, _)| game != path);
+    games.insert(0, (path.to_owned(), rom_path.to_owned()));
+    games.truncate(MAX_GAMES);
+    std::fs::write(
+        library_path,
+        games
+            .iter()
+            .map(|(path, rom_path)| format!("{}\t{}\n", path, rom_path))
+            .collect::<String>(),
+    )
+}
+
+// Games that are gone are left out
+pub fn entries(library_path: &Path) -> Vec<LibraryEntry> {
+    recent_games(library_path)
+        .into_iter()
+        .filter(|(path, _)| Path::new(path).exists())
+        .map(|(path, rom_path)| LibraryEntry {
+            path,
+            stats: RomSettings::load(&rom_path).stats,
+            rom_path,
+        })
+        .collect()
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or_default()
+}
+
+// e.g. "2h 05m", "12m 30s"
+pub fn format_play_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    match seconds {
+        0..=3599 => format!("{}m {:02}s", seconds / 60, seconds % 60),
+        _ => format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60),
+    }
+}
+
+// e.g. "3 days ago", relative to now
+pub fn format_last_played(last_played: u64, now: u64) -> String {
+    let ago = now.saturating_sub(last_played);
+    match ago {
+        _ if last_played == 0 => String::from("never"),
+        0..=59 => String::from("just now"),
+        60..=3599 => format!("{} min ago", ago / 60),
+        3600..=86399 => format!("{} h ago", ago / 3600),
+        _ => format!("{} days ago", ago / 86400),
+    }
+}
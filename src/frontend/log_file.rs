@@ -0,0 +1,153 @@
+use log::Level;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+// How much of the log is kept on disk, and whether it's only written when something goes wrong
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogConfig {
+    // Bytes the log grows to before it's rotated, 0 never rotates
+    pub max_size: u64,
+    // Rotated logs kept next to it as <log>.1 (the newest) up to <log>.<keep>
+    pub keep: usize,
+    // Bytes of the latest lines kept in memory instead of writing every line, written out on errors
+    pub ring: Option<usize>,
+}
+
+// A log file that moves on to a new file once it's full, dropping the oldest. Every line is flushed so that
+// nothing is lost when the emulator crashes.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    keep: usize,
+    file: BufWriter<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    // Every run starts a new log, the one of the last run is rotated like a full one
+    pub fn create(path: &Path, max_size: u64, keep: usize) -> std::io::Result<RotatingFile> {
+        if path.exists() {
+            RotatingFile::shift(path, keep)?;
+        }
+
+        Ok(RotatingFile {
+            path: path.to_owned(),
+            max_size,
+            keep,
+            file: BufWriter::new(File::create(path)?),
+            size: 0,
+        })
+    }
+
+    pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(format!(".{}", index));
+        PathBuf::from(rotated)
+    }
+
+    // Lines never get split, a line larger than the whole log gets a file of its own
+    pub fn write_line(&mut self, line: &str) -> std::io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if self.max_size > 0 && self.size > 0 && self.size + length > self.max_size {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        self.size += length;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file.flush()?;
+        RotatingFile::shift(&self.path, self.keep)?;
+        self.file = BufWriter::new(File::create(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+
+    // Moves every log one up, the oldest falls off
+    fn shift(path: &Path, keep: usize) -> std::io::Result<()> {
+        if keep == 0 {
+            return std::fs::remove_file(path);
+        }
+
+        let oldest = RotatingFile::rotated_path(path, keep);
+        if oldest.exists() {
+            std::fs::remove_file(oldest)?;
+        }
+        for index in (1..keep).rev() {
+            let rotated = RotatingFile::rotated_path(path, index);
+            if rotated.exists() {
+                std::fs::rename(rotated, RotatingFile::rotated_path(path, index + 1))?;
+            }
+        }
+        std::fs::rename(path, RotatingFile::rotated_path(path, 1))
+    }
+}
+
+// The latest lines up to a number of bytes, older ones make room for new ones
+pub struct LogRing {
+    lines: VecDeque<String>,
+    size: usize,
+    capacity: usize,
+}
+
+impl LogRing {
+    pub fn new(capacity: usize) -> LogRing {
+        LogRing {
+            lines: VecDeque::new(),
+            size: 0,
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, line: String) {
+        self.size += line.len();
+        self.lines.push_back(line);
+        while self.size > self.capacity {
+            let Some(oldest) = self.lines.pop_front() else {
+                break;
+            };
+            self.size -= oldest.len();
+        }
+    }
+
+    // Oldest first, the ring is empty afterwards
+    pub fn drain(&mut self) -> impl Iterator<Item = String> + '_ {
+        self.size = 0;
+        self.lines.drain(..)
+    }
+}
+
+// Where log lines end up: straight in the file, or in the ring until an error comes along
+pub struct LogFile {
+    file: RotatingFile,
+    ring: Option<LogRing>,
+}
+
+impl LogFile {
+    pub fn create(path: &Path, config: &LogConfig) -> std::io::Result<LogFile> {
+        Ok(LogFile {
+            file: RotatingFile::create(path, config.max_size, config.keep)?,
+            ring: config.ring.map(LogRing::new),
+        })
+    }
+
+    // The lines leading up to an error are written along with it
+    pub fn log(&mut self, level: Level, line: String) -> std::io::Result<()> {
+        let Some(ring) = &mut self.ring else {
+            return self.file.write_line(&line);
+        };
+
+        ring.push(line);
+        if level == Level::Error {
+            for line in ring.drain() {
+                self.file.write_line(&line)?;
+            }
+        }
+        Ok(())
+    }
+}
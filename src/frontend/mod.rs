@@ -1,3 +1,26 @@
+pub mod archive;
+pub mod capture;
+pub mod cheat_search;
 mod debugger;
+pub mod direct_draw;
+pub mod frame_rng;
+pub mod frame_trace;
+pub mod gamepad;
+pub mod launcher;
+pub mod library;
+pub mod log_file;
+pub mod monkey;
+pub mod netplay;
+pub mod overlay;
+pub mod presence;
 pub mod renderer;
+pub mod rom_settings;
+pub mod save_info;
+pub mod screenshot;
 pub mod settings;
+pub mod state;
+pub mod state_diff;
+pub mod test_suite;
+pub mod turbo;
+pub mod vram_export;
+pub mod watch;
@@ -0,0 +1,86 @@
+use crate::gameboy::GameBoy;
+use crate::system::System;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
+
+// Last scanline of a frame, LY never goes past it
+const LAST_SCANLINE: u8 = 153;
+
+// Something that went wrong while mashing buttons, and on which frame of the run
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonkeyFailure {
+    pub frame: u64,
+    pub message: String,
+}
+
+// How far the run got, along with every input it fed the game
+pub struct MonkeyReport {
+    pub inputs: Vec<u8>,
+    pub failure: Option<MonkeyFailure>,
+}
+
+// Feeds the game the input of each frame, headless, and checks that the emulator holds up: no panics
+// (which covers opcodes that can't be decoded and the PPU indexing out of bounds), no opcodes skipped in
+// lenient mode and LY within the frame. Stops at the first failure. Frames count from the start of the run.
+pub fn run(gb: &mut GameBoy, frames: u64, input: &mut dyn FnMut(u64) -> u8) -> MonkeyReport {
+    let mut inputs = Vec::new();
+    let skipped = gb.cpu.skipped_opcodes();
+
+    for frame in 0..frames {
+        let buttons = input(frame);
+        inputs.push(buttons);
+        gb.update_buttons(buttons);
+
+        let message = match std::panic::catch_unwind(AssertUnwindSafe(|| gb.run_frame())) {
+            Err(payload) => Some(panic_message(payload.as_ref())),
+            Ok(_) if gb.cpu.skipped_opcodes() != skipped => Some(format!(
+                "Skipped {} opcode(s) that couldn't be decoded",
+                gb.cpu.skipped_opcodes() - skipped
+            )),
+            Ok(_) if gb.scanline() > LAST_SCANLINE => Some(format!("LY is out of range at {}", gb.scanline())),
+            Ok(_) => None,
+        };
+        if let Some(message) = message {
+            return MonkeyReport {
+                inputs,
+                failure: Some(MonkeyFailure { frame, message }),
+            };
+        }
+    }
+
+    MonkeyReport { inputs, failure: None }
+}
+
+// One input bitmask per frame in hex, lines starting with # are comments
+pub fn save_movie(path: &Path, header: &str, inputs: &[u8]) -> std::io::Result<()> {
+    let mut movie = header.lines().map(|line| format!("# {}\n", line)).collect::<String>();
+    inputs
+        .iter()
+        .for_each(|input| movie.push_str(&format!("{:02x}\n", input)));
+    std::fs::write(path, movie)
+}
+
+pub fn load_movie(path: &Path) -> std::io::Result<Vec<u8>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            u8::from_str_radix(line, 16).map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("{} is not an input bitmask", line),
+                )
+            })
+        })
+        .collect()
+}
+
+// Panics carry either a literal or a formatted message
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    match (payload.downcast_ref::<&str>(), payload.downcast_ref::<String>()) {
+        (Some(message), _) => String::from(*message),
+        (_, Some(message)) => message.clone(),
+        _ => String::from("Panicked"),
+    }
+}
@@ -0,0 +1,239 @@
+use crate::error::AyyError;
+use crate::system::{RomHash, SaveState, System};
+use log::{debug, info};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+const MAGIC: &[u8; 4] = b"AYYN";
+const PROTOCOL_VERSION: u8 = 1;
+const HANDSHAKE_SIZE: usize = 30;
+// Frame number followed by the input bitmask
+const INPUT_MESSAGE_SIZE: usize = 5;
+// How far back a misprediction can be corrected, the peers never drift further apart than this
+pub const MAX_ROLLBACK: u32 = 8;
+
+// What both peers have to agree on before playing, anything that differs would desync the emulation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionInfo {
+    pub rom_hash: RomHash,
+    pub settings_crc: u32,
+    pub delay: u8,
+}
+
+// Two peers emulating the same console in lockstep, both players share the joypad.
+// Local input is delayed by a few frames to give it time to reach the other side, whenever
+// the remote input still arrives late it is predicted and the frames are replayed once it's known.
+pub struct Netplay {
+    stream: TcpStream,
+    delay: u32,
+    // Next frame to emulate
+    frame: u32,
+    // Frames up to here have confirmed remote input
+    remote_frame: u32,
+    local_inputs: HashMap<u32, u8>,
+    remote_inputs: HashMap<u32, u8>,
+    // Remote input every frame was emulated with, predicted or not
+    used_remote_inputs: HashMap<u32, u8>,
+    // State at the start of each frame that can still be rolled back
+    snapshots: HashMap<u32, SaveState>,
+    received: Vec<u8>,
+}
+
+impl Netplay {
+    pub fn host(port: u16, session: SessionInfo) -> Result<Netplay, AyyError> {
+        let listener = TcpListener::bind(("0.0.0.0", port)).map_err(network_error)?;
+        info!("Waiting for netplay peer on port {}", port);
+
+        let (stream, peer) = listener.accept().map_err(network_error)?;
+        info!("Netplay peer connected from {}", peer);
+        Netplay::start(stream, session)
+    }
+
+    pub fn connect(address: &str, session: SessionInfo) -> Result<Netplay, AyyError> {
+        let stream = TcpStream::connect(address).map_err(network_error)?;
+        info!("Connected to netplay peer {}", address);
+        Netplay::start(stream, session)
+    }
+
+    pub fn start(mut stream: TcpStream, session: SessionInfo) -> Result<Netplay, AyyError> {
+        Netplay::handshake(&mut stream, session)?;
+        Netplay::new(stream, session.delay)
+    }
+
+    // Exchanges the session info with the peer and makes sure it matches ours
+    pub fn handshake(stream: &mut TcpStream, session: SessionInfo) -> Result<(), AyyError> {
+        let mut handshake = Vec::with_capacity(HANDSHAKE_SIZE);
+        handshake.extend_from_slice(MAGIC);
+        handshake.push(PROTOCOL_VERSION);
+        handshake.extend_from_slice(&session.rom_hash.0);
+        handshake.extend_from_slice(&session.settings_crc.to_le_bytes());
+        handshake.push(session.delay);
+        stream.write_all(&handshake).map_err(network_error)?;
+
+        let mut remote = [0u8; HANDSHAKE_SIZE];
+        stream.read_exact(&mut remote).map_err(network_error)?;
+        if &remote[0..4] != MAGIC || remote[4] != PROTOCOL_VERSION {
+            return Err(handshake_error("peer does not speak the same protocol"));
+        }
+
+        let remote = SessionInfo {
+            rom_hash: RomHash(remote[5..25].try_into().unwrap()),
+            settings_crc: u32::from_le_bytes(remote[25..29].try_into().unwrap()),
+            delay: remote[29],
+        };
+        if remote.rom_hash != session.rom_hash {
+            return Err(handshake_error("peer is running a different ROM"));
+        }
+        if remote.settings_crc != session.settings_crc {
+            return Err(handshake_error(
+                "peer is using different emulation settings or save data",
+            ));
+        }
+        if remote.delay != session.delay {
+            return Err(handshake_error("peer is using a different input delay"));
+        }
+
+        Ok(())
+    }
+
+    // Starts a session on a stream that already went through the handshake
+    pub fn new(stream: TcpStream, delay: u8) -> Result<Netplay, AyyError> {
+        stream.set_nodelay(true).map_err(network_error)?;
+        stream.set_nonblocking(true).map_err(network_error)?;
+
+        // Nobody can press anything during the first frames of delay
+        let delay = delay as u32;
+        Ok(Netplay {
+            stream,
+            delay,
+            frame: 0,
+            remote_frame: delay,
+            local_inputs: (0..delay).map(|frame| (frame, 0)).collect(),
+            remote_inputs: (0..delay).map(|frame| (frame, 0)).collect(),
+            used_remote_inputs: HashMap::new(),
+            snapshots: HashMap::new(),
+            received: Vec::new(),
+        })
+    }
+
+    // Emulates the next frame with the given local input. Returns false if the peer is too far
+    // behind, in which case no frame was emulated and the caller should try again later.
+    pub fn advance(&mut self, system: &mut dyn System, input: u8) -> Result<bool, AyyError> {
+        let rollback = self.receive()?;
+
+        if self.frame >= self.remote_frame + MAX_ROLLBACK {
+            return Ok(false);
+        }
+
+        let input_frame = self.frame + self.delay;
+        self.local_inputs.insert(input_frame, input);
+        self.send(input_frame, input)?;
+
+        if let Some(frame) = rollback {
+            debug!("Netplay: rolling back {} frames", self.frame - frame);
+
+            system.load_state(&self.snapshots[&frame])?;
+            system.set_muted(true);
+            for replay in frame..self.frame {
+                self.run_frame(system, replay);
+            }
+            system.set_muted(false);
+        }
+
+        self.run_frame(system, self.frame);
+        self.frame += 1;
+
+        // Everything before the oldest unconfirmed frame is final
+        let oldest = self.frame.min(self.remote_frame);
+        self.snapshots.retain(|&frame, _| frame >= oldest);
+        self.used_remote_inputs.retain(|&frame, _| frame >= oldest);
+        self.local_inputs.retain(|&frame, _| frame >= oldest);
+        self.remote_inputs.retain(|&frame, _| frame + 1 >= oldest);
+
+        Ok(true)
+    }
+
+    fn run_frame(&mut self, system: &mut dyn System, frame: u32) {
+        // Until the peer's input arrives assume it is still holding the same buttons
+        let remote = match self.remote_inputs.get(&frame) {
+            Some(remote) => *remote,
+            None => self
+                .remote_frame
+                .checked_sub(1)
+                .and_then(|frame| self.remote_inputs.get(&frame))
+                .copied()
+                .unwrap_or(0),
+        };
+        let local = self.local_inputs.get(&frame).copied().unwrap_or(0);
+
+        self.snapshots.insert(frame, system.save_state());
+        self.used_remote_inputs.insert(frame, remote);
+        system.update_buttons(local | remote);
+        system.run_frame();
+    }
+
+    fn send(&mut self, frame: u32, input: u8) -> Result<(), AyyError> {
+        let mut message = [0u8; INPUT_MESSAGE_SIZE];
+        message[0..4].copy_from_slice(&frame.to_le_bytes());
+        message[4] = input;
+
+        // The socket is non-blocking, but a few bytes per frame never fill up the send buffer
+        loop {
+            match self.stream.write_all(&message) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(network_error(e)),
+            }
+        }
+    }
+
+    // Reads all pending remote inputs, returns the earliest frame that was mispredicted
+    fn receive(&mut self) -> Result<Option<u32>, AyyError> {
+        let mut buffer = [0u8; 256];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(network_error(std::io::Error::from(ErrorKind::ConnectionAborted))),
+                Ok(size) => self.received.extend_from_slice(&buffer[..size]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(network_error(e)),
+            }
+        }
+
+        let mut rollback: Option<u32> = None;
+        let messages = self.received.len() / INPUT_MESSAGE_SIZE;
+        for message in self.received.chunks_exact(INPUT_MESSAGE_SIZE) {
+            let frame = u32::from_le_bytes(message[0..4].try_into().unwrap());
+            let input = message[4];
+
+            // TCP keeps the inputs in order, so there are no gaps
+            if frame != self.remote_frame {
+                return Err(AyyError::Netplay {
+                    reason: format!("expected input for frame {}, got {}", self.remote_frame, frame),
+                });
+            }
+
+            self.remote_inputs.insert(frame, input);
+            self.remote_frame += 1;
+
+            if let Some(used) = self.used_remote_inputs.get(&frame)
+                && *used != input
+            {
+                rollback = Some(rollback.map_or(frame, |rollback| rollback.min(frame)));
+            }
+        }
+        self.received.drain(..messages * INPUT_MESSAGE_SIZE);
+
+        Ok(rollback)
+    }
+}
+
+fn network_error(e: std::io::Error) -> AyyError {
+    AyyError::Netplay { reason: e.to_string() }
+}
+
+fn handshake_error(reason: &str) -> AyyError {
+    AyyError::Netplay {
+        reason: format!("handshake failed, {}", reason),
+    }
+}
@@ -0,0 +1,51 @@
+use crate::video::palette::Color;
+
+// Pixels that changed are tinted with this
+const HIGHLIGHT: Color = [0xff, 0x00, 0xff];
+// How much of the highlight shows through, out of 255
+const HIGHLIGHT_ALPHA: u16 = 160;
+
+// Highlights the pixels that changed since the previous presented frame, which makes flicker
+// (e.g. sprite multiplexing) and partial screen updates easy to spot
+pub struct ChangeOverlay {
+    previous: Vec<Color>,
+}
+
+impl ChangeOverlay {
+    pub fn new() -> ChangeOverlay {
+        ChangeOverlay { previous: Vec::new() }
+    }
+
+    // Returns the frame with the overlay blended in and remembers the clean frame for the next one.
+    // Nothing is highlighted for the first frame, or after the screen size changed.
+    pub fn apply(&mut self, framebuffer: &[Color]) -> Vec<Color> {
+        let blended = match self.previous.len() == framebuffer.len() {
+            true => framebuffer
+                .iter()
+                .zip(&self.previous)
+                .map(|(pixel, previous)| match pixel == previous {
+                    true => *pixel,
+                    false => ChangeOverlay::blend(pixel),
+                })
+                .collect(),
+            false => framebuffer.to_vec(),
+        };
+
+        self.previous.clear();
+        self.previous.extend_from_slice(framebuffer);
+        blended
+    }
+
+    fn blend(pixel: &Color) -> Color {
+        std::array::from_fn(|channel| {
+            ((pixel[channel] as u16 * (255 - HIGHLIGHT_ALPHA) + HIGHLIGHT[channel] as u16 * HIGHLIGHT_ALPHA) / 255)
+                as u8
+        })
+    }
+}
+
+impl Default for ChangeOverlay {
+    fn default() -> ChangeOverlay {
+        ChangeOverlay::new()
+    }
+}
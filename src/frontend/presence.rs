@@ -0,0 +1,195 @@
+use log::warn;
+use std::time::{Duration, Instant, SystemTime};
+
+// Discord application the presence is published as, it has to be registered on the developer portal.
+// Picked at build time so that forks don't show up as the original project.
+#[cfg(feature = "discord")]
+const APPLICATION_ID: Option<&str> = option_env!("AYYBOY_DISCORD_APPLICATION_ID");
+
+// What the profile shows
+#[derive(Debug, Clone, PartialEq)]
+pub struct Activity {
+    pub title: String,
+    pub state: String,
+    // Shown as elapsed play time, left out while paused
+    pub started: Option<SystemTime>,
+}
+
+// Publishes the game being played to Discord Rich Presence. Talking to the Discord client can block,
+// so that happens on a worker thread, this side only keeps track of the game and its play time.
+pub struct Presence {
+    enabled: bool,
+    title: String,
+    model: &'static str,
+    paused: bool,
+    // Play time of the current game up to the last pause, the stretch since resuming comes on top
+    played: Duration,
+    resumed: Instant,
+    #[cfg(feature = "discord")]
+    worker: Option<std::sync::mpsc::Sender<Option<Activity>>>,
+}
+
+impl Presence {
+    pub fn new(enabled: bool) -> Presence {
+        if enabled && !cfg!(feature = "discord") {
+            warn!("Discord presence isn't compiled in, build with the discord feature");
+        }
+
+        Presence {
+            enabled,
+            title: String::new(),
+            model: "",
+            paused: true,
+            played: Duration::ZERO,
+            resumed: Instant::now(),
+            #[cfg(feature = "discord")]
+            worker: None,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        if self.enabled != enabled {
+            self.enabled = enabled;
+            self.publish();
+        }
+    }
+
+    // Play time starts over with every game
+    pub fn set_game(&mut self, title: String, model: &'static str) {
+        self.title = title;
+        self.model = model;
+        self.played = Duration::ZERO;
+        self.resumed = Instant::now();
+        self.publish();
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        if self.paused == paused {
+            return;
+        }
+
+        if paused {
+            self.played += self.resumed.elapsed();
+        } else {
+            self.resumed = Instant::now();
+        }
+        self.paused = paused;
+        self.publish();
+    }
+
+    // None clears the profile, e.g. while disabled
+    pub fn activity(&self) -> Option<Activity> {
+        if !self.enabled || self.title.is_empty() {
+            return None;
+        }
+
+        let (state, started) = if self.paused {
+            (format!("Paused ({})", self.model), None)
+        } else {
+            let played = self.played + self.resumed.elapsed();
+            (
+                format!("Playing on {}", self.model),
+                SystemTime::now().checked_sub(played),
+            )
+        };
+        Some(Activity {
+            title: self.title.clone(),
+            state,
+            started,
+        })
+    }
+
+    #[cfg(feature = "discord")]
+    fn publish(&mut self) {
+        let activity = self.activity();
+        if self.worker.is_none() {
+            if activity.is_none() {
+                return;
+            }
+            self.worker = Presence::spawn();
+        }
+
+        if let Some(worker) = &self.worker
+            && worker.send(activity).is_err()
+        {
+            warn!("Discord presence worker exited, disabling presence");
+            self.worker = None;
+        }
+    }
+
+    #[cfg(not(feature = "discord"))]
+    fn publish(&mut self) {}
+
+    #[cfg(feature = "discord")]
+    fn spawn() -> Option<std::sync::mpsc::Sender<Option<Activity>>> {
+        let Some(application_id) = APPLICATION_ID else {
+            warn!("Discord presence needs AYYBOY_DISCORD_APPLICATION_ID to be set at build time");
+            return None;
+        };
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        match std::thread::Builder::new()
+            .name(String::from("presence"))
+            .spawn(move || Presence::worker(application_id, receiver))
+        {
+            Ok(_) => Some(sender),
+            Err(e) => {
+                warn!("Failed to spawn Discord presence worker: {}", e);
+                None
+            }
+        }
+    }
+
+    #[cfg(feature = "discord")]
+    fn worker(application_id: &str, receiver: std::sync::mpsc::Receiver<Option<Activity>>) {
+        use discord_rich_presence::{activity, DiscordIpc, DiscordIpcClient};
+
+        let mut client = match DiscordIpcClient::new(application_id) {
+            Ok(client) => client,
+            Err(e) => {
+                warn!("Failed to set up Discord presence: {}", e);
+                return;
+            }
+        };
+        let mut connected = false;
+
+        while let Ok(mut update) = receiver.recv() {
+            // Only the latest state matters
+            while let Ok(newer) = receiver.try_recv() {
+                update = newer;
+            }
+
+            // Discord may have been started (or restarted) since the last update
+            if !connected {
+                connected = client.connect().is_ok();
+                if !connected {
+                    continue;
+                }
+            }
+
+            let result = match &update {
+                Some(update) => {
+                    let mut presence = activity::Activity::new().details(&update.title).state(&update.state);
+                    if let Some(started) = update.started {
+                        let started = started.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+                        presence = presence.timestamps(activity::Timestamps::new().start(started.as_secs() as i64));
+                    }
+                    client.set_activity(presence)
+                }
+                None => client.clear_activity(),
+            };
+            if let Err(e) = result {
+                warn!("Failed to update Discord presence: {}", e);
+                connected = false;
+            }
+        }
+
+        if connected {
+            let _ = client.close();
+        }
+    }
+}
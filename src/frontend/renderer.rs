@@ -1,181 +1,981 @@
+use crate::frontend::archive::{read_rom, LoadedRom};
+use crate::frontend::capture::FrameSink;
 use crate::frontend::debugger::Debugger;
-use crate::gameboy::GameBoy;
-use crate::sound::CPU_CLOCK;
+use crate::frontend::direct_draw::{paint_callback, DirectScreen};
+use crate::frontend::netplay::Netplay;
+use crate::frontend::overlay::ChangeOverlay;
+use crate::frontend::turbo::{Turbo, MAX_TURBO_RATE, MIN_TURBO_RATE};
+use crate::gameboy::{GameBoy, Mode};
+use crate::memory::mapper::rtc::RtcMode;
+use crate::sound::SyncMode;
+use crate::system::{Button, SaveState, System};
+use crate::video::compat::CompatPalette;
 use crate::video::palette::{Color, Palette};
-use crate::video::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::video::{Accuracy, ColorCorrection, Layers};
 use eframe::egui::{
-    vec2, Align2, CentralPanel, Color32, ColorImage, Context, Image, Key, TextureHandle, TextureOptions, Window,
+    vec2, Align2, Area, CentralPanel, Color32, ColorImage, ComboBox, Context, Event, Id, Image, Key, Sense, Slider,
+    TextureHandle, TextureOptions, Ui, ViewportCommand, Window,
 };
-use eframe::{App, CreationContext, Frame};
-use log::info;
+use eframe::{glow, App, Frame};
+use log::{error, info, warn};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use super::settings::Settings;
+use super::gamepad::GamepadRumble;
+use super::library::{record_played, screenshot_path, unix_time, LIBRARY_PATH};
+use super::presence::Presence;
+use super::rom_settings::{PlayStats, RomSettings};
+use super::save_info::SaveInfo;
+use super::screenshot::save_png;
+use super::settings::{BackgroundPolicy, Presentation, Settings};
+use super::state::{FrontendState, Hotkey, StateEvent};
 
 pub const SCALE: usize = 6;
+pub const MAX_RUN_AHEAD: usize = 4;
+pub const MAX_FRAME_SKIP: usize = 4;
+// A+B+Start+Select, which many games soft reset on, power cycles any game
+const SOFT_RESET: u8 = Button::A.mask() | Button::B.mask() | Button::Start.mask() | Button::Select.mask();
+// How long notifications like "printed a page" stay up
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(3);
+// How often a window paused in the background checks whether it's back
+const BACKGROUND_POLL_INTERVAL: Duration = Duration::from_millis(250);
+// Most play time a single repaint adds to the stats
+const MAX_PLAY_TIME_STEP: Duration = Duration::from_secs(1);
+
+const KEY_BINDINGS: [(Key, Button); 8] = [
+    (Key::Enter, Button::Start),
+    (Key::Backspace, Button::Select),
+    (Key::A, Button::A),
+    (Key::S, Button::B),
+    (Key::ArrowUp, Button::Up),
+    (Key::ArrowDown, Button::Down),
+    (Key::ArrowLeft, Button::Left),
+    (Key::ArrowRight, Button::Right),
+];
 
 pub struct Renderer {
     debugger: Debugger,
     screen_texture: TextureHandle,
-    gb: GameBoy,
+    // Replaces the texture when presenting directly, set up with the first frame
+    direct_screen: Option<Arc<Mutex<DirectScreen>>>,
+    system: Box<dyn System>,
     settings: Settings,
-    running: bool,
+    state: FrontendState,
+    // Last title the window was given, it only gets a new one when this changes
+    title: String,
+    paused_rtc: Option<Vec<u8>>,
+    quick_save: Option<SaveState>,
+    netplay: Option<Netplay>,
+    // Gets a copy of every presented frame, e.g. for streaming software
+    frame_sink: Option<Box<dyn FrameSink>>,
+    // Buttons currently held, as a bitmask of Button::mask
+    input: u8,
+    // Every change of the input since the last frame was emulated, and when it happened
+    input_changes: Vec<(Instant, u8)>,
+    // Input when the last frame was emulated, and when that was
+    frame_input: (u8, Instant),
+    turbo: Turbo,
+    // When the next frame is due in timer sync mode
+    next_frame: Instant,
+    // When the frame being emulated should be done to keep up with the hardware
+    frame_deadline: Instant,
+    skipped_frames: usize,
+    audio_latency: Option<Duration>,
+    // Shown in a dialog until dismissed, e.g. a ROM that couldn't be swapped in
+    error: Option<String>,
+    // Shown in a corner until it expires
+    notification: Option<(String, Instant)>,
+    // Highlights what changed since the last frame, only on screen (streams get the clean frame)
+    change_overlay: Option<ChangeOverlay>,
+    // Save state of another emulator to import, typed into the controls window
+    import_path: String,
+    presence: Presence,
+    rumble: GamepadRumble,
+    // Play time and such of the game, saved with its settings
+    stats: PlayStats,
+    stats_updated: Instant,
 }
 
 impl Renderer {
-    pub fn new(cc: &CreationContext, gameboy: GameBoy, settings: Settings) -> Renderer {
-        let screen_texture = cc.egui_ctx.load_texture(
+    pub fn new(
+        ctx: &Context, system: Box<dyn System>, settings: Settings, netplay: Option<Netplay>,
+        frame_sink: Option<Box<dyn FrameSink>>,
+    ) -> Renderer {
+        let (width, height) = system.screen_size();
+        let screen_texture = ctx.load_texture(
             "screen_texture",
-            ColorImage::new([SCREEN_WIDTH, SCREEN_HEIGHT], Color32::BLACK),
+            ColorImage::new([width, height], Color32::BLACK),
             TextureOptions::NEAREST,
         );
 
-        Renderer {
-            debugger: Debugger::new(&cc.egui_ctx),
+        let presence = Presence::new(settings.discord_presence);
+        let turbo = Turbo::new(settings.turbo_buttons, settings.turbo_rate, system.frame_duration());
+        let rumble = GamepadRumble::new(settings.rumble_strength);
+        let mut renderer = Renderer {
+            debugger: Debugger::new(ctx),
             screen_texture,
-            gb: gameboy,
+            direct_screen: None,
+            system,
             settings,
-            running: false,
+            state: FrontendState::Paused,
+            title: String::new(),
+            paused_rtc: None,
+            quick_save: None,
+            netplay,
+            frame_sink,
+            input: 0,
+            input_changes: Vec::new(),
+            frame_input: (0, Instant::now()),
+            turbo,
+            next_frame: Instant::now(),
+            frame_deadline: Instant::now(),
+            skipped_frames: 0,
+            audio_latency: None,
+            error: None,
+            notification: None,
+            change_overlay: None,
+            import_path: String::new(),
+            presence,
+            rumble,
+            stats: PlayStats::default(),
+            stats_updated: Instant::now(),
+        };
+        renderer.start_session();
+        renderer
+            .presence
+            .set_game(renderer.system.title(), renderer.system.model());
+        renderer.load_cartridge();
+        renderer.system.set_accuracy(renderer.settings.accuracy);
+        renderer.system.set_color_correction(renderer.settings.color_correction);
+        renderer.system.set_compat_palette(renderer.settings.compat_palette);
+        renderer.system.set_sync_mode(renderer.settings.sync_mode);
+        if renderer.settings.sync_mode == SyncMode::Audio {
+            renderer.calibrate_audio();
         }
+        renderer
     }
 
-    pub fn update_screen(&mut self, palette_data: &[[Palette; SCREEN_WIDTH]; SCREEN_HEIGHT]) {
-        let mut pixels = vec![Color32::BLACK; SCREEN_WIDTH * SCREEN_HEIGHT];
+    pub fn update_screen(&mut self, framebuffer: &[Color]) {
+        let (width, height) = self.system.screen_size();
+        let overlaid = self.change_overlay.as_mut().map(|overlay| overlay.apply(framebuffer));
+        let framebuffer = overlaid.as_deref().unwrap_or(framebuffer);
 
-        for y in 0..SCREEN_HEIGHT {
-            for x in 0..SCREEN_WIDTH {
-                let color: Color = palette_data[y][x].into();
-                pixels[y * SCREEN_WIDTH + x] = Color32::from_rgba_premultiplied(color[0], color[1], color[2], 255);
-            }
+        if let Some(screen) = &self.direct_screen {
+            let pixels = framebuffer
+                .iter()
+                .flat_map(|color| [color[0], color[1], color[2], 255])
+                .collect();
+            screen.lock().unwrap().set_frame(width, height, pixels);
+            return;
         }
 
+        let pixels = framebuffer
+            .iter()
+            .map(|color| Color32::from_rgba_premultiplied(color[0], color[1], color[2], 255))
+            .collect();
+
         let image = ColorImage {
-            size: [SCREEN_WIDTH, SCREEN_HEIGHT],
+            size: [width, height],
             pixels,
         };
 
         self.screen_texture.set(image, TextureOptions::NEAREST);
     }
 
+    pub fn load_cartridge(&mut self) {
+        // if there's a sav file, load into cart, unless another revision of the ROM wrote it
+        let save_path = format!("{}.sav", self.settings.rom_path);
+        let rtc_path = format!("{}.rtc", self.settings.rom_path);
+        if let Ok(cart_ram) = std::fs::read(&save_path) {
+            match SaveInfo::load(&save_path).and_then(|info| info.rom_hash) {
+                Some(hash) if hash != self.system.rom_hash() && !self.settings.allow_rom_mismatch => {
+                    // Out of the way, or the next save would overwrite it. The RTC belongs to that save and goes
+                    // along with it.
+                    let backup_path = format!("{}.{}", save_path, hash);
+                    let kept = match std::fs::rename(&save_path, &backup_path) {
+                        Ok(_) => format!("it was moved to {}", backup_path),
+                        Err(e) => format!("failed to move it out of the way ({}), it will be overwritten", e),
+                    };
+                    if Path::new(&rtc_path).exists() {
+                        let rtc_backup_path = format!("{}.{}", rtc_path, hash);
+                        if let Err(e) = std::fs::rename(&rtc_path, &rtc_backup_path) {
+                            error!("Failed to move {} to {}: {}", rtc_path, rtc_backup_path, e);
+                        }
+                    }
+                    self.show_error(format!(
+                        "{} was saved by a different ROM (SHA-1 {}), {}. Rename it back and start with \
+                         --allow-rom-mismatch to use it anyway.",
+                        save_path, hash, kept
+                    ));
+                    return;
+                }
+                Some(hash) if hash != self.system.rom_hash() => {
+                    let message = format!(
+                        "Using {} even though a different ROM (SHA-1 {}) saved it",
+                        save_path, hash
+                    );
+                    warn!("{}", message);
+                    self.notification = Some((message, Instant::now() + NOTIFICATION_DURATION));
+                    self.system.load_ram(cart_ram);
+                }
+                _ => {
+                    self.system.load_ram(cart_ram);
+                    info!("Loaded cartridge RAM from {}", save_path);
+                }
+            }
+        }
+
+        // same for the RTC, which may need to catch up on the time spent offline
+        if let Ok(rtc) = std::fs::read(&rtc_path) {
+            self.system.load_rtc(rtc, self.settings.rtc_mode);
+            info!("Loaded cartridge RTC from {}", rtc_path);
+        }
+    }
+
+    pub fn save_cartridge(&mut self) {
+        let cart_ram = self.system.dump_ram();
+        let save_path = format!("{}.sav", self.settings.rom_path);
+        std::fs::write(&save_path, &cart_ram).expect("Failed to save RAM");
+        info!("Saved cartridge RAM to {}", save_path);
+        self.system.mark_saved();
+
+        // the hash and a screenshot go along with it, to tell which game and where it was saved
+        let info = SaveInfo {
+            rom_hash: Some(self.system.rom_hash()),
+            saved_at: unix_time(),
+            play_time: self.stats.play_time,
+        };
+        if let Err(e) = info.save(&save_path) {
+            error!("Failed to save the save info {}: {}", SaveInfo::path(&save_path), e);
+        }
+        let screenshot_path = SaveInfo::screenshot_path(&save_path);
+        let (width, _) = self.system.screen_size();
+        if let Err(e) = save_png(Path::new(&screenshot_path), width, &self.system.framebuffer()) {
+            error!("Failed to save the screenshot {}: {}", screenshot_path, e);
+        }
+
+        // the RTC is stored next to the save, along with the time it was written
+        if let Some(rtc) = self.system.dump_rtc() {
+            let rtc_path = format!("{}.rtc", self.settings.rom_path);
+            std::fs::write(&rtc_path, &rtc).expect("Failed to save RTC");
+            info!("Saved cartridge RTC to {}", rtc_path);
+        }
+    }
+
+    // Swaps in another ROM while the console keeps running, e.g. to A/B test a patch
+    pub fn swap_cartridge(&mut self, opened_path: String) {
+        let LoadedRom { path: rom_path, rom } = match read_rom(&opened_path, None) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                self.show_error(e);
+                return;
+            }
+        };
+
+        self.save_cartridge();
+        self.end_session();
+        if let Err(e) = self.system.swap_cartridge(rom) {
+            self.show_error(format!("Failed to swap cartridge: {}", e));
+            return;
+        }
+        info!("Swapped cartridge to {}", rom_path);
+
+        self.settings.rom_path = rom_path;
+        self.settings.opened_path = opened_path;
+        self.load_cartridge();
+
+        // The other game may need a different accuracy or colors
+        let rom_settings = RomSettings::load(&self.settings.rom_path);
+        self.settings.accuracy = rom_settings.accuracy;
+        self.settings.color_correction = rom_settings.color_correction;
+        self.settings.compat_palette = rom_settings.compat_palette;
+        self.system.set_accuracy(self.settings.accuracy);
+        self.system.set_color_correction(self.settings.color_correction);
+        self.system.set_compat_palette(self.settings.compat_palette);
+
+        self.presence.set_game(self.system.title(), self.system.model());
+        self.start_session();
+    }
+
+    // Puts the game on top of the library, its stats carry on from the last time
+    fn start_session(&mut self) {
+        self.stats = RomSettings::load(&self.settings.rom_path).stats;
+        self.stats.last_played = unix_time();
+        self.stats_updated = Instant::now();
+        if let Err(e) = record_played(
+            Path::new(LIBRARY_PATH),
+            &self.settings.opened_path,
+            &self.settings.rom_path,
+        ) {
+            error!("Failed to add {} to the library: {}", self.settings.opened_path, e);
+        }
+    }
+
+    // Saves the stats and the last frame, which the library shows
+    fn end_session(&mut self) {
+        self.save_rom_settings();
+
+        let path = screenshot_path(&self.settings.rom_path);
+        let (width, _) = self.system.screen_size();
+        if let Err(e) = save_png(Path::new(&path), width, &self.system.framebuffer()) {
+            error!("Failed to save the screenshot {}: {}", path, e);
+        }
+    }
+
+    // Pages from the printer end up next to the ROM, numbered so that nothing gets overwritten
+    fn save_printouts(&mut self) {
+        for printout in self.system.take_printouts() {
+            let path = (1..)
+                .map(|page| format!("{}.print-{:03}.png", self.settings.rom_path, page))
+                .find(|path| !Path::new(path).exists())
+                .unwrap();
+
+            let message = match printout.save_png(&path) {
+                Ok(_) => format!("Printed a page to {}", path),
+                Err(e) => format!("Failed to save printout to {}: {}", path, e),
+            };
+            info!("{}", message);
+            self.notification = Some((message, Instant::now() + NOTIFICATION_DURATION));
+        }
+    }
+
+    fn show_error(&mut self, error: String) {
+        error!("{}", error);
+        self.error = Some(error);
+    }
+
+    fn save_rom_settings(&self) {
+        let settings = RomSettings {
+            accuracy: self.settings.accuracy,
+            color_correction: self.settings.color_correction,
+            compat_palette: self.settings.compat_palette,
+            stats: self.stats.clone(),
+        };
+        let path = RomSettings::path(&self.settings.rom_path);
+        match settings.save(&self.settings.rom_path) {
+            Ok(_) => info!("Saved ROM settings to {}", path),
+            Err(e) => error!("Failed to save ROM settings to {}: {}", path, e),
+        }
+    }
+
+    // The GL context only comes with a frame, so this waits for the first one
+    fn start_direct_draw(&mut self, frame: &Frame) {
+        let screen = match frame.gl() {
+            Some(gl) => DirectScreen::new(gl),
+            None => Err(String::from("OpenGL isn't available")),
+        };
+        match screen {
+            Ok(screen) => {
+                self.direct_screen = Some(Arc::new(Mutex::new(screen)));
+                info!("Drawing the screen directly");
+                // Paused games would show nothing until the next frame otherwise
+                self.update_screen(&self.system.framebuffer());
+            }
+            Err(e) => {
+                self.settings.presentation = Presentation::Image;
+                self.show_error(format!(
+                    "Failed to draw the screen directly, showing it as an image: {}",
+                    e
+                ));
+            }
+        }
+    }
+
+    pub fn calibrate_audio(&mut self) {
+        self.audio_latency = self.system.calibrate_audio();
+        if let Some(latency) = self.audio_latency {
+            info!("Measured audio latency: {}ms", latency.as_millis());
+        }
+    }
+
+    // How long a frame lasts at the speed running now
+    fn frame_duration(&self) -> Duration {
+        self.state.frame_duration(self.system.frame_duration())
+    }
+
+    // Audio sync always runs the next frame, the audio device blocks until it needs more samples
+    fn frame_due(&mut self) -> bool {
+        if self.settings.sync_mode == SyncMode::Audio {
+            return true;
+        }
+
+        let now = Instant::now();
+        if now < self.next_frame {
+            return false;
+        }
+
+        // Don't try to catch up on frames missed while paused or running slow
+        self.next_frame = (self.next_frame + self.frame_duration()).max(now);
+        true
+    }
+
+    // Decides whether the next frame gets drawn. Auto frame-skip only skips while the host can't keep up.
+    fn skip_frame(&mut self) -> bool {
+        let now = Instant::now();
+        let frame_duration = self.frame_duration();
+
+        // Only fall behind as far as skipping frames can catch up again
+        let catch_up = frame_duration * MAX_FRAME_SKIP as u32;
+        self.frame_deadline = (self.frame_deadline + frame_duration).max(now.checked_sub(catch_up).unwrap_or(now));
+
+        let skip = self.skipped_frames < self.settings.frame_skip
+            && (!self.settings.auto_frame_skip || now > self.frame_deadline);
+        self.skipped_frames = if skip { self.skipped_frames + 1 } else { 0 };
+        skip
+    }
+
+    fn import_state(&mut self) {
+        let path = self.import_path.trim().to_owned();
+        let imported = std::fs::read(&path)
+            .map_err(|e| format!("Failed to read {}: {}", path, e))
+            .and_then(|data| match self.system.as_any_mut().downcast_mut::<GameBoy>() {
+                Some(gb) => gb.import_state(&data).map_err(|e| e.to_string()),
+                None => Err("Only Game Boy states can be imported".to_owned()),
+            });
+
+        match imported {
+            Ok(_) => {
+                info!("Imported state {}", path);
+                self.notification = Some((format!("Imported {}", path), Instant::now() + NOTIFICATION_DURATION));
+            }
+            Err(e) => self.show_error(e),
+        }
+    }
+
+    // Moves to the state the event leads to, along with everything that comes with it
+    fn change_state(&mut self, event: StateEvent) {
+        let Some(state) = self.state.next(event) else {
+            return;
+        };
+
+        // in real time mode the RTC keeps counting while the emulation is paused
+        if !state.is_running() {
+            self.paused_rtc = self.system.dump_rtc();
+        } else if let Some(rtc) = self.paused_rtc.take() {
+            self.system.load_rtc(rtc, self.settings.rtc_mode);
+        }
+        self.system.set_speed(state.speed());
+        if state.is_muted() != self.state.is_muted() {
+            self.system.set_muted(state.is_muted());
+        }
+        self.frame_deadline = Instant::now();
+
+        self.state = state;
+        self.notification = Some((state.message().to_owned(), Instant::now() + NOTIFICATION_DURATION));
+    }
+
+    // Minimized or unfocused, the emulator follows the background policy. The debugger having focus still
+    // counts as being in front. Netplay keeps running no matter what, the other side would be stuck
+    // waiting otherwise.
+    fn update_background(&mut self, ctx: &Context) {
+        let background = ctx.input(|i| {
+            !i.raw
+                .viewports
+                .values()
+                .any(|viewport| viewport.focused == Some(true) && viewport.minimized != Some(true))
+        });
+        let event = match background && self.netplay.is_none() {
+            true => StateEvent::Background(self.settings.background),
+            false => StateEvent::Foreground,
+        };
+        self.change_state(event);
+    }
+
+    fn record_input(&mut self, input: u8) {
+        if input != self.input {
+            self.input = input;
+            self.input_changes.push((Instant::now(), input));
+        }
+    }
+
+    // Input at the start of the frame about to run, and the changes during it as offsets into the frame.
+    // The frame stands for the time since the last one was emulated, so every change lands where it
+    // happened within that. Run-ahead already hides the latency and netplay only deals in whole frames,
+    // those get the latest input right at the start, as does the first frame after a pause.
+    fn take_input(&mut self) -> (u8, Vec<(f64, u8)>) {
+        let now = Instant::now();
+        let (start, since) = std::mem::replace(&mut self.frame_input, (self.input, now));
+        let changes = std::mem::take(&mut self.input_changes);
+
+        let window = now.duration_since(since);
+        if self.settings.run_ahead || self.netplay.is_some() || window > self.system.frame_duration() * 2 {
+            return (self.input, Vec::new());
+        }
+
+        let changes = changes
+            .into_iter()
+            .map(|(at, input)| (at.duration_since(since).as_secs_f64() / window.as_secs_f64(), input))
+            .collect();
+        (start, changes)
+    }
+
+    // The presets a CGB offers for DMG games while its boot ROM runs, which is skipped here
+    fn show_palette_settings(&mut self, ui: &mut Ui) {
+        let label = |palette: CompatPalette| match palette.combo() {
+            Some(combo) => format!("{} ({})", palette.name(), combo),
+            None => palette.name().to_owned(),
+        };
+
+        ui.horizontal(|ui| {
+            ui.label("Palette:");
+            let previous = self.settings.compat_palette;
+            ComboBox::from_id_source("compat_palette")
+                .selected_text(label(self.settings.compat_palette))
+                .show_ui(ui, |ui| {
+                    for palette in CompatPalette::ALL {
+                        ui.selectable_value(&mut self.settings.compat_palette, palette, label(palette));
+                    }
+                });
+            if self.settings.compat_palette != previous {
+                self.system.set_compat_palette(self.settings.compat_palette);
+                self.save_rom_settings();
+            }
+        });
+    }
+
+    fn show_turbo_settings(&mut self, ui: &mut Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Turbo:");
+            for (_, button) in KEY_BINDINGS {
+                let mut enabled = self.turbo.buttons() & button.mask() != 0;
+                if ui.checkbox(&mut enabled, format!("{:?}", button)).changed() {
+                    self.turbo.set_button(button, enabled);
+                }
+            }
+        });
+        let mut rate = self.turbo.rate();
+        if ui
+            .add(Slider::new(&mut rate, MIN_TURBO_RATE..=MAX_TURBO_RATE).text("presses per second"))
+            .changed()
+        {
+            self.turbo.set_rate(rate, self.system.frame_duration());
+        }
+    }
+
     pub fn handle_input(&mut self, ctx: &Context) {
+        let dropped_rom = ctx.input(|i| i.raw.dropped_files.iter().find_map(|file| file.path.clone()));
+        if let Some(path) = dropped_rom
+            && self.netplay.is_none()
+        {
+            self.swap_cartridge(path.to_string_lossy().into_owned());
+        }
+
         if ctx.input(|i| i.key_pressed(Key::F1)) {
             self.debugger.toggle_window();
         }
 
         ctx.input(|i| {
             if i.key_released(Key::Space) {
-                self.running = !self.running;
+                self.change_state(StateEvent::TogglePause);
             }
 
             if i.key_released(Key::F2) {
-                self.gb.mmu.apu.update_cpu_clock(CPU_CLOCK * 4);
+                self.change_state(StateEvent::FastForward);
             }
 
             if i.key_released(Key::F3) {
-                self.gb.mmu.apu.reset_cpu_clock();
+                self.change_state(StateEvent::NormalSpeed);
             }
 
-            if i.key_released(Key::F5) {
-                let cart_ram = self.gb.mmu.cartridge.dump_ram();
-                let save_path = format!("{}.sav", self.settings.rom_path);
-                std::fs::write(&save_path, &cart_ram).expect("Failed to save RAM");
-                info!("Saved cartridge RAM to {}", save_path);
+            if i.key_released(Key::F4) {
+                self.change_overlay = match self.change_overlay {
+                    Some(_) => None,
+                    None => Some(ChangeOverlay::new()),
+                };
             }
 
-            if i.key_down(Key::Enter) {
-                self.gb.mmu.joypad.update_button(Key::Enter, true);
-            } else {
-                self.gb.mmu.joypad.update_button(Key::Enter, false);
+            if i.key_released(Key::F5) && self.state.accepts(Hotkey::SaveCartridge) {
+                self.save_cartridge();
             }
 
-            if i.key_down(Key::Backspace) {
-                self.gb.mmu.joypad.update_button(Key::Backspace, true);
-            } else {
-                self.gb.mmu.joypad.update_button(Key::Backspace, false);
+            if i.key_released(Key::F6) && self.state.accepts(Hotkey::SaveState) {
+                self.quick_save = Some(self.system.save_state());
+                self.stats.states_saved += 1;
+                info!("Saved state");
             }
 
-            if i.key_down(Key::A) {
-                self.gb.mmu.joypad.update_button(Key::A, true);
-            } else {
-                self.gb.mmu.joypad.update_button(Key::A, false);
+            // Changing the state on one side only would desync a netplay session
+            if i.key_released(Key::F7)
+                && self.state.accepts(Hotkey::LoadState)
+                && self.netplay.is_none()
+                && let Some(state) = &self.quick_save
+            {
+                let loaded = match self.settings.allow_rom_mismatch {
+                    true => self.system.force_load_state(state),
+                    false => self.system.load_state(state),
+                };
+                match loaded {
+                    Ok(_) => {
+                        self.stats.states_loaded += 1;
+                        info!("Loaded state");
+                    }
+                    Err(e) => self.show_error(format!("Failed to load state: {}", e)),
+                }
             }
 
-            if i.key_down(Key::S) {
-                self.gb.mmu.joypad.update_button(Key::S, true);
-            } else {
-                self.gb.mmu.joypad.update_button(Key::S, false);
+            let previous = self.input;
+            // Key events catch presses shorter than a repaint, the keys held make up for events that never
+            // arrived (e.g. a key let go of while the window wasn't focused)
+            for event in &i.events {
+                if let Event::Key {
+                    key,
+                    pressed,
+                    repeat: false,
+                    ..
+                } = event
+                    && let Some((_, button)) = KEY_BINDINGS.iter().find(|(bound, _)| bound == key)
+                {
+                    self.record_input(match pressed {
+                        true => self.input | button.mask(),
+                        false => self.input & !button.mask(),
+                    });
+                }
             }
+            self.record_input(
+                KEY_BINDINGS
+                    .iter()
+                    .filter(|(key, _)| i.key_down(*key))
+                    .fold(0, |input, (_, button)| input | button.mask()),
+            );
 
-            if i.key_down(Key::ArrowUp) {
-                self.gb.mmu.joypad.update_button(Key::ArrowUp, true);
-            } else {
-                self.gb.mmu.joypad.update_button(Key::ArrowUp, false);
+            // Resetting on one side only would desync a netplay session
+            if self.input & SOFT_RESET == SOFT_RESET && previous & SOFT_RESET != SOFT_RESET && self.netplay.is_none() {
+                self.system.reset();
             }
+        });
+    }
+}
 
-            if i.key_down(Key::ArrowDown) {
-                self.gb.mmu.joypad.update_button(Key::ArrowDown, true);
-            } else {
-                self.gb.mmu.joypad.update_button(Key::ArrowDown, false);
-            }
+// The key bindings, shown while paused and on the launcher
+pub fn show_key_help(ui: &mut Ui) {
+    ui.label("Arrow keys to move");
+    ui.label("A and S to interact");
+    ui.label("Enter to start");
+    ui.label("Backspace to select");
+    ui.label("All four at once to reset");
+    ui.separator();
+    ui.label("Press Space to start/stop emulation");
+    ui.label("Press F1 to open debugger");
+    ui.label("Press F2 to fast forward while running");
+    ui.label("Press F3 to go back to normal speed");
+    ui.label("Press F4 to highlight pixels that changed since the last frame");
+    ui.label("Press F5 to save RAM to disk");
+    ui.label("Press F6/F7 to save/load state");
+}
 
-            if i.key_down(Key::ArrowLeft) {
-                self.gb.mmu.joypad.update_button(Key::ArrowLeft, true);
-            } else {
-                self.gb.mmu.joypad.update_button(Key::ArrowLeft, false);
-            }
+// Pure and mixed CGB colors as the given correction shows them
+fn show_color_preview(ui: &mut Ui, correction: ColorCorrection) {
+    const SAMPLES: [u16; 8] = [0x7fff, 0x001f, 0x03e0, 0x7c00, 0x03ff, 0x7c1f, 0x2dbf, 0x294a];
 
-            if i.key_down(Key::ArrowRight) {
-                self.gb.mmu.joypad.update_button(Key::ArrowRight, true);
-            } else {
-                self.gb.mmu.joypad.update_button(Key::ArrowRight, false);
-            }
-        });
-    }
+    ui.horizontal(|ui| {
+        for sample in SAMPLES {
+            let [r, g, b] = Palette::rgb555_to_rgb888(sample, correction);
+            let (rect, _) = ui.allocate_exact_size(vec2(24.0, 16.0), Sense::hover());
+            ui.painter().rect_filled(rect, 0.0, Color32::from_rgb(r, g, b));
+        }
+    });
 }
 
 impl App for Renderer {
-    fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+    fn update(&mut self, ctx: &Context, frame: &mut Frame) {
+        if self.settings.presentation == Presentation::Direct && self.direct_screen.is_none() {
+            self.start_direct_draw(frame);
+        }
         self.handle_input(ctx);
+        self.presence.set_paused(!self.state.is_running());
+        self.update_background(ctx);
+        let emulating = self.state.is_running();
 
-        if self.running {
-            self.gb.run_frame();
-            self.update_screen(&self.gb.ppu.pull_frame());
-        } else if !self.running && !self.debugger.window_open {
+        // Hangs of the host (e.g. a dialog blocking the window) don't count as playing
+        let now = Instant::now();
+        if emulating {
+            self.stats.play_time += now.duration_since(self.stats_updated).min(MAX_PLAY_TIME_STEP);
+        }
+        self.stats_updated = now;
+
+        let title = self.state.title(&self.system.title(), self.quick_save.is_some());
+        if title != self.title {
+            ctx.send_viewport_cmd(ViewportCommand::Title(title.clone()));
+            self.title = title;
+        }
+
+        if emulating && self.frame_due() {
+            // Skipped frames are still emulated, so audio keeps playing
+            let skip = self.skip_frame();
+            self.system.set_rendering(!skip);
+
+            // Writing to RAM on one side only would desync a netplay session
+            if self.netplay.is_none() {
+                self.debugger.apply_frozen_watches(self.system.as_mut());
+            }
+
+            // With netplay the input goes through the session, which decides when a frame can run
+            let (input, changes) = self.take_input();
+            let input = self.turbo.apply(input);
+            match &mut self.netplay {
+                Some(netplay) => {
+                    if let Err(e) = netplay.advance(self.system.as_mut(), input) {
+                        error!("{}, continuing offline", e);
+                        self.netplay = None;
+                    }
+                }
+                None => {
+                    self.system.update_buttons(input);
+                    for (offset, input) in changes {
+                        self.system.queue_buttons(offset, self.turbo.filter(input));
+                    }
+                    self.system.run_frame();
+                }
+            }
+
+            self.stats.frames += 1;
+            self.save_printouts();
+            self.rumble.update(self.system.take_rumble());
+
+            if self.debugger.check_breakpoint(self.system.as_mut()) {
+                self.change_state(StateEvent::Breakpoint);
+            }
+            self.debugger.check_warnings(self.system.as_mut());
+
+            if !skip {
+                let framebuffer = if self.settings.run_ahead {
+                    self.system.run_ahead(self.settings.run_ahead_frames)
+                } else {
+                    self.system.framebuffer()
+                };
+                self.update_screen(&framebuffer);
+
+                if let Some(sink) = &mut self.frame_sink {
+                    let (width, height) = self.system.screen_size();
+                    sink.push_frame(width, height, &framebuffer);
+                }
+            }
+        } else if !emulating {
+            // Lets the motors spin down while paused
+            self.rumble.update(false);
+        }
+
+        if !self.state.is_running() && !self.debugger.window_open {
             Window::new("Controls")
                 .anchor(Align2::CENTER_CENTER, vec2(0.0, 0.0))
                 .collapsible(false)
                 .resizable(false)
                 .show(ctx, |ui| {
-                    ui.label("Arrow keys to move");
-                    ui.label("A and S to interact");
-                    ui.label("Enter to start");
-                    ui.label("Backspace to select");
+                    show_key_help(ui);
+                    ui.label("Drop a ROM onto the window to swap cartridges");
+
                     ui.separator();
-                    ui.label("Press Space to start/stop emulation");
-                    ui.label("Press F1 to open debugger");
-                    ui.label("Press F2 to increase APU clock speed");
-                    ui.label("Press F3 to reset APU clock speed");
-                    ui.label("Press F5 to save RAM to disk");
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.settings.run_ahead, "Run-ahead");
+                        ui.add_enabled(
+                            self.settings.run_ahead,
+                            Slider::new(&mut self.settings.run_ahead_frames, 1..=MAX_RUN_AHEAD).text("frames"),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.checkbox(&mut self.settings.auto_frame_skip, "Auto frame-skip");
+                        ui.add(Slider::new(&mut self.settings.frame_skip, 0..=MAX_FRAME_SKIP).text("frames"));
+                    });
+
+                    ui.horizontal(|ui| {
+                        ui.label("Accuracy:");
+                        let previous = self.settings.accuracy;
+                        ui.radio_value(&mut self.settings.accuracy, Accuracy::Fast, "Fast");
+                        ui.radio_value(&mut self.settings.accuracy, Accuracy::Balanced, "Balanced");
+                        ui.radio_value(&mut self.settings.accuracy, Accuracy::PerPixel, "Per pixel");
+                        if self.settings.accuracy != previous {
+                            self.system.set_accuracy(self.settings.accuracy);
+                            self.save_rom_settings();
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Colors:");
+                        let previous = self.settings.color_correction;
+                        ui.radio_value(&mut self.settings.color_correction, ColorCorrection::Raw, "Raw");
+                        ui.radio_value(&mut self.settings.color_correction, ColorCorrection::Cgb, "CGB LCD");
+                        ui.radio_value(&mut self.settings.color_correction, ColorCorrection::Gba, "GBA");
+                        if self.settings.color_correction != previous {
+                            self.system.set_color_correction(self.settings.color_correction);
+                            self.save_rom_settings();
+                        }
+                    });
+                    // The paused frame keeps its old colors, so show what the choice looks like
+                    show_color_preview(ui, self.settings.color_correction);
+                    if self.system.mode() == Mode::Dmg {
+                        self.show_palette_settings(ui);
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Layers:");
+                        let previous = self.settings.layers;
+                        let Layers {
+                            background,
+                            window,
+                            sprites,
+                        } = &mut self.settings.layers;
+                        ui.checkbox(background, "Background");
+                        ui.checkbox(window, "Window");
+                        ui.checkbox(sprites, "Sprites");
+                        if self.settings.layers != previous {
+                            self.system.set_layers(self.settings.layers);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Sync to:");
+                        let previous = self.settings.sync_mode;
+                        ui.radio_value(&mut self.settings.sync_mode, SyncMode::Audio, "Audio");
+                        ui.radio_value(&mut self.settings.sync_mode, SyncMode::Timer, "Timer");
+                        if self.settings.sync_mode != previous {
+                            self.system.set_sync_mode(self.settings.sync_mode);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("In the background:");
+                        ui.radio_value(&mut self.settings.background, BackgroundPolicy::Pause, "Pause");
+                        ui.radio_value(&mut self.settings.background, BackgroundPolicy::Throttle, "Slow down");
+                        ui.radio_value(
+                            &mut self.settings.background,
+                            BackgroundPolicy::Continue,
+                            "Keep running",
+                        );
+                    });
+                    self.show_turbo_settings(ui);
+                    #[cfg(feature = "gamepad")]
+                    {
+                        let mut strength = self.rumble.strength();
+                        if ui
+                            .add(
+                                Slider::new(&mut strength, 0..=super::gamepad::MAX_RUMBLE_STRENGTH)
+                                    .text("% gamepad rumble"),
+                            )
+                            .changed()
+                        {
+                            self.rumble.set_strength(strength);
+                        }
+                    }
+                    #[cfg(feature = "discord")]
+                    {
+                        let mut enabled = self.presence.is_enabled();
+                        if ui.checkbox(&mut enabled, "Show game on Discord").changed() {
+                            self.presence.set_enabled(enabled);
+                        }
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Calibrate audio latency").clicked() {
+                            self.calibrate_audio();
+                        }
+                        match self.audio_latency {
+                            Some(latency) => ui.label(format!("{}ms", latency.as_millis())),
+                            None => ui.label("not measured"),
+                        };
+                    });
+
+                    // Decaying the RAM on one side only would desync a netplay session
+                    if self.netplay.is_none()
+                        && let Some(decay) = self.settings.ram_decay
+                    {
+                        ui.separator();
+                        ui.label(format!(
+                            "Experimental: power off without a battery, {}% of the cartridge RAM decays",
+                            decay.percent()
+                        ));
+                        if ui.button("Power cycle").clicked() {
+                            self.system.power_cycle(Some(&decay));
+                            self.notification = Some((
+                                "Power cycled, cartridge RAM decayed".to_owned(),
+                                Instant::now() + NOTIFICATION_DURATION,
+                            ));
+                        }
+                    }
+
+                    // Changing the state on one side only would desync a netplay session
+                    if self.netplay.is_none() {
+                        ui.separator();
+                        ui.label("Experimental: import a SameBoy (BESS) save state");
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut self.import_path);
+                            if ui.button("Import").clicked() {
+                                self.import_state();
+                            }
+                        });
+                    }
+
+                    if self.paused_rtc.is_some() {
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            ui.label("RTC:");
+                            ui.radio_value(&mut self.settings.rtc_mode, RtcMode::Real, "Real time");
+                            ui.radio_value(&mut self.settings.rtc_mode, RtcMode::Emulated, "Emulated time only");
+                        });
+                    }
                 });
         }
 
-        CentralPanel::default().show(ctx, |ui| {
-            let image = Image::new(&self.screen_texture);
-            let image = image.fit_to_exact_size(vec2((SCREEN_WIDTH * SCALE) as f32, (SCREEN_WIDTH * SCALE) as f32));
-            image.paint_at(ui, ui.ctx().screen_rect());
+        if let Some((message, until)) = &self.notification {
+            if Instant::now() < *until {
+                Area::new(Id::new("notification"))
+                    .anchor(Align2::LEFT_TOP, vec2(8.0, 8.0))
+                    .show(ctx, |ui| {
+                        eframe::egui::Frame::popup(ui.style()).show(ui, |ui| ui.label(message));
+                    });
+            } else {
+                self.notification = None;
+            }
+        }
+
+        if let Some(error) = &self.error {
+            let mut dismissed = false;
+            Window::new("Error")
+                .anchor(Align2::CENTER_TOP, vec2(0.0, 16.0))
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.colored_label(Color32::RED, error);
+                    dismissed = ui.button("OK").clicked();
+                });
+            if dismissed {
+                self.error = None;
+            }
+        }
+
+        CentralPanel::default().show(ctx, |ui| match &self.direct_screen {
+            Some(screen) => {
+                ui.painter().add(paint_callback(screen, ui.ctx().screen_rect()));
+            }
+            None => {
+                let image = Image::new(&self.screen_texture);
+                let (width, _) = self.system.screen_size();
+                let image = image.fit_to_exact_size(vec2((width * SCALE) as f32, (width * SCALE) as f32));
+                image.paint_at(ui, ui.ctx().screen_rect());
+            }
         });
 
-        self.debugger.update_ui(ctx, &mut self.gb);
+        self.debugger.update_ui(
+            ctx,
+            self.system.as_mut(),
+            &self.settings.rom_path,
+            self.quick_save.as_ref(),
+        );
+
+        // Waking up for every frame is what keeps a hidden window busy
+        match self.state {
+            FrontendState::Background(BackgroundPolicy::Pause) => ctx.request_repaint_after(BACKGROUND_POLL_INTERVAL),
+            FrontendState::Background(_) => ctx.request_repaint_after(self.frame_duration()),
+            _ => ctx.request_repaint(),
+        }
+    }
 
-        ctx.request_repaint();
+    fn on_exit(&mut self, gl: Option<&glow::Context>) {
+        if let (Some(screen), Some(gl)) = (&self.direct_screen, gl) {
+            screen.lock().unwrap().destroy(gl);
+        }
     }
 }
 
 impl Drop for Renderer {
     fn drop(&mut self) {
         // save battery-backed RAM
-        let cart_ram = self.gb.mmu.cartridge.dump_ram();
-        let save_path = format!("{}.sav", self.settings.rom_path);
-        std::fs::write(&save_path, &cart_ram).expect("Failed to save RAM");
-        info!("Saved cartridge RAM to {}", save_path);
+        self.save_cartridge();
+        self.end_session();
     }
 }
@@ -0,0 +1,101 @@
+use crate::video::compat::CompatPalette;
+use crate::video::{Accuracy, ColorCorrection};
+use clap::ValueEnum;
+use log::warn;
+use std::time::Duration;
+
+// Settings remembered per game, stored next to the ROM as "key=value" lines
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RomSettings {
+    pub accuracy: Accuracy,
+    pub color_correction: ColorCorrection,
+    pub compat_palette: CompatPalette,
+    pub stats: PlayStats,
+}
+
+// How much a game was played, kept along with its settings
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PlayStats {
+    // Time spent with the game running, paused time doesn't count
+    pub play_time: Duration,
+    pub frames: u64,
+    pub states_saved: u64,
+    pub states_loaded: u64,
+    // Seconds since the Unix epoch, 0 if never
+    pub last_played: u64,
+}
+
+impl RomSettings {
+    pub fn path(rom_path: &str) -> String {
+        format!("{}.cfg", rom_path)
+    }
+
+    // Falls back to the defaults if the ROM has no settings yet
+    pub fn load(rom_path: &str) -> RomSettings {
+        std::fs::read_to_string(RomSettings::path(rom_path))
+            .map(|data| RomSettings::parse(&data))
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, rom_path: &str) -> std::io::Result<()> {
+        std::fs::write(RomSettings::path(rom_path), self.serialize())
+    }
+
+    pub fn parse(data: &str) -> RomSettings {
+        let mut settings = RomSettings::default();
+        for line in data.lines().filter(|line| !line.trim().is_empty()) {
+            match line.split_once('=').map(|(key, value)| (key.trim(), value.trim())) {
+                Some(("accuracy", value)) => match Accuracy::from_str(value, true) {
+                    Ok(accuracy) => settings.accuracy = accuracy,
+                    Err(_) => warn!("Ignoring invalid accuracy: {}", value),
+                },
+                Some(("color_correction", value)) => match ColorCorrection::from_str(value, true) {
+                    Ok(correction) => settings.color_correction = correction,
+                    Err(_) => warn!("Ignoring invalid color correction: {}", value),
+                },
+                Some(("palette", value)) => match CompatPalette::from_str(value, true) {
+                    Ok(palette) => settings.compat_palette = palette,
+                    Err(_) => warn!("Ignoring invalid palette: {}", value),
+                },
+                Some((key @ ("play_time" | "frames" | "states_saved" | "states_loaded" | "last_played"), value)) => {
+                    let Ok(value) = value.parse::<u64>() else {
+                        warn!("Ignoring invalid {}: {}", key, value);
+                        continue;
+                    };
+                    let stats = &mut settings.stats;
+                    match key {
+                        "play_time" => stats.play_time = Duration::from_secs(value),
+                        "frames" => stats.frames = value,
+                        "states_saved" => stats.states_saved = value,
+                        "states_loaded" => stats.states_loaded = value,
+                        _ => stats.last_played = value,
+                    }
+                }
+                _ => warn!("Ignoring invalid ROM setting: {}", line),
+            }
+        }
+        settings
+    }
+
+    pub fn serialize(&self) -> String {
+        format!(
+            "accuracy={}\ncolor_correction={}\npalette={}\n\
+             play_time={}\nframes={}\nstates_saved={}\nstates_loaded={}\nlast_played={}\n",
+            value_name(self.accuracy),
+            value_name(self.color_correction),
+            value_name(self.compat_palette),
+            self.stats.play_time.as_secs(),
+            self.stats.frames,
+            self.stats.states_saved,
+            self.stats.states_loaded,
+            self.stats.last_played
+        )
+    }
+}
+
+fn value_name(value: impl ValueEnum) -> String {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_owned())
+        .unwrap_or_default()
+}
@@ -0,0 +1,89 @@
+use crate::system::RomHash;
+use log::warn;
+use std::time::Duration;
+
+// What's known about a .sav, stored next to it as "key=value" lines along with a screenshot taken when it
+// was written. The ROM hash tells saves of another revision of the game apart.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SaveInfo {
+    // None for saves written before the hash was stored
+    pub rom_hash: Option<RomHash>,
+    // Seconds since the Unix epoch
+    pub saved_at: u64,
+    // Play time of the game when it was saved
+    pub play_time: Duration,
+}
+
+impl SaveInfo {
+    pub fn path(save_path: &str) -> String {
+        format!("{}.info", save_path)
+    }
+
+    pub fn screenshot_path(save_path: &str) -> String {
+        format!("{}.png", save_path)
+    }
+
+    // Older saves only came with the ROM hash, in a file of its own
+    fn legacy_hash_path(save_path: &str) -> String {
+        format!("{}.sha1", save_path)
+    }
+
+    // None if the save has no info at all
+    pub fn load(save_path: &str) -> Option<SaveInfo> {
+        if let Ok(data) = std::fs::read_to_string(SaveInfo::path(save_path)) {
+            return Some(SaveInfo::parse(&data));
+        }
+
+        let hash = std::fs::read_to_string(SaveInfo::legacy_hash_path(save_path)).ok()?;
+        Some(SaveInfo {
+            rom_hash: RomHash::parse(&hash),
+            ..SaveInfo::default()
+        })
+    }
+
+    // The legacy hash goes away, it would outlive the save it belonged to otherwise
+    pub fn save(&self, save_path: &str) -> std::io::Result<()> {
+        std::fs::write(SaveInfo::path(save_path), self.serialize())?;
+        match std::fs::remove_file(SaveInfo::legacy_hash_path(save_path)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn parse(data: &str) -> SaveInfo {
+        let mut info = SaveInfo::default();
+        for line in data.lines().filter(|line| !line.trim().is_empty()) {
+            match line.split_once('=').map(|(key, value)| (key.trim(), value.trim())) {
+                Some(("rom_sha1", value)) => match RomHash::parse(value) {
+                    Some(hash) => info.rom_hash = Some(hash),
+                    None => warn!("Ignoring invalid ROM hash: {}", value),
+                },
+                Some((key @ ("saved_at" | "play_time"), value)) => {
+                    let Ok(value) = value.parse::<u64>() else {
+                        warn!("Ignoring invalid {}: {}", key, value);
+                        continue;
+                    };
+                    match key {
+                        "saved_at" => info.saved_at = value,
+                        _ => info.play_time = Duration::from_secs(value),
+                    }
+                }
+                _ => warn!("Ignoring invalid save info: {}", line),
+            }
+        }
+        info
+    }
+
+    pub fn serialize(&self) -> String {
+        let hash = self
+            .rom_hash
+            .map(|hash| format!("rom_sha1={}\n", hash))
+            .unwrap_or_default();
+        format!(
+            "{}saved_at={}\nplay_time={}\n",
+            hash,
+            self.saved_at,
+            self.play_time.as_secs()
+        )
+    }
+}
@@ -0,0 +1,41 @@
+use crate::video::palette::Color;
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+// Writes the pixels as an 8-bit RGB PNG, in rows of the given width. Screenshots, frame traces and
+// tile exports all come out in this format.
+pub fn save_png(path: &Path, width: usize, pixels: &[Color]) -> std::io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, (pixels.len() / width) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(pixels.as_flattened())?;
+    Ok(())
+}
+
+// Width, height and RGB pixels of a PNG written by save_png
+pub fn load_png(path: &Path) -> std::io::Result<(usize, usize, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels)?;
+    if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} is not an 8-bit RGB image", path.display()),
+        ));
+    }
+    pixels.truncate(info.buffer_size());
+    Ok((info.width as usize, info.height as usize, pixels))
+}
+
+// SHA-1 of the RGB pixels, which tells frames apart without having to keep them around
+pub fn frame_hash(pixels: &[u8]) -> String {
+    Sha1::digest(pixels)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
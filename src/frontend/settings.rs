@@ -1,3 +1,63 @@
+use crate::memory::mapper::rtc::RtcMode;
+use crate::memory::ram_decay::RamDecay;
+use crate::sound::SyncMode;
+use crate::video::compat::CompatPalette;
+use crate::video::{Accuracy, ColorCorrection, Layers};
+use clap::ValueEnum;
+
+// What the emulator does while its window is minimized or doesn't have focus
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum BackgroundPolicy {
+    // Stops until the window is back, like pausing but without the controls
+    Pause,
+    // Runs at a tenth of the speed without sound, e.g. to let a game idle along
+    Throttle,
+    // Runs at full speed as if nothing happened
+    Continue,
+}
+
+// How frames get on the screen
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Presentation {
+    // As an image in the UI, works with any graphics backend
+    Image,
+    // Drawn with OpenGL past the UI, less work per frame. Falls back to image if OpenGL isn't there.
+    Direct,
+}
+
 pub struct Settings {
+    // Where the ROM's saves and settings go, next to the zip for zipped ROMs but named after the ROM inside
     pub rom_path: String,
+    // What the user opened, the zip itself for zipped ROMs
+    pub opened_path: String,
+    pub rtc_mode: RtcMode,
+    pub run_ahead: bool,
+    // Number of frames to run ahead when enabled
+    pub run_ahead_frames: usize,
+    pub sync_mode: SyncMode,
+    // Frames skipped in a row, or with auto frame-skip the most that may be skipped in a row
+    pub frame_skip: usize,
+    pub auto_frame_skip: bool,
+    // Stored per ROM, see RomSettings
+    pub accuracy: Accuracy,
+    // Stored per ROM as well
+    pub color_correction: ColorCorrection,
+    // Also per ROM, CGB boot ROM colors for DMG games
+    pub compat_palette: CompatPalette,
+    // Parts of the picture shown, not remembered since hiding them is only for looking into a game
+    pub layers: Layers,
+    // Use saves and save states made with a different ROM instead of refusing them
+    pub allow_rom_mismatch: bool,
+    // Show the game being played on the Discord profile, needs the discord feature
+    pub discord_presence: bool,
+    // Buttons that auto-fire while held, as a bitmask of Button::mask
+    pub turbo_buttons: u8,
+    // Presses per second of the turbo buttons
+    pub turbo_rate: u32,
+    // How hard the gamepad rumbles when a rumble cartridge runs its motor, in percent, needs the gamepad feature
+    pub rumble_strength: u32,
+    // Offers pulling the power with the cartridge RAM decaying like this, for glitch hunting
+    pub ram_decay: Option<RamDecay>,
+    pub background: BackgroundPolicy,
+    pub presentation: Presentation,
 }
@@ -0,0 +1,119 @@
+use super::settings::BackgroundPolicy;
+use std::time::Duration;
+
+// How much faster fast forward runs, the audio device is then asked for fewer samples per frame
+pub const FAST_FORWARD_SPEED: usize = 4;
+// Throttled in the background, frames run this many times slower
+pub const BACKGROUND_SLOWDOWN: u32 = 10;
+
+// What the frontend is doing. It decides the window title, the message shown when it changes and which
+// hotkeys do something, the renderer only reacts to the transitions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrontendState {
+    Paused,
+    Running,
+    FastForward,
+    // No window has focus or it's minimized, runs as the policy says and without sound. Never Continue,
+    // that one just keeps running.
+    Background(BackgroundPolicy),
+}
+
+// Hotkeys that depend on the state, pausing and fast forward go through StateEvent instead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    SaveCartridge,
+    SaveState,
+    LoadState,
+}
+
+// What can move the frontend from one state to another
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StateEvent {
+    TogglePause,
+    FastForward,
+    NormalSpeed,
+    // The debugger stopped on a breakpoint
+    Breakpoint,
+    // Every window lost focus or got minimized, with the policy for the background
+    Background(BackgroundPolicy),
+    // A window has focus again
+    Foreground,
+}
+
+impl FrontendState {
+    // The state after the event, None if it does nothing in this state (e.g. fast forward while paused).
+    // Paused stays paused in the background.
+    pub fn next(self, event: StateEvent) -> Option<FrontendState> {
+        match (self, event) {
+            // Resumes at normal speed
+            (FrontendState::Paused, StateEvent::TogglePause) => Some(FrontendState::Running),
+            (FrontendState::Paused, _) => None,
+            (FrontendState::Background(_), StateEvent::Foreground) => Some(FrontendState::Running),
+            (_, StateEvent::TogglePause | StateEvent::Breakpoint) => Some(FrontendState::Paused),
+            (FrontendState::Background(_), _) => None,
+            (_, StateEvent::Background(BackgroundPolicy::Continue)) => None,
+            (_, StateEvent::Background(policy)) => Some(FrontendState::Background(policy)),
+            (FrontendState::Running, StateEvent::FastForward) => Some(FrontendState::FastForward),
+            (FrontendState::FastForward, StateEvent::NormalSpeed) => Some(FrontendState::Running),
+            _ => None,
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        !matches!(
+            self,
+            FrontendState::Paused | FrontendState::Background(BackgroundPolicy::Pause)
+        )
+    }
+
+    pub fn is_muted(&self) -> bool {
+        matches!(self, FrontendState::Background(_))
+    }
+
+    pub fn speed(&self) -> usize {
+        match self {
+            FrontendState::FastForward => FAST_FORWARD_SPEED,
+            _ => 1,
+        }
+    }
+
+    // How long a frame lasts on the host, e.g. a quarter of the hardware's in fast forward
+    pub fn frame_duration(&self, native: Duration) -> Duration {
+        match self {
+            FrontendState::Background(BackgroundPolicy::Throttle) => native * BACKGROUND_SLOWDOWN,
+            _ => native / self.speed() as u32,
+        }
+    }
+
+    // States are taken and loaded at normal speed, fast forward would be past them before they're seen.
+    // The cartridge can always be saved.
+    pub fn accepts(&self, hotkey: Hotkey) -> bool {
+        !matches!(
+            (self, hotkey),
+            (FrontendState::FastForward, Hotkey::SaveState | Hotkey::LoadState)
+        )
+    }
+
+    // Shown for a moment when the state is entered
+    pub fn message(&self) -> &'static str {
+        match self {
+            FrontendState::Paused => "Paused",
+            FrontendState::Running => "Running",
+            FrontendState::FastForward => "Fast forward",
+            FrontendState::Background(_) => "In the background",
+        }
+    }
+
+    // e.g. "ayyboyy - TETRIS (paused) [state saved]"
+    pub fn title(&self, game: &str, quick_saved: bool) -> String {
+        let status = match self {
+            FrontendState::Paused => " (paused)",
+            FrontendState::Running => "",
+            FrontendState::FastForward => " (fast forward)",
+            FrontendState::Background(BackgroundPolicy::Pause) => " (paused in the background)",
+            FrontendState::Background(_) => " (slowed down in the background)",
+        };
+        let slot = if quick_saved { " [state saved]" } else { "" };
+        format!("ayyboyy - {}{}{}", game, status, slot)
+    }
+}
@@ -0,0 +1,387 @@
+use crate::gameboy::{GameBoy, Mode};
+use crate::lr35902::sm83::Register;
+use crate::memory::io_dispatch::register_docs;
+use crate::memory::{HRAM_END, HRAM_START, OAM_START, VRAM_START, WRAM_BANK0_START, WRAM_BANK1_START};
+use std::fmt;
+
+// Changed bytes closer together than this are listed as one range, so a rewritten table isn't a wall of lines
+const MERGE_GAP: usize = 4;
+// Bytes of a range shown before the rest is only counted
+const SHOWN_BYTES: usize = 8;
+const WAVE_RAM_START: u16 = 0xff30;
+const WAVE_RAM_END: u16 = 0xff3f;
+
+// Part of the console a change belongs to, in the order the changes are listed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Subsystem {
+    Cpu,
+    Interrupts,
+    Timer,
+    Serial,
+    Joypad,
+    Apu,
+    Ppu,
+    Cgb,
+    Wram,
+    Vram,
+    Oam,
+    Hram,
+    Cartridge,
+}
+
+impl fmt::Display for Subsystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Subsystem::Cpu => "CPU",
+            Subsystem::Interrupts => "Interrupts",
+            Subsystem::Timer => "Timer",
+            Subsystem::Serial => "Serial",
+            Subsystem::Joypad => "Joypad",
+            Subsystem::Apu => "APU",
+            Subsystem::Ppu => "PPU",
+            Subsystem::Cgb => "CGB",
+            Subsystem::Wram => "WRAM",
+            Subsystem::Vram => "VRAM",
+            Subsystem::Oam => "OAM",
+            Subsystem::Hram => "HRAM",
+            Subsystem::Cartridge => "Cartridge",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// A register, flag or range of memory that differs between the two snapshots
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change {
+    pub subsystem: Subsystem,
+    pub location: String,
+    pub before: String,
+    pub after: String,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} -> {}", self.location, self.before, self.after)
+    }
+}
+
+// Everything that differs between two snapshots, e.g. a save state and the running game, grouped by
+// subsystem. Meant to track down state a snapshot doesn't carry over, so it only reads and never ticks.
+pub fn diff_states(before: &GameBoy, after: &GameBoy) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_cpu(before, after, &mut changes);
+    diff_io(before, after, &mut changes);
+    diff_palettes(before, after, &mut changes);
+    diff_cartridge(before, after, &mut changes);
+
+    let before_regions = memory_regions(before);
+    let after_regions = memory_regions(after);
+    let find = |regions: &[(Subsystem, String, u16, Vec<u8>)], subsystem: Subsystem, label: &str| {
+        regions
+            .iter()
+            .position(|(other, other_label, _, _)| *other == subsystem && other_label == label)
+    };
+    for (subsystem, label, base, bytes) in &before_regions {
+        match find(&after_regions, *subsystem, label) {
+            Some(idx) => diff_bytes(*subsystem, label, *base, bytes, &after_regions[idx].3, &mut changes),
+            None => push(
+                &mut changes,
+                *subsystem,
+                label,
+                String::from("present"),
+                String::from("missing"),
+            ),
+        }
+    }
+    // Regions only the second snapshot has, e.g. the second VRAM bank when going from DMG to CGB
+    for (subsystem, label, _, _) in &after_regions {
+        if find(&before_regions, *subsystem, label).is_none() {
+            push(
+                &mut changes,
+                *subsystem,
+                label,
+                String::from("missing"),
+                String::from("present"),
+            );
+        }
+    }
+
+    // Stable, so within a subsystem the changes stay in address order
+    changes.sort_by_key(|change| change.subsystem);
+    changes
+}
+
+fn push(changes: &mut Vec<Change>, subsystem: Subsystem, location: &str, before: String, after: String) {
+    if before != after {
+        changes.push(Change {
+            subsystem,
+            location: String::from(location),
+            before,
+            after,
+        });
+    }
+}
+
+fn diff_cpu(before: &GameBoy, after: &GameBoy, changes: &mut Vec<Change>) {
+    for (register, name) in [
+        (Register::AF, "AF"),
+        (Register::BC, "BC"),
+        (Register::DE, "DE"),
+        (Register::HL, "HL"),
+        (Register::SP, "SP"),
+        (Register::PC, "PC"),
+    ] {
+        push(
+            changes,
+            Subsystem::Cpu,
+            name,
+            format!("{:04x}", before.cpu.read_register16(&register)),
+            format!("{:04x}", after.cpu.read_register16(&register)),
+        );
+    }
+    push(
+        changes,
+        Subsystem::Cpu,
+        "IME",
+        before.cpu.interrupt_master_raised().to_string(),
+        after.cpu.interrupt_master_raised().to_string(),
+    );
+    push(
+        changes,
+        Subsystem::Cpu,
+        "Halted",
+        before.cpu.halted.to_string(),
+        after.cpu.halted.to_string(),
+    );
+}
+
+fn diff_io(before: &GameBoy, after: &GameBoy, changes: &mut Vec<Change>) {
+    // DIV only shows the upper byte, the counter below it decides when TIMA counts up next
+    push(
+        changes,
+        Subsystem::Timer,
+        "Counter",
+        format!("{:04x}", before.mmu.timer.counter()),
+        format!("{:04x}", after.mmu.timer.counter()),
+    );
+
+    for (address, doc) in register_docs() {
+        let read = |gb: &GameBoy| match gb.mmu.peek(address) {
+            Ok(value) => format!("{:02x}", value),
+            Err(_) => String::from("--"),
+        };
+        let location = format!("{} ${:04x}", doc.name, address);
+        push(changes, io_subsystem(address), &location, read(before), read(after));
+    }
+
+    push(
+        changes,
+        Subsystem::Ppu,
+        "Mode",
+        format!("{:?}", before.ppu_mode()),
+        format!("{:?}", after.ppu_mode()),
+    );
+    push(
+        changes,
+        Subsystem::Cgb,
+        "Model",
+        format!("{:?}", before.mode),
+        format!("{:?}", after.mode),
+    );
+    push(
+        changes,
+        Subsystem::Cgb,
+        "Double speed",
+        before.mmu.cgb_double_speed.to_string(),
+        after.mmu.cgb_double_speed.to_string(),
+    );
+}
+
+fn io_subsystem(address: u16) -> Subsystem {
+    match address {
+        0xff00 => Subsystem::Joypad,
+        0xff01..=0xff02 => Subsystem::Serial,
+        0xff04..=0xff07 => Subsystem::Timer,
+        0xff0f | 0xffff => Subsystem::Interrupts,
+        0xff10..=0xff3f | 0xff76..=0xff77 => Subsystem::Apu,
+        0xff40..=0xff4b => Subsystem::Ppu,
+        _ => Subsystem::Cgb,
+    }
+}
+
+// Color RAM isn't mapped anywhere, so it's compared color by color
+fn diff_palettes(before: &GameBoy, after: &GameBoy, changes: &mut Vec<Change>) {
+    if before.mode != Mode::Cgb && after.mode != Mode::Cgb {
+        return;
+    }
+
+    for slot in 0..8 {
+        for index in 0..4 {
+            push(
+                changes,
+                Subsystem::Cgb,
+                &format!("BG palette {} color {}", slot, index),
+                format!("{:04x}", before.mmu.cgb_cram.fetch_bg(slot, index)),
+                format!("{:04x}", after.mmu.cgb_cram.fetch_bg(slot, index)),
+            );
+        }
+    }
+    for slot in 0..8 {
+        for index in 0..4 {
+            push(
+                changes,
+                Subsystem::Cgb,
+                &format!("OBJ palette {} color {}", slot, index),
+                format!("{:04x}", before.mmu.cgb_cram.fetch_obj(slot, index)),
+                format!("{:04x}", after.mmu.cgb_cram.fetch_obj(slot, index)),
+            );
+        }
+    }
+}
+
+fn diff_cartridge(before: &GameBoy, after: &GameBoy, changes: &mut Vec<Change>) {
+    let (before, after) = (&before.mmu.cartridge, &after.mmu.cartridge);
+    push(changes, Subsystem::Cartridge, "Mapper", before.name(), after.name());
+    push(
+        changes,
+        Subsystem::Cartridge,
+        "ROM bank",
+        before.current_rom_bank().to_string(),
+        after.current_rom_bank().to_string(),
+    );
+    push(
+        changes,
+        Subsystem::Cartridge,
+        "RAM bank",
+        before.current_ram_bank().to_string(),
+        after.current_ram_bank().to_string(),
+    );
+    push(
+        changes,
+        Subsystem::Cartridge,
+        "RTC",
+        format!("{:?}", before.rtc()),
+        format!("{:?}", after.rtc()),
+    );
+}
+
+// Label, address of the first byte and contents of every memory the snapshot has, banked ones bank by bank
+fn memory_regions(gb: &GameBoy) -> Vec<(Subsystem, String, u16, Vec<u8>)> {
+    let wram = gb.mmu.dump_wram();
+    let mut regions = vec![(
+        Subsystem::Wram,
+        String::from("Bank 0"),
+        WRAM_BANK0_START,
+        wram[..0x1000].to_vec(),
+    )];
+    match gb.mode {
+        Mode::Dmg => regions.push((
+            Subsystem::Wram,
+            String::from("Bank 1"),
+            WRAM_BANK1_START,
+            wram[0x1000..].to_vec(),
+        )),
+        Mode::Cgb => {
+            for bank in 1..=7 {
+                regions.push((
+                    Subsystem::Wram,
+                    format!("Bank {}", bank),
+                    WRAM_BANK1_START,
+                    gb.mmu.cgb_wram_bank(bank).to_vec(),
+                ));
+            }
+        }
+    }
+
+    let vram_banks = if gb.mode == Mode::Cgb { 2 } else { 1 };
+    for bank in 0..vram_banks {
+        regions.push((
+            Subsystem::Vram,
+            format!("Bank {}", bank),
+            VRAM_START,
+            gb.mmu.vram(bank).to_vec(),
+        ));
+    }
+
+    let peek_range =
+        |start: u16, end: u16| -> Vec<u8> { (start..=end).map(|addr| gb.mmu.peek(addr).unwrap_or(0xff)).collect() };
+    regions.push((Subsystem::Oam, String::from("OAM"), OAM_START, gb.mmu.oam().to_vec()));
+    regions.push((
+        Subsystem::Hram,
+        String::from("HRAM"),
+        HRAM_START,
+        peek_range(HRAM_START, HRAM_END),
+    ));
+    regions.push((
+        Subsystem::Apu,
+        String::from("Wave RAM"),
+        WAVE_RAM_START,
+        peek_range(WAVE_RAM_START, WAVE_RAM_END),
+    ));
+    // All banks back to back, addressed by offset into the save file
+    regions.push((
+        Subsystem::Cartridge,
+        String::from("RAM"),
+        0,
+        gb.mmu.cartridge.dump_ram(),
+    ));
+
+    regions
+}
+
+// Lists changed bytes as ranges, e.g. "Bank 0 $c010-$c013: 00 00 00 00 -> 01 02 03 04"
+fn diff_bytes(subsystem: Subsystem, label: &str, base: u16, before: &[u8], after: &[u8], changes: &mut Vec<Change>) {
+    if before.len() != after.len() {
+        push(
+            changes,
+            subsystem,
+            &format!("{} size", label),
+            format!("{} bytes", before.len()),
+            format!("{} bytes", after.len()),
+        );
+    }
+
+    let mut differing = (0..before.len().min(after.len())).filter(|&idx| before[idx] != after[idx]);
+    let Some(mut start) = differing.next() else {
+        return;
+    };
+    let mut end = start;
+    let mut ranges = Vec::new();
+    for idx in differing {
+        if idx - end > MERGE_GAP {
+            ranges.push((start, end));
+            start = idx;
+        }
+        end = idx;
+    }
+    ranges.push((start, end));
+
+    for (start, end) in ranges {
+        let location = if start == end {
+            format!("{} ${:04x}", label, base as usize + start)
+        } else {
+            format!("{} ${:04x}-${:04x}", label, base as usize + start, base as usize + end)
+        };
+        // Pushed as is, the shortened bytes can read the same even though the range differs
+        changes.push(Change {
+            subsystem,
+            location,
+            before: hex_bytes(&before[start..=end]),
+            after: hex_bytes(&after[start..=end]),
+        });
+    }
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    let shown = bytes
+        .iter()
+        .take(SHOWN_BYTES)
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if bytes.len() > SHOWN_BYTES {
+        format!("{} .. ({} bytes)", shown, bytes.len())
+    } else {
+        shown
+    }
+}
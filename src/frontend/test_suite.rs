@@ -0,0 +1,309 @@
+use crate::frontend::screenshot::{frame_hash, save_png};
+use crate::gameboy::GameBoy;
+use crate::lr35902::sm83::Register;
+use crate::serial::SerialDevice;
+use crate::system::System;
+use std::any::Any;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+// Mooneye tests load the Fibonacci numbers into B-L on success and 0x42 on failure
+const MOONEYE_PASS: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAIL: [u8; 6] = [0x42; 6];
+// Blargg tests that can't print write their result to cartridge RAM behind this signature
+const BLARGG_STATUS: u16 = 0xa000;
+const BLARGG_SIGNATURE: [u8; 3] = [0xde, 0xb0, 0x61];
+const BLARGG_RUNNING: u8 = 0x80;
+// Screenshots to compare against live in this directory, named after the ROM
+const GOLDEN_DIRECTORY: &str = "golden";
+
+// How a test ROM reports its result
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Suite {
+    // Prints "Passed"/"Failed" over the link port, or leaves a status in cartridge RAM
+    Blargg,
+    // Fibonacci numbers (or 0x42) in the registers
+    Mooneye,
+    // Draws a picture that has to match a known good screenshot, e.g. acid2
+    Screen,
+}
+
+impl Suite {
+    // Emulated time the slowest tests of the suite need
+    fn timeout(&self) -> Duration {
+        match self {
+            Suite::Blargg => Duration::from_secs(120),
+            Suite::Mooneye => Duration::from_secs(10),
+            Suite::Screen => Duration::from_secs(5),
+        }
+    }
+}
+
+impl fmt::Display for Suite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Suite::Blargg => write!(f, "blargg"),
+            Suite::Mooneye => write!(f, "mooneye"),
+            Suite::Screen => write!(f, "screen"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+    TimedOut,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Outcome::Passed => write!(f, "pass"),
+            Outcome::Failed(reason) if reason.is_empty() => write!(f, "FAIL"),
+            Outcome::Failed(reason) => write!(f, "FAIL ({})", reason),
+            Outcome::TimedOut => write!(f, "TIMEOUT"),
+        }
+    }
+}
+
+pub struct TestResult {
+    pub suite: Suite,
+    // Path relative to the directory the tests were found in
+    pub name: String,
+    pub outcome: Outcome,
+    pub emulated: Duration,
+}
+
+// Runs every test ROM below the directory headless, in a stable order
+pub fn run_tests(dir: &str) -> Result<Vec<TestResult>, String> {
+    let root = Path::new(dir);
+    let mut roms = Vec::new();
+    find_roms(root, &mut roms).map_err(|e| format!("Failed to read {}: {}", dir, e))?;
+    roms.sort();
+
+    Ok(roms.iter().map(|rom| run_test(root, rom)).collect())
+}
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            if path.file_name().is_some_and(|name| name != GOLDEN_DIRECTORY) {
+                find_roms(&path, roms)?;
+            }
+        } else if path
+            .extension()
+            .is_some_and(|extension| extension == "gb" || extension == "gbc")
+        {
+            roms.push(path);
+        }
+    }
+    Ok(())
+}
+
+pub fn run_test(root: &Path, rom: &Path) -> TestResult {
+    let name = rom
+        .strip_prefix(root)
+        .unwrap_or(rom)
+        .to_string_lossy()
+        .replace('\\', "/");
+    let golden = root
+        .join(GOLDEN_DIRECTORY)
+        .join(rom.with_extension("png").file_name().unwrap_or_default());
+    let suite = if golden.exists() {
+        Suite::Screen
+    } else if name.contains("mooneye") {
+        Suite::Mooneye
+    } else {
+        Suite::Blargg
+    };
+
+    let gb = std::fs::read(rom)
+        .map_err(|e| e.to_string())
+        .and_then(|cartridge| GameBoy::try_new(None, cartridge).map_err(|e| e.to_string()));
+    let mut gb = match gb {
+        Ok(gb) => gb,
+        Err(e) => {
+            return TestResult {
+                suite,
+                name,
+                outcome: Outcome::Failed(e),
+                emulated: Duration::ZERO,
+            }
+        }
+    };
+    gb.mmu.serial.connect(Some(Box::new(SerialLog::default())));
+
+    let outcome = match suite {
+        Suite::Blargg => run_until(&mut gb, suite.timeout(), blargg_outcome),
+        Suite::Mooneye => run_until(&mut gb, suite.timeout(), mooneye_outcome),
+        Suite::Screen => {
+            // The picture is only final once the test is done drawing, so always run the full time
+            run_until(&mut gb, suite.timeout(), |_| None);
+            screen_outcome(&gb, &golden)
+        }
+    };
+
+    TestResult {
+        suite,
+        name,
+        outcome,
+        emulated: gb.emulated_time(),
+    }
+}
+
+fn run_until(gb: &mut GameBoy, timeout: Duration, outcome: impl Fn(&mut GameBoy) -> Option<Outcome>) -> Outcome {
+    // Frames are cut short while the LCD is off, so go by emulated time instead
+    while gb.emulated_time() < timeout {
+        gb.run_frame();
+        if let Some(outcome) = outcome(gb) {
+            return outcome;
+        }
+    }
+    Outcome::TimedOut
+}
+
+fn blargg_outcome(gb: &mut GameBoy) -> Option<Outcome> {
+    let output = gb
+        .mmu
+        .serial
+        .device_mut()
+        .and_then(|device| device.as_any_mut().downcast_mut::<SerialLog>())
+        .map(|log| String::from_utf8_lossy(&log.output).into_owned())
+        .unwrap_or_default();
+    if output.contains("Passed") {
+        return Some(Outcome::Passed);
+    }
+    if output.contains("Failed") {
+        // The line naming the failed test is the most useful part
+        let reason = output
+            .lines()
+            .map(str::trim)
+            .find(|line| !line.is_empty())
+            .unwrap_or_default();
+        return Some(Outcome::Failed(reason.to_owned()));
+    }
+
+    let signature = [1, 2, 3].map(|offset| gb.mmu.peek(BLARGG_STATUS + offset).unwrap_or(0));
+    match gb.mmu.peek(BLARGG_STATUS) {
+        Ok(_) if signature != BLARGG_SIGNATURE => None,
+        Ok(0) => Some(Outcome::Passed),
+        Ok(BLARGG_RUNNING) | Err(_) => None,
+        Ok(code) => Some(Outcome::Failed(format!("result code {}", code))),
+    }
+}
+
+fn mooneye_outcome(gb: &mut GameBoy) -> Option<Outcome> {
+    let registers = [
+        Register::B,
+        Register::C,
+        Register::D,
+        Register::E,
+        Register::H,
+        Register::L,
+    ];
+    match registers.each_ref().map(|register| gb.cpu.read_register(register)) {
+        MOONEYE_PASS => Some(Outcome::Passed),
+        MOONEYE_FAIL => Some(Outcome::Failed(String::new())),
+        _ => None,
+    }
+}
+
+fn screen_outcome(gb: &GameBoy, golden: &Path) -> Outcome {
+    let pixels = gb.framebuffer();
+    let (width, _) = gb.screen_size();
+
+    // AYYBOY_BLESS=1 updates the golden screenshots instead of comparing against them
+    if std::env::var("AYYBOY_BLESS").is_ok() {
+        return match save_png(golden, width, &pixels) {
+            Ok(()) => Outcome::Passed,
+            Err(e) => Outcome::Failed(format!("can't write {}: {}", golden.display(), e)),
+        };
+    }
+
+    let expected = match read_png(golden) {
+        Ok(expected) => expected,
+        Err(e) => return Outcome::Failed(format!("can't read {}: {}", golden.display(), e)),
+    };
+    let frame = pixels.as_flattened();
+    if frame == expected {
+        return Outcome::Passed;
+    }
+
+    // The hash identifies the wrong picture between runs, the picture itself is kept to diff against the golden one
+    let actual = std::env::temp_dir().join(golden.file_name().unwrap_or_default());
+    match save_png(&actual, width, &pixels) {
+        Ok(()) => Outcome::Failed(format!(
+            "screen {}, written to {}",
+            screen_hash(frame),
+            actual.display()
+        )),
+        Err(_) => Outcome::Failed(format!("screen {}", screen_hash(frame))),
+    }
+}
+
+fn read_png(path: &Path) -> Result<Vec<u8>, png::DecodingError> {
+    let decoder = png::Decoder::new(std::fs::File::open(path)?);
+    let mut reader = decoder.read_info()?;
+    let mut frame = vec![0; reader.output_buffer_size()];
+    reader.next_frame(&mut frame)?;
+    Ok(frame)
+}
+
+// Short SHA-1 of the RGB pixels
+pub fn screen_hash(frame: &[u8]) -> String {
+    frame_hash(frame)[..8].to_owned()
+}
+
+// Prints the results as a table followed by the pass rate of every suite
+pub fn print_summary(results: &[TestResult]) {
+    let width = results.iter().map(|result| result.name.len()).max().unwrap_or(0).max(4);
+    println!("{:<8} {:<width$} {:>8}  Result", "Suite", "Test", "Time");
+    for result in results {
+        println!(
+            "{:<8} {:<width$} {:>7.1}s  {}",
+            result.suite.to_string(),
+            result.name,
+            result.emulated.as_secs_f64(),
+            result.outcome
+        );
+    }
+
+    println!();
+    for suite in [Suite::Blargg, Suite::Mooneye, Suite::Screen] {
+        let ran = results.iter().filter(|result| result.suite == suite);
+        let total = ran.clone().count();
+        if total > 0 {
+            let passed = ran.filter(|result| result.outcome == Outcome::Passed).count();
+            println!("{:<8} {}/{} passed", suite.to_string(), passed, total);
+        }
+    }
+    let passed = results
+        .iter()
+        .filter(|result| result.outcome == Outcome::Passed)
+        .count();
+    println!("{:<8} {}/{} passed", "total", passed, results.len());
+}
+
+// Keeps everything the test sends over the link port, answering like an unplugged cable
+#[derive(Clone, Default)]
+struct SerialLog {
+    output: Vec<u8>,
+}
+
+impl SerialDevice for SerialLog {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.output.push(data);
+        0xff
+    }
+
+    fn name(&self) -> String {
+        "Serial log".to_string()
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
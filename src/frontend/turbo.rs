@@ -0,0 +1,80 @@
+use crate::system::Button;
+use std::time::Duration;
+
+pub const MIN_TURBO_RATE: u32 = 1;
+pub const MAX_TURBO_RATE: u32 = 30;
+
+// Auto-fire: buttons with turbo enabled alternate between pressed and released while held. It works on
+// the input of each emulated frame, before it goes to the console or a netplay session, so every frame
+// gets the same input no matter how fast the host runs and both netplay peers see the same presses.
+pub struct Turbo {
+    // Bitmask of Button::mask
+    buttons: u8,
+    rate: u32,
+    // Frames a press (and a release) lasts
+    frames_per_toggle: u32,
+    // Frames each button has been held for, so that every press starts out pressed
+    held: [u32; 8],
+    // Buttons held but let go of by turbo for the current frame
+    released: u8,
+}
+
+impl Turbo {
+    // Rate is in presses per second, frame_duration as the system reports it
+    pub fn new(buttons: u8, rate: u32, frame_duration: Duration) -> Turbo {
+        let mut turbo = Turbo {
+            buttons,
+            rate: 0,
+            frames_per_toggle: 1,
+            held: [0; 8],
+            released: 0,
+        };
+        turbo.set_rate(rate, frame_duration);
+        turbo
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    pub fn set_button(&mut self, button: Button, enabled: bool) {
+        match enabled {
+            true => self.buttons |= button.mask(),
+            false => self.buttons &= !button.mask(),
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    // Rounded to whole frames, the fastest is pressed one frame and released the next
+    pub fn set_rate(&mut self, rate: u32, frame_duration: Duration) {
+        self.rate = rate.clamp(MIN_TURBO_RATE, MAX_TURBO_RATE);
+        let frames_per_press = 1.0 / (frame_duration.as_secs_f64() * self.rate as f64);
+        self.frames_per_toggle = ((frames_per_press / 2.0).round() as u32).max(1);
+    }
+
+    // Input to emulate the next frame with, called exactly once per frame
+    pub fn apply(&mut self, input: u8) -> u8 {
+        let mut output = input;
+        for (held, button) in self.held.iter_mut().zip(Button::ALL) {
+            if input & button.mask() == 0 {
+                *held = 0;
+                continue;
+            }
+
+            if self.buttons & button.mask() != 0 && (*held / self.frames_per_toggle) % 2 == 1 {
+                output &= !button.mask();
+            }
+            *held = held.wrapping_add(1);
+        }
+        self.released = input & !output;
+        output
+    }
+
+    // Input changing partway into the frame apply was called for, buttons turbo let go of stay released
+    pub fn filter(&self, input: u8) -> u8 {
+        input & !self.released
+    }
+}
@@ -0,0 +1,75 @@
+use crate::frontend::screenshot::save_png;
+use crate::gameboy::{GameBoy, Mode};
+use crate::video::palette::{Color, Palette};
+use crate::video::sprite::{Sprite, SpriteAttributes};
+use crate::video::tile::{Tile, TileAttributes};
+use std::path::Path;
+
+// $8000-$97ff, the 384 tiles of a bank
+const TILE_DATA_SIZE: usize = 0x1800;
+// YY-CHR and most other tile editors read 256 RGB colors from a .pal, the ones the game doesn't have stay black
+const PAL_COLORS: usize = 256;
+
+// The tiles of a VRAM bank as they are stored, 16 bytes of 2bpp planar rows each. Tile editors call the
+// format "2BPP GB" and show it in the same order as the tileset panels do.
+pub fn vram_tiles(gb: &GameBoy, bank: u8) -> Vec<u8> {
+    gb.mmu.vram(bank)[..TILE_DATA_SIZE].to_vec()
+}
+
+// Puts the tiles next to each other, a given number per row, like the tileset and tilemap panels show them
+pub fn layout_tiles(tiles: &[Tile], per_row: usize) -> Vec<Color> {
+    let width = per_row * 8;
+    let mut pixels = vec![[0; 3]; width * tiles.len().div_ceil(per_row) * 8];
+
+    for (idx, tile) in tiles.iter().enumerate() {
+        for y in 0..8 {
+            for x in 0..8 {
+                let tile_x = (idx % per_row) * 8 + x;
+                let tile_y = (idx / per_row) * 8 + y;
+                pixels[tile_y * width + tile_x] = tile.pixel(x, y).into();
+            }
+        }
+    }
+
+    pixels
+}
+
+pub fn save_tiles_png(tiles: &[Tile], per_row: usize, path: &Path) -> std::io::Result<()> {
+    save_png(path, per_row * 8, &layout_tiles(tiles, per_row))
+}
+
+// The colors the game has set up, as the PPU draws them. On a CGB the 8 background palettes followed by
+// the 8 object ones, on a DMG BGP, OBP0 and OBP1.
+pub fn palettes(gb: &GameBoy) -> Vec<[Color; 4]> {
+    let (background, object) = match gb.mode {
+        Mode::Dmg => (1, 2),
+        Mode::Cgb => (8, 8),
+    };
+
+    let background = (0..background).map(|slot| {
+        let attributes = TileAttributes::from_bits_truncate(slot);
+        [0, 1, 2, 3].map(|value| Palette::from_background(value, &gb.mmu, &gb.mode, &attributes).into())
+    });
+    let object = (0..object).map(|slot| {
+        let attributes = match gb.mode {
+            Mode::Dmg => SpriteAttributes::from_bits_truncate(slot << 4),
+            Mode::Cgb => SpriteAttributes::from_bits_truncate(slot),
+        };
+        let sprite = Sprite {
+            x: 0,
+            y: 0,
+            tile_index: 0,
+            attributes,
+        };
+        [0, 1, 2, 3].map(|value| Palette::from_object(value, &gb.mmu, &sprite, false, &gb.mode).into())
+    });
+
+    background.chain(object).collect()
+}
+
+// The palettes as a .pal file, one RGB triplet per color
+pub fn to_pal(palettes: &[[Color; 4]]) -> Vec<u8> {
+    let mut pal = palettes.as_flattened().as_flattened().to_vec();
+    pal.resize(PAL_COLORS * 3, 0);
+    pal
+}
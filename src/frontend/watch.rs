@@ -0,0 +1,133 @@
+use crate::memory::mmu::Mmu;
+use log::warn;
+
+// How the bytes at a watched address are interpreted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchType {
+    U8,
+    U16,
+    I8,
+    // Two decimal digits per byte, as many games store scores and counters
+    Bcd,
+}
+
+impl WatchType {
+    pub const ALL: [WatchType; 4] = [WatchType::U8, WatchType::U16, WatchType::I8, WatchType::Bcd];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WatchType::U8 => "u8",
+            WatchType::U16 => "u16",
+            WatchType::I8 => "i8",
+            WatchType::Bcd => "bcd",
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            WatchType::U16 => 2,
+            _ => 1,
+        }
+    }
+
+    fn from_name(name: &str) -> Option<WatchType> {
+        WatchType::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watch {
+    pub address: u16,
+    pub kind: WatchType,
+    // Bytes written back every frame while the value is frozen
+    pub frozen: Option<Vec<u8>>,
+}
+
+impl Watch {
+    pub fn new(address: u16, kind: WatchType) -> Watch {
+        Watch {
+            address,
+            kind,
+            frozen: None,
+        }
+    }
+
+    pub fn read(&self, mmu: &Mmu) -> Vec<u8> {
+        (0..self.kind.size())
+            .map(|offset| mmu.read_unchecked(self.address.wrapping_add(offset as u16)))
+            .collect()
+    }
+
+    pub fn format(&self, bytes: &[u8]) -> String {
+        match self.kind {
+            WatchType::U8 => format!("{} (${:02x})", bytes[0], bytes[0]),
+            WatchType::U16 => {
+                let value = u16::from_le_bytes([bytes[0], bytes[1]]);
+                format!("{} (${:04x})", value, value)
+            }
+            WatchType::I8 => format!("{}", bytes[0] as i8),
+            WatchType::Bcd => format!("{:02x}", bytes[0]),
+        }
+    }
+
+    pub fn freeze(&mut self, mmu: &Mmu) {
+        self.frozen = Some(self.read(mmu));
+    }
+
+    // Writes the frozen value back, like a cheat would
+    pub fn apply(&self, mmu: &mut Mmu) {
+        if let Some(bytes) = &self.frozen {
+            for (offset, byte) in bytes.iter().enumerate() {
+                let _ = mmu.write(self.address.wrapping_add(offset as u16), *byte);
+            }
+        }
+    }
+}
+
+// Serializes watches one per line: address, type and the frozen bytes if any, e.g. "c0a0 u16 3412"
+pub fn save_watches(watches: &[Watch]) -> String {
+    watches
+        .iter()
+        .map(|watch| {
+            let mut line = format!("{:04x} {}", watch.address, watch.kind.name());
+            if let Some(bytes) = &watch.frozen {
+                line.push(' ');
+                line.extend(bytes.iter().map(|byte| format!("{:02x}", byte)));
+            }
+            line
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+pub fn load_watches(data: &str) -> Vec<Watch> {
+    data.lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| {
+            let watch = parse_watch(line);
+            if watch.is_none() {
+                warn!("Ignoring invalid RAM watch: {}", line);
+            }
+            watch
+        })
+        .collect()
+}
+
+fn parse_watch(line: &str) -> Option<Watch> {
+    let mut parts = line.split_whitespace();
+    let address = u16::from_str_radix(parts.next()?, 16).ok()?;
+    let kind = WatchType::from_name(parts.next()?)?;
+
+    let frozen = match parts.next() {
+        Some(hex) if hex.is_ascii() && hex.len() == kind.size() * 2 => Some(
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+                .collect::<Option<Vec<u8>>>()?,
+        ),
+        Some(_) => return None,
+        None => None,
+    };
+
+    Some(Watch { address, kind, frozen })
+}
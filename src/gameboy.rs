@@ -1,38 +1,286 @@
+use crate::bess;
+use crate::clock::{ClockDivider, ComponentCycles};
 use crate::error::AyyError;
 use crate::lr35902::cpu::Cpu;
+use crate::lr35902::diagnostics::Diagnostics;
+use crate::lr35902::expr::BreakCondition;
 use crate::lr35902::sm83::Register;
-use crate::lr35902::timer::Timer;
+use crate::memory::banked::BankedAddress;
+use crate::memory::io_registers::IoBreakpoint;
 use crate::memory::mapper::mbc1::Mbc1;
 use crate::memory::mapper::mbc3::Mbc3;
 use crate::memory::mapper::mbc5::Mbc5;
 use crate::memory::mapper::rom::Rom;
-use crate::memory::mapper::Mapper;
+use crate::memory::mapper::rtc::RtcMode;
+use crate::memory::mapper::{header_title, Mapper, ROM_BANK_SIZE};
 use crate::memory::mmu::Mmu;
-use crate::video::ppu::Ppu;
+use crate::memory::peek::MemorySnapshot;
+use crate::memory::ram_decay::RamDecay;
+use crate::memory::ram_init::RamInit;
+use crate::memory::registers::{InterruptFlags, LcdControl};
+use crate::memory::{
+    INTERRUPT_ENABLE_REGISTER, INTERRUPT_FLAGS_REGISTER, IO_END, IO_START, JOYPAD_REGISTER, OAM_END, OAM_START,
+    VRAM_END, VRAM_START,
+};
+use crate::scheduler::{Event, Scheduler};
+use crate::serial::printer::{Printer, Printout};
+use crate::sound::{SyncMode, CPU_CLOCK};
+use crate::system::{Button, RomHash, SaveState, System};
+use crate::video::bus::VideoBus;
+use crate::video::compat::CompatPalette;
+use crate::video::palette::Color;
+use crate::video::ppu::{FrameEvents, Ppu};
 use crate::video::state::State;
 use crate::video::tile::Tile;
-use crate::video::SCANLINE_Y_REGISTER;
+use crate::video::{
+    Accuracy, ColorCorrection, Frame, Layers, CYCLES_PER_FRAME, SCANLINE_Y_REGISTER, SCREEN_HEIGHT, SCREEN_WIDTH,
+};
+use clap::ValueEnum;
 use log::{error, info, warn};
+use std::any::Any;
+use std::collections::VecDeque;
+use std::time::Duration;
 
 const BOOTROM_DMG: &[u8] = include_bytes!("../external/roms/boot/bootix_dmg.bin");
 const BOOTROM_CGB: &[u8] = include_bytes!("../external/roms/boot/sameboy_cgb.bin");
 
-#[derive(PartialEq, Clone)]
+// The CPU starts executing the cartridge at $0100, the header follows right after
+const ENTRY_POINT_END: usize = 0x0104;
+const HEADER_END: usize = 0x0150;
+// Two banks, the smallest ROM there is
+const MIN_ROM_SIZE: usize = 0x8000;
+
+type FrameHandler = Box<dyn Fn(&Frame)>;
+
+// Gets every finished frame. Copies of the console (save states, run-ahead) don't inherit it,
+// just like they don't inherit the audio device.
+#[derive(Default)]
+struct FrameCallback(Option<FrameHandler>);
+
+impl Clone for FrameCallback {
+    fn clone(&self) -> FrameCallback {
+        FrameCallback(None)
+    }
+}
+
+type MemoryHandler = Box<dyn Fn(&MemorySnapshot)>;
+
+// Gets a snapshot of the memory after every frame, e.g. for an achievement runtime to evaluate.
+// The snapshot is reused between frames, copies of the console don't inherit either.
+#[derive(Default)]
+struct MemoryCallback(Option<(MemoryHandler, MemorySnapshot)>);
+
+impl Clone for MemoryCallback {
+    fn clone(&self) -> MemoryCallback {
+        MemoryCallback(None)
+    }
+}
+
+// Where the debugger stops the console. Copies (run-ahead, netplay, save states) run through them, the
+// running console hands them over when a state is loaded.
+#[derive(Default)]
+pub struct Breakpoints {
+    pub addresses: Vec<BankedAddress>,
+    pub io: Vec<IoBreakpoint>,
+    // Checked before every instruction, so only while the debugger is attached
+    pub conditions: Vec<BreakCondition>,
+    pub debugger_attached: bool,
+}
+
+impl Breakpoints {
+    fn is_empty(&self) -> bool {
+        self.addresses.is_empty() && self.io.is_empty() && !self.checks_conditions()
+    }
+
+    fn checks_conditions(&self) -> bool {
+        self.debugger_attached && !self.conditions.is_empty()
+    }
+}
+
+impl Clone for Breakpoints {
+    fn clone(&self) -> Breakpoints {
+        Breakpoints::default()
+    }
+}
+
+#[derive(PartialEq, Clone, Debug, ValueEnum)]
 pub enum Mode {
     Dmg,
     Cgb,
 }
 
+#[derive(Clone)]
 pub struct GameBoy {
     pub cpu: Cpu,
     pub mmu: Mmu,
     pub ppu: Ppu,
-    pub timer: Timer,
+    // What the game runs as, a CGB drops to Dmg for DMG games once the boot ROM locked that in KEY0
     pub mode: Mode,
+    // The console itself, what a power cycle boots again
+    hardware: Mode,
+    // Power-on contents of the RAM and the divider, every power cycle starts out like this again
+    ram_init: RamInit,
+    initial_div: u16,
+    clock: ClockDivider,
+    pub breakpoints: Breakpoints,
+    breakpoint_hit: Option<BankedAddress>,
+    io_breakpoint_hit: Option<IoBreakpoint>,
+    condition_hit: Option<String>,
+    resuming: bool,
+    frames: u64,
+    cycles: u64,
+    // CPU cycles spent halted that were skipped over in one go, see idle_cycles
+    idled: u64,
+    scheduler: Scheduler,
+    // Cycles the CPU ran that the other components haven't seen yet
+    pending: ComponentCycles,
+    rom_hash: RomHash,
+    // Button changes queued for later in the frame, at the scheduler cycle they happen at
+    input_queue: VecDeque<(u64, u8)>,
+    // How many of them were left over when run_frame last returned
+    stale_input: usize,
+    frame_callback: FrameCallback,
+    memory_callback: MemoryCallback,
 }
 
 impl GameBoy {
     pub fn new(bootrom: Option<Vec<u8>>, cartridge: Vec<u8>) -> GameBoy {
+        GameBoy::try_new(bootrom, cartridge).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    // Like new, but hands back an error for ROMs that can't be run instead of panicking
+    pub fn try_new(bootrom: Option<Vec<u8>>, cartridge: Vec<u8>) -> Result<GameBoy, AyyError> {
+        GameBoy::try_new_on(bootrom, cartridge, None)
+    }
+
+    // Like try_new, but on the given hardware instead of what the cartridge header asks for. DMG games on a
+    // CGB run in its compatibility mode, which the CGB boot ROM picks by writing KEY0.
+    pub fn try_new_on(
+        bootrom: Option<Vec<u8>>, cartridge: Vec<u8>, hardware: Option<Mode>,
+    ) -> Result<GameBoy, AyyError> {
+        let mode = hardware.unwrap_or_else(|| GameBoy::cartridge_mode(&cartridge));
+        info!("Emulating GameBoy: {}", if mode == Mode::Dmg { "DMG" } else { "CGB" });
+
+        let rom_hash = RomHash::of(&cartridge);
+        let cartridge = GameBoy::create_mapper(cartridge)?;
+
+        let bootrom = bootrom.unwrap_or_else(|| match mode {
+            Mode::Dmg => BOOTROM_DMG.to_vec(),
+            Mode::Cgb => BOOTROM_CGB.to_vec(),
+        });
+
+        let cpu = Cpu::new();
+        let mmu = Mmu::new(bootrom, cartridge, mode.clone());
+        let ppu = Ppu::new(mode.clone());
+
+        Ok(GameBoy {
+            cpu,
+            mmu,
+            ppu,
+            mode: mode.clone(),
+            hardware: mode,
+            ram_init: RamInit::Zero,
+            initial_div: 0,
+            clock: ClockDivider::new(),
+            breakpoints: Breakpoints::default(),
+            breakpoint_hit: None,
+            io_breakpoint_hit: None,
+            condition_hit: None,
+            resuming: false,
+            frames: 0,
+            cycles: 0,
+            idled: 0,
+            scheduler: Scheduler::new(),
+            pending: ComponentCycles::default(),
+            rom_hash,
+            input_queue: VecDeque::new(),
+            stale_input: 0,
+            frame_callback: FrameCallback::default(),
+            memory_callback: MemoryCallback::default(),
+        })
+    }
+
+    // Replaces the cartridge without resetting the console, the CPU simply continues in the new ROM.
+    // The old mapper is handed back so that the caller can flush its RAM/RTC to disk.
+    pub fn hot_swap_cartridge(&mut self, rom: Vec<u8>) -> Result<Box<dyn Mapper>, AyyError> {
+        // The hardware can't change while it is running, CGB games just run in whatever mode we booted in
+        let mode = GameBoy::cartridge_mode(&rom);
+        if mode != self.mode {
+            warn!("Swapped in a cartridge for different hardware, keeping the current mode");
+        }
+
+        let rom_hash = RomHash::of(&rom);
+        let cartridge = GameBoy::create_mapper(rom)?;
+        self.rom_hash = rom_hash;
+        Ok(std::mem::replace(&mut self.mmu.cartridge, cartridge))
+    }
+
+    // How RAM and the divider come up, for this boot and every power cycle after it
+    pub fn set_power_on_state(&mut self, ram_init: RamInit, initial_div: u16) {
+        self.mmu.init_ram(&ram_init);
+        self.mmu.timer.set_counter(initial_div);
+        self.ram_init = ram_init;
+        self.initial_div = initial_div;
+    }
+
+    // Power cycles the console: CPU, PPU, APU and timer start over from the boot ROM, while the cartridge
+    // stays plugged in with its RAM and clock. Settings and attached devices (audio, link cable) are kept.
+    pub fn reset(&mut self) {
+        self.power_cycle(None);
+    }
+
+    // Like reset, but the cartridge RAM decays as given unless it's None, as if there was no battery
+    // keeping it alive. For exploring what games make of corrupted saves.
+    pub fn power_cycle(&mut self, decay: Option<&RamDecay>) {
+        self.mmu.cartridge.power_cycle(decay);
+        let cartridge = std::mem::replace(&mut self.mmu.cartridge, Box::new(Rom::new(vec![0; 0x8000])));
+        let mut mmu = Mmu::new(self.mmu.bootrom().to_vec(), cartridge, self.hardware.clone());
+        mmu.init_ram(&self.ram_init);
+        mmu.timer.set_counter(self.initial_div);
+        mmu.apu.adopt_output(&mut self.mmu.apu);
+        mmu.serial.connect(self.mmu.serial.take_device());
+        mmu.cgb_cram.set_color_correction(self.mmu.cgb_cram.color_correction());
+        mmu.cgb_cram.set_compat_palette(self.mmu.cgb_cram.compat_palette());
+        mmu.uninit.set_enabled(self.mmu.uninit.is_enabled());
+        mmu.io_log = std::mem::take(&mut self.mmu.io_log);
+        mmu.continue_vram_generation(&self.mmu);
+
+        let mut ppu = Ppu::new(self.hardware.clone());
+        ppu.set_rendering(self.ppu.rendering());
+        ppu.set_accuracy(self.ppu.accuracy());
+        ppu.set_layers(self.ppu.layers());
+        ppu.adopt_worker(&mut self.ppu);
+
+        let mut cpu = Cpu::new();
+        cpu.set_overclock(self.cpu.overclock());
+        cpu.set_stack_check(self.cpu.stack_check_enabled());
+        cpu.set_lenient(self.cpu.lenient());
+
+        self.cpu = cpu;
+        self.mmu = mmu;
+        self.ppu = ppu;
+        self.mode = self.hardware.clone();
+        self.clock = ClockDivider::new();
+        self.scheduler = Scheduler::new();
+        self.pending = ComponentCycles::default();
+        self.input_queue.clear();
+        self.stale_input = 0;
+        self.breakpoint_hit = None;
+        self.io_breakpoint_hit = None;
+        self.condition_hit = None;
+        self.resuming = false;
+        match decay {
+            Some(decay) => info!(
+                "Power cycled the console, {}% of the cartridge RAM decayed",
+                decay.percent()
+            ),
+            None => info!("Reset the console"),
+        }
+    }
+
+    fn create_mapper(cartridge: Vec<u8>) -> Result<Box<dyn Mapper>, AyyError> {
+        let cartridge = GameBoy::validate_rom(cartridge)?;
+
         let title = cartridge[0x0134..=0x0142]
             .iter()
             .take_while(|&&c| c != 0)
@@ -40,93 +288,119 @@ impl GameBoy {
             .collect::<String>();
         info!("ROM Title: {}", title);
 
-        let mode = match cartridge[0x0143] {
-            0xc0 => Mode::Cgb,
-            0x80 => Mode::Cgb, // TODO: CGB enhancements, but backwards compatible with DMG
-            _ => Mode::Dmg,
-        };
-        info!("Emulating GameBoy: {}", if mode == Mode::Dmg { "DMG" } else { "CGB" });
-
         let cartridge: Box<dyn Mapper> = match cartridge[0x0147] {
             0x00 => Box::new(Rom::new(cartridge)),
             0x01 | 0x02 | 0x03 => Box::new(Mbc1::new(cartridge)),
-            0x0f | 0x10 | 0x11 | 0x12 | 0x13 => Box::new(Mbc3::new(cartridge)),
+            0x0f | 0x10 => Box::new(Mbc3::with_rtc(cartridge)),
+            0x11 | 0x12 | 0x13 => Box::new(Mbc3::new(cartridge)),
             0x19 | 0x1a | 0x1b => Box::new(Mbc5::new(cartridge)),
             0x1c | 0x1d | 0x1e => Box::new(Mbc5::with_rumble(cartridge)),
-            _ => panic!("Unsupported cartridge type: {:02x}", cartridge[0x0147]),
+            kind => {
+                return Err(AyyError::InvalidCartridge {
+                    reason: format!("Unsupported cartridge type: {:02x}", kind),
+                })
+            }
         };
         info!("Cartridge type: {}", cartridge.name());
 
-        let bootrom = bootrom.unwrap_or_else(|| match mode {
-            Mode::Dmg => BOOTROM_DMG.to_vec(),
-            Mode::Cgb => BOOTROM_CGB.to_vec(),
-        });
+        Ok(cartridge)
+    }
 
-        let cpu = Cpu::new();
-        let mmu = Mmu::new(bootrom, cartridge, mode.clone());
-        let ppu = Ppu::new(mode.clone());
-        let timer = Timer::new();
+    // Makes sure the mappers never index past the end of the ROM. Dumps that are too short get zero-filled
+    // as long as they still look like a ROM, anything else is refused.
+    fn validate_rom(mut rom: Vec<u8>) -> Result<Vec<u8>, AyyError> {
+        let invalid = |reason: String| Err(AyyError::InvalidCartridge { reason });
 
-        GameBoy {
-            cpu,
-            mmu,
-            ppu,
-            timer,
-            mode,
+        if rom.len() < HEADER_END {
+            if rom.len() < ENTRY_POINT_END {
+                return invalid(format!("ROM is too small to contain a header ({} bytes)", rom.len()));
+            }
+
+            // e.g. a small test program, which runs fine as a plain 32KB ROM
+            warn!(
+                "ROM has no header ({} bytes), zero-filling it to {} bytes",
+                rom.len(),
+                MIN_ROM_SIZE
+            );
+            rom.resize(MIN_ROM_SIZE, 0);
+            return Ok(rom);
+        }
+
+        let declared_size = match rom[0x0148] {
+            code @ 0x00..=0x08 => MIN_ROM_SIZE << code,
+            // Listed in some documentation, but no known cartridge uses them
+            0x52 => 72 * ROM_BANK_SIZE,
+            0x53 => 80 * ROM_BANK_SIZE,
+            0x54 => 96 * ROM_BANK_SIZE,
+            code => return invalid(format!("Unknown ROM size in header: {:02x}", code)),
+        };
+
+        // How much ROM each mapper can bank in
+        let mapper_size = match rom[0x0147] {
+            0x00 => MIN_ROM_SIZE,
+            0x01..=0x03 => 0x200000,
+            0x0f..=0x13 => 0x400000,
+            0x19..=0x1e => 0x800000,
+            _ => declared_size,
+        };
+        if declared_size > mapper_size {
+            return invalid(format!(
+                "Header declares {}KB of ROM, but cartridge type {:02x} can only map {}KB",
+                declared_size / 1024,
+                rom[0x0147],
+                mapper_size / 1024
+            ));
+        }
+
+        if rom.len() < declared_size {
+            // Only trust the size if the header itself is intact
+            if !GameBoy::header_checksum_valid(&rom) {
+                return invalid(format!(
+                    "ROM is truncated ({} of {} bytes) and its header checksum doesn't match",
+                    rom.len(),
+                    declared_size
+                ));
+            }
+
+            warn!(
+                "ROM is truncated ({} of {} bytes), zero-filling the rest",
+                rom.len(),
+                declared_size
+            );
+            rom.resize(declared_size, 0);
+        }
+
+        Ok(rom)
+    }
+
+    fn header_checksum_valid(rom: &[u8]) -> bool {
+        let checksum = rom[0x0134..=0x014c]
+            .iter()
+            .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1));
+        checksum == rom[0x014d]
+    }
+
+    fn cartridge_mode(cartridge: &[u8]) -> Mode {
+        match cartridge.get(0x0143) {
+            Some(0xc0) => Mode::Cgb,
+            Some(0x80) => Mode::Cgb, // TODO: CGB enhancements, but backwards compatible with DMG
+            _ => Mode::Dmg,
         }
     }
 
     pub fn run_frame(&mut self) {
+        self.drop_stale_input();
         loop {
-            let mut did_hdma_transfer_already = false;
+            let mut events = FrameEvents::default();
+            // The scanline the PPU just did (or a loaded state) moves everything around
+            self.schedule_events();
 
             loop {
-                let cycles = match self.cpu.tick(&mut self.mmu, &mut self.timer) {
-                    Ok(cycles) => cycles,
-                    Err(AyyError::WriteToReadOnlyMemory { address, data }) => {
-                        warn!(
-                            "PC @ {:04x} => Attempted to write {:02x} to unmapped read-only memory at {:04x}",
-                            self.cpu.read_register16(&Register::PC),
-                            data,
-                            address
-                        );
-                        0
-                    }
-                    Err(AyyError::OutOfBoundsMemoryAccess { address }) => {
-                        warn!(
-                            "PC @ {:04x} => Attempted to read out-of-bounds memory at {:04x}",
-                            self.cpu.read_register16(&Register::PC),
-                            address
-                        );
-                        0
-                    }
-                    Err(AyyError::WriteToDisabledExternalRam { address, data }) => {
-                        error!(
-                            "PC @ {:04x} => Attempted to write {:02x} to disabled external RAM at {:04x}",
-                            self.cpu.read_register16(&Register::PC),
-                            data,
-                            address
-                        );
-                        0
-                    }
-                    Err(e) => panic!("{}", e),
-                };
-
-                // Taken from a smarter person: https://github.com/NightShade256/Argentum/blob/1be04a77c4a13f5134952f78cf4c3c5b355fe12d/crates/argentum/src/bus.rs#L274
-                let effective_cycles = match self.mmu.cgb_double_speed {
-                    true => cycles >> 1,
-                    false => cycles,
-                };
-
-                self.mmu.apu.tick(effective_cycles);
-                self.timer.tick(&mut self.mmu, cycles);
-                self.ppu.tick_state(&mut self.mmu, effective_cycles);
-                self.mmu.cache_ppu_state(self.ppu.state);
-                if self.ppu.state == State::HBlank && !did_hdma_transfer_already {
-                    self.mmu.tick_hdma();
-                    did_hdma_transfer_already = true;
-                } else if self.ppu.state != State::HBlank && did_hdma_transfer_already {
-                    did_hdma_transfer_already = false;
+                if self.at_breakpoint() {
+                    // The debugger looks at the registers, which have to be up to date
+                    self.sync();
+                    self.stale_input = self.input_queue.len();
+                    return;
                 }
 
                 let cycles_per_scanline = match self.mmu.cgb_double_speed {
@@ -134,8 +408,39 @@ impl GameBoy {
                     false => 456,
                 };
 
+                // The other components only have to be caught up before the CPU looks at them,
+                // and once more afterwards as it might have changed when their next event is
+                let touches_components = self.touches_components();
+                if touches_components {
+                    events += self.sync();
+                }
+
+                let cycles = match self.idle_cycles(cycles_per_scanline) {
+                    Some(cycles) => {
+                        self.idled += cycles as u64;
+                        self.cpu.idle(cycles)
+                    }
+                    None => match self.cpu.tick(&mut self.mmu) {
+                        Ok(cycles) => cycles,
+                        Err(AyyError::Diagnosed { error, diagnostics }) => GameBoy::recover(*error, &diagnostics),
+                        Err(e) => panic!("{}", e),
+                    },
+                };
+
+                let clocks = self.clock.split(cycles, self.mmu.cgb_double_speed);
+                self.cycles += clocks.ppu as u64;
+                self.mmu.cartridge.tick(clocks.cartridge);
+                self.pending += clocks;
+                self.scheduler.advance(cycles);
+
+                // ... or when something they do has to happen right now
+                if touches_components || self.scheduler.due() {
+                    events += self.sync();
+                }
+
                 if self.cpu.elapsed_cycles() >= cycles_per_scanline {
                     self.cpu.reset_cycles(self.cpu.elapsed_cycles() - cycles_per_scanline);
+                    events += self.sync();
                     break;
                 }
             }
@@ -144,25 +449,527 @@ impl GameBoy {
             // This mode takes up the remainder of the scanline after the Drawing Mode finishes,
             // more or less “padding” the duration of the scanline to a total of 456 T-Cycles.
             // The PPU effectively pauses during this mode.
-            self.ppu.tick(&mut self.mmu); // "does a scanline"
+            events += self.ppu.tick(&mut self.mmu); // "does a scanline"
 
             // Do we have a frame to render?
-            if self.mmu.read_unchecked(SCANLINE_Y_REGISTER) == 0 {
+            if events.frame_complete {
                 self.ppu.reset_state();
+                self.mmu.heatmap.end_frame();
+                self.mmu.io_log.end_frame();
+                self.frames += 1;
+                if let Some(callback) = self.frame_callback.0.as_ref().filter(|_| self.ppu.rendering()) {
+                    callback(&self.ppu.pull_frame());
+                }
+                // Skipped frames count as well, the game ran through them all the same
+                if let Some((callback, snapshot)) = self.memory_callback.0.as_mut() {
+                    snapshot.update(&self.mmu);
+                    callback(snapshot);
+                }
+                self.stale_input = self.input_queue.len();
                 break;
             }
         }
     }
 
+    // Hands the components the cycles the CPU ran since they were last caught up, and schedules
+    // whatever they do next
+    fn sync(&mut self) -> FrameEvents {
+        let clocks = std::mem::take(&mut self.pending);
+
+        self.mmu.apu.tick(clocks.timer, self.mmu.cgb_double_speed);
+        self.mmu.tick_timer(clocks.timer);
+        if self.mmu.serial.tick(clocks.timer, self.mmu.cgb_double_speed) {
+            let interrupt_flags = self.mmu.read_as_unchecked::<InterruptFlags>(INTERRUPT_FLAGS_REGISTER);
+            self.mmu.write_unchecked(
+                INTERRUPT_FLAGS_REGISTER,
+                (interrupt_flags | InterruptFlags::SERIAL).bits(),
+            );
+        }
+        self.apply_queued_input();
+        let events = self.ppu.tick_state(&mut self.mmu, clocks.ppu);
+        self.mmu.cache_ppu_state(self.ppu.state);
+        // HBlank DMA copies a block every time mode 0 starts
+        if events.hblank_count > 0 {
+            self.mmu.tick_hdma();
+        }
+
+        if self.mmu.mode() != &self.mode {
+            self.enter_compat_mode();
+        }
+
+        self.schedule_events();
+        events
+    }
+
+    // The boot ROM unmapped itself with KEY0 asking for DMG compatibility, the MMU already switched over
+    fn enter_compat_mode(&mut self) {
+        info!("CGB locked into DMG compatibility mode");
+        self.mode = self.mmu.mode().clone();
+        self.ppu.set_mode(self.mode.clone());
+    }
+
+    // Events are in CPU cycles, the PPU and APU only see every other one in double speed
+    fn schedule_events(&mut self) {
+        let component_cycles = match self.mmu.cgb_double_speed {
+            true => 2,
+            false => 1,
+        };
+        let lcd_enabled = self.mmu.lcd_control().contains(LcdControl::LCD_DISPLAY);
+
+        self.scheduler.schedule(
+            Event::PpuState,
+            lcd_enabled.then(|| self.ppu.cycles_until_next_state() * component_cycles),
+        );
+        self.scheduler
+            .schedule(Event::Timer, self.mmu.timer.cycles_until_tick());
+        self.scheduler.schedule(
+            Event::Serial,
+            self.mmu.serial.cycles_until_bit(self.mmu.cgb_double_speed),
+        );
+        self.scheduler.schedule(
+            Event::FrameSequencer,
+            Some(self.mmu.apu.cycles_until_frame_sequencer() * component_cycles),
+        );
+        let now = self.scheduler.now();
+        self.scheduler.schedule(
+            Event::Input,
+            self.input_queue.front().map(|(at, _)| at.saturating_sub(now) as usize),
+        );
+    }
+
+    // Queues the buttons (a bitmask of Button::mask) to change partway into the frame about to run, with
+    // 0.0 being its start and 1.0 its end. The game sees them at that exact point, so presses shorter
+    // than a frame aren't lost and games polling several times a frame get them in order.
+    pub fn queue_buttons(&mut self, frame_offset: f64, input: u8) {
+        self.drop_stale_input();
+        let frame = match self.mmu.cgb_double_speed {
+            true => CYCLES_PER_FRAME * 2,
+            false => CYCLES_PER_FRAME,
+        };
+        let at = self.scheduler.now() + (frame_offset.clamp(0.0, 1.0) * frame as f64) as u64;
+        // Changes can't overtake the ones queued before them
+        let at = self.input_queue.back().map_or(at, |(last, _)| at.max(*last));
+        self.input_queue.push_back((at, input));
+        self.schedule_events();
+    }
+
+    // Changes the last run_frame didn't get to, e.g. because of a breakpoint, are out of date. The buttons
+    // were set again since, and they would override them.
+    fn drop_stale_input(&mut self) {
+        let stale = std::mem::take(&mut self.stale_input);
+        self.input_queue.drain(..stale);
+    }
+
+    fn apply_queued_input(&mut self) {
+        let now = self.scheduler.now();
+        while let Some(&(at, input)) = self.input_queue.front() {
+            if at > now {
+                break;
+            }
+            self.input_queue.pop_front();
+
+            let before = self.mmu.peek(JOYPAD_REGISTER).unwrap_or(0xff);
+            System::update_buttons(self, input);
+            let after = self.mmu.peek(JOYPAD_REGISTER).unwrap_or(0xff);
+
+            // A selected line going low requests the joypad interrupt, e.g. to wake up a halted CPU
+            if before & !after & 0x0f != 0 {
+                let interrupt_flags = self.mmu.read_as_unchecked::<InterruptFlags>(INTERRUPT_FLAGS_REGISTER);
+                self.mmu.write_unchecked(
+                    INTERRUPT_FLAGS_REGISTER,
+                    (interrupt_flags | InterruptFlags::JOYPAD).bits(),
+                );
+            }
+        }
+    }
+
+    // Whether the next instruction gets at the registers of the other components, which then have to
+    // be caught up first. Per-pixel rendering draws from VRAM/OAM as the line goes, and a CGB DMA or an
+    // interrupt about to be dispatched could touch anything, so those catch up on every instruction.
+    fn touches_components(&mut self) -> bool {
+        if self.mmu.hdma_active() || self.cpu.interrupt_pending(&self.mmu) {
+            return true;
+        }
+        // STOP resets DIV, the cycles before it have to be counted first
+        if matches!(self.mmu.peek(self.cpu.read_register16(&Register::PC)), Ok(0x10)) {
+            return true;
+        }
+
+        match self.cpu.next_memory_access(&self.mmu).map(|(address, _)| address) {
+            Some(IO_START..=IO_END | INTERRUPT_ENABLE_REGISTER) => true,
+            Some(VRAM_START..=VRAM_END | OAM_START..=OAM_END) => self.ppu.accuracy() == Accuracy::PerPixel,
+            _ => false,
+        }
+    }
+
+    // CPU cycles a halted CPU can sleep through in one go instead of being stepped 4 cycles at a time,
+    // e.g. in menus waiting for VBlank. Nothing can wake it up before the next scheduled event,
+    // and the scanline still has to end on time. None if the CPU has to run normally.
+    fn idle_cycles(&self, cycles_per_scanline: usize) -> Option<usize> {
+        if !self.cpu.halted || self.cpu.interrupt_pending(&self.mmu) {
+            return None;
+        }
+
+        let scanline_end = cycles_per_scanline.saturating_sub(self.cpu.elapsed_cycles());
+        let cycles = match self.scheduler.cycles_until_next() {
+            Some(event) => event.min(scanline_end),
+            None => scanline_end,
+        };
+
+        // Whole M-cycles, just like stepping the halted CPU would count them
+        Some((cycles / 4).max(1) * 4)
+    }
+
+    // Stray memory accesses are logged and the game carries on, anything else is fatal
+    fn recover(error: AyyError, diagnostics: &Diagnostics) -> usize {
+        match error {
+            AyyError::WriteToReadOnlyMemory { address, data } => warn!(
+                "PC @ {} => Attempted to write {:02x} to unmapped read-only memory at {:04x}",
+                diagnostics.pc, data, address
+            ),
+            AyyError::OutOfBoundsMemoryAccess { address } => warn!(
+                "PC @ {} => Attempted to read out-of-bounds memory at {:04x}",
+                diagnostics.pc, address
+            ),
+            AyyError::WriteToDisabledExternalRam { address, data } => error!(
+                "PC @ {} => Attempted to write {:02x} to disabled external RAM at {:04x}",
+                diagnostics.pc, data, address
+            ),
+            error => panic!("{}\n{}", error, diagnostics),
+        }
+        0
+    }
+
+    // Ends the frame early when the next instruction has a breakpoint, touches an IO register with one
+    // or a break condition turned true, it then runs first thing on the next call
+    fn at_breakpoint(&mut self) -> bool {
+        if std::mem::take(&mut self.resuming) || self.breakpoints.is_empty() || self.cpu.halted {
+            return false;
+        }
+
+        // Every condition sees every instruction, or it would miss turning false again
+        let mut condition_hit = None;
+        if self.breakpoints.checks_conditions() {
+            for condition in self.breakpoints.conditions.iter_mut() {
+                if condition.triggered(&self.cpu, &self.mmu) && condition_hit.is_none() {
+                    condition_hit = Some(condition.source.clone());
+                }
+            }
+        }
+
+        let pc = BankedAddress::current(self.cpu.read_register16(&Register::PC), self.mmu.cartridge.as_ref());
+        if condition_hit.is_none() && !self.breakpoints.addresses.contains(&pc) {
+            let Some((address, access)) = self.cpu.next_memory_access(&self.mmu) else {
+                return false;
+            };
+            if !self
+                .breakpoints
+                .io
+                .iter()
+                .any(|breakpoint| breakpoint.address == address && breakpoint.access.overlaps(access))
+            {
+                return false;
+            }
+            self.io_breakpoint_hit = Some(IoBreakpoint { address, access });
+        }
+
+        self.breakpoint_hit = Some(pc);
+        self.condition_hit = condition_hit;
+        self.resuming = true;
+        self.cpu.forget_decoded();
+        true
+    }
+
+    // Hands every frame to the callback as soon as it is finished, instead of polling framebuffer
+    pub fn set_frame_callback(&mut self, callback: impl Fn(&Frame) + 'static) {
+        self.frame_callback = FrameCallback(Some(Box::new(callback)));
+    }
+
+    // Hands a side-effect free copy of the memory to the callback at the end of every frame,
+    // enough for an achievement runtime to peek at without slowing down every memory access
+    pub fn set_memory_callback(&mut self, callback: impl Fn(&MemorySnapshot) + 'static) {
+        self.memory_callback = MemoryCallback(Some((Box::new(callback), MemorySnapshot::default())));
+    }
+
+    // Hands the samples to the callback as they are generated, on top of playing them on the audio device.
+    // Interleaved left/right at SAMPLE_RATE, muted systems (e.g. netplay replaying frames) don't produce any.
+    pub fn set_audio_callback(&mut self, callback: impl Fn(&[f32]) + 'static) {
+        self.mmu.apu.set_sample_callback(Some(Box::new(callback)));
+    }
+
+    // Experimental: picks up where a state saved by another emulator left off, see bess
+    pub fn import_state(&mut self, data: &[u8]) -> Result<(), AyyError> {
+        let state = bess::import(self, data)?;
+        self.force_load_state(&SaveState::new(state, self.rom_hash))
+    }
+
+    // The breakpoint that ended the last frame, if any
+    pub fn take_breakpoint_hit(&mut self) -> Option<BankedAddress> {
+        self.breakpoint_hit.take()
+    }
+
+    // The register access that ended the last frame, if an IO breakpoint did.
+    // Comes with the instruction's address from take_breakpoint_hit.
+    pub fn take_io_breakpoint_hit(&mut self) -> Option<IoBreakpoint> {
+        self.io_breakpoint_hit.take()
+    }
+
+    // The break condition that ended the last frame, if one did. Also comes with the instruction's address.
+    pub fn take_condition_hit(&mut self) -> Option<String> {
+        self.condition_hit.take()
+    }
+
+    // Number of frames emulated since power on
+    #[inline]
+    pub fn frame_count(&self) -> u64 {
+        self.frames
+    }
+
+    // Number of T-cycles emulated since power on, counted at single speed so
+    // that it always advances at the same rate as the hardware clock
+    #[inline]
+    pub fn cycle_count(&self) -> u64 {
+        self.cycles
+    }
+
+    // CPU cycles the game spent halted that didn't have to be emulated one by one
+    #[inline]
+    pub fn idle_cycle_count(&self) -> u64 {
+        self.idled
+    }
+
+    // Line the PPU is currently on, including the ones in V-Blank
+    #[inline]
+    pub fn scanline(&self) -> u8 {
+        self.mmu.read_unchecked(SCANLINE_Y_REGISTER)
+    }
+
+    #[inline]
+    pub fn ppu_mode(&self) -> State {
+        self.ppu.state
+    }
+
+    #[inline]
+    pub fn emulated_time(&self) -> Duration {
+        let seconds = self.cycles / CPU_CLOCK as u64;
+        let nanos = (self.cycles % CPU_CLOCK as u64) * 1_000_000_000 / CPU_CLOCK as u64;
+        Duration::new(seconds, nanos as u32)
+    }
+
     pub fn dbg_render_tileset(&mut self, vram_bank: u8) -> Vec<Tile> {
+        self.ppu.update_tile_cache(&mut self.mmu);
         self.ppu.render_tileset(&self.mmu, vram_bank)
     }
 
     pub fn dbg_render_background_tilemap(&mut self) -> Vec<Tile> {
+        self.ppu.update_tile_cache(&mut self.mmu);
         self.ppu.render_background_tilemap(&self.mmu)
     }
 
     pub fn dbg_render_window_tilemap(&mut self) -> Vec<Tile> {
+        self.ppu.update_tile_cache(&mut self.mmu);
         self.ppu.render_window_tilemap(&self.mmu)
     }
 }
+
+impl System for GameBoy {
+    fn run_frame(&mut self) {
+        GameBoy::run_frame(self)
+    }
+
+    fn screen_size(&self) -> (usize, usize) {
+        (SCREEN_WIDTH, SCREEN_HEIGHT)
+    }
+
+    fn frame_duration(&self) -> Duration {
+        Duration::from_secs_f64(CYCLES_PER_FRAME as f64 / CPU_CLOCK as f64)
+    }
+
+    fn framebuffer(&self) -> Vec<Color> {
+        self.ppu
+            .pull_frame()
+            .iter()
+            .flatten()
+            .map(|&pixel| pixel.into())
+            .collect()
+    }
+
+    fn update_button(&mut self, button: Button, pressed: bool) {
+        self.mmu.joypad.update_button(button, pressed);
+    }
+
+    fn queue_buttons(&mut self, frame_offset: f64, input: u8) {
+        GameBoy::queue_buttons(self, frame_offset, input);
+    }
+
+    fn set_speed(&mut self, multiplier: usize) {
+        self.mmu.apu.set_speed(multiplier);
+    }
+
+    fn run_ahead(&mut self, frames: usize) -> Vec<Color> {
+        // Copies don't own the audio device, so the future frames stay silent and aren't paced by audio
+        let mut future = self.clone();
+        for _ in 0..frames {
+            future.run_frame();
+        }
+        System::framebuffer(&future)
+    }
+
+    fn set_muted(&mut self, muted: bool) {
+        self.mmu.apu.set_muted(muted);
+    }
+
+    fn set_rendering(&mut self, enabled: bool) {
+        self.ppu.set_rendering(enabled);
+    }
+
+    fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.ppu.set_accuracy(accuracy);
+    }
+
+    fn set_layers(&mut self, layers: Layers) {
+        self.ppu.set_layers(layers);
+    }
+
+    fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.mmu.cgb_cram.set_color_correction(correction);
+        self.mmu.touch_vram();
+    }
+
+    fn set_compat_palette(&mut self, palette: CompatPalette) {
+        // CGB games bring their own colors. A booting CGB doesn't know yet what it runs, but the boot ROM
+        // goes by the header as well.
+        let dmg_game = matches!(self.mmu.cartridge.read(0x0143), Ok(flag) if flag & 0x80 == 0);
+        if self.mode == Mode::Dmg || (self.mmu.is_bootrom_mapped() && dmg_game) {
+            self.mmu.cgb_cram.set_compat_palette(palette);
+            self.mmu.touch_vram();
+        }
+    }
+
+    fn set_sync_mode(&mut self, mode: SyncMode) {
+        self.mmu.apu.set_sync_mode(mode);
+    }
+
+    fn calibrate_audio(&mut self) -> Option<Duration> {
+        self.mmu.apu.calibrate_latency()
+    }
+
+    fn save_state(&self) -> SaveState {
+        SaveState::new(self.clone(), self.rom_hash)
+    }
+
+    fn force_load_state(&mut self, state: &SaveState) -> Result<(), AyyError> {
+        let mut state = state
+            .downcast_ref::<GameBoy>()
+            .ok_or(AyyError::IncompatibleSaveState)?
+            .clone();
+
+        // The snapshot is silent, keep playing on our audio device
+        state.mmu.apu.adopt_output(&mut self.mmu.apu);
+        state.ppu.set_rendering(self.ppu.rendering());
+        state.ppu.set_accuracy(self.ppu.accuracy());
+        state.ppu.set_layers(self.ppu.layers());
+        state
+            .mmu
+            .cgb_cram
+            .set_color_correction(self.mmu.cgb_cram.color_correction());
+        state
+            .mmu
+            .cgb_cram
+            .set_compat_palette(self.mmu.cgb_cram.compat_palette());
+        state.ppu.adopt_worker(&mut self.ppu);
+        state.cpu.set_stack_check(self.cpu.stack_check_enabled());
+        state.cpu.set_lenient(self.cpu.lenient());
+        state.mmu.uninit.set_enabled(self.mmu.uninit.is_enabled());
+        state.mmu.continue_vram_generation(&self.mmu);
+        // What's on disk is still what we saved last, not what the state saved
+        let dirty = self.mmu.cartridge.ram_dirty() || state.mmu.cartridge.ram() != self.mmu.cartridge.ram();
+        state.mmu.cartridge.set_ram_dirty(dirty);
+        state.breakpoints = std::mem::take(&mut self.breakpoints);
+        state.frame_callback = std::mem::take(&mut self.frame_callback);
+        state.memory_callback = std::mem::take(&mut self.memory_callback);
+        state.mmu.io_log = std::mem::take(&mut self.mmu.io_log);
+        state.mmu.serial.connect(self.mmu.serial.take_device());
+        if let (Some(rumble), Some(from)) = (state.mmu.cartridge.rumble(), self.mmu.cartridge.rumble()) {
+            rumble.take_device(from);
+        }
+        state.mmu.heatmap = std::mem::take(&mut self.mmu.heatmap);
+        *self = state;
+        Ok(())
+    }
+
+    fn rom_hash(&self) -> RomHash {
+        self.rom_hash
+    }
+
+    fn title(&self) -> String {
+        let header = (0x134..0x144)
+            .map(|addr| self.mmu.cartridge.read(addr).unwrap_or(0))
+            .collect::<Vec<u8>>();
+        header_title(&header)
+    }
+
+    fn model(&self) -> &'static str {
+        match self.mode {
+            Mode::Dmg => "DMG",
+            Mode::Cgb => "CGB",
+        }
+    }
+
+    fn mode(&self) -> Mode {
+        self.mode.clone()
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.mmu.cartridge.dump_ram()
+    }
+
+    fn load_ram(&mut self, ram: Vec<u8>) {
+        self.mmu.cartridge.load_ram(ram);
+    }
+
+    fn dump_rtc(&self) -> Option<Vec<u8>> {
+        self.mmu.cartridge.dump_rtc()
+    }
+
+    fn load_rtc(&mut self, rtc: Vec<u8>, mode: RtcMode) {
+        self.mmu.cartridge.load_rtc(rtc, mode);
+    }
+
+    fn mark_saved(&mut self) {
+        self.mmu.cartridge.set_ram_dirty(false);
+    }
+
+    fn swap_cartridge(&mut self, rom: Vec<u8>) -> Result<(), AyyError> {
+        self.hot_swap_cartridge(rom).map(|_| ())
+    }
+
+    fn reset(&mut self) {
+        GameBoy::reset(self);
+    }
+
+    fn power_cycle(&mut self, decay: Option<&RamDecay>) {
+        GameBoy::power_cycle(self, decay);
+    }
+
+    fn take_rumble(&mut self) -> bool {
+        self.mmu.cartridge.take_rumble()
+    }
+
+    fn take_printouts(&mut self) -> Vec<Printout> {
+        self.mmu
+            .serial
+            .device_mut()
+            .and_then(|device| device.as_any_mut().downcast_mut::<Printer>())
+            .map(|printer| printer.take_printouts())
+            .unwrap_or_default()
+    }
+
+    fn memory_snapshot(&self) -> MemorySnapshot {
+        MemorySnapshot::capture(&self.mmu)
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
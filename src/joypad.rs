@@ -1,4 +1,4 @@
-use eframe::egui::Key;
+use crate::system::Button;
 use log::warn;
 
 #[derive(Clone)]
@@ -27,17 +27,16 @@ impl Joypad {
         }
     }
 
-    pub fn update_button(&mut self, key: Key, pressed: bool) {
-        match key {
-            Key::ArrowUp => self.up = pressed,
-            Key::ArrowDown => self.down = pressed,
-            Key::ArrowLeft => self.left = pressed,
-            Key::ArrowRight => self.right = pressed,
-            Key::A => self.a = pressed,
-            Key::S => self.b = pressed,
-            Key::Enter => self.start = pressed,
-            Key::Backspace => self.select = pressed,
-            _ => unreachable!(),
+    pub fn update_button(&mut self, button: Button, pressed: bool) {
+        match button {
+            Button::Up => self.up = pressed,
+            Button::Down => self.down = pressed,
+            Button::Left => self.left = pressed,
+            Button::Right => self.right = pressed,
+            Button::A => self.a = pressed,
+            Button::B => self.b = pressed,
+            Button::Start => self.start = pressed,
+            Button::Select => self.select = pressed,
         }
     }
 
@@ -81,3 +80,9 @@ impl Joypad {
         !state
     }
 }
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
+}
@@ -0,0 +1,18 @@
+#![feature(let_chains)]
+#![feature(custom_test_frameworks)]
+#![test_runner(datatest::runner)]
+
+pub mod bess;
+pub mod clock;
+pub mod error;
+pub mod frontend;
+pub mod gameboy;
+pub mod joypad;
+pub mod lr35902;
+pub mod memory;
+pub mod scheduler;
+pub mod serial;
+pub mod sound;
+pub mod system;
+mod tests;
+pub mod video;
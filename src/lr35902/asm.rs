@@ -0,0 +1,179 @@
+use crate::error::AyyError;
+use crate::lr35902::sm83::{AddressingMode, ByteSource, Condition, Instruction, Operand, Sm83};
+
+#[derive(Debug, Clone, PartialEq)]
+enum OperandTemplate {
+    // Encoded in the opcode itself, has to match exactly (registers, conditions, rst vectors, bit numbers)
+    Literal(String),
+    // Immediate that follows the opcode, `indirect` for the (n) forms
+    Imm8 { indirect: bool },
+    Imm16 { indirect: bool },
+    // Signed 8-bit immediate, optionally displacing a register (e.g. sp+n)
+    Signed { register: Option<String> },
+}
+
+#[derive(Clone)]
+struct Template {
+    mnemonic: String,
+    operands: Vec<OperandTemplate>,
+    opcode: Vec<u8>,
+}
+
+// A tiny SM83 assembler. Rather than keeping a second opcode table around, the templates are
+// generated by running every opcode through the decoder, so both always agree on the syntax.
+#[derive(Clone)]
+pub struct Assembler {
+    templates: Vec<Template>,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        let mut sm83 = Sm83::new();
+        let mut templates = Vec::new();
+
+        for opcode in 0..=0xffu8 {
+            let encodings = if opcode == 0xcb {
+                (0..=0xffu8)
+                    .map(|cb_opcode| vec![0xcb, cb_opcode])
+                    .collect::<Vec<Vec<u8>>>()
+            } else {
+                vec![vec![opcode]]
+            };
+
+            for encoding in encodings {
+                let mut bytes = encoding.clone();
+                bytes.resize(3, 0);
+
+                if let Ok(instruction) = sm83.decode(&ByteSource::new(&bytes, 0), 0) {
+                    templates.push(Assembler::template_from(&instruction, encoding));
+                }
+            }
+        }
+
+        Assembler { templates }
+    }
+
+    pub fn assemble(&self, line: &str) -> Result<Vec<u8>, AyyError> {
+        let line = line.trim().to_lowercase();
+        let (mnemonic, operands) = match line.split_once(char::is_whitespace) {
+            Some((mnemonic, operands)) => (mnemonic, operands.trim()),
+            None => (line.as_str(), ""),
+        };
+        let operands = operands
+            .split(',')
+            .map(|operand| operand.chars().filter(|c| !c.is_whitespace()).collect::<String>())
+            .filter(|operand| !operand.is_empty())
+            .map(|operand| operand.replace("hli", "hl+").replace("hld", "hl-"))
+            .collect::<Vec<String>>();
+
+        'templates: for template in self.templates.iter().filter(|template| template.mnemonic == mnemonic) {
+            if template.operands.len() != operands.len() {
+                continue;
+            }
+
+            let mut bytes = template.opcode.clone();
+            for (expected, operand) in template.operands.iter().zip(operands.iter()) {
+                match Assembler::encode_operand(expected, operand) {
+                    Some(encoded) => bytes.extend(encoded),
+                    None => continue 'templates,
+                }
+            }
+
+            return Ok(bytes);
+        }
+
+        Err(AyyError::AssemblerFailure { line })
+    }
+
+    fn template_from(instruction: &Instruction, opcode: Vec<u8>) -> Template {
+        // Operands of single byte instructions are always part of the opcode
+        let has_immediate = instruction.length > opcode.len();
+
+        let mut operands = Vec::new();
+        for operand in [&instruction.lhs, &instruction.rhs].into_iter().flatten() {
+            let template = match operand {
+                Operand::Conditional(Condition::None) => continue,
+                Operand::Imm8(_, mode) if has_immediate => OperandTemplate::Imm8 {
+                    indirect: mode.contains(AddressingMode::Indirect),
+                },
+                Operand::Imm16(_, mode) if has_immediate => OperandTemplate::Imm16 {
+                    indirect: mode.contains(AddressingMode::Indirect),
+                },
+                Operand::Offset(_) => OperandTemplate::Signed { register: None },
+                Operand::DisplacedReg16(register, _, _) => OperandTemplate::Signed {
+                    register: Some(register.to_string()),
+                },
+                _ => OperandTemplate::Literal(operand.to_string()),
+            };
+            operands.push(template);
+        }
+
+        Template {
+            mnemonic: format!("{:?}", instruction.opcode).to_lowercase(),
+            operands,
+            opcode,
+        }
+    }
+
+    fn encode_operand(expected: &OperandTemplate, operand: &str) -> Option<Vec<u8>> {
+        match expected {
+            OperandTemplate::Literal(literal) => {
+                // Numbers encoded in the opcode (rst, bit) may be written in any base
+                let matches = match (parse_number(literal), parse_number(operand)) {
+                    (Some(lhs), Some(rhs)) => lhs == rhs,
+                    _ => literal == operand,
+                };
+                matches.then(Vec::new)
+            }
+            OperandTemplate::Imm8 { indirect } => {
+                let value = parse_number(unwrap_indirect(operand, *indirect)?)?;
+                (0..=0xff).contains(&value).then(|| vec![value as u8])
+            }
+            OperandTemplate::Imm16 { indirect } => {
+                let value = parse_number(unwrap_indirect(operand, *indirect)?)?;
+                (0..=0xffff)
+                    .contains(&value)
+                    .then(|| (value as u16).to_le_bytes().to_vec())
+            }
+            OperandTemplate::Signed { register } => {
+                let value = match register {
+                    Some(register) => parse_number(operand.strip_prefix(register.as_str())?)?,
+                    None => parse_number(operand)?,
+                };
+                // Accept both signed values and their unsigned byte representation
+                (-0x80..=0xff).contains(&value).then(|| vec![value as u8])
+            }
+        }
+    }
+}
+
+impl Default for Assembler {
+    fn default() -> Assembler {
+        Assembler::new()
+    }
+}
+
+fn unwrap_indirect(operand: &str, indirect: bool) -> Option<&str> {
+    if indirect {
+        operand.strip_prefix('(')?.strip_suffix(')')
+    } else if operand.starts_with('(') {
+        None
+    } else {
+        Some(operand)
+    }
+}
+
+fn parse_number(value: &str) -> Option<i64> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    let number = if let Some(hex) = value.strip_prefix("0x").or_else(|| value.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else {
+        value.parse::<i64>().ok()?
+    };
+
+    Some(if negative { -number } else { number })
+}
@@ -1,14 +1,21 @@
 use crate::error::AyyError;
+use crate::lr35902::diagnostics::{Diagnostics, StackFault, StackWarning, HISTORY_LENGTH, MAX_STACK_WARNINGS};
 use crate::lr35902::handlers::Handlers;
 use crate::lr35902::irq::{Ime, Vector};
 use crate::lr35902::registers::{Flags, Registers};
-use crate::lr35902::sm83::{Opcode, Register, Sm83};
-use crate::lr35902::timer::Timer;
+use crate::lr35902::sm83::{AddressingMode, ByteSource, Fetch, Instruction, Opcode, Operand, Register, Sm83};
+use crate::memory::banked::BankedAddress;
+use crate::memory::io_registers::Access;
 use crate::memory::mmu::Mmu;
 use crate::memory::registers::{InterruptEnable, InterruptFlags};
-use crate::memory::{DIV_REGISTER, INTERRUPT_ENABLE_REGISTER, INTERRUPT_FLAGS_REGISTER};
+use crate::memory::{INTERRUPT_ENABLE_REGISTER, INTERRUPT_FLAGS_REGISTER, IO_END, IO_START, ROM_END};
 use crate::video::SCANLINE_Y_REGISTER;
-use log::trace;
+use log::{trace, warn};
+use std::collections::VecDeque;
+
+// Skipped opcodes are all logged up to here, after that only every SKIP_WARNING_INTERVAL-th is
+const MAX_SKIP_WARNINGS: u64 = 16;
+const SKIP_WARNING_INTERVAL: u64 = 10_000;
 
 #[derive(Clone)]
 pub struct Cpu {
@@ -16,8 +23,18 @@ pub struct Cpu {
     registers: Registers,
     cycles: usize,
     ime: Ime,
-    div_cycles: usize,
+    overclock: usize,
+    overclock_cycles: usize,
     pub halted: bool,
+    history: VecDeque<BankedAddress>,
+    // Address of the instruction (or interrupt dispatch) being executed
+    instruction_pc: u16,
+    stack_check: bool,
+    stack_warnings: Vec<StackWarning>,
+    lenient: bool,
+    skipped_opcodes: u64,
+    // The instruction at PC as decoded by next_memory_access, which the next step runs instead of decoding it again
+    decoded: Option<(u16, Instruction)>,
 }
 
 impl Cpu {
@@ -30,20 +47,67 @@ impl Cpu {
                 enabled: false,
                 enable_pending: false,
             },
-            div_cycles: 0,
+            overclock: 1,
+            overclock_cycles: 0,
             halted: false,
+            history: VecDeque::with_capacity(HISTORY_LENGTH),
+            instruction_pc: 0,
+            stack_check: false,
+            stack_warnings: Vec::new(),
+            lenient: false,
+            skipped_opcodes: 0,
+            decoded: None,
         }
     }
 
-    pub fn tick(&mut self, mmu: &mut Mmu, timer: &mut Timer) -> Result<usize, AyyError> {
+    // Errors leave with a snapshot of where the system was, see Diagnostics
+    pub fn tick(&mut self, mmu: &mut Mmu) -> Result<usize, AyyError> {
+        let result = self.step(mmu);
+        mmu.uninit.end_instruction();
+        result.map_err(|error| AyyError::Diagnosed {
+            error: Box::new(error),
+            diagnostics: Box::new(self.diagnose(mmu)),
+        })
+    }
+
+    fn step(&mut self, mmu: &mut Mmu) -> Result<usize, AyyError> {
+        let decoded = self.decoded.take();
         self.handle_interrupts(mmu)?;
 
+        // Set if the previous instruction was EI
+        let enable_pending = self.ime.enable_pending;
+
         if self.halted {
             self.cycles += 4;
             return Ok(4);
         }
 
-        let instruction = self.sm83.decode(mmu, self.registers.pc)?;
+        if mmu.uninit.is_enabled() {
+            mmu.uninit.begin_instruction(self.registers.pc);
+        }
+
+        let instruction = match decoded.filter(|(pc, _)| *pc == self.registers.pc) {
+            Some((_, instruction)) => {
+                mmu.record_fetch(self.registers.pc, instruction.length);
+                Ok(instruction)
+            }
+            None => self.sm83.decode(&Fetch(mmu), self.registers.pc),
+        };
+        let instruction = match instruction {
+            Ok(instruction) => instruction,
+            Err(AyyError::IllegalOpcode { opcode } | AyyError::DecoderFailure { opcode, .. }) if self.lenient => {
+                self.warn_skipped_opcode(mmu, opcode);
+                self.sm83
+                    .decode(&ByteSource::new(&[0x00], self.registers.pc), self.registers.pc)?
+            }
+            Err(e) => return Err(e),
+        };
+        if self.history.len() == HISTORY_LENGTH {
+            self.history.pop_front();
+        }
+        self.history
+            .push_back(BankedAddress::current(self.registers.pc, mmu.cartridge.as_ref()));
+        self.instruction_pc = self.registers.pc;
         let instruction_bytes = (0..instruction.length)
             .map(|i| mmu.read_unchecked(self.registers.pc + i as u16))
             .collect::<Vec<u8>>();
@@ -83,7 +147,7 @@ impl Cpu {
             Opcode::Or => Handlers::or(self, mmu, &instruction),
             Opcode::Daa => Handlers::decimal_adjust_accumulator(self, mmu, &instruction),
             Opcode::Halt => Handlers::halt(self, mmu, &instruction),
-            Opcode::Stop => Handlers::stop(self, mmu, timer, &instruction),
+            Opcode::Stop => Handlers::stop(self, mmu, &instruction),
             Opcode::Jp | Opcode::Jr | Opcode::Call => Handlers::jump(self, mmu, &instruction),
             Opcode::Rst => Handlers::restart(self, mmu, &instruction),
             Opcode::Ret | Opcode::Reti => Handlers::ret(self, mmu, &instruction),
@@ -101,33 +165,190 @@ impl Cpu {
                 cpu: format!("{}", self),
             }),
         }?;
+        let cycles = self.scale_cycles(cycles);
+
+        // "EI instruction enables IME the following cycle to its execution."
+        //   - TCAGBD.pdf, chapter 3.3
+        // The instruction after EI always runs before an interrupt is serviced, and a DI
+        // right after EI cancels it
+        if enable_pending && self.ime.enable_pending {
+            self.ime.enabled = true;
+            self.ime.enable_pending = false;
+
+            trace!("IME pending, enabled");
+        }
 
         self.cycles += cycles;
         self.cycles += mmu.get_and_reset_cycles();
-        self.div_cycles += cycles;
-
-        self.tick_div(mmu);
 
         Ok(cycles)
     }
 
+    // Experimental and not accurate: runs `factor` times as many instructions in the same amount of
+    // PPU/APU/timer time, which reduces lag in busy scenes but breaks timing sensitive games
+    pub fn set_overclock(&mut self, factor: usize) {
+        self.overclock = factor.max(1);
+        self.overclock_cycles = 0;
+    }
+
+    pub fn overclock(&self) -> usize {
+        self.overclock
+    }
+
     #[inline]
-    pub fn elapsed_cycles(&self) -> usize {
-        self.cycles
+    fn scale_cycles(&mut self, cycles: usize) -> usize {
+        // keep the remainder around so that no cycles get lost over time
+        self.overclock_cycles += cycles;
+        let scaled = self.overclock_cycles / self.overclock;
+        self.overclock_cycles %= self.overclock;
+        scaled
+    }
+
+    // For ROM developers: watches for the stack running into ROM or IO registers, or over the code
+    // being executed, and keeps a warning for the debugger. Off by default as it costs a little on every push/pop.
+    pub fn set_stack_check(&mut self, enabled: bool) {
+        self.stack_check = enabled;
     }
 
     #[inline]
-    pub fn reset_cycles(&mut self, cycles: usize) {
-        self.cycles = cycles;
+    pub fn stack_check_enabled(&self) -> bool {
+        self.stack_check
+    }
+
+    // Oldest first, each faulty instruction is only listed once
+    #[inline]
+    pub fn stack_warnings(&self) -> &[StackWarning] {
+        &self.stack_warnings
+    }
+
+    pub fn clear_stack_warnings(&mut self) {
+        self.stack_warnings.clear();
+    }
+
+    // For corrupted dumps and homebrew in development: opcodes that can't be decoded run as a NOP
+    // instead of stopping the emulation. Whatever the game does afterwards is anyone's guess.
+    pub fn set_lenient(&mut self, enabled: bool) {
+        self.lenient = enabled;
+    }
+
+    #[inline]
+    pub fn lenient(&self) -> bool {
+        self.lenient
     }
 
+    // Opcodes run as a NOP in lenient mode so far
     #[inline]
-    pub fn tick_div(&mut self, mmu: &mut Mmu) {
-        if self.div_cycles >= 256 {
-            let div = mmu.read_unchecked(DIV_REGISTER).wrapping_add(1);
-            mmu.write_unchecked(DIV_REGISTER, div);
-            self.div_cycles -= 256;
+    pub fn skipped_opcodes(&self) -> u64 {
+        self.skipped_opcodes
+    }
+
+    // Broken code tends to run through the same bad bytes over and over, so the log is rate limited
+    fn warn_skipped_opcode(&mut self, mmu: &Mmu, opcode: u8) {
+        self.skipped_opcodes += 1;
+        if self.skipped_opcodes > MAX_SKIP_WARNINGS && !self.skipped_opcodes.is_multiple_of(SKIP_WARNING_INTERVAL) {
+            return;
+        }
+
+        warn!(
+            "PC @ {} => Skipped undecodable opcode {:02x} ({} so far)",
+            BankedAddress::current(self.registers.pc, mmu.cartridge.as_ref()),
+            opcode,
+            self.skipped_opcodes
+        );
+        if self.skipped_opcodes == MAX_SKIP_WARNINGS {
+            warn!(
+                "Only every {}th skipped opcode is logged from now on",
+                SKIP_WARNING_INTERVAL
+            );
+        }
+    }
+
+    fn check_stack_access(&mut self, mmu: &Mmu, address: u16, push: bool) {
+        let misplaced =
+            address <= ROM_END || (IO_START..=IO_END).contains(&address) || address == INTERRUPT_ENABLE_REGISTER;
+        let fault = match push {
+            true if misplaced => StackFault::Overflow,
+            false if misplaced => StackFault::Underflow,
+            // The code might live in RAM, e.g. an HRAM DMA routine
+            true if (self.instruction_pc..=self.registers.pc).contains(&address) => StackFault::OverlapsCode,
+            _ => return,
+        };
+
+        let pc = BankedAddress::current(self.instruction_pc, mmu.cartridge.as_ref());
+        if self.stack_warnings.len() == MAX_STACK_WARNINGS
+            || self
+                .stack_warnings
+                .iter()
+                .any(|warning| warning.pc == pc && warning.fault == fault)
+        {
+            return;
+        }
+
+        let warning = StackWarning { pc, address, fault };
+        warn!("{}", warning);
+        self.stack_warnings.push(warning);
+    }
+
+    // Memory the next instruction reads or writes through one of its operands, stack accesses aside
+    pub fn next_memory_access(&mut self, mmu: &Mmu) -> Option<(u16, Access)> {
+        let pc = self.registers.pc;
+        if !matches!(&self.decoded, Some((decoded_pc, _)) if *decoded_pc == pc) {
+            self.decoded = Some((pc, self.sm83.decode(mmu, pc).ok()?));
         }
+        let (_, instruction) = self.decoded.as_ref()?;
+        // Loads write their destination, everything else with a memory destination (INC, RL, ...) also reads it.
+        // RES and SET have theirs after the bit number.
+        let (lhs_access, rhs_access) = match instruction.opcode {
+            Opcode::Ld | Opcode::Ldh => (Access::Write, Access::Read),
+            Opcode::Res | Opcode::Set => (Access::Read, Access::ReadWrite),
+            _ => (Access::ReadWrite, Access::Read),
+        };
+
+        [(&instruction.lhs, lhs_access), (&instruction.rhs, rhs_access)]
+            .iter()
+            .find_map(|(operand, access)| {
+                let address = match operand.as_ref()? {
+                    Operand::Reg16(register, mode) if mode.contains(AddressingMode::Indirect) => {
+                        self.read_register16(register)
+                    }
+                    Operand::Reg8(register, mode) if mode.contains(AddressingMode::Indirect) => {
+                        0xff00 + self.read_register(register) as u16
+                    }
+                    Operand::Imm16(address, mode) if mode.contains(AddressingMode::Indirect) => *address,
+                    Operand::Imm8(offset, mode) if mode.contains(AddressingMode::Indirect) => 0xff00 + *offset as u16,
+                    _ => return None,
+                };
+                Some((address, *access))
+            })
+    }
+
+    // Whether an interrupt is requested and enabled, which ends HALT and may be dispatched
+    pub fn interrupt_pending(&self, mmu: &Mmu) -> bool {
+        mmu.read_unchecked(INTERRUPT_ENABLE_REGISTER) & mmu.read_unchecked(INTERRUPT_FLAGS_REGISTER) & 0b0001_1111 != 0
+    }
+
+    // Lets a halted CPU sleep through several cycles at once
+    #[inline]
+    pub fn idle(&mut self, cycles: usize) -> usize {
+        self.forget_decoded();
+        self.cycles += cycles;
+        cycles
+    }
+
+    // Memory at PC might change before the instruction runs, e.g. while the debugger has the console stopped
+    #[inline]
+    pub fn forget_decoded(&mut self) {
+        self.decoded = None;
+    }
+
+    #[inline]
+    pub fn elapsed_cycles(&self) -> usize {
+        self.cycles
+    }
+
+    #[inline]
+    pub fn reset_cycles(&mut self, cycles: usize) {
+        self.cycles = cycles;
     }
 
     #[inline]
@@ -225,17 +446,51 @@ impl Cpu {
         self.registers.f &= !flag;
     }
 
+    fn diagnose(&mut self, mmu: &Mmu) -> Diagnostics {
+        let history = self
+            .history
+            .iter()
+            .map(|addr| {
+                let mapped = BankedAddress::current(addr.addr, mmu.cartridge.as_ref()) == *addr;
+                match self.sm83.decode(mmu, addr.addr) {
+                    Ok(instruction) if mapped => format!("{}  {}", addr, instruction),
+                    _ => addr.to_string(),
+                }
+            })
+            .collect();
+
+        Diagnostics {
+            pc: BankedAddress::current(self.registers.pc, mmu.cartridge.as_ref()),
+            ram_bank: mmu.cartridge.current_ram_bank(),
+            vram_bank: mmu.current_vram_bank(),
+            wram_bank: mmu.current_wram_bank(),
+            ppu_state: mmu.ppu_state(),
+            scanline: mmu.read_unchecked(SCANLINE_Y_REGISTER),
+            history,
+        }
+    }
+
     #[inline]
     pub fn push_stack(&mut self, mmu: &mut Mmu, value: u16) -> Result<(), AyyError> {
-        self.registers.sp -= 2;
-        mmu.write16(self.registers.sp, value)?;
+        // The high byte goes out first, which matters for pushes that hit IE or IO registers
+        self.registers.sp = self.registers.sp.wrapping_sub(2);
+        if self.stack_check {
+            self.check_stack_access(mmu, self.registers.sp.wrapping_add(1), true);
+            self.check_stack_access(mmu, self.registers.sp, true);
+        }
+        mmu.cpu_write(self.registers.sp.wrapping_add(1), (value >> 8) as u8)?;
+        mmu.cpu_write(self.registers.sp, value as u8)?;
         Ok(())
     }
 
     #[inline]
     pub fn pop_stack(&mut self, mmu: &Mmu) -> Result<u16, AyyError> {
-        let value = mmu.read16(self.registers.sp)?;
-        self.registers.sp += 2;
+        if self.stack_check {
+            self.check_stack_access(mmu, self.registers.sp, false);
+            self.check_stack_access(mmu, self.registers.sp.wrapping_add(1), false);
+        }
+        let value = mmu.cpu_read16(self.registers.sp)?;
+        self.registers.sp = self.registers.sp.wrapping_add(2);
         Ok(value)
     }
 
@@ -251,55 +506,58 @@ impl Cpu {
     #[inline]
     pub fn disable_interrupts(&mut self) {
         self.ime.enabled = false;
+        self.ime.enable_pending = false;
     }
 
     #[inline]
-    #[cfg(test)]
     pub fn interrupt_master_raised(&self) -> bool {
         self.ime.enabled
     }
 
     fn handle_interrupts(&mut self, mmu: &mut Mmu) -> Result<(), AyyError> {
-        // "EI instruction enables IME the following cycle to its execution."
-        //   - TCAGBD.pdf, chapter 3.3
-        if self.ime.enable_pending {
-            self.ime.enabled = true;
-            self.ime.enable_pending = false;
+        let interrupt_enable = mmu.read_as::<InterruptEnable>(INTERRUPT_ENABLE_REGISTER)?;
+        let mut interrupt_flags = mmu.read_as::<InterruptFlags>(INTERRUPT_FLAGS_REGISTER)?;
 
-            trace!("IME pending, enabled");
+        if interrupt_enable.bits() & interrupt_flags.bits() & 0b0001_1111 == 0 {
+            return Ok(());
         }
 
-        let interrupt_enable = mmu.read_as::<InterruptEnable>(INTERRUPT_ENABLE_REGISTER)?;
-        let mut interrupt_flags = mmu.read_as::<InterruptFlags>(INTERRUPT_FLAGS_REGISTER)?;
+        // Any pending interrupt ends HALT, even if it is not going to be serviced
+        self.halted = false;
 
-        if interrupt_enable.bits() & interrupt_flags.bits() != 0 {
-            if self.ime.enabled {
-                // handle interrupt vector
-                let vector = Vector::from_flags(&interrupt_enable, &interrupt_flags);
-                trace!("Handling interrupt: {} => ${:04x}", vector, vector.to_address());
+        // With IME disabled the CPU simply continues after the HALT instruction
+        if !self.ime.enabled {
+            return Ok(());
+        }
 
-                // save $pc, jump to interrupt vector
-                self.push_stack(mmu, self.registers.pc)?;
-                self.registers.pc = vector.to_address();
+        // handle interrupt vector
+        let vector = Vector::from_flags(&interrupt_enable, &interrupt_flags);
+        trace!("Handling interrupt: {} => ${:04x}", vector, vector.to_address());
 
-                // clear interrupt flag
-                vector.clear_flag(&mut interrupt_flags);
-                mmu.write_unchecked(INTERRUPT_FLAGS_REGISTER, interrupt_flags.bits());
-                self.ime.enabled = false;
-            }
+        // save $pc, jump to interrupt vector
+        self.instruction_pc = self.registers.pc;
+        self.push_stack(mmu, self.registers.pc)?;
+        self.registers.pc = vector.to_address();
 
-            // unhalt the CPU
-            self.halted = false;
+        // clear interrupt flag
+        vector.clear_flag(&mut interrupt_flags);
+        mmu.write_unchecked(INTERRUPT_FLAGS_REGISTER, interrupt_flags.bits());
+        self.ime.enabled = false;
 
-            // "The entire process lasts 5 M-cycles."
-            //   - Pandocs
-            self.cycles += 20;
-        }
+        // "The entire process lasts 5 M-cycles."
+        //   - Pandocs
+        self.cycles += 20;
 
         Ok(())
     }
 }
 
+impl Default for Cpu {
+    fn default() -> Cpu {
+        Cpu::new()
+    }
+}
+
 impl std::fmt::Display for Cpu {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(
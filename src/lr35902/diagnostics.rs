@@ -0,0 +1,66 @@
+use crate::memory::banked::BankedAddress;
+use crate::video::state::State;
+
+// Number of executed instructions the CPU remembers for diagnostics
+pub const HISTORY_LENGTH: usize = 16;
+// Stack warnings kept until they are cleared, later ones are dropped
+pub const MAX_STACK_WARNINGS: usize = 64;
+
+// Where the system was when an error came out of the CPU, attached to it as AyyError::Diagnosed
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    pub pc: BankedAddress,
+    pub ram_bank: u8,
+    pub vram_bank: u8,
+    pub wram_bank: u8,
+    pub ppu_state: State,
+    pub scanline: u8,
+    // Oldest first, disassembled if the bank is still mapped
+    pub history: Vec<String>,
+}
+
+impl std::fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "PC: {}  RAM Bank: {}  VRAM Bank: {}  WRAM Bank: {}  PPU: {:?}  LY: {}",
+            self.pc, self.ram_bank, self.vram_bank, self.wram_bank, self.ppu_state, self.scanline
+        )?;
+        write!(f, "Last instructions:")?;
+        for instruction in &self.history {
+            write!(f, "\n  {}", instruction)?;
+        }
+        Ok(())
+    }
+}
+
+// What a stack access did wrong, see Cpu::set_stack_check
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackFault {
+    // A push wrote into ROM or IO registers, the stack grew past where it was meant to end
+    Overflow,
+    // A pop read from ROM or IO registers, more was popped than pushed
+    Underflow,
+    // A push overwrote the instruction being executed or the one right after it
+    OverlapsCode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StackWarning {
+    // The instruction that accessed the stack
+    pub pc: BankedAddress,
+    // The address that was accessed
+    pub address: u16,
+    pub fault: StackFault,
+}
+
+impl std::fmt::Display for StackWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let fault = match self.fault {
+            StackFault::Overflow => "stack overflow, pushed to",
+            StackFault::Underflow => "stack underflow, popped from",
+            StackFault::OverlapsCode => "stack overwrote running code at",
+        };
+        write!(f, "{}  {} ${:04x}", self.pc, fault, self.address)
+    }
+}
@@ -0,0 +1,90 @@
+use crate::error::AyyError;
+use crate::lr35902::sm83::{ByteSource, DecodeSource, Instruction, Sm83};
+
+const ROM_BANK_SIZE: usize = 0x4000;
+
+pub struct DisassembledInstruction {
+    pub bank: usize,
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub instruction: Option<Instruction>,
+}
+
+// Disassembles `count` instructions starting at `addr` as seen by the CPU with `bank` mapped
+// into $4000-$7fff. Bytes that can't be decoded are emitted as data.
+pub fn disassemble(rom: &[u8], bank: usize, addr: u16, count: usize) -> Result<Vec<DisassembledInstruction>, AyyError> {
+    // Bank 0 is always mapped at $0000-$3fff
+    let (bank, base) = if (addr as usize) < ROM_BANK_SIZE {
+        (0, 0x0000)
+    } else {
+        (bank, 0x4000)
+    };
+
+    let start = bank * ROM_BANK_SIZE;
+    let end = (start + ROM_BANK_SIZE).min(rom.len());
+    if start >= end {
+        return Err(AyyError::OutOfBoundsMemoryAccess { address: addr });
+    }
+
+    disassemble_source(&ByteSource::new(&rom[start..end], base), bank, addr, count)
+}
+
+// Same as disassemble, but reads from anything the decoder can, e.g. the live memory map
+pub fn disassemble_source(
+    source: &dyn DecodeSource, bank: usize, addr: u16, count: usize,
+) -> Result<Vec<DisassembledInstruction>, AyyError> {
+    let mut sm83 = Sm83::new();
+    let mut instructions = Vec::new();
+    let mut pc = addr;
+
+    while instructions.len() < count {
+        // Stop at the end of the bank, the next one is not necessarily mapped after it
+        if source.read(pc).is_err() {
+            break;
+        }
+
+        let instruction = sm83.decode(source, pc).ok();
+        let length = instruction.as_ref().map_or(1, |instruction| instruction.length);
+        let bytes = (0..length)
+            .map_while(|i| source.read(pc.wrapping_add(i as u16)).ok())
+            .collect::<Vec<u8>>();
+
+        instructions.push(DisassembledInstruction {
+            bank,
+            address: pc,
+            bytes,
+            instruction,
+        });
+
+        pc = match pc.checked_add(length as u16) {
+            Some(pc) => pc,
+            None => break,
+        };
+    }
+
+    Ok(instructions)
+}
+
+impl std::fmt::Display for DisassembledInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        match &self.instruction {
+            Some(instruction) => write!(
+                f,
+                "{:02x}:{:04x}  {:<8}  {}",
+                self.bank, self.address, bytes, instruction
+            ),
+            None => write!(
+                f,
+                "{:02x}:{:04x}  {:<8}  db {:#04x}",
+                self.bank, self.address, bytes, self.bytes[0]
+            ),
+        }
+    }
+}
@@ -0,0 +1,318 @@
+use crate::error::AyyError;
+use crate::lr35902::cpu::Cpu;
+use crate::lr35902::registers::Flags;
+use crate::lr35902::sm83::Register;
+use crate::memory::mmu::Mmu;
+
+// Longest first, so that e.g. "<=" isn't read as "<" followed by "="
+const OPERATORS: [&str; 20] = [
+    "&&", "||", "==", "!=", "<=", ">=", "<<", ">>", "<", ">", "+", "-", "*", "/", "%", "&", "|", "^", "!", "~",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(&'static str),
+    Open(char),
+    Close(char),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum UnaryOp {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl BinaryOp {
+    // Binding strength, same order as in C
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Equal | BinaryOp::NotEqual => 6,
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 7,
+            BinaryOp::ShiftLeft | BinaryOp::ShiftRight => 8,
+            BinaryOp::Add | BinaryOp::Subtract => 9,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Remainder => 10,
+        }
+    }
+
+    fn from_operator(operator: &str) -> Option<BinaryOp> {
+        Some(match operator {
+            "||" => BinaryOp::Or,
+            "&&" => BinaryOp::And,
+            "==" => BinaryOp::Equal,
+            "!=" => BinaryOp::NotEqual,
+            "<" => BinaryOp::Less,
+            "<=" => BinaryOp::LessEqual,
+            ">" => BinaryOp::Greater,
+            ">=" => BinaryOp::GreaterEqual,
+            "|" => BinaryOp::BitOr,
+            "^" => BinaryOp::BitXor,
+            "&" => BinaryOp::BitAnd,
+            "<<" => BinaryOp::ShiftLeft,
+            ">>" => BinaryOp::ShiftRight,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Subtract,
+            "*" => BinaryOp::Multiply,
+            "/" => BinaryOp::Divide,
+            "%" => BinaryOp::Remainder,
+            _ => return None,
+        })
+    }
+
+    fn apply(&self, lhs: i64, rhs: i64) -> i64 {
+        match self {
+            BinaryOp::Or => (lhs != 0 || rhs != 0) as i64,
+            BinaryOp::And => (lhs != 0 && rhs != 0) as i64,
+            BinaryOp::Equal => (lhs == rhs) as i64,
+            BinaryOp::NotEqual => (lhs != rhs) as i64,
+            BinaryOp::Less => (lhs < rhs) as i64,
+            BinaryOp::LessEqual => (lhs <= rhs) as i64,
+            BinaryOp::Greater => (lhs > rhs) as i64,
+            BinaryOp::GreaterEqual => (lhs >= rhs) as i64,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::BitXor => lhs ^ rhs,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::ShiftLeft => lhs.wrapping_shl(rhs as u32),
+            BinaryOp::ShiftRight => lhs.wrapping_shr(rhs as u32),
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Subtract => lhs.wrapping_sub(rhs),
+            BinaryOp::Multiply => lhs.wrapping_mul(rhs),
+            // Dividing by zero isn't worth stopping the game over
+            BinaryOp::Divide => lhs.checked_div(rhs).unwrap_or(0),
+            BinaryOp::Remainder => lhs.checked_rem(rhs).unwrap_or(0),
+        }
+    }
+}
+
+// A small C-like expression over the CPU registers, flags and memory, e.g. `[0xc345] > 3 && A == 0xff`.
+// Registers are A-L, AF, BC, DE, HL, SP and PC, the flags ZF, NF, HF and CF, and [addr] reads a byte.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    // Bits of the flag in F
+    Flag(u8),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(source: &str) -> Result<Expr, AyyError> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let expr = parser.expression(0)?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => invalid(format!("unexpected {:?}", token)),
+        }
+    }
+
+    // Side-effect free, memory is read like the debugger views do
+    pub fn eval(&self, cpu: &Cpu, mmu: &Mmu) -> i64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(
+                register @ (Register::AF | Register::BC | Register::DE | Register::HL | Register::SP | Register::PC),
+            ) => cpu.read_register16(register) as i64,
+            Expr::Register(register) => cpu.read_register(register) as i64,
+            Expr::Flag(bits) => cpu.read_flag(Flags::from_bits_truncate(*bits)) as i64,
+            Expr::Memory(address) => mmu.peek(address.eval(cpu, mmu) as u16).unwrap_or(0xff) as i64,
+            Expr::Unary(op, operand) => {
+                let value = operand.eval(cpu, mmu);
+                match op {
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Complement => !value,
+                }
+            }
+            Expr::Binary(op, lhs, rhs) => op.apply(lhs.eval(cpu, mmu), rhs.eval(cpu, mmu)),
+        }
+    }
+}
+
+// An expression the debugger stops on, when it turns true rather than for as long as it stays true.
+// Otherwise continuing would stop again on the very next instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakCondition {
+    pub source: String,
+    expr: Expr,
+    held: bool,
+}
+
+impl BreakCondition {
+    pub fn parse(source: &str) -> Result<BreakCondition, AyyError> {
+        Ok(BreakCondition {
+            source: source.trim().to_owned(),
+            expr: Expr::parse(source)?,
+            held: false,
+        })
+    }
+
+    pub fn triggered(&mut self, cpu: &Cpu, mmu: &Mmu) -> bool {
+        let holds = self.expr.eval(cpu, mmu) != 0;
+        let triggered = holds && !self.held;
+        self.held = holds;
+        triggered
+    }
+}
+
+fn invalid<T>(reason: String) -> Result<T, AyyError> {
+    Err(AyyError::InvalidExpression { reason })
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, AyyError> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+
+    while let Some(c) = rest.chars().next() {
+        if let Some(operator) = OPERATORS.iter().find(|operator| rest.starts_with(**operator)) {
+            tokens.push(Token::Operator(operator));
+            rest = &rest[operator.len()..];
+        } else if "([".contains(c) {
+            tokens.push(Token::Open(c));
+            rest = &rest[1..];
+        } else if ")]".contains(c) {
+            tokens.push(Token::Close(c));
+            rest = &rest[1..];
+        } else if c.is_ascii_alphanumeric() || c == '$' || c == '_' {
+            let end = rest[1..]
+                .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+                .map_or(rest.len(), |end| end + 1);
+            let word = &rest[..end];
+            tokens.push(match c {
+                '$' => Token::Number(parse_number(word, &word[1..], 16)?),
+                '0'..='9' => match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(digits) => Token::Number(parse_number(word, digits, 16)?),
+                    None => Token::Number(parse_number(word, word, 10)?),
+                },
+                _ => Token::Name(word.to_lowercase()),
+            });
+            rest = &rest[end..];
+        } else {
+            return invalid(format!("unexpected '{}'", c));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(word: &str, digits: &str, radix: u32) -> Result<i64, AyyError> {
+    i64::from_str_radix(digits, radix).or_else(|_| invalid(format!("invalid number {}", word)))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Result<Token, AyyError> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        token.map_or_else(|| invalid(String::from("unexpected end")), Ok)
+    }
+
+    // Precedence climbing, every operator is left associative
+    fn expression(&mut self, min_precedence: u8) -> Result<Expr, AyyError> {
+        let mut lhs = self.operand()?;
+
+        while let Some(Token::Operator(operator)) = self.peek()
+            && let Some(op) = BinaryOp::from_operator(operator)
+            && op.precedence() > min_precedence
+        {
+            self.position += 1;
+            let rhs = self.expression(op.precedence())?;
+            lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        }
+
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Expr, AyyError> {
+        match self.next()? {
+            Token::Number(value) => Ok(Expr::Number(value)),
+            Token::Name(name) => name_to_expr(&name),
+            Token::Operator(operator @ ("!" | "-" | "~")) => {
+                let op = match operator {
+                    "!" => UnaryOp::Not,
+                    "-" => UnaryOp::Negate,
+                    _ => UnaryOp::Complement,
+                };
+                Ok(Expr::Unary(op, Box::new(self.operand()?)))
+            }
+            Token::Open(open) => {
+                let inner = self.expression(0)?;
+                let close = if open == '(' { ')' } else { ']' };
+                match self.next()? {
+                    Token::Close(c) if c == close => {}
+                    token => return invalid(format!("expected '{}', found {:?}", close, token)),
+                }
+                Ok(match open {
+                    '[' => Expr::Memory(Box::new(inner)),
+                    _ => inner,
+                })
+            }
+            token => invalid(format!("unexpected {:?}", token)),
+        }
+    }
+}
+
+fn name_to_expr(name: &str) -> Result<Expr, AyyError> {
+    let register = match name {
+        "a" => Register::A,
+        "b" => Register::B,
+        "c" => Register::C,
+        "d" => Register::D,
+        "e" => Register::E,
+        "h" => Register::H,
+        "l" => Register::L,
+        "f" => Register::F,
+        "af" => Register::AF,
+        "bc" => Register::BC,
+        "de" => Register::DE,
+        "hl" => Register::HL,
+        "sp" => Register::SP,
+        "pc" => Register::PC,
+        "zf" => return Ok(Expr::Flag(Flags::ZERO.bits())),
+        "nf" => return Ok(Expr::Flag(Flags::SUBTRACT.bits())),
+        "hf" => return Ok(Expr::Flag(Flags::HALF_CARRY.bits())),
+        "cf" => return Ok(Expr::Flag(Flags::CARRY.bits())),
+        _ => return invalid(format!("unknown register or flag {}", name)),
+    };
+    Ok(Expr::Register(register))
+}
@@ -5,8 +5,6 @@ use crate::lr35902::registers::Flags;
 use crate::lr35902::sm83::{AddressingMode, Condition, Instruction, Opcode, Operand, Register};
 use crate::memory::mmu::Mmu;
 
-use super::timer::Timer;
-
 macro_rules! invalid_handler {
     ($instruction:expr) => {
         Err(InvalidHandler {
@@ -77,7 +75,7 @@ impl Handlers {
                     cpu.write_register(reg, src as u8);
                 } else {
                     let addr = 0xff00 + cpu.read_register(reg) as u16;
-                    mmu.write(addr, src as u8)?;
+                    mmu.cpu_write(addr, src as u8)?;
                 }
             }
             Instruction {
@@ -86,7 +84,7 @@ impl Handlers {
                 ..
             } if mode.contains(AddressingMode::Increment) => {
                 let addr = cpu.read_register16(&Register::HL);
-                mmu.write(addr, src as u8)?;
+                mmu.cpu_write(addr, src as u8)?;
                 cpu.write_register16(&Register::HL, addr.wrapping_add(1));
             }
             Instruction {
@@ -95,7 +93,7 @@ impl Handlers {
                 ..
             } if mode.contains(AddressingMode::Decrement) => {
                 let addr = cpu.read_register16(&Register::HL);
-                mmu.write(addr, src as u8)?;
+                mmu.cpu_write(addr, src as u8)?;
                 cpu.write_register16(&Register::HL, addr.wrapping_sub(1));
             }
             Instruction {
@@ -111,7 +109,7 @@ impl Handlers {
                 ..
             } if mode.contains(AddressingMode::Indirect) => {
                 let addr = cpu.read_register16(reg);
-                mmu.write(addr, src as u8)?;
+                mmu.cpu_write(addr, src as u8)?;
             }
             Instruction {
                 opcode: Opcode::Ld,
@@ -120,7 +118,7 @@ impl Handlers {
                 ..
             } => {
                 let value = cpu.read_register16(reg);
-                mmu.write16(*addr, value)?;
+                mmu.cpu_write16(*addr, value)?;
             }
             Instruction {
                 opcode: Opcode::Ld,
@@ -129,14 +127,14 @@ impl Handlers {
                 ..
             } => {
                 let value = cpu.read_register(reg);
-                mmu.write(*addr, value)?;
+                mmu.cpu_write(*addr, value)?;
             }
             Instruction {
                 opcode: Opcode::Ldh,
                 lhs: Some(Operand::Imm8(addr, _)),
                 ..
             } => {
-                mmu.write(0xff00 + *addr as u16, src as u8)?;
+                mmu.cpu_write(0xff00 + *addr as u16, src as u8)?;
             }
             Instruction {
                 opcode: Opcode::Ldh,
@@ -144,7 +142,7 @@ impl Handlers {
                 rhs: Some(Operand::Imm8(addr, _)),
                 ..
             } => {
-                let value = mmu.read(0xff00 + *addr as u16)?;
+                let value = mmu.cpu_read(0xff00 + *addr as u16)?;
                 cpu.write_register(reg, value);
             }
             _ => return invalid_handler!(instruction),
@@ -383,10 +381,10 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(&Register::HL);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let carry = cpu.read_flag(Flags::CARRY) as u8;
                 let result = (value << 1) | carry;
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 cpu.update_flag(Flags::ZERO, result == 0);
                 cpu.update_flag(Flags::SUBTRACT, false);
@@ -432,9 +430,9 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(&Register::HL);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let result = (value << 1) | (value >> 7);
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 cpu.update_flag(Flags::ZERO, result == 0);
                 cpu.update_flag(Flags::SUBTRACT, false);
@@ -487,10 +485,10 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(&Register::HL);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let carry = cpu.read_flag(Flags::CARRY) as u8;
                 let result = (value >> 1) | (carry << 7);
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 cpu.update_flag(Flags::ZERO, result == 0);
                 cpu.update_flag(Flags::SUBTRACT, false);
@@ -536,9 +534,9 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(&Register::HL);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let result = (value >> 1) | (value << 7);
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 cpu.update_flag(Flags::ZERO, result == 0);
                 cpu.update_flag(Flags::SUBTRACT, false);
@@ -592,9 +590,9 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(&Register::HL);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let result = value << 1;
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 cpu.update_flag(Flags::ZERO, result == 0);
                 cpu.update_flag(Flags::SUBTRACT, false);
@@ -634,9 +632,9 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(&Register::HL);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let result = (value >> 1) | (value & 0x80);
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 cpu.update_flag(Flags::ZERO, result == 0);
                 cpu.update_flag(Flags::SUBTRACT, false);
@@ -667,9 +665,9 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(&Register::HL);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let result = value >> 1;
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 cpu.update_flag(Flags::ZERO, result == 0);
                 cpu.update_flag(Flags::SUBTRACT, false);
@@ -703,9 +701,9 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(&Register::HL);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let result = (value >> 4) | (value << 4);
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
                 result
             }
             _ => return invalid_handler!(instruction),
@@ -741,9 +739,9 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(register);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let result = value & !(1 << *bit);
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 Ok(instruction.cycles.0)
             }
@@ -773,9 +771,9 @@ impl Handlers {
                 ..
             } => {
                 let addr = cpu.read_register16(register);
-                let value = mmu.read(addr)?;
+                let value = mmu.cpu_read(addr)?;
                 let result = value | (1 << *bit);
-                mmu.write(addr, result)?;
+                mmu.cpu_write(addr, result)?;
 
                 Ok(instruction.cycles.0)
             }
@@ -822,8 +820,8 @@ impl Handlers {
     }
 
     #[inline]
-    pub fn stop(cpu: &mut Cpu, mmu: &mut Mmu, timer: &mut Timer, instruction: &Instruction) -> Result<usize, AyyError> {
-        timer.reset_divider(mmu);
+    pub fn stop(cpu: &mut Cpu, mmu: &mut Mmu, instruction: &Instruction) -> Result<usize, AyyError> {
+        mmu.reset_divider();
         mmu.enable_pending_speed_switch();
         Ok(instruction.cycles.0)
     }
@@ -966,9 +964,9 @@ impl Handlers {
             Operand::Reg16(reg, mode) => {
                 if mode.contains(AddressingMode::Indirect) {
                     let addr = cpu.read_register16(reg);
-                    let value = mmu.read(addr)?;
+                    let value = mmu.cpu_read(addr)?;
                     let result = value.wrapping_add(1);
-                    mmu.write(addr, result)?;
+                    mmu.cpu_write(addr, result)?;
 
                     cpu.update_flag(Flags::ZERO, result == 0);
                     cpu.update_flag(Flags::SUBTRACT, false);
@@ -1003,9 +1001,9 @@ impl Handlers {
             Operand::Reg16(reg, mode) => {
                 if mode.contains(AddressingMode::Indirect) {
                     let addr = cpu.read_register16(reg);
-                    let value = mmu.read(addr)?;
+                    let value = mmu.cpu_read(addr)?;
                     let result = value.wrapping_sub(1);
-                    mmu.write(addr, result)?;
+                    mmu.cpu_write(addr, result)?;
 
                     cpu.update_flag(Flags::ZERO, result == 0);
                     cpu.update_flag(Flags::SUBTRACT, true);
@@ -1077,24 +1075,25 @@ impl Handlers {
             Operand::Reg16(reg, mode) if mode.contains(AddressingMode::Direct) => {
                 Ok(cpu.read_register16(&reg) as usize)
             }
+            // Memory operands are always a single byte, reading the one after it would count as an access
             Operand::Reg16(reg, mode) if mode.contains(AddressingMode::Indirect) => {
                 let addr = cpu.read_register16(&reg);
                 Handlers::process_additional_address_mode(cpu, reg, addr, mode);
-                Ok(mmu.read16(addr)? as usize)
+                Ok(mmu.cpu_read(addr)? as usize)
             }
             Operand::Reg8(reg, mode) if mode.contains(AddressingMode::Direct) => Ok(cpu.read_register(&reg) as usize),
             Operand::Reg8(reg, mode) if mode.contains(AddressingMode::Indirect) => {
                 // ld a, (c)
                 let addr = cpu.read_register(&reg);
-                Ok(mmu.read(0xff00 + addr as u16)? as usize)
+                Ok(mmu.cpu_read(0xff00 + addr as u16)? as usize)
             }
             Operand::Imm16(imm, mode) if mode.contains(AddressingMode::Direct) => Ok(*imm as usize),
-            Operand::Imm16(imm, mode) if mode.contains(AddressingMode::Indirect) => Ok(mmu.read16(*imm)? as usize),
+            Operand::Imm16(imm, mode) if mode.contains(AddressingMode::Indirect) => Ok(mmu.cpu_read(*imm)? as usize),
             Operand::Imm8(imm, mode) if mode.contains(AddressingMode::Direct) => Ok(*imm as usize),
             Operand::Imm8(imm, mode) if mode.contains(AddressingMode::Indirect) && is_ldh => {
                 // ldh a, (imm)
                 let addr = 0xff00 + *imm as u16;
-                Ok(mmu.read(addr)? as usize)
+                Ok(mmu.cpu_read(addr)? as usize)
             }
             Operand::Bit(bit) => Ok(*bit as usize),
             Operand::Offset(offset) => Ok(*offset as usize),
@@ -1,4 +1,8 @@
+pub mod asm;
 pub mod cpu;
+pub mod diagnostics;
+pub mod disasm;
+pub mod expr;
 mod handlers;
 mod irq;
 pub mod registers;
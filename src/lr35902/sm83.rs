@@ -4,7 +4,57 @@ use bitflags::bitflags;
 use std::cmp::PartialEq;
 use std::collections::HashMap;
 
-type FDecode = fn(&Mmu, u16, Opcode) -> Result<Instruction, AyyError>;
+type FDecode = fn(&dyn DecodeSource, u16, Opcode) -> Result<Instruction, AyyError>;
+
+// Anything the decoder can fetch instruction bytes from
+pub trait DecodeSource {
+    fn read(&self, addr: u16) -> Result<u8, AyyError>;
+
+    fn read16(&self, addr: u16) -> Result<u16, AyyError> {
+        let lo = self.read(addr)? as u16;
+        let hi = self.read(addr.wrapping_add(1))? as u16;
+        Ok((hi << 8) | lo)
+    }
+}
+
+// Decoding ahead of time or for a disassembly, which the game doesn't notice
+impl DecodeSource for Mmu {
+    #[inline]
+    fn read(&self, addr: u16) -> Result<u8, AyyError> {
+        Mmu::peek(self, addr)
+    }
+}
+
+// Instruction fetches of the running CPU, which show up in the heatmap unlike decoding for a disassembly
+pub struct Fetch<'a>(pub &'a Mmu);
+
+impl DecodeSource for Fetch<'_> {
+    #[inline]
+    fn read(&self, addr: u16) -> Result<u8, AyyError> {
+        self.0.cpu_read(addr)
+    }
+}
+
+// A plain byte slice mapped at `base`, e.g. a single ROM bank
+pub struct ByteSource<'a> {
+    bytes: &'a [u8],
+    base: u16,
+}
+
+impl<'a> ByteSource<'a> {
+    pub fn new(bytes: &'a [u8], base: u16) -> ByteSource<'a> {
+        ByteSource { bytes, base }
+    }
+}
+
+impl DecodeSource for ByteSource<'_> {
+    fn read(&self, addr: u16) -> Result<u8, AyyError> {
+        addr.checked_sub(self.base)
+            .and_then(|offset| self.bytes.get(offset as usize))
+            .copied()
+            .ok_or(AyyError::OutOfBoundsMemoryAccess { address: addr })
+    }
+}
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum Register {
@@ -148,7 +198,7 @@ impl Sm83 {
         }
     }
 
-    pub fn decode(&mut self, mmu: &mut Mmu, current_pc: u16) -> Result<Instruction, AyyError> {
+    pub fn decode(&mut self, mmu: &dyn DecodeSource, current_pc: u16) -> Result<Instruction, AyyError> {
         let mut opcode_byte = mmu.read(current_pc)?;
 
         #[cfg(debug_assertions)]
@@ -1346,6 +1396,12 @@ impl Sm83 {
     }
 }
 
+impl Default for Sm83 {
+    fn default() -> Sm83 {
+        Sm83::new()
+    }
+}
+
 impl std::fmt::Display for Instruction {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let mut output = format!("{:?}", self.opcode).to_lowercase();
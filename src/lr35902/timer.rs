@@ -1,70 +1,120 @@
-use crate::memory::mmu::Mmu;
-use crate::memory::registers::InterruptFlags;
-use crate::memory::{DIV_REGISTER, INTERRUPT_FLAGS_REGISTER, TAC_REGISTER, TIMA_REGISTER, TMA_REGISTER};
+use crate::memory::{DIV_REGISTER, TAC_REGISTER, TIMA_REGISTER, TMA_REGISTER};
 
+// DIV, TIMA, TMA and TAC in one place. DIV is the upper byte of a 16-bit counter running off the CPU clock,
+// and TIMA counts up whenever the counter bit TAC selects falls from 1 to 0. Resetting DIV or changing TAC
+// can make that bit fall too, which counts TIMA up just the same, like on hardware.
+#[derive(Clone)]
 pub struct Timer {
-    cycles: usize,
+    counter: u16,
+    tima: u8,
+    tma: u8,
+    tac: u8,
 }
 
 impl Timer {
     pub fn new() -> Timer {
-        Timer { cycles: 0 }
-    }
-
-    pub fn tick(&mut self, mmu: &mut Mmu, cycles: usize) {
-        if self.read_tac(mmu) & 0b100 == 0 {
-            return;
+        Timer {
+            counter: 0,
+            tima: 0,
+            tma: 0,
+            tac: 0,
         }
+    }
 
-        self.cycles += cycles;
+    // Advances by CPU cycles, so in double speed the timer runs twice as fast just like DIV.
+    // Returns whether TIMA overflowed, which requests the timer interrupt.
+    pub fn tick(&mut self, cycles: usize) -> bool {
+        let edges = match self.period() {
+            Some(period) => (self.counter as usize % period + cycles) / period,
+            None => 0,
+        };
+        self.counter = self.counter.wrapping_add(cycles as u16);
 
-        let tima = self.read_tima(mmu);
-        let tma = self.read_tma(mmu);
+        let mut overflowed = false;
+        for _ in 0..edges {
+            overflowed |= self.increment();
+        }
+        overflowed
+    }
 
-        let mut cycles: usize = match self.read_tac(mmu) & 0b11 {
-            0b00 => 1024,
-            0b01 => 16,
-            0b10 => 64,
-            0b11 => 256,
-            _ => unreachable!(),
-        };
+    // CPU cycles until TIMA counts up next, None while the timer is stopped
+    pub fn cycles_until_tick(&self) -> Option<usize> {
+        self.period().map(|period| period - self.counter as usize % period)
+    }
 
-        cycles = match mmu.cgb_double_speed {
-            true => cycles * 2,
-            false => cycles,
-        };
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            DIV_REGISTER => (self.counter >> 8) as u8,
+            TIMA_REGISTER => self.tima,
+            TMA_REGISTER => self.tma,
+            TAC_REGISTER => self.tac,
+            _ => unreachable!("{:04x} is not a timer register", addr),
+        }
+    }
 
-        if self.cycles >= cycles {
-            if tima == 0xff {
-                mmu.write_unchecked(TIMA_REGISTER, tma);
-                mmu.write_unchecked(
-                    INTERRUPT_FLAGS_REGISTER,
-                    (mmu.read_as_unchecked::<InterruptFlags>(INTERRUPT_FLAGS_REGISTER) | InterruptFlags::TIMER).bits(),
-                );
-            } else {
-                mmu.write_unchecked(TIMA_REGISTER, tima.wrapping_add(1));
-            }
-
-            self.cycles -= cycles;
+    // Returns whether TIMA overflowed from the write
+    pub fn write(&mut self, addr: u16, data: u8) -> bool {
+        let before = self.selected_bit();
+        match addr {
+            // Any write clears the whole counter
+            DIV_REGISTER => self.counter = 0,
+            TIMA_REGISTER => self.tima = data,
+            TMA_REGISTER => self.tma = data,
+            TAC_REGISTER => self.tac = data & 0b111,
+            _ => unreachable!("{:04x} is not a timer register", addr),
         }
+
+        before && !self.selected_bit() && self.increment()
     }
 
-    pub fn reset_divider(&mut self, mmu: &mut Mmu) {
-        mmu.write_unchecked(DIV_REGISTER, 0);
+    // STOP resets DIV like a write does
+    pub fn reset_divider(&mut self) -> bool {
+        self.write(DIV_REGISTER, 0)
     }
 
     #[inline]
-    fn read_tima(&self, mmu: &Mmu) -> u8 {
-        mmu.read_unchecked(TIMA_REGISTER)
+    pub fn counter(&self) -> u16 {
+        self.counter
+    }
+
+    // Sets the counter DIV is the upper byte of, without any of the side effects a write to DIV has.
+    // Games that seed their RNG off DIV then see the same values every run for a given counter.
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
     }
 
+    // The counter bit TIMA follows is the one just below the period, ANDed with the enable bit
     #[inline]
-    fn read_tma(&self, mmu: &Mmu) -> u8 {
-        mmu.read_unchecked(TMA_REGISTER)
+    fn selected_bit(&self) -> bool {
+        self.period()
+            .is_some_and(|period| self.counter as usize & (period / 2) != 0)
     }
 
+    // In CPU cycles, None while stopped
     #[inline]
-    fn read_tac(&self, mmu: &Mmu) -> u8 {
-        mmu.read_unchecked(TAC_REGISTER)
+    fn period(&self) -> Option<usize> {
+        if self.tac & 0b100 == 0 {
+            return None;
+        }
+
+        Some(match self.tac & 0b11 {
+            0b00 => 1024,
+            0b01 => 16,
+            0b10 => 64,
+            _ => 256,
+        })
+    }
+
+    // Reloads from TMA on overflow
+    fn increment(&mut self) -> bool {
+        let (tima, overflowed) = self.tima.overflowing_add(1);
+        self.tima = if overflowed { self.tma } else { tima };
+        overflowed
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Timer {
+        Timer::new()
     }
 }
@@ -1,58 +1,449 @@
-#![feature(let_chains)]
-#![feature(custom_test_frameworks)]
-#![test_runner(datatest::runner)]
-
-mod error;
-mod frontend;
-mod gameboy;
-mod joypad;
-mod lr35902;
-mod memory;
-mod sound;
-mod tests;
-mod video;
-
-use crate::frontend::renderer::{Renderer, SCALE};
-use crate::gameboy::GameBoy;
-use crate::video::{SCREEN_HEIGHT, SCREEN_WIDTH};
-use clap::Parser;
+use ayyboy::frontend::archive::{load_rom, read_rom, ExtractionGuard, LoadedRom};
+use ayyboy::frontend::capture::{FrameSink, FrameStream};
+use ayyboy::frontend::frame_rng::FrameRng;
+use ayyboy::frontend::frame_trace;
+use ayyboy::frontend::gamepad::MAX_RUMBLE_STRENGTH;
+use ayyboy::frontend::launcher::{Launcher, SystemLoader};
+use ayyboy::frontend::log_file::{LogConfig, LogFile};
+use ayyboy::frontend::monkey;
+use ayyboy::frontend::netplay::{Netplay, SessionInfo, MAX_ROLLBACK};
+use ayyboy::frontend::renderer::{Renderer, MAX_FRAME_SKIP, MAX_RUN_AHEAD, SCALE};
+use ayyboy::frontend::rom_settings::RomSettings;
+use ayyboy::frontend::settings::{BackgroundPolicy, Presentation, Settings};
+use ayyboy::frontend::test_suite::{print_summary, run_tests, Outcome};
+use ayyboy::frontend::turbo::{MAX_TURBO_RATE, MIN_TURBO_RATE};
+use ayyboy::gameboy::GameBoy;
+use ayyboy::lr35902::disasm::disassemble;
+use ayyboy::memory::mapper::rtc::RtcMode;
+use ayyboy::memory::patch::crc32;
+use ayyboy::memory::ram_decay::RamDecay;
+use ayyboy::memory::ram_init::RamInit;
+use ayyboy::serial::LinkDevice;
+use ayyboy::sound::output::RodioSink;
+use ayyboy::sound::SyncMode;
+use ayyboy::system::{Button, RomHash, System};
+use ayyboy::video::compat::CompatPalette;
+use ayyboy::video::{Accuracy, ColorCorrection, Layers, SCREEN_HEIGHT, SCREEN_WIDTH};
+use clap::{Parser, Subcommand};
 use dark_light::Mode;
-use eframe::egui::{Style, ViewportBuilder, Visuals};
-use eframe::NativeOptions;
-use fern::Dispatch;
-use frontend::settings::Settings;
-use log::{info, LevelFilter};
-use std::fs::File;
-use zip::ZipArchive;
+use eframe::egui::{Context, Style, ViewportBuilder, Visuals};
+use eframe::{App, NativeOptions};
+use fern::{Dispatch, Output};
+use log::{error, info, warn, LevelFilter};
+use std::path::Path;
+use std::process::ExitCode;
+use std::sync::Mutex;
 
 #[derive(Parser, Debug)]
 struct Args {
-    rom: String,
+    /// Starts without a game if omitted, one can be opened from the window
+    rom: Option<String>,
     #[arg(long)]
     bios: Option<String>,
     #[arg(long, default_value_t = false)]
     log_to_file: bool,
+    /// Size the log file grows to before the next one is started, 0 lets it grow forever
+    #[arg(long, value_name = "MB", default_value_t = 100, requires = "log_to_file")]
+    log_max_size: u64,
+    /// Full log files to keep besides the current one (ayyboy_trace.log.1 is the newest)
+    #[arg(long, value_name = "FILES", default_value_t = 2, requires = "log_to_file")]
+    log_keep: usize,
+    /// Only keep the last MB of the log in memory, and write it out when an error is logged
+    #[arg(long, value_name = "MB", requires = "log_to_file")]
+    log_ring: Option<usize>,
+    #[arg(long, value_enum, default_value_t = RtcMode::Real)]
+    rtc: RtcMode,
+    /// Hardware to run on, defaults to what the cartridge header asks for. DMG games run in the CGB's compatibility
+    /// mode on a CGB
+    #[arg(long, value_enum)]
+    mode: Option<ayyboy::gameboy::Mode>,
+    /// Experimental: multiplies the CPU speed without affecting PPU/APU timing (breaks accuracy)
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=8))]
+    overclock: u8,
+    /// Power-on contents of WRAM/VRAM/OAM: zero, pattern or random:<seed>
+    #[arg(long, default_value = "zero")]
+    ram_init: RamInit,
+    /// Experimental: adds a power-off action to the controls that decays this share of the cartridge RAM, as if it
+    /// had no battery. PERCENT[:SEED], the seed picks which bits decay first
+    #[arg(long, value_name = "PERCENT[:SEED]")]
+    ram_decay: Option<RamDecay>,
+    /// Power-on value of the 16-bit divider (DIV is its upper byte), keeps DIV-seeded RNG the same every run
+    #[arg(long, value_name = "COUNTER", default_value_t = 0)]
+    initial_div: u16,
+    /// Hides input lag by showing the frame N frames ahead, 0 disables it
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=MAX_RUN_AHEAD as i64))]
+    run_ahead: u8,
+    /// Only draw every N+1th frame, for hosts too slow to render every frame
+    #[arg(long, default_value_t = 0, value_parser = clap::value_parser!(u8).range(0..=MAX_FRAME_SKIP as i64))]
+    frame_skip: u8,
+    /// Skip drawing frames only while the host can't keep up, at most --frame-skip (or 4) in a row
+    #[arg(long, default_value_t = false)]
+    auto_frame_skip: bool,
+    /// Draw scanlines on a separate thread while the CPU keeps running
+    #[arg(long, default_value_t = false)]
+    render_thread: bool,
+    /// Rendering accuracy, defaults to what was last picked for the ROM or balanced
+    #[arg(long, value_enum)]
+    accuracy: Option<Accuracy>,
+    /// How CGB colors are shown, defaults to what was last picked for the ROM or raw
+    #[arg(long, value_enum)]
+    color_correction: Option<ColorCorrection>,
+    /// Colors DMG games like a CGB would with one of its boot ROM palettes, defaults to what was last picked for
+    /// the ROM or off
+    #[arg(long, value_enum)]
+    palette: Option<CompatPalette>,
+    /// Device plugged into the link port, printed pages are saved as PNGs next to the ROM
+    #[arg(long, value_enum, default_value_t = LinkDevice::None)]
+    link: LinkDevice,
+    /// Pace the emulation by the audio device (no drift between video and sound) or by a timer
+    #[arg(long, value_enum, default_value_t = SyncMode::Audio)]
+    sync: SyncMode,
+    /// Host a netplay session on the given port
+    #[arg(long, value_name = "PORT", conflicts_with = "netplay_connect")]
+    netplay_host: Option<u16>,
+    /// Join a netplay session at host:port
+    #[arg(long, value_name = "ADDRESS")]
+    netplay_connect: Option<String>,
+    /// Frames local input is delayed by during netplay
+    #[arg(long, value_name = "FRAMES", default_value_t = 2, value_parser = clap::value_parser!(u8).range(0..=MAX_ROLLBACK as i64))]
+    netplay_delay: u8,
+    /// Stream the screen as raw RGB24 video to clients connecting to this local port
+    #[arg(long, value_name = "PORT")]
+    stream: Option<u16>,
+    /// IPS or BPS patch to apply to the ROM, defaults to a .ips/.bps file with the same name as the ROM
+    #[arg(long)]
+    patch: Option<String>,
+    /// Use saves and save states made with a different revision of the ROM instead of refusing them
+    #[arg(long, default_value_t = false)]
+    allow_rom_mismatch: bool,
+    /// Show the game being played on your Discord profile (needs the discord feature)
+    #[arg(long, default_value_t = false)]
+    discord_presence: bool,
+    /// Buttons that fire repeatedly while held, e.g. --turbo a,b
+    #[arg(long, value_enum, value_delimiter = ',')]
+    turbo: Vec<Button>,
+    /// Presses per second of the turbo buttons
+    #[arg(long, value_name = "HZ", default_value_t = 10, value_parser = clap::value_parser!(u32).range(MIN_TURBO_RATE as i64..=MAX_TURBO_RATE as i64))]
+    turbo_rate: u32,
+    /// How hard the gamepad rumbles along with rumble cartridges in percent, 0 disables it (needs the gamepad feature)
+    #[arg(long, value_name = "PERCENT", default_value_t = MAX_RUMBLE_STRENGTH, value_parser = clap::value_parser!(u32).range(0..=MAX_RUMBLE_STRENGTH as i64))]
+    rumble_strength: u32,
+    /// What to do while the window is minimized or unfocused, netplay always keeps running
+    #[arg(long, value_enum, default_value_t = BackgroundPolicy::Continue)]
+    background: BackgroundPolicy,
+    /// How frames are drawn: as an image in the UI, or directly with OpenGL for less overhead per frame
+    #[arg(long, value_enum, default_value_t = Presentation::Image)]
+    presentation: Presentation,
+    /// Warn in the debugger when the stack runs into ROM or IO registers, or over the code being run
+    #[arg(long, default_value_t = false)]
+    stack_check: bool,
+    /// Warn in the debugger when the game reads WRAM/HRAM it never wrote, which is random on hardware
+    #[arg(long, default_value_t = false)]
+    dev_mode: bool,
+    /// Run opcodes that can't be decoded as NOPs (with a warning) instead of stopping, for corrupted dumps
+    #[arg(long, default_value_t = false)]
+    lenient: bool,
+    /// Run headless for this many frames pressing random buttons, stops at the first frame the emulator fails on
+    #[arg(long, value_name = "FRAMES")]
+    monkey: Option<u64>,
+    /// Seed of the buttons --monkey presses, the same seed presses the same buttons
+    #[arg(long, value_name = "SEED", default_value_t = 0)]
+    monkey_seed: u64,
+    /// Save the buttons --monkey pressed up to a failure to this file
+    #[arg(long, value_name = "PATH")]
+    monkey_movie: Option<String>,
+    /// Have --monkey press the buttons saved with --monkey-movie instead, to reproduce a failure
+    #[arg(long, value_name = "PATH")]
+    monkey_replay: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-fn main() {
-    let args = Args::parse();
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Disassemble instructions from a ROM without running it
+    Disasm {
+        rom: String,
+        /// ROM bank mapped into $4000-$7fff
+        #[arg(long, default_value_t = 1)]
+        bank: usize,
+        #[arg(long, default_value = "0x100", value_parser = parse_address)]
+        addr: u16,
+        #[arg(long, default_value_t = 32)]
+        count: usize,
+    },
+    /// Run the blargg, mooneye and acid2 test ROMs in a directory headless and print which pass
+    TestSuite {
+        #[arg(long, default_value = "external/roms/tests")]
+        dir: String,
+    },
+    /// Run a ROM headless and record a hash of every frame, optionally diffing them against an earlier trace
+    FrameTrace {
+        rom: String,
+        #[arg(long, default_value_t = 600)]
+        frames: u64,
+        /// Directory the hashes and frames go to
+        #[arg(long, value_name = "TRACE_DIR")]
+        out: String,
+        /// Trace of another build to compare with, both versions of the first frame that differs are saved to --out
+        #[arg(long, value_name = "OTHER_TRACE_DIR")]
+        compare_run: Option<String>,
+        /// Press random buttons every frame, the same ones each run with the same seed
+        #[arg(long, value_name = "SEED")]
+        mash: Option<u64>,
+    },
+}
 
-    setup_logging(args.log_to_file);
+// Exit codes are returned instead of exiting right away, so that the unpacked ROMs get cleaned up
+fn main() -> ExitCode {
+    let mut args = Args::parse();
+    // Zipped ROMs stay unpacked for as long as we run
+    let _extracted = ExtractionGuard::acquire();
+
+    if let Some(command) = args.command.take() {
+        return run_command(command);
+    }
+
+    let log_config = LogConfig {
+        max_size: args.log_max_size * 1024 * 1024,
+        keep: args.log_keep,
+        ring: args.log_ring.map(|size| size * 1024 * 1024),
+    };
+    setup_logging(args.log_to_file.then_some(log_config));
 
     let bootrom = match &args.bios {
         Some(bios) => Some(std::fs::read(bios).expect("Failed to read BIOS file")),
         None => None,
     };
 
-    let mut gameboy = GameBoy::new(bootrom, load_rom(&args.rom));
+    let frame_sink = match args.stream.map(FrameStream::bind) {
+        Some(Ok(stream)) => Some(Box::new(stream) as Box<dyn FrameSink>),
+        Some(Err(e)) => {
+            error!("{}", e);
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+
+    if let Some(frames) = args.monkey {
+        return run_monkey(&args, bootrom, frames);
+    }
+
+    let netplay_requested = args.netplay_host.is_some() || args.netplay_connect.is_some();
+
+    // Without a ROM we idle on the controls screen until one is opened
+    let Some(rom_path) = args.rom.clone() else {
+        if netplay_requested {
+            error!("Netplay needs a ROM to play");
+            return ExitCode::FAILURE;
+        }
+
+        run_launcher(args, bootrom, frame_sink, None);
+        return ExitCode::SUCCESS;
+    };
+
+    // Both peers have to start from the exact same state
+    let mut rtc_mode = args.rtc;
+    if netplay_requested && rtc_mode == RtcMode::Real {
+        info!("Netplay: using emulated RTC so that both peers stay in sync");
+        rtc_mode = RtcMode::Emulated;
+    }
+
+    let loaded = read_rom(&rom_path, args.patch.clone()).and_then(|LoadedRom { path, rom }| {
+        let rom_hash = RomHash::of(&rom);
+        build_system(&args, bootrom.clone(), &rom_path, &path, rom, rtc_mode).map(|system| (system, rom_hash))
+    });
+    let ((system, game_settings), rom_hash) = match loaded {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            error!("{}", e);
+            // Show what went wrong instead of just closing, another ROM can be picked from there
+            if netplay_requested {
+                return ExitCode::FAILURE;
+            }
+            run_launcher(args, bootrom, frame_sink, Some((rom_path, e)));
+            return ExitCode::SUCCESS;
+        }
+    };
+
+    let netplay = if netplay_requested {
+        let mut settings = format!(
+            "{:?} {:?} {} {} {} {:?} {:?}",
+            args.mode,
+            args.ram_init,
+            args.initial_div,
+            args.overclock,
+            args.lenient,
+            bootrom.as_deref().map(crc32),
+            args.link
+        )
+        .into_bytes();
+        settings.extend(std::fs::read(format!("{}.sav", &game_settings.rom_path)).unwrap_or_default());
+        settings.extend(std::fs::read(format!("{}.rtc", &game_settings.rom_path)).unwrap_or_default());
+
+        let session = SessionInfo {
+            rom_hash,
+            settings_crc: crc32(&settings),
+            delay: args.netplay_delay,
+        };
+        let netplay = match (args.netplay_host, &args.netplay_connect) {
+            (Some(port), _) => Netplay::host(port, session),
+            (_, Some(address)) => Netplay::connect(address, session),
+            _ => unreachable!(),
+        };
+
+        match netplay {
+            Ok(netplay) => Some(netplay),
+            Err(e) => {
+                error!("{}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        None
+    };
+
+    run_window(move |ctx| Box::new(Renderer::new(ctx, system, game_settings, netplay, frame_sink)));
+    ExitCode::SUCCESS
+}
+
+// Opens the window without a game, optionally showing the ROM that failed to open and why
+fn run_launcher(
+    args: Args, bootrom: Option<Vec<u8>>, frame_sink: Option<Box<dyn FrameSink>>, failed: Option<(String, String)>,
+) {
+    let rtc_mode = args.rtc;
+    let loader: SystemLoader = Box::new(move |opened_path: &str| {
+        let LoadedRom { path, rom } = read_rom(opened_path, args.patch.clone())?;
+        build_system(&args, bootrom.clone(), opened_path, &path, rom, rtc_mode)
+    });
+
+    let mut launcher = Launcher::new(loader, frame_sink);
+    if let Some((rom_path, error)) = failed {
+        launcher.show_error(&rom_path, error);
+    }
+    run_window(move |_| Box::new(launcher));
+}
+
+// Presses buttons at random (or those of a movie) headless until the emulator fails or the frames run out
+fn run_monkey(args: &Args, bootrom: Option<Vec<u8>>, frames: u64) -> ExitCode {
+    let Some(rom_path) = &args.rom else {
+        error!("--monkey needs a ROM to play");
+        return ExitCode::FAILURE;
+    };
+    let mut gameboy = match read_rom(rom_path, args.patch.clone())
+        .and_then(|loaded| build_gameboy(args, bootrom, &loaded.path, loaded.rom))
+    {
+        Ok(gameboy) => gameboy,
+        Err(e) => {
+            error!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let replay = match args
+        .monkey_replay
+        .as_deref()
+        .map(|path| (path, monkey::load_movie(Path::new(path))))
+    {
+        Some((_, Ok(inputs))) => Some(inputs),
+        Some((path, Err(e))) => {
+            error!("Failed to read movie {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+        None => None,
+    };
+    let rng = FrameRng::new(args.monkey_seed);
+    let mut input = |frame: u64| match &replay {
+        // Nothing is held once the movie is over
+        Some(inputs) => inputs.get(frame as usize).copied().unwrap_or(0),
+        None => rng.buttons(frame),
+    };
+    let report = monkey::run(&mut gameboy, frames, &mut input);
+
+    let Some(failure) = report.failure else {
+        println!("Ran {} frames without failing", frames);
+        return ExitCode::SUCCESS;
+    };
+    println!("Failed on frame {}: {}", failure.frame, failure.message);
+    if let Some(path) = &args.monkey_movie {
+        let header = format!(
+            "ayyboy monkey movie for {}\nrom {} seed {}\nfailed on frame {}: {}",
+            gameboy.title(),
+            gameboy.rom_hash(),
+            args.monkey_seed,
+            failure.frame,
+            failure.message.lines().next().unwrap_or_default()
+        );
+        match monkey::save_movie(Path::new(path), &header, &report.inputs) {
+            Ok(_) => println!("Saved the inputs to {}, replay them with --monkey-replay", path),
+            Err(e) => eprintln!("Failed to save the inputs to {}: {}", path, e),
+        }
+    }
+    // Lets scripts and CI tell that something broke
+    ExitCode::FAILURE
+}
+
+// The console as the options set it up, without anything that needs the host like audio
+fn build_gameboy(args: &Args, bootrom: Option<Vec<u8>>, rom_path: &str, rom: Vec<u8>) -> Result<GameBoy, String> {
+    let mut gameboy = GameBoy::try_new_on(bootrom, rom, args.mode.clone())
+        .map_err(|e| format!("Failed to open {}: {}", rom_path, e))?;
+    gameboy.set_power_on_state(args.ram_init.clone(), args.initial_div);
+    if args.overclock > 1 {
+        warn!(
+            "CPU overclocked by {}x, expect timing sensitive games to break",
+            args.overclock
+        );
+        gameboy.cpu.set_overclock(args.overclock as usize);
+    }
+    gameboy.ppu.set_threaded_rendering(args.render_thread);
+    gameboy.cpu.set_stack_check(args.stack_check);
+    gameboy.cpu.set_lenient(args.lenient);
+    gameboy.mmu.uninit.set_enabled(args.dev_mode);
+    Ok(gameboy)
+}
 
-    // if there's a sav file, load into cart
-    let save_path = format!("{}.sav", &args.rom);
-    if let Ok(cart_ram) = std::fs::read(&save_path) {
-        gameboy.mmu.cartridge.load_ram(cart_ram);
-        info!("Loaded cartridge RAM from {}", save_path);
+// The opened path is what the user picked, the ROM path where the ROM's files go (see read_rom)
+fn build_system(
+    args: &Args, bootrom: Option<Vec<u8>>, opened_path: &str, rom_path: &str, rom: Vec<u8>, rtc_mode: RtcMode,
+) -> Result<(Box<dyn System>, Settings), String> {
+    let mut gameboy = build_gameboy(args, bootrom, rom_path, rom)?;
+    match RodioSink::open() {
+        Ok(sink) => gameboy.mmu.apu.set_audio_sink(Box::new(sink)),
+        Err(e) => error!("{}, running without sound", e),
     }
+    gameboy.mmu.serial.connect(args.link.create());
+
+    let rom_settings = RomSettings::load(rom_path);
+    let settings = Settings {
+        rom_path: rom_path.to_owned(),
+        opened_path: opened_path.to_owned(),
+        rtc_mode,
+        run_ahead: args.run_ahead > 0,
+        run_ahead_frames: (args.run_ahead as usize).max(1),
+        sync_mode: args.sync,
+        frame_skip: match args.frame_skip {
+            0 if args.auto_frame_skip => MAX_FRAME_SKIP,
+            frames => frames as usize,
+        },
+        auto_frame_skip: args.auto_frame_skip,
+        accuracy: args.accuracy.unwrap_or(rom_settings.accuracy),
+        color_correction: args.color_correction.unwrap_or(rom_settings.color_correction),
+        compat_palette: args.palette.unwrap_or(rom_settings.compat_palette),
+        layers: Layers::default(),
+        allow_rom_mismatch: args.allow_rom_mismatch,
+        discord_presence: args.discord_presence,
+        turbo_buttons: args.turbo.iter().fold(0, |buttons, button| buttons | button.mask()),
+        turbo_rate: args.turbo_rate,
+        rumble_strength: args.rumble_strength,
+        ram_decay: args.ram_decay,
+        background: args.background,
+        presentation: args.presentation,
+    };
+
+    Ok((Box::new(gameboy), settings))
+}
 
+fn run_window(app: impl FnOnce(&Context) -> Box<dyn App> + 'static) {
     let native_options = NativeOptions {
         viewport: ViewportBuilder::default()
             .with_inner_size([(SCREEN_WIDTH * SCALE) as f32, (SCREEN_HEIGHT * SCALE) as f32])
@@ -74,54 +465,132 @@ fn main() {
                 ..Style::default()
             };
             cc.egui_ctx.set_style(style);
-            Box::new(Renderer::new(cc, gameboy, Settings { rom_path: args.rom }))
+            app(&cc.egui_ctx)
         }),
     );
 }
 
-fn load_rom(filepath: &str) -> Vec<u8> {
-    if filepath.ends_with(".zip") {
-        let file = File::open(&filepath).unwrap();
-        let unzipped_filepath = unzip_rom(file);
-        info!("Unzipped {} to {}", &filepath, unzipped_filepath);
-        std::fs::read(&unzipped_filepath).expect("Failed to read ROM file")
-    } else {
-        std::fs::read(&filepath).expect("Failed to read ROM file")
+fn run_command(command: Command) -> ExitCode {
+    match command {
+        Command::Disasm { rom, bank, addr, count } => {
+            let rom = match load_rom(&rom) {
+                Ok(loaded) => loaded.rom,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            match disassemble(&rom, bank, addr, count) {
+                Ok(instructions) => instructions.iter().for_each(|instruction| println!("{}", instruction)),
+                Err(e) => {
+                    eprintln!("Failed to disassemble: {}", e);
+                    return ExitCode::FAILURE;
+                }
+            }
+            ExitCode::SUCCESS
+        }
+        Command::TestSuite { dir } => match run_tests(&dir) {
+            Ok(results) => {
+                print_summary(&results);
+                // Lets scripts and CI tell that something regressed
+                match results.iter().any(|result| result.outcome != Outcome::Passed) {
+                    true => ExitCode::FAILURE,
+                    false => ExitCode::SUCCESS,
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                ExitCode::FAILURE
+            }
+        },
+        Command::FrameTrace {
+            rom,
+            frames,
+            out,
+            compare_run,
+            mash,
+        } => {
+            let mut gb = match load_rom(&rom)
+                .map_err(|e| e.to_string())
+                .and_then(|loaded| GameBoy::try_new(None, loaded.rom).map_err(|e| e.to_string()))
+            {
+                Ok(gb) => gb,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let out = Path::new(&out);
+            let this = match frame_trace::record(&mut gb, frames, out, mash.map(FrameRng::new).as_ref()) {
+                Ok(hashes) => hashes,
+                Err(e) => {
+                    eprintln!("Failed to write the trace to {}: {}", out.display(), e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            println!("Recorded {} frames to {}", this.len(), out.display());
+
+            let Some(other_dir) = compare_run else {
+                return ExitCode::SUCCESS;
+            };
+            let other_dir = Path::new(&other_dir);
+            let other = match frame_trace::load_hashes(other_dir) {
+                Ok(hashes) => hashes,
+                Err(e) => {
+                    eprintln!("Failed to read the trace in {}: {}", other_dir.display(), e);
+                    return ExitCode::FAILURE;
+                }
+            };
+            let Some(frame) = frame_trace::first_divergence(&this, &other) else {
+                println!("All {} frames match {}", this.len(), other_dir.display());
+                return ExitCode::SUCCESS;
+            };
+
+            println!("First divergent frame: {}", frame);
+            match frame_trace::dump_divergence(out, other_dir, frame, &this, &other) {
+                Ok(dumped) => dumped.iter().for_each(|path| println!("  {}", path.display())),
+                Err(e) => eprintln!("Failed to save the divergent frames: {}", e),
+            }
+            ExitCode::FAILURE
+        }
     }
 }
 
-fn unzip_rom(file: File) -> String {
-    let mut archive = ZipArchive::new(file).unwrap();
-    let mut rom = archive.by_index(0).unwrap();
-
-    let filepath = match rom.enclosed_name() {
-        Some(name) => name.to_owned(),
-        None => panic!("No file found in zip archive"),
+fn parse_address(value: &str) -> Result<u16, String> {
+    let (digits, radix) = match value.strip_prefix("0x").or_else(|| value.strip_prefix('$')) {
+        Some(hex) => (hex, 16),
+        None => (value, 10),
     };
-    let tempfolder = std::env::temp_dir();
-    let filepath = tempfolder.join(&filepath);
-    let filepath = filepath.to_str().unwrap().to_owned();
 
-    let mut unpacked_file = File::create(&filepath).unwrap();
-    std::io::copy(&mut rom, &mut unpacked_file).unwrap();
-
-    filepath
+    u16::from_str_radix(digits, radix).map_err(|e| format!("invalid address {}: {}", value, e))
 }
 
-fn setup_logging(log_to_file: bool) {
-    let base_config = if !log_to_file {
-        Dispatch::new()
+// Logs to stdout, or traces into a file that is rotated as configured
+fn setup_logging(log_file: Option<LogConfig>) {
+    let base_config = match log_file {
+        None => Dispatch::new()
             .level(LevelFilter::Off)
             .level_for("ayyboy", LevelFilter::Debug)
-            .chain(std::io::stdout())
-    } else {
-        const LOG_PATH: &str = "./ayyboy_trace.log";
-        std::fs::remove_file(LOG_PATH).unwrap_or_default();
+            .chain(std::io::stdout()),
+        Some(config) => {
+            const LOG_PATH: &str = "./ayyboy_trace.log";
+            let file = Mutex::new(LogFile::create(Path::new(LOG_PATH), &config).expect("Failed to create log file"));
 
-        Dispatch::new()
-            .level(LevelFilter::Off)
-            .level_for("ayyboy", LevelFilter::Trace)
-            .chain(fern::log_file(LOG_PATH).unwrap())
+            // Panics are logged as errors, which also writes out what the ring kept before them
+            let default_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                error!("{}", info);
+                default_hook(info);
+            }));
+
+            Dispatch::new()
+                .level(LevelFilter::Off)
+                .level_for("ayyboy", LevelFilter::Trace)
+                .chain(Output::call(move |record| {
+                    // Nowhere left to report it
+                    let _ = file.lock().unwrap().log(record.level(), record.args().to_string());
+                }))
+        }
     };
 
     base_config
@@ -0,0 +1,51 @@
+use crate::memory::mapper::Mapper;
+use crate::memory::ROM_END;
+
+// Everything from here to ROM_END is whatever ROM bank the mapper has switched in
+pub const SWITCHABLE_ROM_START: u16 = 0x4000;
+
+// An address qualified with the ROM bank it belongs to, written as "bank:addr" like in the disassembly.
+// Outside of the switchable ROM area the bank is always 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BankedAddress {
+    pub bank: u16,
+    pub addr: u16,
+}
+
+impl BankedAddress {
+    pub fn new(bank: u16, addr: u16) -> BankedAddress {
+        match (SWITCHABLE_ROM_START..=ROM_END).contains(&addr) {
+            true => BankedAddress { bank, addr },
+            false => BankedAddress { bank: 0, addr },
+        }
+    }
+
+    // The address as the CPU sees it right now
+    pub fn current(addr: u16, mapper: &dyn Mapper) -> BankedAddress {
+        BankedAddress::new(mapper.current_rom_bank(), addr)
+    }
+
+    // Accepts "bank:addr" or a plain address, which is qualified with the currently mapped bank.
+    // Both parts are hex, optionally prefixed with "$" or "0x".
+    pub fn parse(value: &str, mapper: &dyn Mapper) -> Option<BankedAddress> {
+        let hex = |value: &str| {
+            let value = value.trim();
+            let digits = value
+                .strip_prefix("0x")
+                .or_else(|| value.strip_prefix('$'))
+                .unwrap_or(value);
+            u16::from_str_radix(digits, 16).ok()
+        };
+
+        match value.split_once(':') {
+            Some((bank, addr)) => Some(BankedAddress::new(hex(bank)?, hex(addr)?)),
+            None => Some(BankedAddress::current(hex(value)?, mapper)),
+        }
+    }
+}
+
+impl std::fmt::Display for BankedAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:02x}:{:04x}", self.bank, self.addr)
+    }
+}
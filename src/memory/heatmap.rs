@@ -0,0 +1,67 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+pub const PAGE_SIZE: usize = 0x100;
+pub const PAGE_COUNT: usize = 0x10000 / PAGE_SIZE;
+
+// Per-page read/write counters. Reads only borrow the Mmu immutably, so the counters are atomics,
+// which also lets other threads (e.g. a frontend) observe them without locking.
+pub struct AccessHeatmap {
+    reads: [AtomicU32; PAGE_COUNT],
+    writes: [AtomicU32; PAGE_COUNT],
+    // Counts of the last completed frame, this is what gets displayed
+    frame_reads: [u32; PAGE_COUNT],
+    frame_writes: [u32; PAGE_COUNT],
+}
+
+impl AccessHeatmap {
+    pub fn new() -> AccessHeatmap {
+        AccessHeatmap {
+            reads: std::array::from_fn(|_| AtomicU32::new(0)),
+            writes: std::array::from_fn(|_| AtomicU32::new(0)),
+            frame_reads: [0; PAGE_COUNT],
+            frame_writes: [0; PAGE_COUNT],
+        }
+    }
+
+    #[inline]
+    pub fn record_read(&self, addr: u16) {
+        self.reads[addr as usize / PAGE_SIZE].fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn record_write(&self, addr: u16) {
+        self.writes[addr as usize / PAGE_SIZE].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Publishes the counts of the frame that just finished and starts counting from zero again
+    pub fn end_frame(&mut self) {
+        for page in 0..PAGE_COUNT {
+            self.frame_reads[page] = self.reads[page].swap(0, Ordering::Relaxed);
+            self.frame_writes[page] = self.writes[page].swap(0, Ordering::Relaxed);
+        }
+    }
+
+    #[inline]
+    pub fn reads(&self, page: usize) -> u32 {
+        self.frame_reads[page]
+    }
+
+    #[inline]
+    pub fn writes(&self, page: usize) -> u32 {
+        self.frame_writes[page]
+    }
+}
+
+impl Default for AccessHeatmap {
+    fn default() -> AccessHeatmap {
+        AccessHeatmap::new()
+    }
+}
+
+// Copies (run-ahead, save states) start counting from zero, the heatmap stays with the console that is
+// actually running like the IO log does
+impl Clone for AccessHeatmap {
+    fn clone(&self) -> AccessHeatmap {
+        AccessHeatmap::new()
+    }
+}
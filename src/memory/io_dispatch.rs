@@ -0,0 +1,342 @@
+use crate::memory::registers::{self, RegisterDoc};
+use crate::memory::{
+    BACKGROUND_PALETTE_DATA_REGISTER, BACKGROUND_PALETTE_INDEX_REGISTER, BOOTROM_MAPPER_REGISTER, CPU_MODE_REGISTER,
+    DIV_REGISTER, DOUBLE_SPEED_SWITCH_REGISTER, HDMA_LENGTH_MODE_START_REGISTER, HDMA_VRAM_DST_HIGH_REGISTER,
+    HDMA_VRAM_DST_LOW_REGISTER, HDMA_VRAM_SRC_HIGH_REGISTER, HDMA_VRAM_SRC_LOW_REGISTER, INFRARED_REGISTER,
+    INTERRUPT_ENABLE_REGISTER, INTERRUPT_FLAGS_REGISTER, IO_END, IO_START, JOYPAD_REGISTER, OAM_DMA_REGISTER,
+    OBJECT_PALETTE_DATA_REGISTER, OBJECT_PALETTE_INDEX_REGISTER, TAC_REGISTER, TIMA_REGISTER, TMA_REGISTER,
+    VRAM_BANK_SELECT_REGISTER, WRAM_BANK_SELECT_REGISTER,
+};
+use crate::serial::{SERIAL_CONTROL_REGISTER, SERIAL_DATA_REGISTER};
+use crate::sound::{
+    NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24, NR30, NR31, NR32, NR33, NR34, NR41, NR42, NR43, NR44, NR50,
+    NR51, NR52, PCM12, PCM34, WAVE_PATTERN_RAM_END, WAVE_PATTERN_RAM_START,
+};
+use crate::video::{
+    BG_PALETTE_REGISTER, LCD_CONTROL_REGISTER, LCD_STATUS_REGISTER, OBJ0_PALETTE_REGISTER, OBJ1_PALETTE_REGISTER,
+    SCANLINE_Y_COMPARE_REGISTER, SCANLINE_Y_REGISTER, SCROLL_X_REGISTER, SCROLL_Y_REGISTER, WINDOW_X_REGISTER,
+    WINDOW_Y_REGISTER,
+};
+
+// Who answers for an IO register, Mmu::read_io/write_io do what each of them stands for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IoHandler {
+    // Plain memory, the hardware picks the value up from there
+    Memory,
+    Joypad,
+    Serial,
+    Timer,
+    Apu,
+    // Read-only, writes go nowhere
+    Pcm,
+    LcdStatus,
+    Lyc,
+    OamDma,
+    BootRom,
+    // Only the boot ROM can write it
+    CpuMode,
+    DoubleSpeed,
+    HdmaSourceHigh,
+    HdmaSourceLow,
+    HdmaDestinationHigh,
+    HdmaDestinationLow,
+    HdmaStart,
+    PaletteIndex,
+    PaletteData,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoEntry {
+    pub handler: IoHandler,
+    // Registers that only exist on the CGB are plain memory on the DMG
+    pub cgb_only: bool,
+    // Bits that always read as 1, e.g. unused ones
+    pub read_mask: u8,
+    // Bits writes can change, the others keep their value
+    pub write_mask: u8,
+    // Name and layout for the debugger, None for registers that don't exist
+    pub doc: Option<&'static RegisterDoc>,
+}
+
+impl IoEntry {
+    const fn new(handler: IoHandler) -> IoEntry {
+        IoEntry {
+            handler,
+            cgb_only: false,
+            read_mask: 0,
+            write_mask: 0xff,
+            doc: None,
+        }
+    }
+
+    const fn cgb(handler: IoHandler) -> IoEntry {
+        IoEntry {
+            cgb_only: true,
+            ..IoEntry::new(handler)
+        }
+    }
+
+    const fn masked(self, read_mask: u8, write_mask: u8) -> IoEntry {
+        IoEntry {
+            read_mask,
+            write_mask,
+            ..self
+        }
+    }
+
+    const fn doc(self, doc: &'static RegisterDoc) -> IoEntry {
+        IoEntry { doc: Some(doc), ..self }
+    }
+}
+
+pub const IO_REGISTER_COUNT: usize = (IO_END - IO_START) as usize + 1;
+
+// Indexed by the address minus IO_START, anything not listed is plain memory
+pub const IO_TABLE: [IoEntry; IO_REGISTER_COUNT] = io_table();
+
+const fn io_table() -> [IoEntry; IO_REGISTER_COUNT] {
+    let mut table = [IoEntry::new(IoHandler::Memory); IO_REGISTER_COUNT];
+
+    set(
+        &mut table,
+        JOYPAD_REGISTER,
+        IoEntry::new(IoHandler::Joypad).doc(&registers::P1),
+    );
+    set(
+        &mut table,
+        SERIAL_DATA_REGISTER,
+        IoEntry::new(IoHandler::Serial).doc(&registers::SB),
+    );
+    set(
+        &mut table,
+        SERIAL_CONTROL_REGISTER,
+        IoEntry::new(IoHandler::Serial).doc(&registers::SC),
+    );
+    set(
+        &mut table,
+        DIV_REGISTER,
+        IoEntry::new(IoHandler::Timer).doc(&registers::DIV),
+    );
+    set(
+        &mut table,
+        TIMA_REGISTER,
+        IoEntry::new(IoHandler::Timer).doc(&registers::TIMA),
+    );
+    set(
+        &mut table,
+        TMA_REGISTER,
+        IoEntry::new(IoHandler::Timer).doc(&registers::TMA),
+    );
+    // Only the enable bit and the clock select exist
+    set(
+        &mut table,
+        TAC_REGISTER,
+        IoEntry::new(IoHandler::Timer)
+            .masked(0b1111_1000, 0b0000_0111)
+            .doc(&registers::TAC),
+    );
+    set(
+        &mut table,
+        INTERRUPT_FLAGS_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::IF),
+    );
+
+    set(&mut table, NR10, IoEntry::new(IoHandler::Apu).doc(&registers::NR10));
+    set(&mut table, NR11, IoEntry::new(IoHandler::Apu).doc(&registers::NR11));
+    set(&mut table, NR12, IoEntry::new(IoHandler::Apu).doc(&registers::NR12));
+    set(&mut table, NR13, IoEntry::new(IoHandler::Apu).doc(&registers::NR13));
+    set(&mut table, NR14, IoEntry::new(IoHandler::Apu).doc(&registers::NR14));
+    set(&mut table, NR21, IoEntry::new(IoHandler::Apu).doc(&registers::NR21));
+    set(&mut table, NR22, IoEntry::new(IoHandler::Apu).doc(&registers::NR22));
+    set(&mut table, NR23, IoEntry::new(IoHandler::Apu).doc(&registers::NR23));
+    set(&mut table, NR24, IoEntry::new(IoHandler::Apu).doc(&registers::NR24));
+    set(&mut table, NR30, IoEntry::new(IoHandler::Apu).doc(&registers::NR30));
+    set(&mut table, NR31, IoEntry::new(IoHandler::Apu).doc(&registers::NR31));
+    set(&mut table, NR32, IoEntry::new(IoHandler::Apu).doc(&registers::NR32));
+    set(&mut table, NR33, IoEntry::new(IoHandler::Apu).doc(&registers::NR33));
+    set(&mut table, NR34, IoEntry::new(IoHandler::Apu).doc(&registers::NR34));
+    set(&mut table, NR41, IoEntry::new(IoHandler::Apu).doc(&registers::NR41));
+    set(&mut table, NR42, IoEntry::new(IoHandler::Apu).doc(&registers::NR42));
+    set(&mut table, NR43, IoEntry::new(IoHandler::Apu).doc(&registers::NR43));
+    set(&mut table, NR44, IoEntry::new(IoHandler::Apu).doc(&registers::NR44));
+    set(&mut table, NR50, IoEntry::new(IoHandler::Apu).doc(&registers::NR50));
+    set(&mut table, NR51, IoEntry::new(IoHandler::Apu).doc(&registers::NR51));
+    set(&mut table, NR52, IoEntry::new(IoHandler::Apu).doc(&registers::NR52));
+    let mut addr = WAVE_PATTERN_RAM_START;
+    while addr <= WAVE_PATTERN_RAM_END {
+        set(&mut table, addr, IoEntry::new(IoHandler::Apu));
+        addr += 1;
+    }
+
+    set(
+        &mut table,
+        LCD_CONTROL_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::LCDC),
+    );
+    // Bits 0-2 are the mode and LY=LYC, the PPU sets those. Bit 7 doesn't exist and reads as 1.
+    set(
+        &mut table,
+        LCD_STATUS_REGISTER,
+        IoEntry::new(IoHandler::LcdStatus)
+            .masked(0b1000_0000, 0b0111_1000)
+            .doc(&registers::STAT),
+    );
+    set(
+        &mut table,
+        SCROLL_Y_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::SCY),
+    );
+    set(
+        &mut table,
+        SCROLL_X_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::SCX),
+    );
+    set(
+        &mut table,
+        SCANLINE_Y_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::LY),
+    );
+    set(
+        &mut table,
+        SCANLINE_Y_COMPARE_REGISTER,
+        IoEntry::new(IoHandler::Lyc).doc(&registers::LYC),
+    );
+    set(
+        &mut table,
+        OAM_DMA_REGISTER,
+        IoEntry::new(IoHandler::OamDma).doc(&registers::DMA),
+    );
+    set(
+        &mut table,
+        BG_PALETTE_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::BGP),
+    );
+    set(
+        &mut table,
+        OBJ0_PALETTE_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::OBP0),
+    );
+    set(
+        &mut table,
+        OBJ1_PALETTE_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::OBP1),
+    );
+    set(
+        &mut table,
+        WINDOW_Y_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::WY),
+    );
+    set(
+        &mut table,
+        WINDOW_X_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::WX),
+    );
+    set(
+        &mut table,
+        BOOTROM_MAPPER_REGISTER,
+        IoEntry::new(IoHandler::BootRom).doc(&registers::BOOT),
+    );
+
+    set(
+        &mut table,
+        CPU_MODE_REGISTER,
+        IoEntry::cgb(IoHandler::CpuMode).doc(&registers::KEY0),
+    );
+    // Only the current speed and the armed switch exist
+    set(
+        &mut table,
+        DOUBLE_SPEED_SWITCH_REGISTER,
+        IoEntry::cgb(IoHandler::DoubleSpeed)
+            .masked(0b0111_1110, 0xff)
+            .doc(&registers::KEY1),
+    );
+    set(
+        &mut table,
+        VRAM_BANK_SELECT_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::VBK),
+    );
+    set(
+        &mut table,
+        HDMA_VRAM_SRC_HIGH_REGISTER,
+        IoEntry::cgb(IoHandler::HdmaSourceHigh).doc(&registers::HDMA1),
+    );
+    set(
+        &mut table,
+        HDMA_VRAM_SRC_LOW_REGISTER,
+        IoEntry::cgb(IoHandler::HdmaSourceLow).doc(&registers::HDMA2),
+    );
+    set(
+        &mut table,
+        HDMA_VRAM_DST_HIGH_REGISTER,
+        IoEntry::cgb(IoHandler::HdmaDestinationHigh).doc(&registers::HDMA3),
+    );
+    set(
+        &mut table,
+        HDMA_VRAM_DST_LOW_REGISTER,
+        IoEntry::cgb(IoHandler::HdmaDestinationLow).doc(&registers::HDMA4),
+    );
+    set(
+        &mut table,
+        HDMA_LENGTH_MODE_START_REGISTER,
+        IoEntry::cgb(IoHandler::HdmaStart).doc(&registers::HDMA5),
+    );
+    set(
+        &mut table,
+        INFRARED_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::RP),
+    );
+    set(
+        &mut table,
+        BACKGROUND_PALETTE_INDEX_REGISTER,
+        IoEntry::cgb(IoHandler::PaletteIndex).doc(&registers::BCPS),
+    );
+    set(
+        &mut table,
+        BACKGROUND_PALETTE_DATA_REGISTER,
+        IoEntry::cgb(IoHandler::PaletteData).doc(&registers::BCPD),
+    );
+    set(
+        &mut table,
+        OBJECT_PALETTE_INDEX_REGISTER,
+        IoEntry::cgb(IoHandler::PaletteIndex).doc(&registers::OCPS),
+    );
+    set(
+        &mut table,
+        OBJECT_PALETTE_DATA_REGISTER,
+        IoEntry::cgb(IoHandler::PaletteData).doc(&registers::OCPD),
+    );
+    set(
+        &mut table,
+        WRAM_BANK_SELECT_REGISTER,
+        IoEntry::new(IoHandler::Memory).doc(&registers::SVBK),
+    );
+    set(&mut table, PCM12, IoEntry::cgb(IoHandler::Pcm).doc(&registers::PCM12));
+    set(&mut table, PCM34, IoEntry::cgb(IoHandler::Pcm).doc(&registers::PCM34));
+
+    table
+}
+
+const fn set(table: &mut [IoEntry; IO_REGISTER_COUNT], addr: u16, entry: IoEntry) {
+    table[(addr - IO_START) as usize] = entry;
+}
+
+#[inline]
+pub fn io_entry(addr: u16) -> IoEntry {
+    IO_TABLE[(addr - IO_START) as usize]
+}
+
+// Documentation of the register at the address, IE sits past the IO registers but has its own as well
+pub fn register_doc(address: u16) -> Option<&'static RegisterDoc> {
+    match address {
+        IO_START..=IO_END => IO_TABLE[(address - IO_START) as usize].doc,
+        INTERRUPT_ENABLE_REGISTER => Some(&registers::IE),
+        _ => None,
+    }
+}
+
+// Every documented register by address, in order
+pub fn register_docs() -> impl Iterator<Item = (u16, &'static RegisterDoc)> {
+    (IO_START..=IO_END)
+        .chain(std::iter::once(INTERRUPT_ENABLE_REGISTER))
+        .filter_map(|address| register_doc(address).map(|doc| (address, doc)))
+}
@@ -0,0 +1,115 @@
+use crate::memory::{INTERRUPT_ENABLE_REGISTER, IO_END, IO_START};
+
+// Enough for a few minutes of a busy sound driver, recording stops once it's full
+pub const MAX_IO_WRITES: usize = 1 << 20;
+const BINARY_MAGIC: &[u8; 4] = b"AYIO";
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct IoWrite {
+    pub frame: u64,
+    pub scanline: u8,
+    pub address: u16,
+    pub value: u8,
+}
+
+// Every write the CPU makes to an IO register, with the frame and scanline it happened on.
+// Meant to be attached to bug reports, see to_csv and to_binary for the formats.
+pub struct IoLog {
+    writes: Vec<IoWrite>,
+    recording: bool,
+    frame: u64,
+}
+
+impl IoLog {
+    pub fn new() -> IoLog {
+        IoLog {
+            writes: Vec::new(),
+            recording: false,
+            frame: 0,
+        }
+    }
+
+    // Frame numbers continue from the console's frame counter
+    pub fn start(&mut self, frame: u64) {
+        self.recording = true;
+        self.frame = frame;
+    }
+
+    pub fn stop(&mut self) {
+        self.recording = false;
+    }
+
+    pub fn clear(&mut self) {
+        self.writes.clear();
+    }
+
+    #[inline]
+    pub fn is_recording(&self) -> bool {
+        self.recording
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.writes.len() >= MAX_IO_WRITES
+    }
+
+    #[inline]
+    pub fn record(&mut self, address: u16, value: u8, scanline: u8) {
+        if !(IO_START..=IO_END).contains(&address) && address != INTERRUPT_ENABLE_REGISTER {
+            return;
+        }
+
+        if self.is_full() {
+            self.recording = false;
+            return;
+        }
+
+        self.writes.push(IoWrite {
+            frame: self.frame,
+            scanline,
+            address,
+            value,
+        });
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    pub fn writes(&self) -> &[IoWrite] {
+        &self.writes
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("frame,scanline,address,value\n");
+        for write in &self.writes {
+            csv += &format!(
+                "{},{},{:04x},{:02x}\n",
+                write.frame, write.scanline, write.address, write.value
+            );
+        }
+        csv
+    }
+
+    // "AYIO" followed by one record per write: frame (u32 LE), scanline, register ($ff00 + n, $ffff for IE), value
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut data = BINARY_MAGIC.to_vec();
+        for write in &self.writes {
+            data.extend_from_slice(&(write.frame as u32).to_le_bytes());
+            data.extend_from_slice(&[write.scanline, write.address as u8, write.value]);
+        }
+        data
+    }
+}
+
+impl Default for IoLog {
+    fn default() -> IoLog {
+        IoLog::new()
+    }
+}
+
+// Copies (run-ahead, save states) don't record, the log stays with the console that is actually running
+impl Clone for IoLog {
+    fn clone(&self) -> IoLog {
+        IoLog::new()
+    }
+}
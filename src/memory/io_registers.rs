@@ -0,0 +1,56 @@
+use crate::memory::io_dispatch::{register_doc, register_docs};
+
+// Names as the Pandocs call them, for the debugger's register pickers and annotations
+pub fn register_name(address: u16) -> Option<&'static str> {
+    register_doc(address).map(|doc| doc.name)
+}
+
+// Case doesn't matter, e.g. "lcdc"
+pub fn register_address(name: &str) -> Option<u16> {
+    let name = name.trim();
+    register_docs()
+        .find(|(_, doc)| doc.name.eq_ignore_ascii_case(name))
+        .map(|(address, _)| address)
+}
+
+// How an instruction touches memory, or which accesses an IO breakpoint stops on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+    // Either of them, or a read-modify-write like INC (HL)
+    ReadWrite,
+}
+
+impl Access {
+    pub const ALL: [Access; 3] = [Access::Read, Access::Write, Access::ReadWrite];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Access::Read => "Read",
+            Access::Write => "Write",
+            Access::ReadWrite => "Read/Write",
+        }
+    }
+
+    #[inline]
+    pub fn overlaps(&self, other: Access) -> bool {
+        *self == Access::ReadWrite || other == Access::ReadWrite || *self == other
+    }
+}
+
+// Stops before an instruction reads and/or writes the register
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoBreakpoint {
+    pub address: u16,
+    pub access: Access,
+}
+
+impl std::fmt::Display for IoBreakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match register_name(self.address) {
+            Some(name) => write!(f, "{} ${:04x} ({})", name, self.address, self.access.name()),
+            None => write!(f, "${:04x} ({})", self.address, self.access.name()),
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use btleplug::api::{Central, Characteristic, Manager as _, Peripheral as _, ScanFilter, WriteType};
+use btleplug::platform::{Manager, Peripheral};
+use log::{error, info};
+use regex::Regex;
+use tokio::runtime::Runtime;
+use tokio::time::{self, Duration};
+
+pub struct Lovense {
+    runtime: Runtime,
+    peripheral: Peripheral,
+    tx: Characteristic,
+}
+
+impl Lovense {
+    pub fn connect() -> Option<Lovense> {
+        let runtime = Runtime::new().unwrap();
+        let (peripheral, tx) = runtime.block_on(Lovense::find_toy())?;
+
+        Some(Lovense {
+            runtime,
+            peripheral,
+            tx,
+        })
+    }
+
+    pub fn vibrate(&self, strength: u8) {
+        let command = format!("Vibrate:{};", strength);
+
+        self.runtime.block_on(async {
+            if let Err(e) = self
+                .peripheral
+                .write(&self.tx, command.as_bytes(), WriteType::WithoutResponse)
+                .await
+            {
+                error!("Failed to send vibration command: {}", e);
+            }
+        });
+    }
+
+    async fn find_toy() -> Option<(Peripheral, Characteristic)> {
+        let manager = Manager::new().await.unwrap();
+        let adapters = manager.adapters().await.unwrap();
+        let central = adapters.into_iter().nth(0).expect("No adapters found");
+
+        info!("Scanning for Lovense toy");
+        central.start_scan(ScanFilter::default()).await.unwrap();
+
+        // Wait for a peripheral to be discovered
+        time::sleep(time::Duration::from_secs(5)).await;
+
+        let peripherals = central.peripherals().await.unwrap();
+        let service_regex = Regex::new(r"^..300001-002.-4bd4-bbd5-a6920e4c5653").unwrap(); // Regex from: @Acurisu
+        let tx_regex = Regex::new(r"^..300002-002.-4bd4-bbd5-a6920e4c5653").unwrap();
+
+        for peripheral in peripherals {
+            // Connect to all peripherals to discover the Lovense service
+            if let Ok(_) = peripheral.connect().await {
+                // Discover services
+                peripheral.discover_services().await.unwrap();
+
+                let services = peripheral.services();
+                let lovense_service = services
+                    .iter()
+                    .find(|&service| service_regex.is_match(&service.uuid.to_string()));
+
+                // If the service is found, return the peripheral and the TX characteristic
+                if let Some(service) = lovense_service {
+                    info!("Found Lovense toy");
+
+                    let tx_characteristic = service
+                        .characteristics
+                        .iter()
+                        .find(|&characteristic| tx_regex.is_match(&characteristic.uuid.to_string()))
+                        .unwrap();
+
+                    info!("Queuing vibration command to signal connection");
+                    peripheral
+                        .write(&tx_characteristic, "Vibrate:5;".as_bytes(), WriteType::WithoutResponse)
+                        .await
+                        .unwrap();
+                    time::sleep(Duration::from_secs(2)).await;
+                    peripheral
+                        .write(&tx_characteristic, "Vibrate:0;".as_bytes(), WriteType::WithoutResponse)
+                        .await
+                        .unwrap();
+
+                    central.stop_scan().await.unwrap();
+
+                    return Some((peripheral, tx_characteristic.clone()));
+                }
+            }
+        }
+
+        central.stop_scan().await.unwrap();
+
+        None
+    }
+}
@@ -1,5 +1,6 @@
 use crate::error::AyyError;
-use crate::memory::mapper::Mapper;
+use crate::memory::mapper::{Banked, Mapper};
+use crate::memory::ram_decay::RamDecay;
 use crate::memory::{EXTERNAL_RAM_END, EXTERNAL_RAM_START};
 use log::{error, trace, warn};
 
@@ -18,11 +19,7 @@ const BANKING_MODE_END: u16 = 0x7fff;
 
 #[derive(Clone)]
 pub struct Mbc1 {
-    rom: Vec<u8>,
-    rom_bank: u16,
-    ram: Vec<u8>,
-    ram_bank: u8,
-    ram_enabled: bool,
+    banked: Banked,
     banking_mode: bool,
     secondary_banking_allowed: bool,
 }
@@ -32,11 +29,7 @@ impl Mbc1 {
         let secondary_banking_allowed = memory.len() > 0x80000; // 512 KiB ROM
 
         Mbc1 {
-            rom: memory,
-            rom_bank: 1,
-            ram: vec![0; 0x8000],
-            ram_bank: 0,
-            ram_enabled: false,
+            banked: Banked::new(memory),
             banking_mode: false,
             secondary_banking_allowed,
         }
@@ -44,26 +37,34 @@ impl Mbc1 {
 }
 
 impl Mapper for Mbc1 {
+    fn banked(&self) -> Option<&Banked> {
+        Some(&self.banked)
+    }
+
+    fn banked_mut(&mut self) -> Option<&mut Banked> {
+        Some(&mut self.banked)
+    }
+
     #[inline]
     fn read(&self, addr: u16) -> Result<u8, AyyError> {
         match addr {
-            ROM_SLOT_0_START..=ROM_SLOT_0_END => Ok(self.rom[addr as usize]),
+            ROM_SLOT_0_START..=ROM_SLOT_0_END => Ok(self.banked.rom[addr as usize]),
             ROM_SLOT_1_START..=ROM_SLOT_1_END => {
-                let rom_addr = (addr as usize % 0x4000) + (self.rom_bank as usize * 0x4000);
-                if rom_addr < self.rom.len() {
-                    Ok(self.rom[rom_addr])
+                let rom_addr = (addr as usize % 0x4000) + (self.banked.rom_bank as usize * 0x4000);
+                if rom_addr < self.banked.rom.len() {
+                    Ok(self.banked.rom[rom_addr])
                 } else {
                     Err(AyyError::OutOfBoundsMemoryAccess { address: addr })
                 }
             }
-            EXTERNAL_RAM_START..=EXTERNAL_RAM_END if self.ram_enabled => {
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END if self.banked.ram_enabled => {
                 let base_addr = (addr - EXTERNAL_RAM_START) as usize;
-                let ram_addr = base_addr + (self.ram_bank as usize * 0x2000);
-                Ok(self.ram[ram_addr])
+                let ram_addr = base_addr + (self.banked.ram_bank as usize * 0x2000);
+                Ok(self.banked.ram[ram_addr])
             }
             // The RAM is only accessible if RAM is enabled, otherwise reads
             // return open bus values (often $FF, but not guaranteed) and writes are ignored.
-            EXTERNAL_RAM_START..=EXTERNAL_RAM_END if !self.ram_enabled => {
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END if !self.banked.ram_enabled => {
                 warn!("MBC1: Attempted to read from disabled RAM");
                 Ok(0xff)
             }
@@ -75,38 +76,39 @@ impl Mapper for Mbc1 {
     fn write(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
         match addr {
             RAM_ENABLE_START..=RAM_ENABLE_END => {
-                self.ram_enabled = (data & 0x0f) == 0x0a;
-                trace!("MBC1: RAM enabled: {}", self.ram_enabled);
+                self.banked.ram_enabled = (data & 0x0f) == 0x0a;
+                trace!("MBC1: RAM enabled: {}", self.banked.ram_enabled);
             }
             ROM_BANK_START..=ROM_BANK_END => {
-                self.rom_bank = (data & 0b0001_1111) as u16;
-                if self.rom_bank == 0 {
-                    self.rom_bank = 1;
+                self.banked.rom_bank = (data & 0b0001_1111) as u16;
+                if self.banked.rom_bank == 0 {
+                    self.banked.rom_bank = 1;
                 }
-                trace!("MBC1: Switched to ROM bank {}", self.rom_bank);
+                trace!("MBC1: Switched to ROM bank {}", self.banked.rom_bank);
             }
             SECONDARY_BANK_REGISTER_START..=SECONDARY_BANK_REGISTER_END if self.banking_mode => {
                 if self.secondary_banking_allowed {
-                    self.rom_bank = ((self.rom_bank as u8 & 0b0001_1111) | ((data & 0b11) << 5)) as u16;
-                    trace!("MBC1: Switched to ROM bank {}", self.rom_bank);
+                    self.banked.rom_bank = ((self.banked.rom_bank as u8 & 0b0001_1111) | ((data & 0b11) << 5)) as u16;
+                    trace!("MBC1: Switched to ROM bank {}", self.banked.rom_bank);
                 } else {
                     warn!("MBC1: Attempted to switch to ROM bank, but not allowed");
                 }
             }
             SECONDARY_BANK_REGISTER_START..=SECONDARY_BANK_REGISTER_END if !self.banking_mode => {
-                self.ram_bank = data & 0b11;
-                trace!("MBC1: Switched to RAM bank {}", self.ram_bank);
+                self.banked.ram_bank = data & 0b11;
+                trace!("MBC1: Switched to RAM bank {}", self.banked.ram_bank);
             }
             BANKING_MODE_START..=BANKING_MODE_END => {
                 self.banking_mode = data & 0b0000_0001 == 1;
                 trace!("MBC1: Switched to banking mode: {}", self.banking_mode);
             }
-            EXTERNAL_RAM_START..=EXTERNAL_RAM_END if self.ram_enabled => {
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END if self.banked.ram_enabled => {
                 let base_addr = (addr - EXTERNAL_RAM_START) as usize;
-                let ram_addr = base_addr + (self.ram_bank as usize * 0x2000);
-                self.ram[ram_addr] = data;
+                let ram_addr = base_addr + (self.banked.ram_bank as usize * 0x2000);
+                self.banked.ram[ram_addr] = data;
+                self.banked.ram_dirty = true;
             }
-            EXTERNAL_RAM_START..=EXTERNAL_RAM_END if !self.ram_enabled => {
+            EXTERNAL_RAM_START..=EXTERNAL_RAM_END if !self.banked.ram_enabled => {
                 warn!("MBC1: Attempted to write to disabled RAM");
             }
             _ => {
@@ -117,22 +119,9 @@ impl Mapper for Mbc1 {
         Ok(())
     }
 
-    fn dump_ram(&self) -> Vec<u8> {
-        self.ram.clone()
-    }
-
-    fn load_ram(&mut self, ram: Vec<u8>) {
-        self.ram = ram;
-    }
-
-    #[inline]
-    fn current_rom_bank(&self) -> u16 {
-        self.rom_bank
-    }
-
-    #[inline]
-    fn current_ram_bank(&self) -> u8 {
-        self.ram_bank
+    fn power_cycle(&mut self, decay: Option<&RamDecay>) {
+        self.banked.power_cycle(decay);
+        self.banking_mode = false;
     }
 
     #[inline]
@@ -1,49 +1,62 @@
 use log::{error, trace};
 
-use crate::memory::mapper::Mapper;
+use crate::error::AyyError;
+use crate::memory::mapper::rtc::{Rtc, RtcMode, RtcTime};
+use crate::memory::mapper::{Banked, Mapper};
+use crate::memory::ram_decay::RamDecay;
 
 #[derive(Clone)]
 pub struct Mbc3 {
-    rom: Vec<u8>,
-    ram: Vec<u8>,
-    rom_bank: u16,
-    ram_bank: u8,
-    ram_enabled: bool,
-    rtc_mapped: bool, // TODO: fake
+    banked: Banked,
+    rtc: Rtc,
+    rtc_register: Option<u8>,
+    has_rtc: bool,
 }
 
 impl Mbc3 {
     pub fn new(memory: Vec<u8>) -> Mbc3 {
         Mbc3 {
-            rom: memory,
-            ram: vec![0; 0x8000],
-            rom_bank: 1,
-            ram_bank: 0,
-            ram_enabled: false,
-            rtc_mapped: false,
+            banked: Banked::new(memory),
+            rtc: Rtc::new(),
+            rtc_register: None,
+            has_rtc: false,
+        }
+    }
+
+    pub fn with_rtc(memory: Vec<u8>) -> Mbc3 {
+        Mbc3 {
+            has_rtc: true,
+            ..Mbc3::new(memory)
         }
     }
 }
 
 impl Mapper for Mbc3 {
+    fn banked(&self) -> Option<&Banked> {
+        Some(&self.banked)
+    }
+
+    fn banked_mut(&mut self) -> Option<&mut Banked> {
+        Some(&mut self.banked)
+    }
+
     #[inline]
-    fn read(&self, addr: u16) -> Result<u8, crate::error::AyyError> {
+    fn read(&self, addr: u16) -> Result<u8, AyyError> {
         match addr {
-            0x0000..=0x3fff => Ok(self.rom[addr as usize]),
+            0x0000..=0x3fff => Ok(self.banked.rom[addr as usize]),
             0x4000..=0x7fff => {
-                let addr = (addr as usize % 0x4000) + (self.rom_bank as usize * 0x4000);
-                Ok(self.rom[addr])
-            }
-            0xa000..=0xbfff if self.rtc_mapped => {
-                // TODO: This needs precedence over RAM
-                error!("MBC3: Faking unmapped RTC register read");
-                Ok(0x00)
-            }
-            0xa000..=0xbfff if self.ram_enabled => {
-                let base_addr = (addr - 0xa000) as usize;
-                let addr = base_addr + (self.ram_bank as usize * 0x2000);
-                Ok(self.ram[addr])
+                let addr = (addr as usize % 0x4000) + (self.banked.rom_bank as usize * 0x4000);
+                Ok(self.banked.rom[addr])
             }
+            // The RTC registers are mapped over the RAM, and only accessible while it's enabled too
+            0xa000..=0xbfff if self.banked.ram_enabled => match self.rtc_register {
+                Some(register) => Ok(self.rtc.read(register)),
+                None => {
+                    let base_addr = (addr - 0xa000) as usize;
+                    let addr = base_addr + (self.banked.ram_bank as usize * 0x2000);
+                    Ok(self.banked.ram[addr])
+                }
+            },
             _ => {
                 error!("MBC3: Unmapped read from address {:04x}", addr);
                 Ok(0x00)
@@ -52,43 +65,60 @@ impl Mapper for Mbc3 {
     }
 
     #[inline]
-    fn write(&mut self, addr: u16, data: u8) -> Result<(), crate::error::AyyError> {
+    fn write(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
         match addr {
             0x0000..=0x1fff => {
-                self.ram_enabled = data & 0x0f == 0x0a;
-                // TODO: enable RTC
-                trace!("MBC3: RAM access toggled to {}", self.ram_enabled);
+                self.banked.ram_enabled = data & 0x0f == 0x0a;
+                trace!("MBC3: RAM access toggled to {}", self.banked.ram_enabled);
                 Ok(())
             }
             0x2000..=0x3fff => {
-                self.rom_bank = (data & 0b0111_1111) as u16;
-                if self.rom_bank == 0 {
-                    self.rom_bank = 1;
+                self.banked.rom_bank = (data & 0b0111_1111) as u16;
+                if self.banked.rom_bank == 0 {
+                    self.banked.rom_bank = 1;
                 }
-                trace!("MBC3: Switched to ROM bank {}", self.rom_bank);
+                trace!("MBC3: Switched to ROM bank {}", self.banked.rom_bank);
                 Ok(())
             }
             0x4000..=0x5fff if data <= 0x03 => {
                 // only RAM bank 1-3 allowed, rest goes to RTC
-                self.rtc_mapped = false;
-                self.ram_bank = data & 0x0f;
-                trace!("MBC3: Switched to RAM bank {}", self.ram_bank);
+                self.rtc_register = None;
+                self.banked.ram_bank = data & 0x0f;
+                trace!("MBC3: Switched to RAM bank {}", self.banked.ram_bank);
                 Ok(())
             }
-            0x4000..=0x5fff if data > 0x03 => {
-                error!("MBC3: Faking unmapped RTC register select {}", data);
-                self.rtc_mapped = true;
+            0x4000..=0x5fff if self.has_rtc && (0x08..=0x0c).contains(&data) => {
+                self.rtc_register = Some(data);
+                trace!("MBC3: Mapped RTC register {:02x}", data);
+                Ok(())
+            }
+            0x4000..=0x5fff => {
+                error!("MBC3: Unmapped RAM bank/RTC register select {}", data);
+                Ok(())
+            }
+            0x6000..=0x7fff => {
+                self.rtc.latch(data);
                 Ok(())
             }
             0xa000..=0xbfff => {
-                if self.ram_enabled {
-                    let base_addr = (addr - 0xa000) as usize;
-                    let addr = base_addr + (self.ram_bank as usize * 0x2000);
-                    self.ram[addr] = data;
+                if self.banked.ram_enabled {
+                    match self.rtc_register {
+                        // The clock is saved along with the RAM
+                        Some(register) => {
+                            self.rtc.write(register, data);
+                            self.banked.ram_dirty = true;
+                        }
+                        None => {
+                            let base_addr = (addr - 0xa000) as usize;
+                            let addr = base_addr + (self.banked.ram_bank as usize * 0x2000);
+                            self.banked.ram[addr] = data;
+                            self.banked.ram_dirty = true;
+                        }
+                    }
                 } else {
                     error!(
                         "MBC3: Attempted write to RAM bank {} while RAM is disabled",
-                        self.ram_bank
+                        self.banked.ram_bank
                     );
                 }
                 Ok(())
@@ -100,26 +130,42 @@ impl Mapper for Mbc3 {
         }
     }
 
-    fn dump_ram(&self) -> Vec<u8> {
-        self.ram.clone()
+    fn power_cycle(&mut self, decay: Option<&RamDecay>) {
+        self.banked.power_cycle(decay);
+        self.rtc_register = None;
+        // The clock runs off the same battery
+        if decay.is_some() {
+            self.rtc = Rtc::new();
+        }
     }
 
-    fn load_ram(&mut self, ram: Vec<u8>) {
-        self.ram = ram;
+    #[inline]
+    fn tick(&mut self, cycles: usize) {
+        if self.has_rtc {
+            self.rtc.tick(cycles);
+        }
     }
 
-    #[inline]
-    fn current_rom_bank(&self) -> u16 {
-        self.rom_bank
+    fn dump_rtc(&self) -> Option<Vec<u8>> {
+        self.has_rtc.then(|| self.rtc.dump())
     }
 
-    #[inline]
-    fn current_ram_bank(&self) -> u8 {
-        self.ram_bank
+    fn load_rtc(&mut self, rtc: Vec<u8>, mode: RtcMode) {
+        if self.has_rtc {
+            self.rtc.load(&rtc, mode);
+        }
     }
 
     #[inline]
     fn name(&self) -> String {
-        String::from("MBC3")
+        if !self.has_rtc {
+            String::from("MBC3")
+        } else {
+            String::from("MBC3+TIMER")
+        }
+    }
+
+    fn rtc(&self) -> Option<RtcTime> {
+        self.has_rtc.then(|| self.rtc.time())
     }
 }
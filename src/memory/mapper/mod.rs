@@ -1,20 +1,136 @@
 use crate::error::AyyError;
+use crate::memory::mapper::rtc::{RtcMode, RtcTime};
+use crate::memory::mapper::rumble::Rumble;
+use crate::memory::ram_decay::RamDecay;
 use dyn_clone::DynClone;
+use log::warn;
 
+#[cfg(feature = "nsfw")]
+mod lovense;
 pub mod mbc1;
 pub mod mbc3;
 pub mod mbc5;
 pub mod rom;
+pub mod rtc;
+pub mod rumble;
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+pub const RAM_BANK_SIZE: usize = 0x2000;
 
 pub trait Mapper: DynClone {
     fn read(&self, addr: u16) -> Result<u8, AyyError>;
     fn write(&mut self, addr: u16, data: u8) -> Result<(), AyyError>;
-    fn dump_ram(&self) -> Vec<u8>;
-    fn load_ram(&mut self, ram: Vec<u8>);
-    fn current_rom_bank(&self) -> u16;
-    fn current_ram_bank(&self) -> u8;
     fn name(&self) -> String;
 
+    // The ROM and RAM banking of MBCs, None for plain ROMs. The queries below are answered from it unless
+    // the mapper knows better.
+    fn banked(&self) -> Option<&Banked> {
+        None
+    }
+
+    fn banked_mut(&mut self) -> Option<&mut Banked> {
+        None
+    }
+
+    // Cartridge RAM as declared in the header, without copying it like dump_ram does
+    fn ram(&self) -> &[u8] {
+        self.banked().map_or(&[], |banked| &banked.ram[..banked.ram_size])
+    }
+
+    fn dump_ram(&self) -> Vec<u8> {
+        self.ram().to_vec()
+    }
+
+    fn load_ram(&mut self, ram: Vec<u8>) {
+        let name = self.name();
+        if let Some(banked) = self.banked_mut() {
+            banked.load_ram(&name, ram);
+        }
+    }
+
+    fn current_rom_bank(&self) -> u16 {
+        self.banked().map_or(0, |banked| banked.rom_bank)
+    }
+
+    fn current_ram_bank(&self) -> u8 {
+        self.banked().map_or(0, |banked| banked.ram_bank)
+    }
+
+    // Size of the loaded ROM image in bytes
+    fn rom_size(&self) -> usize {
+        self.banked().map_or(0, |banked| banked.rom.len())
+    }
+
+    // Writes straight into the loaded ROM image as currently mapped, used by the debugger for live patching
+    fn patch_rom(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
+        match self.banked_mut() {
+            Some(banked) => patch_banked_rom(&mut banked.rom, banked.rom_bank, addr, data),
+            None => Err(AyyError::OutOfBoundsMemoryAccess { address: addr }),
+        }
+    }
+
+    // Cartridge hardware that runs off the system clock (e.g. the MBC3 RTC)
+    fn tick(&mut self, _cycles: usize) {}
+
+    fn dump_rtc(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    fn load_rtc(&mut self, _rtc: Vec<u8>, _mode: RtcMode) {}
+
+    // The cartridge losing power: the banking registers start over, and the RAM decays unless a battery
+    // keeps it (decay is None then)
+    fn power_cycle(&mut self, decay: Option<&RamDecay>) {
+        if let Some(banked) = self.banked_mut() {
+            banked.power_cycle(decay);
+        }
+    }
+
+    // Cartridge RAM as declared in the header, which is also what dump_ram returns
+    fn ram_size(&self) -> usize {
+        self.banked().map_or(0, |banked| banked.ram_size)
+    }
+
+    fn rom_bank_count(&self) -> usize {
+        self.rom_size().div_ceil(ROM_BANK_SIZE)
+    }
+
+    fn ram_bank_count(&self) -> usize {
+        self.ram_size().div_ceil(RAM_BANK_SIZE)
+    }
+
+    // Whether the RAM (and RTC) survive the console being switched off, i.e. are worth saving
+    fn has_battery(&self) -> bool {
+        self.banked().is_some_and(|banked| header_has_battery(&banked.rom))
+    }
+
+    // The clock as it currently runs, not what the game last latched
+    fn rtc(&self) -> Option<RtcTime> {
+        None
+    }
+
+    // RAM (or the RTC) written since it was loaded from or saved to disk
+    fn ram_dirty(&self) -> bool {
+        self.banked().is_some_and(|banked| banked.ram_dirty)
+    }
+
+    fn set_ram_dirty(&mut self, dirty: bool) {
+        if let Some(banked) = self.banked_mut() {
+            banked.ram_dirty = dirty;
+        }
+    }
+
+    // Whether the rumble motor ran since the last call. Games pulse it to set the strength, so a
+    // pulse shorter than the polling interval still counts.
+    fn take_rumble(&mut self) -> bool {
+        false
+    }
+
+    // The motor of rumble carts, copies of the cartridge don't drive it until it's handed over
+    fn rumble(&mut self) -> Option<&mut Rumble> {
+        None
+    }
+
     fn read16(&self, addr: u16) -> Result<u16, AyyError> {
         let lo = self.read(addr)? as u16;
         let hi = self.read(addr + 1)? as u16;
@@ -31,3 +147,116 @@ pub trait Mapper: DynClone {
 }
 
 dyn_clone::clone_trait_object!(Mapper);
+
+// Cartridge RAM size declared in the header, which is also the .sav size other emulators use
+pub fn header_ram_size(rom: &[u8]) -> usize {
+    match rom.get(0x0149) {
+        Some(0x01) => 0x800,
+        Some(0x02) => 0x2000,
+        Some(0x03) => 0x8000,
+        Some(0x04) => 0x20000,
+        Some(0x05) => 0x10000,
+        _ => 0,
+    }
+}
+
+// Cartridge types that come with a battery, from the header ($0147)
+pub fn header_has_battery(rom: &[u8]) -> bool {
+    matches!(
+        rom.get(0x0147),
+        Some(0x03 | 0x06 | 0x09 | 0x0d | 0x0f | 0x10 | 0x13 | 0x1b | 0x1e | 0x22 | 0xff)
+    )
+}
+
+// Game title from the header ($0134-$0143), up to the first NUL. CGB games use the last bytes for the
+// manufacturer code and CGB flag instead, their title ends at $013e.
+pub fn header_title(header: &[u8]) -> String {
+    let length = match header.get(15) {
+        Some(flag) if flag & 0x80 != 0 => 11,
+        _ => header.len(),
+    };
+    let title = header[..length]
+        .iter()
+        .take_while(|byte| **byte != 0)
+        .filter(|byte| byte.is_ascii_graphic() || **byte == b' ')
+        .map(|byte| *byte as char)
+        .collect::<String>();
+    title.trim().to_owned()
+}
+
+// Writes into a ROM image with `rom_bank` mapped at $4000-$7fff, which is what patch_rom does on every mapper
+pub fn patch_banked_rom(rom: &mut [u8], rom_bank: u16, addr: u16, data: u8) -> Result<(), AyyError> {
+    let offset = match addr {
+        0x0000..=0x3fff => addr as usize,
+        0x4000..=0x7fff => (addr as usize % ROM_BANK_SIZE) + (rom_bank as usize * ROM_BANK_SIZE),
+        _ => return Err(AyyError::OutOfBoundsMemoryAccess { address: addr }),
+    };
+
+    let byte = rom
+        .get_mut(offset)
+        .ok_or(AyyError::OutOfBoundsMemoryAccess { address: addr })?;
+    *byte = data;
+    Ok(())
+}
+
+// The ROM and RAM banking every MBC has, the mappers add their own registers around it
+#[derive(Clone)]
+pub struct Banked {
+    pub rom: Vec<u8>,
+    pub ram: Vec<u8>,
+    // Declared in the header, `ram` is larger so that any bank the game selects stays in bounds
+    pub ram_size: usize,
+    pub rom_bank: u16,
+    pub ram_bank: u8,
+    pub ram_enabled: bool,
+    pub ram_dirty: bool,
+}
+
+impl Banked {
+    pub fn new(rom: Vec<u8>) -> Banked {
+        let ram_size = header_ram_size(&rom);
+
+        Banked {
+            rom,
+            ram: vec![0; ram_size.max(0x8000)],
+            ram_size,
+            rom_bank: 1,
+            ram_bank: 0,
+            ram_enabled: false,
+            ram_dirty: false,
+        }
+    }
+
+    // See Mapper::power_cycle, the mapper resets its own registers on top
+    pub fn power_cycle(&mut self, decay: Option<&RamDecay>) {
+        self.rom_bank = 1;
+        self.ram_bank = 0;
+        self.ram_enabled = false;
+        if let Some(decay) = decay {
+            decay.apply(&mut self.ram[..self.ram_size]);
+            self.ram_dirty = true;
+        }
+    }
+
+    pub fn load_ram(&mut self, mapper: &str, ram: Vec<u8>) {
+        let ram = normalize_ram(mapper, ram, self.ram_size);
+        self.ram.fill(0);
+        self.ram[..ram.len()].copy_from_slice(&ram);
+        self.ram_dirty = false;
+    }
+}
+
+// Pads or trims a save from disk to the header RAM size, so a mismatched file can't break bank math
+pub fn normalize_ram(mapper: &str, mut ram: Vec<u8>, size: usize) -> Vec<u8> {
+    if ram.len() != size {
+        warn!(
+            "{}: Save is {} bytes but the cartridge has {} bytes of RAM, {} it",
+            mapper,
+            ram.len(),
+            size,
+            if ram.len() < size { "padding" } else { "trimming" }
+        );
+        ram.resize(size, 0);
+    }
+    ram
+}
@@ -1,5 +1,5 @@
 use crate::error::AyyError;
-use crate::memory::mapper::Mapper;
+use crate::memory::mapper::{patch_banked_rom, Mapper};
 
 #[derive(Clone)]
 pub struct Rom {
@@ -15,7 +15,8 @@ impl Rom {
 impl Mapper for Rom {
     #[inline]
     fn read(&self, addr: u16) -> Result<u8, AyyError> {
-        Ok(self.memory[addr as usize])
+        // There's no external RAM, and the ROM may be smaller than 32K, the bus floats high
+        Ok(self.memory.get(addr as usize).copied().unwrap_or(0xff))
     }
 
     #[inline]
@@ -24,24 +25,17 @@ impl Mapper for Rom {
         Err(AyyError::WriteToReadOnlyMemory { address: addr, data })
     }
 
-    fn dump_ram(&self) -> Vec<u8> {
-        Vec::new()
-    }
-
-    fn load_ram(&mut self, _ram: Vec<u8>) {}
-
-    #[inline]
-    fn current_rom_bank(&self) -> u16 {
-        0
-    }
-
-    #[inline]
-    fn current_ram_bank(&self) -> u8 {
-        0
+    fn patch_rom(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
+        // Without banking, bank 1 is always what's at $4000
+        patch_banked_rom(&mut self.memory, 1, addr, data)
     }
 
     #[inline]
     fn name(&self) -> String {
         String::from("ROM")
     }
+
+    fn rom_size(&self) -> usize {
+        self.memory.len()
+    }
 }
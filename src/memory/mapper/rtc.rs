@@ -0,0 +1,206 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use log::{info, warn};
+
+// The RTC is driven by its own 32.768 KHz oscillator, which is independent of CGB double speed
+const CYCLES_PER_SECOND: usize = 4194304;
+
+// BGB/VBA-M layout: 5 current registers, 5 latched registers (u32 each), UNIX timestamp (u64)
+const RTC_SAVE_SIZE: usize = 48;
+// Older emulators store the timestamp as u32
+const RTC_SAVE_SIZE_LEGACY: usize = 44;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum RtcMode {
+    // Time keeps advancing while the emulator is closed
+    Real,
+    // Time only advances while the game is running
+    Emulated,
+}
+
+#[derive(Clone, Default)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days_lo: u8,
+    days_hi: u8, // bit 0: day bit 8, bit 6: halt, bit 7: day carry
+}
+
+impl RtcRegisters {
+    fn as_array(&self) -> [u8; 5] {
+        [self.seconds, self.minutes, self.hours, self.days_lo, self.days_hi]
+    }
+
+    fn from_array(data: [u8; 5]) -> RtcRegisters {
+        RtcRegisters {
+            seconds: data[0] & 0b0011_1111,
+            minutes: data[1] & 0b0011_1111,
+            hours: data[2] & 0b0001_1111,
+            days_lo: data[3],
+            days_hi: data[4] & 0b1100_0001,
+        }
+    }
+}
+
+// Readable form of the clock registers, for display
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RtcTime {
+    pub days: u16,
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub halted: bool,
+    // The day counter overflowed past 511
+    pub day_carry: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct Rtc {
+    registers: RtcRegisters,
+    latched: RtcRegisters,
+    latch_armed: bool,
+    cycles: usize,
+}
+
+impl Rtc {
+    pub fn new() -> Rtc {
+        Rtc::default()
+    }
+
+    #[inline]
+    pub fn is_halted(&self) -> bool {
+        self.registers.days_hi & 0b0100_0000 != 0
+    }
+
+    pub fn time(&self) -> RtcTime {
+        let registers = &self.registers;
+        RtcTime {
+            days: ((registers.days_hi as u16 & 0b1) << 8) | registers.days_lo as u16,
+            hours: registers.hours,
+            minutes: registers.minutes,
+            seconds: registers.seconds,
+            halted: self.is_halted(),
+            day_carry: registers.days_hi & 0b1000_0000 != 0,
+        }
+    }
+
+    pub fn tick(&mut self, cycles: usize) {
+        if self.is_halted() {
+            return;
+        }
+
+        self.cycles += cycles;
+        while self.cycles >= CYCLES_PER_SECOND {
+            self.cycles -= CYCLES_PER_SECOND;
+            self.advance(1);
+        }
+    }
+
+    pub fn latch(&mut self, data: u8) {
+        // Writing 0x00 followed by 0x01 copies the current time into the readable registers
+        if self.latch_armed && data == 0x01 {
+            self.latched = self.registers.clone();
+        }
+        self.latch_armed = data == 0x00;
+    }
+
+    pub fn read(&self, register: u8) -> u8 {
+        match register {
+            0x08 => self.latched.seconds,
+            0x09 => self.latched.minutes,
+            0x0a => self.latched.hours,
+            0x0b => self.latched.days_lo,
+            0x0c => self.latched.days_hi | 0b0011_1110,
+            _ => 0xff,
+        }
+    }
+
+    pub fn write(&mut self, register: u8, data: u8) {
+        match register {
+            0x08 => {
+                self.registers.seconds = data & 0b0011_1111;
+                // Writing the seconds resets the sub-second divider
+                self.cycles = 0;
+            }
+            0x09 => self.registers.minutes = data & 0b0011_1111,
+            0x0a => self.registers.hours = data & 0b0001_1111,
+            0x0b => self.registers.days_lo = data,
+            0x0c => self.registers.days_hi = data & 0b1100_0001,
+            _ => {}
+        }
+    }
+
+    pub fn dump(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(RTC_SAVE_SIZE);
+
+        for value in self.registers.as_array().iter().chain(self.latched.as_array().iter()) {
+            data.extend_from_slice(&(*value as u32).to_le_bytes());
+        }
+        data.extend_from_slice(&Rtc::now().to_le_bytes());
+
+        data
+    }
+
+    pub fn load(&mut self, data: &[u8], mode: RtcMode) {
+        if data.len() != RTC_SAVE_SIZE && data.len() != RTC_SAVE_SIZE_LEGACY {
+            warn!("RTC: Ignoring save data with unexpected size {}", data.len());
+            return;
+        }
+
+        let register = |index: usize| data[index * 4];
+        self.registers = RtcRegisters::from_array(std::array::from_fn(register));
+        self.latched = RtcRegisters::from_array(std::array::from_fn(|i| register(i + 5)));
+
+        let timestamp = match data.len() {
+            RTC_SAVE_SIZE => u64::from_le_bytes(data[40..48].try_into().unwrap()),
+            _ => u32::from_le_bytes(data[40..44].try_into().unwrap()) as u64,
+        };
+
+        if mode == RtcMode::Real && !self.is_halted() {
+            let elapsed = Rtc::now().saturating_sub(timestamp);
+            info!("RTC: Advancing clock by {} seconds spent offline", elapsed);
+            self.advance(elapsed);
+        }
+    }
+
+    fn advance(&mut self, seconds: u64) {
+        let registers = &mut self.registers;
+
+        let mut total = seconds + registers.seconds as u64;
+        registers.seconds = (total % 60) as u8;
+        total /= 60;
+        if total == 0 {
+            return;
+        }
+
+        total += registers.minutes as u64;
+        registers.minutes = (total % 60) as u8;
+        total /= 60;
+        if total == 0 {
+            return;
+        }
+
+        total += registers.hours as u64;
+        registers.hours = (total % 24) as u8;
+        total /= 24;
+        if total == 0 {
+            return;
+        }
+
+        let days = total + (((registers.days_hi as u64) & 0b1) << 8 | registers.days_lo as u64);
+        registers.days_lo = days as u8;
+        registers.days_hi = (registers.days_hi & 0b1111_1110) | ((days >> 8) & 0b1) as u8;
+        if days > 0x1ff {
+            registers.days_hi |= 0b1000_0000;
+        }
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default()
+    }
+}
@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::Arc;
+
+use log::{debug, warn};
+
+// Amount of wakeups that can be queued for the worker. The worker always
+// applies the most recent motor state, so dropping wakeups is harmless.
+#[cfg(feature = "nsfw")]
+const RUMBLE_QUEUE_SIZE: usize = 4;
+
+// Minimum time between two commands sent to the device. Games toggle the
+// motor a lot faster than BLE toys can follow.
+#[cfg(feature = "nsfw")]
+const RUMBLE_RATE_LIMIT: std::time::Duration = std::time::Duration::from_millis(50);
+
+pub struct Rumble {
+    active: bool,
+    desired: Arc<AtomicBool>,
+    wakeup: Option<SyncSender<()>>,
+}
+
+impl Rumble {
+    #[cfg(feature = "nsfw")]
+    pub fn new() -> Rumble {
+        let mut rumble = Rumble::disabled();
+        let (wakeup, receiver) = std::sync::mpsc::sync_channel(RUMBLE_QUEUE_SIZE);
+
+        let desired = rumble.desired.clone();
+        match std::thread::Builder::new()
+            .name(String::from("rumble"))
+            .spawn(move || Rumble::worker(receiver, desired))
+        {
+            Ok(_) => rumble.wakeup = Some(wakeup),
+            Err(e) => warn!("Failed to spawn rumble worker: {}", e),
+        }
+
+        rumble
+    }
+
+    #[cfg(not(feature = "nsfw"))]
+    pub fn new() -> Rumble {
+        Rumble::disabled()
+    }
+
+    pub fn disabled() -> Rumble {
+        Rumble {
+            active: false,
+            desired: Arc::new(AtomicBool::new(false)),
+            wakeup: None,
+        }
+    }
+
+    // Moves the motor over from the cartridge being replaced, e.g. by a loaded state, which is left detached.
+    // The motor is brought to this cartridge's state right away.
+    pub fn take_device(&mut self, from: &mut Rumble) {
+        let active = self.active;
+        self.active = from.active;
+        self.desired = std::mem::replace(&mut from.desired, Arc::new(AtomicBool::new(false)));
+        self.wakeup = from.wakeup.take();
+        self.set(active);
+    }
+
+    #[inline]
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn set(&mut self, active: bool) {
+        // Only motor state transitions are forwarded to the worker
+        if self.active == active {
+            return;
+        }
+
+        self.active = active;
+        self.desired.store(active, Ordering::Release);
+
+        if let Some(wakeup) = &self.wakeup {
+            match wakeup.try_send(()) {
+                Ok(_) => {}
+                Err(TrySendError::Full(_)) => debug!("Rumble queue full, worker will pick up the latest state"),
+                Err(TrySendError::Disconnected(_)) => {
+                    warn!("Rumble worker exited, disabling rumble");
+                    self.wakeup = None;
+                }
+            }
+        }
+    }
+
+    #[cfg(feature = "nsfw")]
+    fn worker(receiver: std::sync::mpsc::Receiver<()>, desired: Arc<AtomicBool>) {
+        use super::lovense::Lovense;
+        use std::time::Instant;
+
+        // Discovery takes a few seconds, do it here instead of stalling the emulation thread
+        let Some(toy) = Lovense::connect() else {
+            return;
+        };
+
+        let mut applied = false;
+        let mut last_command = Instant::now() - RUMBLE_RATE_LIMIT;
+
+        while receiver.recv().is_ok() {
+            let elapsed = last_command.elapsed();
+            if elapsed < RUMBLE_RATE_LIMIT {
+                std::thread::sleep(RUMBLE_RATE_LIMIT - elapsed);
+            }
+
+            // Collapse everything that queued up while we were waiting
+            while receiver.try_recv().is_ok() {}
+
+            let active = desired.load(Ordering::Acquire);
+            if active != applied {
+                toy.vibrate(if active { 10 } else { 0 });
+                applied = active;
+                last_command = Instant::now();
+            }
+        }
+
+        // All mappers are gone, make sure the motor doesn't keep running
+        if applied {
+            toy.vibrate(0);
+        }
+    }
+}
+
+// Copies (run-ahead, netplay, save states) get a motor that isn't connected to anything, only the console
+// that is actually running drives the real one
+impl Clone for Rumble {
+    fn clone(&self) -> Rumble {
+        Rumble {
+            active: self.active,
+            ..Rumble::disabled()
+        }
+    }
+}
+
+// Detached like a copy, only Rumble::new starts the worker that drives the motor
+impl Default for Rumble {
+    fn default() -> Rumble {
+        Rumble::disabled()
+    }
+}
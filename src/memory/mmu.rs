@@ -1,28 +1,35 @@
 use crate::error::AyyError;
 use crate::gameboy::Mode;
 use crate::joypad::Joypad;
+use crate::lr35902::timer::Timer;
+use crate::memory::banked::BankedAddress;
+use crate::memory::heatmap::AccessHeatmap;
+use crate::memory::io_dispatch::{io_entry, IoHandler};
+use crate::memory::io_log::IoLog;
 use crate::memory::mapper::Mapper;
+use crate::memory::ram_init::RamInit;
+use crate::memory::registers::{InterruptFlags, LcdStatus};
+use crate::memory::uninit::UninitTracker;
 use crate::memory::{
-    BOOTROM_MAPPER_REGISTER, EXTERNAL_RAM_END, EXTERNAL_RAM_START, JOYPAD_REGISTER, OAM_DMA_REGISTER, ROM_END,
-    ROM_START,
+    BOOTROM_MAPPER_REGISTER, CPU_MODE_REGISTER, EXTERNAL_RAM_END, EXTERNAL_RAM_START, HRAM_END, HRAM_START,
+    INTERRUPT_FLAGS_REGISTER, IO_END, IO_START, ROM_END, ROM_START,
 };
+use crate::serial::Serial;
 use crate::sound::apu::Apu;
-use crate::sound::{
-    NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24, NR30, NR31, NR32, NR33, NR34, NR41, NR42, NR43, NR44, NR50,
-    NR51, NR52, WAVE_PATTERN_RAM_END, WAVE_PATTERN_RAM_START,
-};
 use crate::video::cram::Cram;
 use crate::video::state::State;
-use crate::video::LCD_STATUS_REGISTER;
+use crate::video::tile_cache::{DirtyTiles, DIRTY_TILE_WORDS};
+use crate::video::{
+    BG_PALETTE_REGISTER, LCD_CONTROL_REGISTER, LCD_STATUS_REGISTER, OBJ0_PALETTE_REGISTER, OBJ1_PALETTE_REGISTER,
+    SCANLINE_Y_COMPARE_REGISTER, SCANLINE_Y_REGISTER, TILE_DATA_COUNT,
+};
 use log::{debug, error, trace};
 
 use super::addressable::Addressable;
 use super::{
-    BACKGROUND_PALETTE_DATA_REGISTER, BACKGROUND_PALETTE_INDEX_REGISTER, DOUBLE_SPEED_SWITCH_REGISTER,
-    HDMA_LENGTH_MODE_START_REGISTER, HDMA_VRAM_DST_HIGH_REGISTER, HDMA_VRAM_DST_LOW_REGISTER,
-    HDMA_VRAM_SRC_HIGH_REGISTER, HDMA_VRAM_SRC_LOW_REGISTER, OBJECT_PALETTE_DATA_REGISTER,
-    OBJECT_PALETTE_INDEX_REGISTER, VRAM_BANK_SELECT_REGISTER, VRAM_END, VRAM_START, WRAM_BANK1_END, WRAM_BANK1_START,
-    WRAM_BANK_SELECT_REGISTER,
+    BACKGROUND_PALETTE_DATA_REGISTER, ECHO_RAM_END, ECHO_RAM_START, HDMA_LENGTH_MODE_START_REGISTER, OAM_END,
+    OAM_START, OBJECT_PALETTE_DATA_REGISTER, VRAM_BANK_SELECT_REGISTER, VRAM_END, VRAM_START, WRAM_BANK0_START,
+    WRAM_BANK1_END, WRAM_BANK1_START, WRAM_BANK_SELECT_REGISTER,
 };
 
 // The last instruction unmaps the boot ROM. Execution continues normally,
@@ -30,16 +37,31 @@ use super::{
 const DMG_BOOTROM_SIZE: u16 = 0xff;
 const CGB_BOOTROM_SIZE: u16 = 0x8ff;
 
+// Copies are what save states, run-ahead and netplay snapshots are made of, so cloning only copies the
+// emulated state. What belongs to the host (the audio device, the IO log, the heatmap, the link device
+// and the rumble motor) comes out empty or disconnected in a copy, and GameBoy::force_load_state hands
+// it over from the console being replaced.
+#[derive(Clone)]
 pub struct Mmu {
     pub cartridge: Box<dyn Mapper>,
     pub joypad: Joypad,
     pub apu: Apu,
+    pub serial: Serial,
+    pub timer: Timer,
     pub cgb_cram: Cram,
     pub cgb_double_speed: bool,
+    pub heatmap: AccessHeatmap,
+    pub io_log: IoLog,
+    pub uninit: UninitTracker,
     cgb_prepare_speed_switch: bool,
     memory: Vec<u8>,
     cgb_vram_bank1: Vec<u8>, // 0x2000 bank 1
     cgb_wram_bank1: Vec<u8>, // 0x1000 bank 1-7
+    dirty_tiles: DirtyTiles,
+    // Counts up on every change to VRAM or the palettes
+    vram_generation: u64,
+    // Same for OAM
+    oam_generation: u64,
     cgb_hdma_src: u16,
     cgb_hdma_dst: u16,
     cgb_hdma_transfer_length: u16,
@@ -49,6 +71,11 @@ pub struct Mmu {
     mode: Mode,
     last_ppu_state: State,
     cycles: usize,
+    #[cfg(test)]
+    flat_memory: bool,
+    // Writes in bus order while in flat memory mode, to check instruction timing against test data
+    #[cfg(test)]
+    write_log: Vec<(u16, u8)>,
 }
 
 impl Mmu {
@@ -58,8 +85,14 @@ impl Mmu {
             memory: vec![0; 0x10000],
             cgb_vram_bank1: vec![0; 0x2000],
             cgb_wram_bank1: vec![0; 0x1000 * 7],
+            dirty_tiles: [u64::MAX; DIRTY_TILE_WORDS],
+            vram_generation: 0,
+            oam_generation: 0,
             cgb_cram: Cram::new(),
             cgb_double_speed: false,
+            heatmap: AccessHeatmap::new(),
+            io_log: IoLog::new(),
+            uninit: UninitTracker::new(),
             cgb_prepare_speed_switch: false,
             cgb_hdma_src: 0,
             cgb_hdma_dst: 0,
@@ -68,21 +101,158 @@ impl Mmu {
             cgb_hdma_is_hblank_mode: false,
             bootrom,
             joypad: Joypad::new(),
-            apu: Apu::new(),
+            apu: Apu::new(mode.clone()),
+            serial: Serial::new(mode.clone()),
+            timer: Timer::new(),
             mode,
             last_ppu_state: State::OamScan,
             cycles: 0,
+            #[cfg(test)]
+            flat_memory: false,
+            #[cfg(test)]
+            write_log: Vec::new(),
         }
     }
 
+    pub fn init_ram(&mut self, init: &RamInit) {
+        let mut rng = init.rng();
+
+        // fixed order so that a seed always produces the same memory
+        init.fill(
+            &mut self.memory[WRAM_BANK0_START as usize..=WRAM_BANK1_END as usize],
+            &mut rng,
+        );
+        init.fill(&mut self.cgb_wram_bank1, &mut rng);
+        init.fill(&mut self.memory[VRAM_START as usize..=VRAM_END as usize], &mut rng);
+        init.fill(&mut self.cgb_vram_bank1, &mut rng);
+        init.fill(&mut self.memory[OAM_START as usize..=OAM_END as usize], &mut rng);
+        self.dirty_tiles = [u64::MAX; DIRTY_TILE_WORDS];
+        self.touch_vram();
+        self.oam_generation = self.oam_generation.wrapping_add(1);
+    }
+
+    // A CGB DMA to VRAM is waiting for the next HBlank
+    #[inline]
+    pub fn hdma_active(&self) -> bool {
+        self.cgb_hdma_started
+    }
+
     #[inline]
     pub fn cache_ppu_state(&mut self, state: State) {
         self.last_ppu_state = state;
     }
 
+    #[inline]
+    fn lcd_enabled(&self) -> bool {
+        self.memory[LCD_CONTROL_REGISTER as usize] & 0b1000_0000 != 0
+    }
+
+    // LY=LYC as the PPU compares it, this is what STAT bit 2 shows
+    #[inline]
+    fn lyc_matches(&self) -> bool {
+        self.lcd_enabled()
+            && self.memory[SCANLINE_Y_REGISTER as usize] == self.memory[SCANLINE_Y_COMPARE_REGISTER as usize]
+    }
+
+    // The PPU ORs all enabled STAT sources into one line, the interrupt fires when it goes high
+    fn stat_line(&self, stat: u8, lyc_matches: bool) -> bool {
+        let stat = LcdStatus::from_bits_truncate(stat);
+        self.lcd_enabled()
+            && match self.last_ppu_state {
+                State::HBlank => stat.contains(LcdStatus::MODE_0_CONDITION),
+                State::VBlank => stat.contains(LcdStatus::MODE_1_CONDITION),
+                State::OamScan => stat.contains(LcdStatus::MODE_2_CONDITION),
+                State::Drawing => false,
+            }
+            || (lyc_matches && stat.contains(LcdStatus::LYC_EQ_LY_ENABLE))
+    }
+
+    fn request_stat_interrupt(&mut self) {
+        self.memory[INTERRUPT_FLAGS_REGISTER as usize] |= InterruptFlags::STAT.bits();
+    }
+
+    fn request_timer_interrupt(&mut self) {
+        self.memory[INTERRUPT_FLAGS_REGISTER as usize] |= InterruptFlags::TIMER.bits();
+    }
+
+    // Runs DIV and TIMA for the given CPU cycles, the CPU itself only reports how many it took
+    pub fn tick_timer(&mut self, cycles: usize) {
+        if self.timer.tick(cycles) {
+            self.request_timer_interrupt();
+        }
+    }
+
+    // STOP resets DIV, which can count TIMA up like a write to DIV does
+    pub fn reset_divider(&mut self) {
+        if self.timer.reset_divider() {
+            self.request_timer_interrupt();
+        }
+    }
+
+    // DMG bug: for a cycle STAT acts as if $ff was written, so any active source raises the interrupt,
+    // which some games rely on (Legend of Zerd, Road Rash). The read-only bits 0-2 are masked off already.
+    fn write_lcd_status(&mut self, data: u8) {
+        let lyc_matches = self.lyc_matches();
+        let before = self.stat_line(self.memory[LCD_STATUS_REGISTER as usize], lyc_matches);
+        if self.mode == Mode::Dmg && !before && self.stat_line(0xff, lyc_matches) {
+            trace!("STAT write glitch, triggering STAT");
+            self.request_stat_interrupt();
+        }
+        self.memory[LCD_STATUS_REGISTER as usize] = data;
+    }
+
+    // The PPU compares LY and LYC all the time, so a new LYC that matches the current line
+    // raises the interrupt right away instead of when the next line starts
+    fn write_lyc(&mut self, data: u8) {
+        let stat = self.memory[LCD_STATUS_REGISTER as usize];
+        let before = self.stat_line(stat, self.lyc_matches());
+        self.memory[SCANLINE_Y_COMPARE_REGISTER as usize] = data;
+        if !before && self.stat_line(stat, self.lyc_matches()) {
+            trace!("LYC written to match LY, triggering STAT");
+            self.request_stat_interrupt();
+        }
+    }
+
+    // Unmapping the boot ROM locks KEY0, a CGB told to run a DMG game stays in compatibility mode from then on.
+    // The CGB registers go away and the PPU keeps the colors the boot ROM picked.
+    fn write_bootrom_mapper(&mut self, data: u8) {
+        let was_mapped = self.is_bootrom_mapped();
+        self.memory[BOOTROM_MAPPER_REGISTER as usize] = data;
+        if was_mapped
+            && !self.is_bootrom_mapped()
+            && self.mode == Mode::Cgb
+            && self.memory[CPU_MODE_REGISTER as usize] & 0b0000_0100 != 0
+        {
+            self.mode = Mode::Dmg;
+            self.cgb_cram.keep_boot_colors();
+            self.touch_vram();
+            self.serial.set_mode(Mode::Dmg);
+        }
+    }
+
+    // The PPU owns the palettes while it is drawing
+    #[inline]
+    fn is_cram_blocked(&self) -> bool {
+        self.last_ppu_state == State::Drawing && self.lcd_enabled()
+    }
+
     #[inline]
     pub fn read(&self, addr: u16) -> Result<u8, AyyError> {
-        if cfg!(test) {
+        if self.uninit.is_enabled()
+            && let Some(index) = self.tracked_index(addr)
+        {
+            self.uninit
+                .record_read(index, addr, |pc| BankedAddress::current(pc, self.cartridge.as_ref()));
+        }
+
+        self.peek(addr)
+    }
+
+    // What a read returns, without it showing up in the heatmap or the uninitialized-read tracking
+    #[inline]
+    pub fn peek(&self, addr: u16) -> Result<u8, AyyError> {
+        #[cfg(test)]
+        if self.flat_memory {
             return Ok(self.memory[addr as usize]);
         }
 
@@ -113,47 +283,154 @@ impl Mmu {
                     Ok(self.memory[addr as usize])
                 }
             }
-            JOYPAD_REGISTER => Ok(self.joypad.as_u8(self.memory[addr as usize])),
-            DOUBLE_SPEED_SWITCH_REGISTER if self.mode == Mode::Cgb => {
-                Ok(((self.cgb_double_speed as u16) << 7) as u8 | self.cgb_prepare_speed_switch as u8)
+            IO_START..=IO_END => Ok(self.read_io(addr)),
+            _ => Ok(self.memory[addr as usize]),
+        }
+    }
+
+    // IO registers go through IO_TABLE, which knows who answers for each of them
+    fn read_io(&self, addr: u16) -> u8 {
+        let entry = io_entry(addr);
+        let value = match entry.handler {
+            _ if entry.cgb_only && self.mode != Mode::Cgb => self.memory[addr as usize],
+            IoHandler::Joypad => self.joypad.as_u8(self.memory[addr as usize]),
+            IoHandler::Serial => self.serial.read(addr),
+            IoHandler::Timer => self.timer.read(addr),
+            IoHandler::Apu | IoHandler::Pcm => self.apu.read(addr),
+            IoHandler::LcdStatus => {
+                (self.memory[addr as usize] & 0b1111_1000)
+                    | ((self.lyc_matches() as u8) << 2)
+                    | self.last_ppu_state.as_u8()
             }
-            LCD_STATUS_REGISTER => Ok((self.memory[addr as usize] & 0b1111_1100) | self.last_ppu_state.as_u8()),
-            HDMA_LENGTH_MODE_START_REGISTER if self.mode == Mode::Cgb => {
+            IoHandler::DoubleSpeed => ((self.cgb_double_speed as u8) << 7) | self.cgb_prepare_speed_switch as u8,
+            IoHandler::HdmaStart => {
                 let remaining_length = ((self.cgb_hdma_transfer_length / 0x10).wrapping_sub(1) as u8) & 0b0111_1111;
-                Ok(((!self.cgb_hdma_started as u8) << 7) | remaining_length)
+                ((!self.cgb_hdma_started as u8) << 7) | remaining_length
             }
-            NR10
-            | NR11
-            | NR12
-            | NR13
-            | NR14
-            | NR21
-            | NR22
-            | NR23
-            | NR24
-            | NR30
-            | NR31
-            | NR32
-            | NR33
-            | NR34
-            | NR41
-            | NR42
-            | NR43
-            | NR44
-            | NR50
-            | NR51
-            | NR52
-            | WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END => Ok(self.apu.read(addr)),
-            BACKGROUND_PALETTE_INDEX_REGISTER
-            | BACKGROUND_PALETTE_DATA_REGISTER
-            | OBJECT_PALETTE_INDEX_REGISTER
-            | OBJECT_PALETTE_DATA_REGISTER
-                if self.mode == Mode::Cgb =>
-            {
-                Ok(self.cgb_cram.read(addr))
+            IoHandler::PaletteData if self.is_cram_blocked() => 0xff,
+            IoHandler::PaletteIndex | IoHandler::PaletteData => self.cgb_cram.read(addr),
+            IoHandler::Memory
+            | IoHandler::Lyc
+            | IoHandler::OamDma
+            | IoHandler::BootRom
+            | IoHandler::CpuMode
+            | IoHandler::HdmaSourceHigh
+            | IoHandler::HdmaSourceLow
+            | IoHandler::HdmaDestinationHigh
+            | IoHandler::HdmaDestinationLow => self.memory[addr as usize],
+        };
+        value | entry.read_mask
+    }
+
+    fn write_io(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
+        let entry = io_entry(addr);
+        let data = data & entry.write_mask;
+        match entry.handler {
+            // Locked for good once the boot ROM is gone, compatibility mode included
+            IoHandler::CpuMode if !self.is_bootrom_mapped() => {}
+            _ if entry.cgb_only && self.mode != Mode::Cgb => self.memory[addr as usize] = data,
+            IoHandler::Memory => {
+                self.memory[addr as usize] = (self.memory[addr as usize] & !entry.write_mask) | data;
             }
-            _ => Ok(self.memory[addr as usize]),
+            IoHandler::Joypad | IoHandler::CpuMode => self.memory[addr as usize] = data,
+            IoHandler::Serial => self.serial.write(addr, data),
+            IoHandler::Timer => {
+                if self.timer.write(addr, data) {
+                    self.request_timer_interrupt();
+                }
+            }
+            IoHandler::Apu => self.apu.write(addr, data),
+            IoHandler::Pcm => {}
+            IoHandler::LcdStatus => self.write_lcd_status(data),
+            IoHandler::Lyc => self.write_lyc(data),
+            IoHandler::OamDma => self.start_dma_transfer(data)?,
+            IoHandler::BootRom => self.write_bootrom_mapper(data),
+            IoHandler::DoubleSpeed => {
+                self.cgb_prepare_speed_switch = data & 0b0000_0001 == 1;
+
+                debug!(
+                    "Queuing CGB speed mode: {}",
+                    if self.cgb_prepare_speed_switch {
+                        "double"
+                    } else {
+                        "normal"
+                    }
+                );
+            }
+            IoHandler::HdmaSourceHigh => self.cgb_hdma_src = (data as u16) << 8,
+            IoHandler::HdmaSourceLow => {
+                self.cgb_hdma_src = (self.cgb_hdma_src | data as u16) & 0b1111_1111_1111_0000;
+            }
+            IoHandler::HdmaDestinationHigh => self.cgb_hdma_dst = (data as u16) << 8,
+            IoHandler::HdmaDestinationLow => {
+                self.cgb_hdma_dst = ((self.cgb_hdma_dst | data as u16) & 0b0001_1111_1111_0000) | 0x8000;
+            }
+            IoHandler::HdmaStart => {
+                if data & 0b1000_0000 == 0 && self.cgb_hdma_is_hblank_mode {
+                    self.cgb_hdma_started = false;
+                    debug!("HDMA transfer cancelled");
+                } else {
+                    self.start_hdma_transfer(data)?
+                }
+            }
+            IoHandler::PaletteData if self.is_cram_blocked() => self.cgb_cram.write_blocked(addr),
+            IoHandler::PaletteIndex | IoHandler::PaletteData => self.cgb_cram.write(addr, data),
         }
+        Ok(())
+    }
+
+    // Where a WRAM/HRAM byte lives among the bytes UninitTracker follows, every WRAM bank has its own
+    #[inline]
+    fn tracked_index(&self, addr: u16) -> Option<usize> {
+        match addr {
+            WRAM_BANK0_START..WRAM_BANK1_START => Some((addr - WRAM_BANK0_START) as usize),
+            WRAM_BANK1_START..=WRAM_BANK1_END => {
+                Some(0x1000 * self.current_wram_bank().max(1) as usize + (addr - WRAM_BANK1_START) as usize)
+            }
+            HRAM_START..=HRAM_END => Some(0x8000 + (addr - HRAM_START) as usize),
+            // Echo RAM is the same bytes as WRAM
+            ECHO_RAM_START..=ECHO_RAM_END => self.tracked_index(addr - (ECHO_RAM_START - WRAM_BANK0_START)),
+            _ => None,
+        }
+    }
+
+    #[inline]
+    fn mark_tile_dirty(&mut self, addr: u16, bank: u8) {
+        let offset = (addr - VRAM_START) as usize / 16;
+        if offset < TILE_DATA_COUNT {
+            let tile = bank as usize * TILE_DATA_COUNT + offset;
+            self.dirty_tiles[tile / 64] |= 1 << (tile % 64);
+        }
+    }
+
+    // Changes whenever VRAM or the palettes might have, so that views of it (e.g. in the debugger) can tell
+    // whether there's anything new to draw. Only ever compare it for equality.
+    #[inline]
+    pub fn vram_generation(&self) -> u64 {
+        self.vram_generation
+    }
+
+    // Something changed the colors without a write, e.g. the color correction
+    #[inline]
+    pub fn touch_vram(&mut self) {
+        self.vram_generation = self.vram_generation.wrapping_add(1);
+    }
+
+    // Like vram_generation, for OAM
+    #[inline]
+    pub fn oam_generation(&self) -> u64 {
+        self.oam_generation
+    }
+
+    // A new Mmu taking over (e.g. from a save state) could be at the generations views last saw, with other memory
+    pub fn continue_vram_generation(&mut self, previous: &Mmu) {
+        self.vram_generation = previous.vram_generation.wrapping_add(1);
+        self.oam_generation = previous.oam_generation.wrapping_add(1);
+    }
+
+    // Tiles written since the last call, this is how the PPU's tile cache learns about VRAM writes
+    pub fn take_dirty_tiles(&mut self) -> DirtyTiles {
+        std::mem::replace(&mut self.dirty_tiles, [0; DIRTY_TILE_WORDS])
     }
 
     #[inline]
@@ -165,6 +442,17 @@ impl Mmu {
         }
     }
 
+    pub fn vram(&self, bank: u8) -> &[u8] {
+        match bank {
+            0 => &self.memory[VRAM_START as usize..=VRAM_END as usize],
+            _ => &self.cgb_vram_bank1,
+        }
+    }
+
+    pub fn oam(&self) -> &[u8] {
+        &self.memory[OAM_START as usize..=OAM_END as usize]
+    }
+
     #[inline]
     pub fn read_as<T>(&self, addr: u16) -> Result<T, AyyError>
     where
@@ -180,6 +468,47 @@ impl Mmu {
         Ok((hi << 8) | lo)
     }
 
+    // Reads and writes of the CPU itself, i.e. fetches and operands, are the only ones the heatmap counts.
+    // Everything the emulator does on its own (DMA, debugger, interrupt checks) goes through read/write.
+    #[inline]
+    pub fn cpu_read(&self, addr: u16) -> Result<u8, AyyError> {
+        self.heatmap.record_read(addr);
+        self.read(addr)
+    }
+
+    // The CPU fetching an instruction it already decoded ahead of time, which counts like reading it with cpu_read
+    pub fn record_fetch(&self, addr: u16, length: usize) {
+        for offset in 0..length {
+            let addr = addr.wrapping_add(offset as u16);
+            self.heatmap.record_read(addr);
+            if self.uninit.is_enabled()
+                && let Some(index) = self.tracked_index(addr)
+            {
+                self.uninit
+                    .record_read(index, addr, |pc| BankedAddress::current(pc, self.cartridge.as_ref()));
+            }
+        }
+    }
+
+    #[inline]
+    pub fn cpu_read16(&self, addr: u16) -> Result<u16, AyyError> {
+        let lo = self.cpu_read(addr)? as u16;
+        let hi = self.cpu_read(addr.wrapping_add(1))? as u16;
+        Ok((hi << 8) | lo)
+    }
+
+    #[inline]
+    pub fn cpu_write(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
+        self.heatmap.record_write(addr);
+        self.write(addr, data)
+    }
+
+    #[inline]
+    pub fn cpu_write16(&mut self, addr: u16, data: u16) -> Result<(), AyyError> {
+        self.cpu_write(addr, data as u8)?;
+        self.cpu_write(addr.wrapping_add(1), (data >> 8) as u8)
+    }
+
     #[inline]
     pub fn read_unchecked(&self, addr: u16) -> u8 {
         self.read(addr).unwrap()
@@ -203,13 +532,32 @@ impl Mmu {
         self.write16(addr, data).unwrap();
     }
 
+    // Only the CPU writes through here, the hardware updates its own registers with write_unchecked
     #[inline]
     pub fn write(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
-        if cfg!(test) {
+        if self.io_log.is_recording() {
+            self.io_log
+                .record(addr, data, self.memory[SCANLINE_Y_REGISTER as usize]);
+        }
+
+        self.store(addr, data)
+    }
+
+    #[inline]
+    fn store(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
+        #[cfg(test)]
+        if self.flat_memory {
             self.memory[addr as usize] = data;
+            self.write_log.push((addr, data));
             return Ok(());
         }
 
+        if self.uninit.is_enabled()
+            && let Some(index) = self.tracked_index(addr)
+        {
+            self.uninit.record_write(index);
+        }
+
         let bootrom_size = match self.mode {
             Mode::Dmg => DMG_BOOTROM_SIZE,
             Mode::Cgb => CGB_BOOTROM_SIZE,
@@ -224,9 +572,15 @@ impl Mmu {
                 error!("Attempted to write to bootrom");
             }
             ROM_START..=ROM_END => self.cartridge.write(addr, data)?,
-            VRAM_START..=VRAM_END if self.current_vram_bank() == 0 => self.memory[addr as usize] = data,
+            VRAM_START..=VRAM_END if self.current_vram_bank() == 0 => {
+                self.memory[addr as usize] = data;
+                self.mark_tile_dirty(addr, 0);
+                self.touch_vram();
+            }
             VRAM_START..=VRAM_END if self.current_vram_bank() == 1 => {
-                self.cgb_vram_bank1[(addr - VRAM_START) as usize] = data
+                self.cgb_vram_bank1[(addr - VRAM_START) as usize] = data;
+                self.mark_tile_dirty(addr, 1);
+                self.touch_vram();
             }
             EXTERNAL_RAM_START..=EXTERNAL_RAM_END => self.cartridge.write(addr, data)?,
             WRAM_BANK1_START..=WRAM_BANK1_END => {
@@ -237,68 +591,23 @@ impl Mmu {
                     self.memory[addr as usize] = data
                 }
             }
-            OAM_DMA_REGISTER => self.start_dma_transfer(data)?,
-            HDMA_VRAM_SRC_HIGH_REGISTER if self.mode == Mode::Cgb => {
-                self.cgb_hdma_src = (data as u16) << 8;
-            }
-            HDMA_VRAM_SRC_LOW_REGISTER if self.mode == Mode::Cgb => {
-                self.cgb_hdma_src = (self.cgb_hdma_src | data as u16) & 0b1111_1111_1111_0000;
-            }
-            HDMA_VRAM_DST_HIGH_REGISTER if self.mode == Mode::Cgb => {
-                self.cgb_hdma_dst = (data as u16) << 8;
-            }
-            HDMA_VRAM_DST_LOW_REGISTER if self.mode == Mode::Cgb => {
-                self.cgb_hdma_dst = ((self.cgb_hdma_dst | data as u16) & 0b0001_1111_1111_0000) | 0x8000;
-            }
-            HDMA_LENGTH_MODE_START_REGISTER if self.mode == Mode::Cgb => {
-                if data & 0b1000_0000 == 0 && self.cgb_hdma_is_hblank_mode {
-                    self.cgb_hdma_started = false;
-                    debug!("HDMA transfer cancelled");
-                } else {
-                    self.start_hdma_transfer(data)?
+            IO_START..=IO_END => {
+                self.write_io(addr, data)?;
+                // The DMG palettes and CGB color RAM decide what VRAM looks like
+                if matches!(
+                    addr,
+                    BG_PALETTE_REGISTER
+                        | OBJ0_PALETTE_REGISTER
+                        | OBJ1_PALETTE_REGISTER
+                        | BACKGROUND_PALETTE_DATA_REGISTER
+                        | OBJECT_PALETTE_DATA_REGISTER
+                ) {
+                    self.touch_vram();
                 }
             }
-            DOUBLE_SPEED_SWITCH_REGISTER if self.mode == Mode::Cgb => {
-                self.cgb_prepare_speed_switch = data & 0b0000_0001 == 1;
-
-                debug!(
-                    "Queuing CGB speed mode: {}",
-                    if self.cgb_prepare_speed_switch {
-                        "double"
-                    } else {
-                        "normal"
-                    }
-                );
-            }
-            NR10
-            | NR11
-            | NR12
-            | NR13
-            | NR14
-            | NR21
-            | NR22
-            | NR23
-            | NR24
-            | NR30
-            | NR31
-            | NR32
-            | NR33
-            | NR34
-            | NR41
-            | NR42
-            | NR43
-            | NR44
-            | NR50
-            | NR51
-            | NR52
-            | WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END => self.apu.write(addr, data),
-            BACKGROUND_PALETTE_INDEX_REGISTER
-            | BACKGROUND_PALETTE_DATA_REGISTER
-            | OBJECT_PALETTE_INDEX_REGISTER
-            | OBJECT_PALETTE_DATA_REGISTER
-                if self.mode == Mode::Cgb =>
-            {
-                self.cgb_cram.write(addr, data)
+            OAM_START..=OAM_END => {
+                self.memory[addr as usize] = data;
+                self.oam_generation = self.oam_generation.wrapping_add(1);
             }
             _ => self.memory[addr as usize] = data,
         }
@@ -317,12 +626,26 @@ impl Mmu {
 
     #[inline]
     pub fn write_unchecked(&mut self, addr: u16, data: u8) {
-        self.write(addr, data).unwrap();
+        self.store(addr, data).unwrap();
+    }
+
+    // Like write, but ROM writes modify the loaded image instead of talking to the MBC
+    pub fn patch(&mut self, addr: u16, data: u8) -> Result<(), AyyError> {
+        match addr {
+            ROM_START..=ROM_END => self.cartridge.patch_rom(addr, data),
+            _ => self.write(addr, data),
+        }
     }
 
     #[inline]
     pub fn is_bootrom_mapped(&self) -> bool {
-        self.read(BOOTROM_MAPPER_REGISTER).unwrap() == 0x00
+        self.memory[BOOTROM_MAPPER_REGISTER as usize] == 0x00
+    }
+
+    // The PPU mode as of the last CPU step
+    #[inline]
+    pub fn ppu_state(&self) -> State {
+        self.last_ppu_state
     }
 
     #[inline]
@@ -334,6 +657,33 @@ impl Mmu {
         }
     }
 
+    // One of the switchable CGB WRAM banks (1-7), mapped in or not
+    pub fn cgb_wram_bank(&self, bank: u8) -> &[u8] {
+        let offset = (bank as usize - 1) * 0x1000;
+        &self.cgb_wram_bank1[offset..offset + 0x1000]
+    }
+
+    pub fn bootrom(&self) -> &[u8] {
+        &self.bootrom
+    }
+
+    pub fn mode(&self) -> &Mode {
+        &self.mode
+    }
+
+    // Snapshot of $c000-$dfff as currently mapped, taken without touching the heatmap
+    pub fn dump_wram(&self) -> Vec<u8> {
+        let mut wram = self.memory[WRAM_BANK0_START as usize..WRAM_BANK1_START as usize].to_vec();
+        match self.current_wram_bank() {
+            0 => wram.extend_from_slice(&self.memory[WRAM_BANK1_START as usize..=WRAM_BANK1_END as usize]),
+            bank => {
+                let offset = (bank as usize - 1) * 0x1000;
+                wram.extend_from_slice(&self.cgb_wram_bank1[offset..offset + 0x1000]);
+            }
+        }
+        wram
+    }
+
     #[inline]
     pub fn current_wram_bank(&self) -> u8 {
         if self.mode == Mode::Cgb {
@@ -364,9 +714,10 @@ impl Mmu {
         let src_addr = (data as u16) << 8;
         trace!("OAM DMA transfer from ${:04x}", src_addr);
 
+        // The DMA unit copies the bytes, not the CPU, so it neither shows up as reads nor goes through write
         for i in 0..0xa0 {
-            let byte = self.read(src_addr + i)?;
-            self.write(0xfe00 + i, byte)?;
+            let byte = self.peek(src_addr + i)?;
+            self.store(OAM_START + i, byte)?;
         }
 
         self.cycles += 160;
@@ -457,9 +808,16 @@ impl Mmu {
         cycles
     }
 
+    // Turns the whole address space into plain RAM, as expected by the SM83 tests
     #[cfg(test)]
     pub fn resize_memory(&mut self, size: usize) {
         self.memory.resize(size, 0);
+        self.flat_memory = true;
+    }
+
+    #[cfg(test)]
+    pub fn take_write_log(&mut self) -> Vec<(u16, u8)> {
+        std::mem::take(&mut self.write_log)
     }
 
     #[cfg(test)]
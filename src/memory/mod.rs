@@ -1,7 +1,17 @@
 pub mod addressable;
+pub mod banked;
+pub mod heatmap;
+pub mod io_dispatch;
+pub mod io_log;
+pub mod io_registers;
 pub mod mapper;
 pub mod mmu;
+pub mod patch;
+pub mod peek;
+pub mod ram_decay;
+pub mod ram_init;
 pub mod registers;
+pub mod uninit;
 
 pub const INTERRUPT_ENABLE_REGISTER: u16 = 0xffff;
 pub const INTERRUPT_FLAGS_REGISTER: u16 = 0xff0f;
@@ -24,6 +34,8 @@ pub const HDMA_VRAM_DST_HIGH_REGISTER: u16 = 0xff53;
 pub const HDMA_VRAM_DST_LOW_REGISTER: u16 = 0xff54;
 pub const HDMA_LENGTH_MODE_START_REGISTER: u16 = 0xff55;
 pub const DOUBLE_SPEED_SWITCH_REGISTER: u16 = 0xff4d;
+pub const CPU_MODE_REGISTER: u16 = 0xff4c;
+pub const INFRARED_REGISTER: u16 = 0xff56;
 
 pub const ROM_START: u16 = 0x0000;
 pub const ROM_END: u16 = 0x7fff;
@@ -31,5 +43,14 @@ pub const EXTERNAL_RAM_START: u16 = 0xa000;
 pub const EXTERNAL_RAM_END: u16 = 0xbfff;
 pub const VRAM_START: u16 = 0x8000;
 pub const VRAM_END: u16 = 0x9fff;
+pub const WRAM_BANK0_START: u16 = 0xc000;
 pub const WRAM_BANK1_START: u16 = 0xd000;
 pub const WRAM_BANK1_END: u16 = 0xdfff;
+pub const ECHO_RAM_START: u16 = 0xe000;
+pub const ECHO_RAM_END: u16 = 0xfdff;
+pub const OAM_START: u16 = 0xfe00;
+pub const OAM_END: u16 = 0xfe9f;
+pub const IO_START: u16 = 0xff00;
+pub const IO_END: u16 = 0xff7f;
+pub const HRAM_START: u16 = 0xff80;
+pub const HRAM_END: u16 = 0xfffe;
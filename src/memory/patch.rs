@@ -0,0 +1,214 @@
+use crate::error::AyyError;
+
+const IPS_MAGIC: &[u8] = b"PATCH";
+const IPS_EOF: &[u8] = b"EOF";
+const BPS_MAGIC: &[u8] = b"BPS1";
+// Largest ROM there is (8 MiB), more than enough for any patched Game Boy ROM
+const MAX_RESERVED_SIZE: usize = 0x800000;
+
+// Applies an IPS or BPS patch to a ROM image, the format is detected from the patch header
+pub fn apply_patch(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, AyyError> {
+    if patch.starts_with(IPS_MAGIC) {
+        apply_ips(rom, patch)
+    } else if patch.starts_with(BPS_MAGIC) {
+        apply_bps(rom, patch)
+    } else {
+        Err(invalid("unknown patch format"))
+    }
+}
+
+fn apply_ips(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, AyyError> {
+    let mut output = rom.to_vec();
+    let mut reader = PatchReader::new(&patch[IPS_MAGIC.len()..]);
+
+    loop {
+        let record = reader.bytes(3)?;
+        if record == IPS_EOF {
+            break;
+        }
+
+        let offset = (record[0] as usize) << 16 | (record[1] as usize) << 8 | record[2] as usize;
+        let size = reader.u16_be()? as usize;
+
+        // A size of 0 marks a run-length encoded record
+        let data = if size == 0 {
+            let count = reader.u16_be()? as usize;
+            vec![reader.byte()?; count]
+        } else {
+            reader.bytes(size)?.to_vec()
+        };
+
+        if output.len() < offset + data.len() {
+            output.resize(offset + data.len(), 0);
+        }
+        output[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    // Some patches truncate the ROM, the new size follows the EOF marker
+    if let Ok(size) = reader.bytes(3) {
+        output.truncate((size[0] as usize) << 16 | (size[1] as usize) << 8 | size[2] as usize);
+    }
+
+    Ok(output)
+}
+
+fn apply_bps(rom: &[u8], patch: &[u8]) -> Result<Vec<u8>, AyyError> {
+    if patch.len() < BPS_MAGIC.len() + 12 {
+        return Err(invalid("BPS patch is truncated"));
+    }
+
+    // The footer holds the CRC32 of the source, the target and the patch itself
+    let footer = &patch[patch.len() - 12..];
+    let source_crc = u32::from_le_bytes(footer[0..4].try_into().unwrap());
+    let target_crc = u32::from_le_bytes(footer[4..8].try_into().unwrap());
+    let patch_crc = u32::from_le_bytes(footer[8..12].try_into().unwrap());
+
+    if crc32(&patch[..patch.len() - 4]) != patch_crc {
+        return Err(invalid("BPS patch checksum mismatch"));
+    }
+    if crc32(rom) != source_crc {
+        return Err(invalid("ROM does not match the BPS source checksum"));
+    }
+
+    let mut reader = PatchReader::new(&patch[BPS_MAGIC.len()..patch.len() - 12]);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+
+    if source_size != rom.len() {
+        return Err(invalid("ROM size does not match the BPS source size"));
+    }
+
+    // The sizes come from the patch, don't let a broken one reserve more than a large ROM
+    let mut output = Vec::with_capacity(target_size.min(MAX_RESERVED_SIZE));
+    let mut source_offset = 0isize;
+    let mut target_offset = 0isize;
+
+    while !reader.is_empty() {
+        let action = reader.number()?;
+        let length = (action >> 2) + 1;
+
+        match action & 0b11 {
+            // SourceRead
+            0 => {
+                let start = output.len();
+                output.extend_from_slice(
+                    rom.get(start..start.saturating_add(length))
+                        .ok_or(invalid("SourceRead out of bounds"))?,
+                );
+            }
+            // TargetRead
+            1 => output.extend_from_slice(reader.bytes(length)?),
+            // SourceCopy
+            2 => {
+                source_offset = source_offset.saturating_add(reader.signed_number()?);
+                let start = source_offset as usize;
+                output.extend_from_slice(
+                    rom.get(start..start.saturating_add(length))
+                        .ok_or(invalid("SourceCopy out of bounds"))?,
+                );
+                source_offset += length as isize;
+            }
+            // TargetCopy, may overlap with the bytes it is producing so copy one at a time
+            _ => {
+                target_offset = target_offset.saturating_add(reader.signed_number()?);
+                for _ in 0..length {
+                    let byte = *output
+                        .get(target_offset as usize)
+                        .ok_or(invalid("TargetCopy out of bounds"))?;
+                    output.push(byte);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if output.len() != target_size || crc32(&output) != target_crc {
+        return Err(invalid("Patched ROM does not match the BPS target checksum"));
+    }
+
+    Ok(output)
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn invalid(reason: &str) -> AyyError {
+    AyyError::InvalidPatch {
+        reason: reason.to_string(),
+    }
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8]) -> PatchReader<'a> {
+        PatchReader { data, offset: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], AyyError> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset.saturating_add(length))
+            .ok_or(invalid("Unexpected end of patch"))?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, AyyError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<u16, AyyError> {
+        let bytes = self.bytes(2)?;
+        Ok((bytes[0] as u16) << 8 | bytes[1] as u16)
+    }
+
+    // BPS variable length number, 7 bits per byte with the high bit marking the last one
+    fn number(&mut self) -> Result<usize, AyyError> {
+        let too_large = || invalid("Number in patch is too large");
+        let mut value = 0usize;
+        let mut shift = 1usize;
+
+        loop {
+            let byte = self.byte()?;
+            value = ((byte & 0x7f) as usize)
+                .checked_mul(shift)
+                .and_then(|digit| value.checked_add(digit))
+                .ok_or_else(too_large)?;
+            if byte & 0x80 != 0 {
+                break;
+            }
+            shift = shift.checked_mul(0x80).ok_or_else(too_large)?;
+            value = value.checked_add(shift).ok_or_else(too_large)?;
+        }
+
+        Ok(value)
+    }
+
+    fn signed_number(&mut self) -> Result<isize, AyyError> {
+        let value = self.number()?;
+        let magnitude = (value >> 1) as isize;
+        Ok(if value & 1 != 0 { -magnitude } else { magnitude })
+    }
+}
@@ -0,0 +1,48 @@
+use crate::gameboy::Mode;
+use crate::memory::mmu::Mmu;
+
+// WRAM banks 2-7 follow the CPU's address space, as achievement runtimes (rcheevos) lay out the CGB
+pub const CGB_WRAM_PEEK_START: u32 = 0x10000;
+
+// Copy of the memory as it was at the end of a frame, for achievement runtimes and other tools that
+// evaluate memory once per frame. Peeking never has side effects, and every value is from the same instant.
+#[derive(Clone, Default)]
+pub struct MemorySnapshot {
+    memory: Vec<u8>,
+}
+
+impl MemorySnapshot {
+    pub fn capture(mmu: &Mmu) -> MemorySnapshot {
+        let mut snapshot = MemorySnapshot::default();
+        snapshot.update(mmu);
+        snapshot
+    }
+
+    // Captures again into the same buffer, so that taking one every frame doesn't allocate
+    pub fn update(&mut self, mmu: &Mmu) {
+        self.memory.clear();
+        self.memory
+            .extend((0..=0xffff).map(|addr| mmu.peek(addr).unwrap_or(0xff)));
+
+        if *mmu.mode() == Mode::Cgb {
+            for bank in 2..=7 {
+                self.memory.extend_from_slice(mmu.cgb_wram_bank(bank));
+            }
+        }
+    }
+
+    // None past the end, e.g. the extra WRAM banks on a DMG
+    #[inline]
+    pub fn peek(&self, addr: u32) -> Option<u8> {
+        self.memory.get(addr as usize).copied()
+    }
+
+    #[inline]
+    pub fn peek16(&self, addr: u32) -> Option<u16> {
+        Some(u16::from_le_bytes([self.peek(addr)?, self.peek(addr + 1)?]))
+    }
+
+    pub fn size(&self) -> usize {
+        self.memory.len()
+    }
+}
@@ -0,0 +1,57 @@
+use crate::memory::ram_init::XorShift;
+use std::str::FromStr;
+
+// What happens to cartridge RAM when the console loses power without a battery keeping it alive.
+// Every cell has a value it settles to once its charge is gone and a retention it holds out for,
+// both fixed by the seed like they are for one particular chip. Losing power for longer lets the
+// weaker cells decay, so the same bits go first every time, as they do on real SRAM.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RamDecay {
+    // Share of the bits that decay, 100 leaves nothing of the old contents
+    percent: u8,
+    seed: u64,
+}
+
+impl RamDecay {
+    pub fn new(percent: u8, seed: u64) -> RamDecay {
+        RamDecay {
+            percent: percent.min(100),
+            seed,
+        }
+    }
+
+    pub fn percent(&self) -> u8 {
+        self.percent
+    }
+
+    pub fn apply(&self, ram: &mut [u8]) {
+        let threshold = self.percent as u64 * 0x100 / 100;
+        let mut cells = XorShift::new(self.seed);
+
+        for byte in ram.iter_mut() {
+            let retention = cells.next_u64();
+            let settled = cells.next_u64() as u8;
+            let decayed = (0..8)
+                .filter(|bit| (retention >> (bit * 8)) & 0xff < threshold)
+                .fold(0u8, |mask, bit| mask | (1 << bit));
+            *byte = (*byte & !decayed) | (settled & decayed);
+        }
+    }
+}
+
+impl FromStr for RamDecay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (percent, seed) = s.split_once(':').unwrap_or((s, "0"));
+        let percent = percent
+            .parse::<u8>()
+            .ok()
+            .filter(|percent| *percent <= 100)
+            .ok_or_else(|| format!("expected a percentage from 0 to 100, got {}", percent))?;
+        let seed = seed
+            .parse::<u64>()
+            .map_err(|e| format!("invalid seed {}: {}", seed, e))?;
+        Ok(RamDecay::new(percent, seed))
+    }
+}
@@ -0,0 +1,72 @@
+use std::str::FromStr;
+
+// Power-on contents of WRAM, VRAM and OAM
+#[derive(Debug, Clone, PartialEq)]
+pub enum RamInit {
+    Zero,
+    // Alternating blocks of 0x00 and 0xff, roughly what most DMG units power up with
+    Pattern,
+    Random(u64),
+}
+
+impl RamInit {
+    pub fn fill(&self, memory: &mut [u8], rng: &mut XorShift) {
+        match self {
+            RamInit::Zero => memory.fill(0x00),
+            RamInit::Pattern => {
+                for (idx, byte) in memory.iter_mut().enumerate() {
+                    *byte = if (idx / 8) % 2 == 0 { 0x00 } else { 0xff };
+                }
+            }
+            RamInit::Random(_) => memory.iter_mut().for_each(|byte| *byte = rng.next_u64() as u8),
+        }
+    }
+
+    pub fn rng(&self) -> XorShift {
+        match self {
+            RamInit::Random(seed) => XorShift::new(*seed),
+            _ => XorShift::new(0),
+        }
+    }
+}
+
+impl FromStr for RamInit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zero" => Ok(RamInit::Zero),
+            "pattern" => Ok(RamInit::Pattern),
+            _ => match s.strip_prefix("random:") {
+                Some(seed) => seed
+                    .parse::<u64>()
+                    .map(RamInit::Random)
+                    .map_err(|e| format!("invalid seed {}: {}", seed, e)),
+                None => Err(format!("expected zero, pattern or random:<seed>, got {}", s)),
+            },
+        }
+    }
+}
+
+// Small and fast PRNG, only needs to be reproducible for a given seed
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        // xorshift gets stuck on a zero state
+        XorShift {
+            state: seed ^ 0x9e37_79b9_7f4a_7c15,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+}
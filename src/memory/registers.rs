@@ -83,3 +83,525 @@ impl From<u8> for AudioMasterControl {
         Self::from_bits_truncate(byte)
     }
 }
+
+// One field of an IO register, for the debugger's documentation tooltips
+#[derive(Debug, PartialEq)]
+pub struct RegisterField {
+    // Bits the field occupies, e.g. 0b0000_0011
+    pub mask: u8,
+    pub name: &'static str,
+    // Meaning of every value the field can take, empty for plain numbers
+    pub values: &'static [&'static str],
+}
+
+impl RegisterField {
+    const fn new(mask: u8, name: &'static str, values: &'static [&'static str]) -> RegisterField {
+        RegisterField { mask, name, values }
+    }
+
+    pub fn value(&self, register: u8) -> u8 {
+        (register & self.mask) >> self.mask.trailing_zeros()
+    }
+
+    // "7", "6-4" or the like
+    pub fn bits(&self) -> String {
+        let (high, low) = (7 - self.mask.leading_zeros(), self.mask.trailing_zeros());
+        match high == low {
+            true => high.to_string(),
+            false => format!("{}-{}", high, low),
+        }
+    }
+}
+
+// What an IO register does and how its bits are laid out, as the Pandocs describe it
+#[derive(Debug, PartialEq)]
+pub struct RegisterDoc {
+    // Name the Pandocs call it by, e.g. "LCDC"
+    pub name: &'static str,
+    pub summary: &'static str,
+    pub fields: &'static [RegisterField],
+}
+
+impl RegisterDoc {
+    // The summary followed by every field decoded from the given value
+    pub fn describe(&self, register: u8) -> String {
+        let mut description = self.summary.to_string();
+        for field in self.fields {
+            let value = field.value(register);
+            description.push_str(&format!("\nBit {:<4} {}: {}", field.bits(), field.name, value));
+            if let Some(meaning) = field.values.get(value as usize) {
+                description.push_str(&format!(" ({})", meaning));
+            }
+        }
+        description
+    }
+}
+
+const OFF_ON: &[&str] = &["off", "on"];
+const ENVELOPE: [RegisterField; 3] = [
+    RegisterField::new(0b1111_0000, "Initial volume", &[]),
+    RegisterField::new(0b0000_1000, "Envelope direction", &["decrease", "increase"]),
+    RegisterField::new(0b0000_0111, "Sweep pace (0 = off)", &[]),
+];
+const LENGTH_DUTY: [RegisterField; 2] = [
+    RegisterField::new(0b1100_0000, "Wave duty", &["12.5%", "25%", "50%", "75%"]),
+    RegisterField::new(0b0011_1111, "Initial length timer", &[]),
+];
+const PERIOD_HIGH_CONTROL: [RegisterField; 3] = [
+    RegisterField::new(0b1000_0000, "Trigger", &["-", "restart channel"]),
+    RegisterField::new(0b0100_0000, "Length enable", OFF_ON),
+    RegisterField::new(0b0000_0111, "Period high bits", &[]),
+];
+const PALETTE: [RegisterField; 4] = [
+    RegisterField::new(0b1100_0000, "Color 3", &["white", "light gray", "dark gray", "black"]),
+    RegisterField::new(0b0011_0000, "Color 2", &["white", "light gray", "dark gray", "black"]),
+    RegisterField::new(0b0000_1100, "Color 1", &["white", "light gray", "dark gray", "black"]),
+    RegisterField::new(0b0000_0011, "Color 0", &["white", "light gray", "dark gray", "black"]),
+];
+const INTERRUPTS: [RegisterField; 5] = [
+    RegisterField::new(0b0001_0000, "Joypad", OFF_ON),
+    RegisterField::new(0b0000_1000, "Serial", OFF_ON),
+    RegisterField::new(0b0000_0100, "Timer", OFF_ON),
+    RegisterField::new(0b0000_0010, "STAT", OFF_ON),
+    RegisterField::new(0b0000_0001, "VBlank", OFF_ON),
+];
+const CGB_PALETTE_INDEX: [RegisterField; 2] = [
+    RegisterField::new(0b1000_0000, "Auto increment", OFF_ON),
+    RegisterField::new(0b0011_1111, "Address", &[]),
+];
+
+// Every named register, IO_TABLE hands them out by address
+pub const P1: RegisterDoc = RegisterDoc {
+    name: "P1",
+    summary: "Joypad: select a button group, read it back in the low bits (0 = pressed)",
+    fields: &[
+        RegisterField::new(0b0010_0000, "Select buttons", &["selected", "-"]),
+        RegisterField::new(0b0001_0000, "Select d-pad", &["selected", "-"]),
+        RegisterField::new(0b0000_1000, "Start / Down", &["pressed", "released"]),
+        RegisterField::new(0b0000_0100, "Select / Up", &["pressed", "released"]),
+        RegisterField::new(0b0000_0010, "B / Left", &["pressed", "released"]),
+        RegisterField::new(0b0000_0001, "A / Right", &["pressed", "released"]),
+    ],
+};
+
+pub const SB: RegisterDoc = RegisterDoc {
+    name: "SB",
+    summary: "Serial transfer data, shifted out while the partner's byte is shifted in",
+    fields: &[],
+};
+
+pub const SC: RegisterDoc = RegisterDoc {
+    name: "SC",
+    summary: "Serial transfer control",
+    fields: &[
+        RegisterField::new(0b1000_0000, "Transfer", &["idle", "in progress"]),
+        RegisterField::new(0b0000_0010, "Clock speed (CGB)", &["normal", "fast"]),
+        RegisterField::new(0b0000_0001, "Clock", &["external", "internal"]),
+    ],
+};
+
+pub const DIV: RegisterDoc = RegisterDoc {
+    name: "DIV",
+    summary: "Divider: upper byte of a counter running at 16384Hz, any write resets it",
+    fields: &[],
+};
+
+pub const TIMA: RegisterDoc = RegisterDoc {
+    name: "TIMA",
+    summary: "Timer counter, raises the timer interrupt and reloads from TMA when it overflows",
+    fields: &[],
+};
+
+pub const TMA: RegisterDoc = RegisterDoc {
+    name: "TMA",
+    summary: "Timer modulo, loaded into TIMA when it overflows",
+    fields: &[],
+};
+
+pub const TAC: RegisterDoc = RegisterDoc {
+    name: "TAC",
+    summary: "Timer control",
+    fields: &[
+        RegisterField::new(0b0000_0100, "Enable", OFF_ON),
+        RegisterField::new(0b0000_0011, "Clock", &["4096Hz", "262144Hz", "65536Hz", "16384Hz"]),
+    ],
+};
+
+pub const IF: RegisterDoc = RegisterDoc {
+    name: "IF",
+    summary: "Interrupt flags, requested interrupts",
+    fields: &INTERRUPTS,
+};
+
+pub const NR10: RegisterDoc = RegisterDoc {
+    name: "NR10",
+    summary: "Channel 1 sweep",
+    fields: &[
+        RegisterField::new(0b0111_0000, "Pace (0 = off)", &[]),
+        RegisterField::new(0b0000_1000, "Direction", &["up", "down"]),
+        RegisterField::new(0b0000_0111, "Step", &[]),
+    ],
+};
+
+pub const NR11: RegisterDoc = RegisterDoc {
+    name: "NR11",
+    summary: "Channel 1 length timer and duty cycle",
+    fields: &LENGTH_DUTY,
+};
+
+pub const NR12: RegisterDoc = RegisterDoc {
+    name: "NR12",
+    summary: "Channel 1 volume and envelope, the DAC is off while bits 7-3 are all 0",
+    fields: &ENVELOPE,
+};
+
+pub const NR13: RegisterDoc = RegisterDoc {
+    name: "NR13",
+    summary: "Channel 1 period low bits",
+    fields: &[],
+};
+
+pub const NR14: RegisterDoc = RegisterDoc {
+    name: "NR14",
+    summary: "Channel 1 period high bits and control",
+    fields: &PERIOD_HIGH_CONTROL,
+};
+
+pub const NR21: RegisterDoc = RegisterDoc {
+    name: "NR21",
+    summary: "Channel 2 length timer and duty cycle",
+    fields: &LENGTH_DUTY,
+};
+
+pub const NR22: RegisterDoc = RegisterDoc {
+    name: "NR22",
+    summary: "Channel 2 volume and envelope, the DAC is off while bits 7-3 are all 0",
+    fields: &ENVELOPE,
+};
+
+pub const NR23: RegisterDoc = RegisterDoc {
+    name: "NR23",
+    summary: "Channel 2 period low bits",
+    fields: &[],
+};
+
+pub const NR24: RegisterDoc = RegisterDoc {
+    name: "NR24",
+    summary: "Channel 2 period high bits and control",
+    fields: &PERIOD_HIGH_CONTROL,
+};
+
+pub const NR30: RegisterDoc = RegisterDoc {
+    name: "NR30",
+    summary: "Channel 3 DAC enable",
+    fields: &[RegisterField::new(0b1000_0000, "DAC", OFF_ON)],
+};
+
+pub const NR31: RegisterDoc = RegisterDoc {
+    name: "NR31",
+    summary: "Channel 3 initial length timer",
+    fields: &[],
+};
+
+pub const NR32: RegisterDoc = RegisterDoc {
+    name: "NR32",
+    summary: "Channel 3 output level",
+    fields: &[RegisterField::new(
+        0b0110_0000,
+        "Volume",
+        &["mute", "100%", "50%", "25%"],
+    )],
+};
+
+pub const NR33: RegisterDoc = RegisterDoc {
+    name: "NR33",
+    summary: "Channel 3 period low bits",
+    fields: &[],
+};
+
+pub const NR34: RegisterDoc = RegisterDoc {
+    name: "NR34",
+    summary: "Channel 3 period high bits and control",
+    fields: &PERIOD_HIGH_CONTROL,
+};
+
+pub const NR41: RegisterDoc = RegisterDoc {
+    name: "NR41",
+    summary: "Channel 4 length timer",
+    fields: &[RegisterField::new(0b0011_1111, "Initial length timer", &[])],
+};
+
+pub const NR42: RegisterDoc = RegisterDoc {
+    name: "NR42",
+    summary: "Channel 4 volume and envelope, the DAC is off while bits 7-3 are all 0",
+    fields: &ENVELOPE,
+};
+
+pub const NR43: RegisterDoc = RegisterDoc {
+    name: "NR43",
+    summary: "Channel 4 frequency and randomness",
+    fields: &[
+        RegisterField::new(0b1111_0000, "Clock shift", &[]),
+        RegisterField::new(0b0000_1000, "LFSR width", &["15 bit", "7 bit"]),
+        RegisterField::new(0b0000_0111, "Clock divider", &[]),
+    ],
+};
+
+pub const NR44: RegisterDoc = RegisterDoc {
+    name: "NR44",
+    summary: "Channel 4 control",
+    fields: &[
+        RegisterField::new(0b1000_0000, "Trigger", &["-", "restart channel"]),
+        RegisterField::new(0b0100_0000, "Length enable", OFF_ON),
+    ],
+};
+
+pub const NR50: RegisterDoc = RegisterDoc {
+    name: "NR50",
+    summary: "Master volume and VIN panning",
+    fields: &[
+        RegisterField::new(0b1000_0000, "VIN left", OFF_ON),
+        RegisterField::new(0b0111_0000, "Left volume", &[]),
+        RegisterField::new(0b0000_1000, "VIN right", OFF_ON),
+        RegisterField::new(0b0000_0111, "Right volume", &[]),
+    ],
+};
+
+pub const NR51: RegisterDoc = RegisterDoc {
+    name: "NR51",
+    summary: "Sound panning",
+    fields: &[
+        RegisterField::new(0b1000_0000, "Channel 4 left", OFF_ON),
+        RegisterField::new(0b0100_0000, "Channel 3 left", OFF_ON),
+        RegisterField::new(0b0010_0000, "Channel 2 left", OFF_ON),
+        RegisterField::new(0b0001_0000, "Channel 1 left", OFF_ON),
+        RegisterField::new(0b0000_1000, "Channel 4 right", OFF_ON),
+        RegisterField::new(0b0000_0100, "Channel 3 right", OFF_ON),
+        RegisterField::new(0b0000_0010, "Channel 2 right", OFF_ON),
+        RegisterField::new(0b0000_0001, "Channel 1 right", OFF_ON),
+    ],
+};
+
+pub const NR52: RegisterDoc = RegisterDoc {
+    name: "NR52",
+    summary: "Sound on/off, the channel bits are read-only and show which channels are playing",
+    fields: &[
+        RegisterField::new(0b1000_0000, "Audio", OFF_ON),
+        RegisterField::new(0b0000_1000, "Channel 4", OFF_ON),
+        RegisterField::new(0b0000_0100, "Channel 3", OFF_ON),
+        RegisterField::new(0b0000_0010, "Channel 2", OFF_ON),
+        RegisterField::new(0b0000_0001, "Channel 1", OFF_ON),
+    ],
+};
+
+pub const LCDC: RegisterDoc = RegisterDoc {
+    name: "LCDC",
+    summary: "LCD control",
+    fields: &[
+        RegisterField::new(0b1000_0000, "LCD & PPU", OFF_ON),
+        RegisterField::new(0b0100_0000, "Window tile map", &["$9800", "$9c00"]),
+        RegisterField::new(0b0010_0000, "Window", OFF_ON),
+        RegisterField::new(0b0001_0000, "BG & window tiles", &["$8800", "$8000"]),
+        RegisterField::new(0b0000_1000, "BG tile map", &["$9800", "$9c00"]),
+        RegisterField::new(0b0000_0100, "OBJ size", &["8x8", "8x16"]),
+        RegisterField::new(0b0000_0010, "OBJ", OFF_ON),
+        RegisterField::new(0b0000_0001, "BG & window (CGB: priority)", OFF_ON),
+    ],
+};
+
+pub const STAT: RegisterDoc = RegisterDoc {
+    name: "STAT",
+    summary: "LCD status, selects which conditions raise the STAT interrupt",
+    fields: &[
+        RegisterField::new(0b0100_0000, "LYC=LY interrupt", OFF_ON),
+        RegisterField::new(0b0010_0000, "OAM scan interrupt", OFF_ON),
+        RegisterField::new(0b0001_0000, "VBlank interrupt", OFF_ON),
+        RegisterField::new(0b0000_1000, "HBlank interrupt", OFF_ON),
+        RegisterField::new(0b0000_0100, "LYC=LY", &["no", "yes"]),
+        RegisterField::new(0b0000_0011, "PPU mode", &["HBlank", "VBlank", "OAM scan", "drawing"]),
+    ],
+};
+
+pub const SCY: RegisterDoc = RegisterDoc {
+    name: "SCY",
+    summary: "Background scroll Y",
+    fields: &[],
+};
+
+pub const SCX: RegisterDoc = RegisterDoc {
+    name: "SCX",
+    summary: "Background scroll X",
+    fields: &[],
+};
+
+pub const LY: RegisterDoc = RegisterDoc {
+    name: "LY",
+    summary: "LCD Y coordinate, the scanline being drawn (144-153 is VBlank)",
+    fields: &[],
+};
+
+pub const LYC: RegisterDoc = RegisterDoc {
+    name: "LYC",
+    summary: "LY compare, sets the LYC=LY flag in STAT when LY matches",
+    fields: &[],
+};
+
+pub const DMA: RegisterDoc = RegisterDoc {
+    name: "DMA",
+    summary: "OAM DMA, copies $XX00-$XX9F to OAM",
+    fields: &[],
+};
+
+pub const BGP: RegisterDoc = RegisterDoc {
+    name: "BGP",
+    summary: "Background palette (DMG)",
+    fields: &PALETTE,
+};
+
+pub const OBP0: RegisterDoc = RegisterDoc {
+    name: "OBP0",
+    summary: "Object palette 0 (DMG), color 0 is transparent",
+    fields: &PALETTE,
+};
+
+pub const OBP1: RegisterDoc = RegisterDoc {
+    name: "OBP1",
+    summary: "Object palette 1 (DMG), color 0 is transparent",
+    fields: &PALETTE,
+};
+
+pub const WY: RegisterDoc = RegisterDoc {
+    name: "WY",
+    summary: "Window Y position",
+    fields: &[],
+};
+
+pub const WX: RegisterDoc = RegisterDoc {
+    name: "WX",
+    summary: "Window X position plus 7",
+    fields: &[],
+};
+
+pub const KEY0: RegisterDoc = RegisterDoc {
+    name: "KEY0",
+    summary: "CPU mode (CGB), set by the boot ROM and locked once it unmaps itself",
+    fields: &[RegisterField::new(0b0000_0100, "DMG compatibility", &["no", "yes"])],
+};
+
+pub const KEY1: RegisterDoc = RegisterDoc {
+    name: "KEY1",
+    summary: "Speed switch (CGB), STOP switches the speed while armed",
+    fields: &[
+        RegisterField::new(0b1000_0000, "Current speed", &["normal", "double"]),
+        RegisterField::new(0b0000_0001, "Switch armed", &["no", "yes"]),
+    ],
+};
+
+pub const VBK: RegisterDoc = RegisterDoc {
+    name: "VBK",
+    summary: "VRAM bank (CGB)",
+    fields: &[RegisterField::new(0b0000_0001, "Bank", &[])],
+};
+
+pub const BOOT: RegisterDoc = RegisterDoc {
+    name: "BOOT",
+    summary: "Boot ROM mapping, any non-zero write unmaps it for good",
+    fields: &[],
+};
+
+pub const HDMA1: RegisterDoc = RegisterDoc {
+    name: "HDMA1",
+    summary: "VRAM DMA source high byte (CGB)",
+    fields: &[],
+};
+
+pub const HDMA2: RegisterDoc = RegisterDoc {
+    name: "HDMA2",
+    summary: "VRAM DMA source low byte (CGB), the low 4 bits are ignored",
+    fields: &[],
+};
+
+pub const HDMA3: RegisterDoc = RegisterDoc {
+    name: "HDMA3",
+    summary: "VRAM DMA destination high byte (CGB), only bits 4-0 count",
+    fields: &[],
+};
+
+pub const HDMA4: RegisterDoc = RegisterDoc {
+    name: "HDMA4",
+    summary: "VRAM DMA destination low byte (CGB), the low 4 bits are ignored",
+    fields: &[],
+};
+
+pub const HDMA5: RegisterDoc = RegisterDoc {
+    name: "HDMA5",
+    summary: "VRAM DMA length, mode and start (CGB)",
+    fields: &[
+        RegisterField::new(0b1000_0000, "Mode", &["general purpose", "HBlank"]),
+        RegisterField::new(0b0111_1111, "Length / 16 - 1", &[]),
+    ],
+};
+
+pub const RP: RegisterDoc = RegisterDoc {
+    name: "RP",
+    summary: "Infrared port (CGB)",
+    fields: &[
+        RegisterField::new(0b1100_0000, "Read enable", &["off", "-", "-", "on"]),
+        RegisterField::new(0b0000_0010, "Receiving", &["light", "no light"]),
+        RegisterField::new(0b0000_0001, "LED", OFF_ON),
+    ],
+};
+
+pub const BCPS: RegisterDoc = RegisterDoc {
+    name: "BCPS",
+    summary: "Background palette index (CGB), which CRAM byte BCPD accesses",
+    fields: &CGB_PALETTE_INDEX,
+};
+
+pub const BCPD: RegisterDoc = RegisterDoc {
+    name: "BCPD",
+    summary: "Background palette data (CGB), colors are little endian RGB555",
+    fields: &[],
+};
+
+pub const OCPS: RegisterDoc = RegisterDoc {
+    name: "OCPS",
+    summary: "Object palette index (CGB), which CRAM byte OCPD accesses",
+    fields: &CGB_PALETTE_INDEX,
+};
+
+pub const OCPD: RegisterDoc = RegisterDoc {
+    name: "OCPD",
+    summary: "Object palette data (CGB), colors are little endian RGB555",
+    fields: &[],
+};
+
+pub const SVBK: RegisterDoc = RegisterDoc {
+    name: "SVBK",
+    summary: "WRAM bank mapped at $D000 (CGB), 0 selects bank 1",
+    fields: &[RegisterField::new(0b0000_0111, "Bank", &[])],
+};
+
+pub const PCM12: RegisterDoc = RegisterDoc {
+    name: "PCM12",
+    summary: "Digital output of channels 1 and 2 (CGB), read-only",
+    fields: &[
+        RegisterField::new(0b1111_0000, "Channel 2", &[]),
+        RegisterField::new(0b0000_1111, "Channel 1", &[]),
+    ],
+};
+
+pub const PCM34: RegisterDoc = RegisterDoc {
+    name: "PCM34",
+    summary: "Digital output of channels 3 and 4 (CGB), read-only",
+    fields: &[
+        RegisterField::new(0b1111_0000, "Channel 4", &[]),
+        RegisterField::new(0b0000_1111, "Channel 3", &[]),
+    ],
+};
+
+pub const IE: RegisterDoc = RegisterDoc {
+    name: "IE",
+    summary: "Interrupt enable",
+    fields: &INTERRUPTS,
+};
@@ -0,0 +1,120 @@
+use crate::memory::banked::BankedAddress;
+use log::warn;
+use std::cell::RefCell;
+
+// Bytes tracked: 8 WRAM banks of $1000 and HRAM
+pub const TRACKED_BYTES: usize = 0x8000 + 0x80;
+// Reads reported until they are cleared, later ones are dropped
+pub const MAX_UNINIT_READS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UninitRead {
+    // The instruction that did the read
+    pub pc: BankedAddress,
+    pub address: u16,
+}
+
+impl std::fmt::Display for UninitRead {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}  read uninitialized ${:04x}", self.pc, self.address)
+    }
+}
+
+#[derive(Clone)]
+struct Reported {
+    // Every byte is only reported once, a loop over uninitialized memory would flood the list otherwise
+    bytes: Vec<u64>,
+    reads: Vec<UninitRead>,
+}
+
+// For ROM developers: remembers which WRAM/HRAM bytes the program ever wrote and reports reads of
+// the others, which would only work by chance on hardware (power-on RAM is random). Reads only
+// borrow the Mmu immutably, hence the RefCell.
+#[derive(Clone)]
+pub struct UninitTracker {
+    enabled: bool,
+    written: Vec<u64>,
+    // Set while the CPU executes an instruction, reads outside of one (the debugger, DMA) aren't the program's
+    pc: Option<u16>,
+    reported: RefCell<Reported>,
+}
+
+impl UninitTracker {
+    pub fn new() -> UninitTracker {
+        UninitTracker {
+            enabled: false,
+            written: vec![0; TRACKED_BYTES / 64],
+            pc: None,
+            reported: RefCell::new(Reported {
+                bytes: vec![0; TRACKED_BYTES / 64],
+                reads: Vec::new(),
+            }),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    #[inline]
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    #[inline]
+    pub fn begin_instruction(&mut self, pc: u16) {
+        self.pc = Some(pc);
+    }
+
+    #[inline]
+    pub fn end_instruction(&mut self) {
+        self.pc = None;
+    }
+
+    // `index` is the byte's position in TRACKED_BYTES, see Mmu::tracked_index
+    #[inline]
+    pub fn record_write(&mut self, index: usize) {
+        self.written[index / 64] |= 1 << (index % 64);
+    }
+
+    #[inline]
+    pub fn record_read(&self, index: usize, address: u16, pc: impl FnOnce(u16) -> BankedAddress) {
+        let Some(instruction) = self.pc else {
+            return;
+        };
+        if self.written[index / 64] & (1 << (index % 64)) != 0 {
+            return;
+        }
+
+        let mut reported = self.reported.borrow_mut();
+        if reported.bytes[index / 64] & (1 << (index % 64)) != 0 || reported.reads.len() == MAX_UNINIT_READS {
+            return;
+        }
+        reported.bytes[index / 64] |= 1 << (index % 64);
+
+        let read = UninitRead {
+            pc: pc(instruction),
+            address,
+        };
+        warn!("{}", read);
+        reported.reads.push(read);
+    }
+
+    // Oldest first
+    pub fn reads(&self) -> Vec<UninitRead> {
+        self.reported.borrow().reads.clone()
+    }
+
+    // Forgets what was reported so far, the same bytes get reported again
+    pub fn clear(&mut self) {
+        let reported = self.reported.get_mut();
+        reported.bytes.fill(0);
+        reported.reads.clear();
+    }
+}
+
+impl Default for UninitTracker {
+    fn default() -> UninitTracker {
+        UninitTracker::new()
+    }
+}
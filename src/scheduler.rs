@@ -0,0 +1,71 @@
+// Points in time the other components have to be caught up at, because something the CPU can see
+// happens there: a PPU mode change, TIMA counting up, a serial bit, a frame sequencer step or
+// a button being pressed or released
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    PpuState,
+    Timer,
+    Serial,
+    FrameSequencer,
+    Input,
+}
+
+const EVENT_COUNT: usize = 5;
+
+// Keeps the absolute cycle (in CPU T-cycles) each event is due at. Between events the components
+// don't have to be ticked at all, they are handed all the cycles the CPU ran in one go.
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    now: u64,
+    deadlines: [Option<u64>; EVENT_COUNT],
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            now: 0,
+            deadlines: [None; EVENT_COUNT],
+        }
+    }
+
+    #[inline]
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    #[inline]
+    pub fn advance(&mut self, cycles: usize) {
+        self.now += cycles as u64;
+    }
+
+    // Replaces when the event is due, `cycles` from now. None means it won't happen on its own.
+    #[inline]
+    pub fn schedule(&mut self, event: Event, cycles: Option<usize>) {
+        self.deadlines[event as usize] = cycles.map(|cycles| self.now + cycles as u64);
+    }
+
+    #[inline]
+    pub fn deadline(&self, event: Event) -> Option<u64> {
+        self.deadlines[event as usize]
+    }
+
+    // Whether an event has come up and the components need to catch up
+    #[inline]
+    pub fn due(&self) -> bool {
+        self.cycles_until_next() == Some(0)
+    }
+
+    pub fn cycles_until_next(&self) -> Option<usize> {
+        self.deadlines
+            .iter()
+            .flatten()
+            .min()
+            .map(|deadline| deadline.saturating_sub(self.now) as usize)
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
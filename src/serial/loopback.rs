@@ -0,0 +1,73 @@
+use std::any::Any;
+use std::collections::VecDeque;
+
+use crate::serial::SerialDevice;
+
+// Bytes the echo device holds back before answering with them
+const ECHO_DELAY: usize = 1;
+
+// A cable from the link port back into itself, every transfer receives the byte that was sent
+#[derive(Clone)]
+pub struct Loopback;
+
+impl SerialDevice for Loopback {
+    fn exchange(&mut self, data: u8) -> u8 {
+        data
+    }
+
+    fn name(&self) -> String {
+        "Loopback".to_string()
+    }
+
+    fn drives_clock(&self) -> bool {
+        true
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Stands in for a second Game Boy that sends back what it received, a byte late like a real
+// partner answering the previous byte. Answers $ff until it has something to echo.
+#[derive(Clone)]
+pub struct Echo {
+    received: VecDeque<u8>,
+}
+
+impl Echo {
+    pub fn new() -> Echo {
+        Echo {
+            received: VecDeque::new(),
+        }
+    }
+}
+
+impl Default for Echo {
+    fn default() -> Echo {
+        Echo::new()
+    }
+}
+
+impl SerialDevice for Echo {
+    fn exchange(&mut self, data: u8) -> u8 {
+        self.received.push_back(data);
+        if self.received.len() > ECHO_DELAY {
+            self.received.pop_front().unwrap()
+        } else {
+            0xff
+        }
+    }
+
+    fn name(&self) -> String {
+        "Echo".to_string()
+    }
+
+    fn drives_clock(&self) -> bool {
+        true
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
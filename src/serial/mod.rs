@@ -0,0 +1,198 @@
+use clap::ValueEnum;
+use std::any::Any;
+
+use crate::gameboy::Mode;
+use crate::serial::loopback::{Echo, Loopback};
+use crate::serial::printer::Printer;
+
+pub mod loopback;
+pub mod printer;
+
+pub const SERIAL_DATA_REGISTER: u16 = 0xff01;
+pub const SERIAL_CONTROL_REGISTER: u16 = 0xff02;
+
+// The internal clock shifts at 8192Hz, or at 262144Hz with the fast clock of the CGB. In CPU cycles,
+// so that double speed is twice as fast.
+const CYCLES_PER_BIT: usize = 512;
+const FAST_CYCLES_PER_BIT: usize = 16;
+const TRANSFER_START: u8 = 0b1000_0000;
+const FAST_CLOCK: u8 = 0b0000_0010;
+const INTERNAL_CLOCK: u8 = 0b0000_0001;
+
+// Something plugged into the link port
+pub trait SerialDevice {
+    // One byte per transfer: gets what the Game Boy shifted out and returns what it shifted in
+    fn exchange(&mut self, data: u8) -> u8;
+    fn name(&self) -> String;
+    // Whether the device clocks transfers itself like a second Game Boy would, otherwise only
+    // transfers the Game Boy clocks go anywhere
+    fn drives_clock(&self) -> bool {
+        false
+    }
+    // Frontends downcast to get at device specific output, e.g. printed pages
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum LinkDevice {
+    // Nothing connected, transfers read $ff
+    #[default]
+    None,
+    Printer,
+    // Receives whatever the Game Boy sends
+    Loopback,
+    // Answers every byte with the one sent before it
+    Echo,
+}
+
+impl LinkDevice {
+    pub fn create(&self) -> Option<Box<dyn SerialDevice>> {
+        match self {
+            LinkDevice::None => None,
+            LinkDevice::Printer => Some(Box::new(Printer::new())),
+            LinkDevice::Loopback => Some(Box::new(Loopback)),
+            LinkDevice::Echo => Some(Box::new(Echo::new())),
+        }
+    }
+}
+
+// The link port (SB/SC). A transfer shifts SB out one bit at a time while the partner's byte
+// is shifted in, and requests the serial interrupt after the eighth bit.
+pub struct Serial {
+    data: u8,
+    control: u8,
+    // Cycles into the current bit and bits shifted so far
+    cycles: usize,
+    bits: u8,
+    // The partner's byte, known once the first bit went out
+    incoming: Option<u8>,
+    device: Option<Box<dyn SerialDevice>>,
+    // The clock speed bit only exists in CGB mode
+    cgb: bool,
+}
+
+// Copies (run-ahead, netplay, save states) are left unplugged, the device stays with the console that is
+// actually running and GameBoy::force_load_state plugs it into the one taking over
+impl Clone for Serial {
+    fn clone(&self) -> Serial {
+        Serial {
+            data: self.data,
+            control: self.control,
+            cycles: self.cycles,
+            bits: self.bits,
+            incoming: self.incoming,
+            device: None,
+            cgb: self.cgb,
+        }
+    }
+}
+
+impl Serial {
+    pub fn new(mode: Mode) -> Serial {
+        Serial {
+            data: 0,
+            control: 0,
+            cycles: 0,
+            bits: 0,
+            incoming: None,
+            device: None,
+            cgb: mode == Mode::Cgb,
+        }
+    }
+
+    // A CGB locked into compatibility mode loses the fast clock
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.cgb = mode == Mode::Cgb;
+    }
+
+    pub fn connect(&mut self, device: Option<Box<dyn SerialDevice>>) {
+        self.device = device;
+    }
+
+    // Unplugs the device, e.g. to plug it into a freshly reset console
+    pub fn take_device(&mut self) -> Option<Box<dyn SerialDevice>> {
+        self.device.take()
+    }
+
+    pub fn device_mut(&mut self) -> Option<&mut (dyn SerialDevice + 'static)> {
+        self.device.as_deref_mut()
+    }
+
+    pub fn read(&self, addr: u16) -> u8 {
+        match addr {
+            SERIAL_DATA_REGISTER => self.data,
+            _ if self.cgb => self.control | 0b0111_1100,
+            _ => self.control | 0b0111_1110,
+        }
+    }
+
+    pub fn write(&mut self, addr: u16, data: u8) {
+        match addr {
+            SERIAL_DATA_REGISTER => self.data = data,
+            _ => {
+                self.control = data;
+                self.cycles = 0;
+                self.bits = 0;
+                self.incoming = None;
+            }
+        }
+    }
+
+    // Returns whether a transfer finished, which requests the serial interrupt
+    pub fn tick(&mut self, cycles: usize, double_speed: bool) -> bool {
+        if !self.clocked() {
+            return false;
+        }
+
+        let cycles_per_bit = self.cycles_per_bit(double_speed);
+        self.cycles += cycles;
+        while self.cycles >= cycles_per_bit {
+            self.cycles -= cycles_per_bit;
+
+            // Nothing connected reads as all ones
+            let incoming = *self.incoming.get_or_insert_with(|| match &mut self.device {
+                Some(device) => device.exchange(self.data),
+                None => 0xff,
+            });
+            self.data = (self.data << 1) | ((incoming >> (7 - self.bits)) & 1);
+            self.bits += 1;
+
+            if self.bits == 8 {
+                self.control &= !TRANSFER_START;
+                self.cycles = 0;
+                self.bits = 0;
+                self.incoming = None;
+                return true;
+            }
+        }
+
+        false
+    }
+
+    // Cycles until the next bit is shifted, None without a transfer making progress
+    pub fn cycles_until_bit(&self, double_speed: bool) -> Option<usize> {
+        self.clocked()
+            .then(|| self.cycles_per_bit(double_speed).saturating_sub(self.cycles))
+    }
+
+    // In CPU cycles. A partner clocking the transfer does so at its own pace, which double speed doesn't change.
+    fn cycles_per_bit(&self, double_speed: bool) -> usize {
+        match self.control & INTERNAL_CLOCK != 0 {
+            true if self.cgb && self.control & FAST_CLOCK != 0 => FAST_CYCLES_PER_BIT,
+            true => CYCLES_PER_BIT,
+            false if double_speed => CYCLES_PER_BIT * 2,
+            false => CYCLES_PER_BIT,
+        }
+    }
+
+    fn clocked(&self) -> bool {
+        self.control & TRANSFER_START != 0
+            && (self.control & INTERNAL_CLOCK != 0 || self.device.as_ref().is_some_and(|device| device.drives_clock()))
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Serial {
+        Serial::new(Mode::Dmg)
+    }
+}
@@ -0,0 +1,287 @@
+use std::any::Any;
+use std::fs::File;
+use std::io::BufWriter;
+
+use crate::serial::SerialDevice;
+
+// A page is 20 tiles wide, data packets carry two rows of tiles at a time
+pub const PRINTER_WIDTH: usize = 160;
+const TILES_PER_ROW: usize = PRINTER_WIDTH / 8;
+const BUFFER_SIZE: usize = 0x2000;
+// Status polls the printer stays busy for after a print command, games wait for it to finish
+const PRINTING_POLLS: usize = 8;
+
+const MAGIC_1: u8 = 0x88;
+const MAGIC_2: u8 = 0x33;
+const DEVICE_ID: u8 = 0x81;
+
+const COMMAND_INIT: u8 = 0x01;
+const COMMAND_PRINT: u8 = 0x02;
+const COMMAND_DATA: u8 = 0x04;
+
+const STATUS_CHECKSUM_ERROR: u8 = 0b0000_0001;
+const STATUS_PRINTING: u8 = 0b0000_0010;
+const STATUS_DATA_FULL: u8 = 0b0000_0100;
+const STATUS_UNPROCESSED: u8 = 0b0000_1000;
+
+// Where in a packet the next byte goes. Packets look like
+// 88 33 <command> <compression> <length lo> <length hi> <data...> <checksum lo> <checksum hi> 00 00,
+// the printer answers the last two bytes with its ID and its status.
+#[derive(Clone, Copy, PartialEq)]
+enum Packet {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    DeviceId,
+    Status,
+}
+
+// A finished page, one shade (0 white - 3 black) per pixel
+#[derive(Clone, PartialEq, Debug)]
+pub struct Printout {
+    pub height: usize,
+    pub shades: Vec<u8>,
+}
+
+impl Printout {
+    pub fn save_png(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), PRINTER_WIDTH as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+
+        let pixels = self
+            .shades
+            .iter()
+            .map(|shade| [0xff, 0xaa, 0x55, 0x00][*shade as usize])
+            .collect::<Vec<u8>>();
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        writer.write_image_data(&pixels).map_err(|e| e.to_string())
+    }
+}
+
+// Game Boy Printer. Collects image data, and turns it into a page when told to print.
+// Consecutive prints without a feed after them (the low nibble of the margins) end up on the same page.
+#[derive(Clone)]
+pub struct Printer {
+    packet: Packet,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    printing_polls: usize,
+    // 2bpp tile data received since the last print
+    buffer: Vec<u8>,
+    // Rows printed onto the current page so far
+    page: Vec<u8>,
+    printouts: Vec<Printout>,
+}
+
+impl Printer {
+    pub fn new() -> Printer {
+        Printer {
+            packet: Packet::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            printing_polls: 0,
+            buffer: Vec::new(),
+            page: Vec::new(),
+            printouts: Vec::new(),
+        }
+    }
+
+    // Pages finished since the last call
+    pub fn take_printouts(&mut self) -> Vec<Printout> {
+        std::mem::take(&mut self.printouts)
+    }
+
+    fn receive(&mut self, data: u8) {
+        self.packet = match self.packet {
+            Packet::Magic1 if data == MAGIC_1 => Packet::Magic2,
+            Packet::Magic1 => Packet::Magic1,
+            Packet::Magic2 if data == MAGIC_2 => Packet::Command,
+            Packet::Magic2 => Packet::Magic1,
+            Packet::Command => {
+                self.command = data;
+                self.checksum = data as u16;
+                Packet::Compression
+            }
+            Packet::Compression => {
+                self.compressed = data & 1 != 0;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                Packet::LengthLow
+            }
+            Packet::LengthLow => {
+                self.length = data as usize;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                Packet::LengthHigh
+            }
+            Packet::LengthHigh => {
+                self.length |= (data as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                self.data.clear();
+                if self.length == 0 {
+                    Packet::ChecksumLow
+                } else {
+                    Packet::Data
+                }
+            }
+            Packet::Data => {
+                self.data.push(data);
+                self.checksum = self.checksum.wrapping_add(data as u16);
+                if self.data.len() == self.length {
+                    Packet::ChecksumLow
+                } else {
+                    Packet::Data
+                }
+            }
+            Packet::ChecksumLow => {
+                self.received_checksum = data as u16;
+                Packet::ChecksumHigh
+            }
+            Packet::ChecksumHigh => {
+                self.received_checksum |= (data as u16) << 8;
+                Packet::DeviceId
+            }
+            Packet::DeviceId => {
+                self.execute();
+                Packet::Status
+            }
+            Packet::Status => Packet::Magic1,
+        };
+    }
+
+    fn execute(&mut self) {
+        if self.received_checksum != self.checksum {
+            self.status |= STATUS_CHECKSUM_ERROR;
+            return;
+        }
+        self.status &= !STATUS_CHECKSUM_ERROR;
+
+        match self.command {
+            COMMAND_INIT => {
+                self.buffer.clear();
+                self.printing_polls = 0;
+            }
+            COMMAND_DATA => {
+                let data = std::mem::take(&mut self.data);
+                match self.compressed {
+                    true => Printer::decompress(&data, &mut self.buffer),
+                    false => self.buffer.extend_from_slice(&data),
+                }
+                self.buffer.truncate(BUFFER_SIZE);
+            }
+            COMMAND_PRINT if self.data.len() >= 4 => {
+                let (margins, palette) = (self.data[1], self.data[2]);
+                self.print(palette);
+                self.printing_polls = PRINTING_POLLS;
+
+                if margins & 0x0f != 0 {
+                    self.finish_page();
+                }
+            }
+            // Status inquiry, or something we don't know about
+            _ => {}
+        }
+    }
+
+    fn current_status(&mut self) -> u8 {
+        let mut status = self.status;
+        if self.printing_polls > 0 {
+            self.printing_polls -= 1;
+            status |= STATUS_PRINTING | STATUS_DATA_FULL;
+        } else if self.buffer.len() >= BUFFER_SIZE {
+            status |= STATUS_DATA_FULL;
+        } else if !self.buffer.is_empty() {
+            status |= STATUS_UNPROCESSED;
+        }
+        status
+    }
+
+    // Runs of the same byte are stored as 0x80 | (count - 2) followed by the byte,
+    // everything else as count - 1 followed by the bytes themselves
+    fn decompress(data: &[u8], output: &mut Vec<u8>) {
+        let mut bytes = data.iter();
+        while let Some(&header) = bytes.next() {
+            if header & 0x80 != 0 {
+                let count = (header & 0x7f) as usize + 2;
+                if let Some(&byte) = bytes.next() {
+                    output.extend(std::iter::repeat_n(byte, count));
+                }
+            } else {
+                output.extend(bytes.by_ref().take(header as usize + 1));
+            }
+        }
+    }
+
+    fn print(&mut self, palette: u8) {
+        let rows = self.buffer.len() / (TILES_PER_ROW * 16) * 8;
+        let start = self.page.len();
+        self.page.resize(start + rows * PRINTER_WIDTH, 0);
+
+        for y in 0..rows {
+            for x in 0..PRINTER_WIDTH {
+                let tile = (y / 8) * TILES_PER_ROW + x / 8;
+                let offset = tile * 16 + (y % 8) * 2;
+                let bit = 7 - (x % 8);
+                let color = ((self.buffer[offset] >> bit) & 1) | (((self.buffer[offset + 1] >> bit) & 1) << 1);
+                self.page[start + y * PRINTER_WIDTH + x] = (palette >> (color * 2)) & 0b11;
+            }
+        }
+
+        self.buffer.clear();
+    }
+
+    fn finish_page(&mut self) {
+        if self.page.is_empty() {
+            return;
+        }
+
+        let shades = std::mem::take(&mut self.page);
+        self.printouts.push(Printout {
+            height: shades.len() / PRINTER_WIDTH,
+            shades,
+        });
+    }
+}
+
+impl Default for Printer {
+    fn default() -> Printer {
+        Printer::new()
+    }
+}
+
+impl SerialDevice for Printer {
+    fn exchange(&mut self, data: u8) -> u8 {
+        // The answer was shifted out while the byte came in, so it depends on what came before
+        let reply = match self.packet {
+            Packet::DeviceId => DEVICE_ID,
+            Packet::Status => self.current_status(),
+            _ => 0x00,
+        };
+        self.receive(data);
+        reply
+    }
+
+    fn name(&self) -> String {
+        "Game Boy Printer".to_string()
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
@@ -1,20 +1,89 @@
-use log::error;
-use rodio::buffer::SamplesBuffer;
-use rodio::{OutputStream, Sink};
+use log::{error, warn};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::time::{Duration, Instant};
 
 use super::channels::noise::NoiseChannel;
 use super::channels::square::{SquareChannel1, SquareChannel2};
 use super::channels::wave::WaveChannel;
-use super::channels::Channel;
-use super::stereo::StereoSide;
+use super::channels::{Channel, ChannelState};
+use super::output::AudioSink;
 use super::{
-    BUFFER_SIZE, CPU_CLOCK, NR10, NR14, NR21, NR24, NR30, NR34, NR41, NR44, NR50, NR51, NR52, SAMPLE_RATE,
-    WAVE_PATTERN_RAM_END, WAVE_PATTERN_RAM_START,
+    SyncMode, BUFFER_SIZE, CPU_CLOCK, NR10, NR14, NR21, NR24, NR30, NR34, NR41, NR44, NR50, NR51, NR52, PCM12, PCM34,
+    SAMPLE_RATE, WAVE_PATTERN_RAM_END, WAVE_PATTERN_RAM_START,
 };
+use crate::clock::ClockDivider;
+use crate::gameboy::Mode;
 use crate::memory::addressable::Addressable;
 
 // TODO: Mostly taken from https://github.com/NightShade256/Argentum/
 
+// Buffers queued on the device unless calibrated otherwise
+const DEFAULT_QUEUE_DEPTH: usize = 2;
+const MAX_QUEUE_DEPTH: usize = 16;
+// Stereo samples the device fades over from what it played last after a save state is loaded
+const DECLICK_SAMPLES: usize = 64;
+
+// Gets every buffer of samples as it fills up, interleaved left/right at SAMPLE_RATE
+pub type SampleCallback = Box<dyn Fn(&[f32])>;
+
+// Handle to the audio device. Copies of the APU (e.g. in save states) are always silent,
+// only the running system owns the device.
+struct AudioOutput {
+    sink: Option<Box<dyn AudioSink>>,
+    muted: bool,
+    sync_mode: SyncMode,
+    // Buffers that may be queued on the device, enough to cover its latency
+    queue_depth: usize,
+    // Embedders get the samples here, with or without a device
+    callback: Option<SampleCallback>,
+    // Last left/right sample the device got, and where the next buffer has to fade in from
+    // when the waveform jumped because another state was loaded
+    last_sample: Cell<[f32; 2]>,
+    declick_from: Cell<Option<[f32; 2]>>,
+}
+
+impl AudioOutput {
+    fn headless() -> AudioOutput {
+        AudioOutput {
+            sink: None,
+            muted: false,
+            sync_mode: SyncMode::Audio,
+            queue_depth: DEFAULT_QUEUE_DEPTH,
+            callback: None,
+            last_sample: Cell::new([0.0; 2]),
+            declick_from: Cell::new(None),
+        }
+    }
+
+    // Only what the device plays is faded, callbacks get the exact samples so that they stay
+    // the same whether or not a state was loaded in between
+    fn declick<'a>(&self, buffer: &'a [f32]) -> Cow<'a, [f32]> {
+        if let [.., left, right] = buffer {
+            self.last_sample.set([*left, *right]);
+        }
+        let Some(from) = self.declick_from.take() else {
+            return Cow::Borrowed(buffer);
+        };
+
+        let mut faded = buffer.to_vec();
+        for (idx, samples) in faded.chunks_exact_mut(2).take(DECLICK_SAMPLES).enumerate() {
+            let progress = idx as f32 / DECLICK_SAMPLES as f32;
+            for (sample, from) in samples.iter_mut().zip(from) {
+                *sample = from + (*sample - from) * progress;
+            }
+        }
+        Cow::Owned(faded)
+    }
+}
+
+impl Clone for AudioOutput {
+    fn clone(&self) -> AudioOutput {
+        AudioOutput::headless()
+    }
+}
+
+#[derive(Clone)]
 pub struct Apu {
     // The volume value for the left channel
     left_volume: u8,
@@ -40,11 +109,20 @@ pub struct Apu {
     // Implementation of the noise wave channel
     noise: NoiseChannel,
 
-    // Used to clock FS and sample generation
+    // Used to clock the FS, in cycles at the normal rate
+    frame_sequencer_clock: usize,
+
+    // CPU cycles since the last sample
     sample_clock: usize,
 
-    // Current CPU clock rate
-    cpu_clock: usize,
+    // Hands the channels and the FS every other CPU cycle in double speed
+    clock: ClockDivider,
+
+    // How many times faster than the hardware the emulation runs (fast forward), set by the host
+    speed: usize,
+
+    // Full buffers so far, fast forward only plays some of them
+    buffers_filled: usize,
 
     // The audio buffer which contains 32-bit float samples
     pub buffer: [f32; BUFFER_SIZE],
@@ -61,18 +139,12 @@ pub struct Apu {
     // Stub
     right_vin: bool,
 
-    // Output stream sink
-    audio_sink: Sink,
-
-    // Output stream, we need to keep this alive
-    _stream: OutputStream,
+    // Audio device the samples are pushed to, silent when running without one
+    output: AudioOutput,
 }
 
 impl Apu {
-    pub fn new() -> Self {
-        let (stream, stream_handle) = OutputStream::try_default().unwrap();
-        let audio_sink = Sink::try_new(&stream_handle).unwrap();
-
+    pub fn new(mode: Mode) -> Self {
         Self {
             left_volume: 0,
             right_volume: 0,
@@ -80,83 +152,230 @@ impl Apu {
             apu_enabled: false,
             square1: SquareChannel1::default(),
             square2: SquareChannel2::default(),
-            wave: WaveChannel::default(),
+            wave: WaveChannel::new(mode),
             noise: NoiseChannel::default(),
+            frame_sequencer_clock: 0,
             sample_clock: 0,
-            cpu_clock: CPU_CLOCK,
+            clock: ClockDivider::new(),
+            speed: 1,
+            buffers_filled: 0,
             buffer: [0.0; BUFFER_SIZE],
             buffer_position: 0,
             frame_sequencer_position: 0,
             left_vin: false,
             right_vin: false,
-            audio_sink,
-            _stream: stream,
+            output: AudioOutput::headless(),
         }
     }
 
     pub fn push_samples(&self, buffer: &[f32]) {
-        while self.audio_sink.len() > 2 {
-            // Wait for the sink to have played enough samples
-            std::thread::sleep(std::time::Duration::from_millis(1));
+        // Muted output doesn't wait for the device either, so that frames can be replayed quickly
+        if self.output.muted {
+            return;
+        }
+
+        if let Some(callback) = &self.output.callback {
+            callback(buffer);
+        }
+
+        let Some(audio_sink) = &self.output.sink else {
+            return;
+        };
+
+        match self.output.sync_mode {
+            SyncMode::Audio => {
+                while audio_sink.queued() > self.output.queue_depth {
+                    // Wait for the sink to have played enough samples
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+            SyncMode::Timer => {
+                // The device plays slower than the timer runs, drop samples instead of building up lag
+                if audio_sink.queued() > self.output.queue_depth * 2 {
+                    return;
+                }
+            }
         }
 
-        self.audio_sink
-            .append(SamplesBuffer::new(2, SAMPLE_RATE as u32, buffer));
+        audio_sink.append(&self.output.declick(buffer));
+    }
+
+    // CPU cycles, like the timer gets them. Samples are twice as many CPU cycles apart in double speed so
+    // that they stay SAMPLE_RATE apart in emulated time, the channels and the frame sequencer only see
+    // every other CPU cycle like on the hardware.
+    pub fn tick(&mut self, cycles: usize, double_speed: bool) {
+        let sample_period = Apu::sample_period(double_speed);
+        let mut remaining = cycles;
+
+        while remaining > 0 {
+            // The period halves when double speed is switched off, the sample that's overdue comes right away
+            let span = remaining.min(sample_period.saturating_sub(self.sample_clock));
+            remaining -= span;
+            self.sample_clock += span;
+
+            let component_cycles = self.clock.split(span, double_speed).apu;
+            self.advance(component_cycles);
+
+            if self.sample_clock >= sample_period {
+                self.sample_clock = 0;
+                self.sample();
+            }
+        }
     }
 
-    pub fn tick(&mut self, cycles: usize) {
-        for _ in 0..cycles {
-            // This clock is incremented every T-cycle.
-            // This is used to clock the frame sequencer and
-            // to generate sample
-            self.sample_clock = self.sample_clock.wrapping_add(1);
+    // Runs the channels and the frame sequencer for cycles at the normal rate
+    fn advance(&mut self, cycles: usize) {
+        let mut remaining = cycles;
+
+        while remaining > 0 {
+            // Between frame sequencer steps the channels only count down their timers, which they can skip
+            // over in one go
+            let span = remaining.min(8192 - self.frame_sequencer_clock);
+            remaining -= span;
+            self.frame_sequencer_clock += span;
 
-            // Tick all the connected channels
-            self.square1.tick();
-            self.square2.tick();
-            self.wave.tick();
-            self.noise.tick();
+            self.square1.tick(span);
+            self.square2.tick(span);
+            self.wave.tick(span);
+            self.noise.tick(span);
 
             // Tick the frame sequencer. It generates clocks for the length,
             // envelope and sweep functions
-            if self.sample_clock % 8192 == 0 {
+            if self.frame_sequencer_clock == 8192 {
                 self.clock_components();
                 self.frame_sequencer_position = (self.frame_sequencer_position + 1) % 8;
-                self.sample_clock = 0;
+                self.frame_sequencer_clock = 0;
+                self.update_length_phase();
             }
+        }
+    }
 
-            // Each (CPU CLOCK / SAMPLE RATE) cycles one sample is generated
-            // and pushed to the buffer
-            if self.sample_clock % (self.cpu_clock / SAMPLE_RATE) == 0 {
-                let left_amplitude = self.get_amplitude_for_channel(0, StereoSide::Left)
-                    + self.get_amplitude_for_channel(1, StereoSide::Left)
-                    + self.get_amplitude_for_channel(2, StereoSide::Left)
-                    + self.get_amplitude_for_channel(3, StereoSide::Left);
-                let right_amplitude = self.get_amplitude_for_channel(0, StereoSide::Right)
-                    + self.get_amplitude_for_channel(1, StereoSide::Right)
-                    + self.get_amplitude_for_channel(2, StereoSide::Right)
-                    + self.get_amplitude_for_channel(3, StereoSide::Right);
-
-                self.buffer[self.buffer_position + 0] = (self.left_volume as f32 / 7.0) * left_amplitude / 4.0;
-                self.buffer[self.buffer_position + 1] = (self.right_volume as f32 / 7.0) * right_amplitude / 4.0;
-
-                self.buffer_position += 2;
+    // Each sample period one sample is generated and pushed to the buffer. Fast forward only plays every
+    // speed-th full buffer and drops the others, what's played keeps its pitch.
+    fn sample(&mut self) {
+        let [left, right] = self.mix();
+        self.buffer[self.buffer_position] = (self.left_volume as f32 / 7.0) * left / 4.0;
+        self.buffer[self.buffer_position + 1] = (self.right_volume as f32 / 7.0) * right / 4.0;
+        self.buffer_position += 2;
+
+        // Checks if the buffer is full and pushes samples to audio sink
+        if self.buffer_position >= BUFFER_SIZE {
+            if self.buffers_filled.is_multiple_of(self.speed) {
+                self.push_samples(self.buffer.as_ref());
             }
+            self.buffers_filled = self.buffers_filled.wrapping_add(1);
+            self.buffer_position = 0;
+        }
+    }
 
-            // Checks if the buffer is full and pushes samples to audio sink
-            if self.buffer_position >= BUFFER_SIZE {
-                self.push_samples(self.buffer.as_ref());
-                self.buffer_position = 0;
+    // Plays the samples on the given device from now on, without one the APU runs silently
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.output.sink = Some(sink);
+    }
+
+    // Cycles until the frame sequencer clocks lengths, envelopes or the sweep next
+    pub fn cycles_until_frame_sequencer(&self) -> usize {
+        8192 - self.frame_sequencer_clock % 8192
+    }
+
+    // Takes over the audio device of another APU, used when restoring a save state
+    pub fn adopt_output(&mut self, other: &mut Apu) {
+        std::mem::swap(&mut self.output, &mut other.output);
+        // The speed is the host's choice (e.g. fast forward), not part of the state
+        self.speed = other.speed;
+        self.output.declick_from.set(Some(self.output.last_sample.get()));
+    }
+
+    pub fn set_sample_callback(&mut self, callback: Option<SampleCallback>) {
+        self.output.callback = callback;
+    }
+
+    pub fn load_wave_ram(&mut self, wave_ram: &[u8]) {
+        self.wave.load_wave_ram(wave_ram);
+    }
+
+    // Which of the four channels are currently playing, as reported in NR52
+    pub fn channels_enabled(&self) -> [bool; 4] {
+        [
+            self.square1.channel_enabled,
+            self.square2.channel_enabled,
+            self.wave.channel_enabled,
+            self.noise.enabled,
+        ]
+    }
+
+    // Frequency, volume and envelope of the four channels, in NR52 order
+    pub fn channel_states(&self) -> [ChannelState; 4] {
+        [
+            ChannelState::of(&self.square1),
+            ChannelState::of(&self.square2),
+            ChannelState::of(&self.wave),
+            ChannelState::of(&self.noise),
+        ]
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.output.muted = muted;
+    }
+
+    pub fn set_sync_mode(&mut self, sync_mode: SyncMode) {
+        self.output.sync_mode = sync_mode;
+    }
+
+    // Measures how long the device takes to start consuming freshly queued samples and queues
+    // enough buffers to cover that, so that waiting on the device never starves it. Returns the latency.
+    pub fn calibrate_latency(&mut self) -> Option<Duration> {
+        const CALIBRATION_BUFFERS: usize = 4;
+        let audio_sink = self.output.sink.as_ref()?;
+
+        // Whatever is still queued would distort the measurement
+        let timeout = Instant::now() + Duration::from_secs(1);
+        while audio_sink.queued() > 0 && Instant::now() < timeout {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+
+        let start = Instant::now();
+        for _ in 0..CALIBRATION_BUFFERS {
+            audio_sink.append(&[0.0; BUFFER_SIZE]);
+        }
+        while audio_sink.queued() == CALIBRATION_BUFFERS {
+            if start.elapsed() > Duration::from_secs(1) {
+                warn!("Audio device didn't consume any samples during calibration");
+                return None;
             }
+            std::thread::sleep(Duration::from_millis(1));
         }
+        let latency = start.elapsed();
+
+        let buffer_duration = Duration::from_secs_f64((BUFFER_SIZE / 2) as f64 / SAMPLE_RATE as f64);
+        self.output.queue_depth = ((latency.as_secs_f64() / buffer_duration.as_secs_f64()).ceil() as usize)
+            .clamp(DEFAULT_QUEUE_DEPTH, MAX_QUEUE_DEPTH);
+
+        Some(latency)
+    }
+
+    // Sounds keep their pitch at any speed, running faster only leaves out more of them
+    pub fn set_speed(&mut self, speed: usize) {
+        self.speed = speed.max(1);
     }
 
-    pub fn update_cpu_clock(&mut self, cpu_clock: usize) {
-        self.cpu_clock = cpu_clock;
+    // CPU cycles between two samples, (CPU CLOCK / SAMPLE RATE) in single speed
+    #[inline]
+    fn sample_period(double_speed: bool) -> usize {
+        match double_speed {
+            true => 2 * CPU_CLOCK / SAMPLE_RATE,
+            false => CPU_CLOCK / SAMPLE_RATE,
+        }
     }
 
-    pub fn reset_cpu_clock(&mut self) {
-        self.cpu_clock = CPU_CLOCK;
+    // Lengths are clocked on even frame sequencer steps, NRx4 writes before an odd one clock them early
+    fn update_length_phase(&mut self) {
+        let extra_length_clock = self.frame_sequencer_position % 2 == 1;
+
+        self.square1.extra_length_clock = extra_length_clock;
+        self.square2.extra_length_clock = extra_length_clock;
+        self.wave.extra_length_clock = extra_length_clock;
+        self.noise.extra_length_clock = extra_length_clock;
     }
 
     fn clock_components(&mut self) {
@@ -197,25 +416,22 @@ impl Apu {
         }
     }
 
-    fn get_amplitude_for_channel(&self, channel: u8, side: StereoSide) -> f32 {
-        // Tries to get the amplitude for the given channel and side
-        // If the bit is not set in NR51, the channel does not go to the
-        // given side
-
-        let channel_offset = match side {
-            StereoSide::Right => 0,
-            StereoSide::Left => 4,
-        };
-
-        let enabled = (self.nr51 & (1 << (channel + channel_offset))) != 0;
-
-        match channel {
-            0 if enabled => self.square1.get_amplitude(),
-            1 if enabled => self.square2.get_amplitude(),
-            2 if enabled => self.wave.get_amplitude(),
-            3 if enabled => self.noise.get_amplitude(),
-            _ => 0.0,
+    // Left and right amplitude of the four channels. NR51 bits 4-7 send them to the left side and 0-3 to
+    // the right, as plain multiplies so that the compiler can keep all four channels in one vector.
+    fn mix(&self) -> [f32; 2] {
+        let amplitudes = [
+            self.square1.get_amplitude(),
+            self.square2.get_amplitude(),
+            self.wave.get_amplitude(),
+            self.noise.get_amplitude(),
+        ];
+
+        let mut mixed = [0.0; 2];
+        for (channel, amplitude) in amplitudes.iter().enumerate() {
+            mixed[0] += amplitude * ((self.nr51 >> (channel + 4)) & 1) as f32;
+            mixed[1] += amplitude * ((self.nr51 >> channel) & 1) as f32;
         }
+        mixed
     }
 }
 
@@ -245,6 +461,8 @@ impl Addressable for Apu {
             NR21..=NR24 => self.square2.read(addr),
             NR30..=NR34 | WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END => self.wave.read(addr),
             NR41..=NR44 => self.noise.read(addr),
+            PCM12 => self.square1.digital_output() | (self.square2.digital_output() << 4),
+            PCM34 => self.wave.digital_output() | (self.noise.digital_output() << 4),
 
             _ => unreachable!(),
         }
@@ -274,6 +492,7 @@ impl Addressable for Apu {
                     self.apu_enabled = true;
 
                     self.frame_sequencer_position = 0;
+                    self.update_length_phase();
 
                     self.square1.wave_position = 0;
                     self.square2.wave_position = 0;
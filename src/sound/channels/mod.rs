@@ -3,7 +3,100 @@ pub mod square;
 pub mod wave;
 
 pub trait Channel {
-    fn tick(&mut self);
-    fn get_amplitude(&self) -> f32;
+    // Runs the channel for the given T-cycles at once. The APU ends spans at everything that changes the
+    // channel's timing (frame sequencer steps, register writes), so the frequency holds throughout.
+    fn tick(&mut self, cycles: usize);
     fn step_length(&mut self);
+    // The 0-15 value going into the DAC, which the CGB shows in PCM12/PCM34
+    fn digital_output(&self) -> u8;
+    fn dac_enabled(&self) -> bool;
+
+    // The DAC turns the digital output into -1.0 to 1.0, silent while it or the channel is off
+    fn get_amplitude(&self) -> f32 {
+        if self.dac_enabled() && self.is_enabled() {
+            (self.digital_output() as f32 / 7.5) - 1.0
+        } else {
+            0.0
+        }
+    }
+
+    // Read-only views of the channel for the debugger
+    fn is_enabled(&self) -> bool;
+    // Rate at which the waveform repeats (the LFSR clock for noise) in Hz
+    fn frequency(&self) -> f32;
+    // Current output volume, scaled to 0-15 for every channel
+    fn volume(&self) -> u8;
+    fn envelope(&self) -> Option<Envelope> {
+        None
+    }
+}
+
+// Runs a frequency timer that counts down every T-cycle and reloads with the period once it hit 0,
+// skipping straight over the cycles in between. Returns how often it reloaded, i.e. stepped the waveform.
+pub fn advance_timer(timer: &mut u16, period: usize, cycles: usize) -> usize {
+    let current = *timer as usize;
+    if cycles <= current {
+        *timer = (current - cycles) as u16;
+        return 0;
+    }
+
+    let remaining = cycles - current;
+    *timer = ((period - remaining % period) % period) as u16;
+    remaining.div_ceil(period)
+}
+
+// Length handling of an NRx4 write. While the frame sequencer's next step doesn't clock lengths, enabling the
+// length counter clocks it once right away, and so does a trigger that reloads an empty counter.
+// Returns false if the extra clock ran the counter out, which disables the channel
+pub fn write_length_control(
+    length_counter: &mut u16, length_enabled: &mut bool, max_length: u16, value: u8, extra_clock: bool,
+) -> bool {
+    let was_enabled = std::mem::replace(length_enabled, (value & 0b0100_0000) != 0);
+    let trigger = (value & 0b1000_0000) != 0;
+    let mut keep_enabled = true;
+
+    if extra_clock && !was_enabled && *length_enabled && *length_counter > 0 {
+        *length_counter -= 1;
+        keep_enabled = *length_counter > 0 || trigger;
+    }
+
+    // Only a trigger reloads an empty counter
+    if trigger && *length_counter == 0 {
+        *length_counter = max_length;
+
+        if extra_clock && *length_enabled {
+            *length_counter -= 1;
+        }
+    }
+
+    keep_enabled
+}
+
+// Volume envelope configuration as written to NRx2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub initial_volume: u8,
+    pub incrementing: bool,
+    // Frame sequencer steps between volume changes, 0 holds the volume
+    pub period: u8,
+}
+
+// Snapshot of a channel, as shown in the APU panel
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelState {
+    pub enabled: bool,
+    pub frequency: f32,
+    pub volume: u8,
+    pub envelope: Option<Envelope>,
+}
+
+impl ChannelState {
+    pub fn of(channel: &dyn Channel) -> ChannelState {
+        ChannelState {
+            enabled: channel.is_enabled(),
+            frequency: channel.frequency(),
+            volume: channel.volume(),
+            envelope: channel.envelope(),
+        }
+    }
 }
@@ -1,9 +1,9 @@
 use log::error;
 
 use crate::memory::addressable::Addressable;
-use crate::sound::{NR41, NR42, NR43, NR44};
+use crate::sound::{CPU_CLOCK, NR41, NR42, NR43, NR44};
 
-use super::Channel;
+use super::{advance_timer, write_length_control, Channel, Envelope};
 
 #[derive(Default, Clone)]
 pub struct NoiseChannel {
@@ -26,7 +26,7 @@ pub struct NoiseChannel {
     // The sound length counter. If this is >0 and bit 6 in NR24 is set
     // then it is decremented with clocks from FS. If this then hits 0
     // the sound channel is then disabled
-    length_counter: u8,
+    length_counter: u16,
 
     // The polynomial counter, used to control the RNG
     nr43: u8,
@@ -34,6 +34,10 @@ pub struct NoiseChannel {
     // Whether the length timer is enabled or not
     length_enabled: bool,
 
+    // Set by the APU while the frame sequencer's next step doesn't clock lengths,
+    // NRx4 writes then clock the length counter early
+    pub extra_length_clock: bool,
+
     // The initial volume of the envelope function
     initial_volume: u8,
 
@@ -53,6 +57,17 @@ pub struct NoiseChannel {
 }
 
 impl NoiseChannel {
+    fn clock_lfsr(&mut self) {
+        let xor_result = (self.lfsr & 0b01) ^ ((self.lfsr & 0b10) >> 1);
+
+        self.lfsr = (self.lfsr >> 1) | (xor_result << 14);
+
+        if ((self.nr43 >> 3) & 0b01) != 0 {
+            self.lfsr &= !(1 << 6);
+            self.lfsr |= xor_result << 6;
+        }
+    }
+
     pub fn step_volume(&mut self) {
         if self.period != 0 {
             if self.period_timer > 0 {
@@ -77,34 +92,24 @@ impl NoiseChannel {
 }
 
 impl Channel for NoiseChannel {
-    fn tick(&mut self) {
-        // If the frequency timer decrement to 0, it is reloaded with the formula
-        // `divisor_code << clock_shift` and wave position is advanced by one.
-        if self.frequency_timer == 0 {
-            let divisor_code = (self.nr43 & 0x07) as u16;
-
-            self.frequency_timer = (if divisor_code == 0 { 8 } else { divisor_code << 4 }) << ((self.nr43 >> 4) as u32);
-
-            let xor_result = (self.lfsr & 0b01) ^ ((self.lfsr & 0b10) >> 1);
-
-            self.lfsr = (self.lfsr >> 1) | (xor_result << 14);
-
-            if ((self.nr43 >> 3) & 0b01) != 0 {
-                self.lfsr &= !(1 << 6);
-                self.lfsr |= xor_result << 6;
-            }
+    fn tick(&mut self, cycles: usize) {
+        // Every time the frequency timer runs out, it is reloaded with `divisor_code << clock_shift`
+        // and the LFSR is clocked once
+        let divisor_code = (self.nr43 & 0x07) as u16;
+        let reload = (if divisor_code == 0 { 8 } else { divisor_code << 4 }) << ((self.nr43 >> 4) as u32);
+        // The timer is 16 bits wide, a reload that overflows it to 0 runs through all of them
+        let period = if reload == 0 { 0x10000 } else { reload as usize };
+
+        for _ in 0..advance_timer(&mut self.frequency_timer, period, cycles) {
+            self.clock_lfsr();
         }
-
-        self.frequency_timer = self.frequency_timer.wrapping_sub(1);
     }
 
-    fn get_amplitude(&self) -> f32 {
-        if self.dac_enabled && self.enabled {
-            let input = (!self.lfsr & 0b01) as f32 * self.current_volume as f32;
-
-            (input / 7.5) - 1.0
+    fn digital_output(&self) -> u8 {
+        if self.enabled {
+            (!self.lfsr & 0b01) as u8 * self.current_volume
         } else {
-            0.0
+            0
         }
     }
 
@@ -118,6 +123,33 @@ impl Channel for NoiseChannel {
             }
         }
     }
+
+    fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // The LFSR is clocked every `divisor_code << clock_shift` T-cycles, same as in `tick`
+    fn frequency(&self) -> f32 {
+        let divisor_code = (self.nr43 & 0x07) as u32;
+        let divisor = if divisor_code == 0 { 8 } else { divisor_code << 4 };
+        CPU_CLOCK as f32 / (divisor << (self.nr43 >> 4)) as f32
+    }
+
+    fn volume(&self) -> u8 {
+        self.current_volume
+    }
+
+    fn envelope(&self) -> Option<Envelope> {
+        Some(Envelope {
+            initial_volume: self.initial_volume,
+            incrementing: self.is_incrementing,
+            period: self.period,
+        })
+    }
 }
 
 impl Addressable for NoiseChannel {
@@ -137,7 +169,7 @@ impl Addressable for NoiseChannel {
     #[inline]
     fn write(&mut self, addr: u16, value: u8) {
         match addr {
-            NR41 => self.length_counter = 64 - (value & 0b0011_1111),
+            NR41 => self.length_counter = 64 - (value & 0b0011_1111) as u16,
             NR42 => {
                 // Update the envelope function parameters
                 self.is_incrementing = (value & 0x08) != 0;
@@ -154,11 +186,14 @@ impl Addressable for NoiseChannel {
             }
             NR43 => self.nr43 = value,
             NR44 => {
-                self.length_enabled = ((value >> 6) & 0x01) != 0;
-
-                // If length counter is zero reload it with 64.
-                if self.length_counter == 0 {
-                    self.length_counter = 64;
+                if !write_length_control(
+                    &mut self.length_counter,
+                    &mut self.length_enabled,
+                    64,
+                    value,
+                    self.extra_length_clock,
+                ) {
+                    self.enabled = false;
                 }
 
                 // Restart the channel iff DAC is enabled and trigger is set.
@@ -1,8 +1,8 @@
 use log::error;
 
-use super::Channel;
+use super::{advance_timer, write_length_control, Channel, Envelope};
 use crate::memory::addressable::Addressable;
-use crate::sound::{NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24};
+use crate::sound::{CPU_CLOCK, NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24};
 
 const WAVE_DUTY: [[f32; 8]; 4] = [
     [0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0], // 12.5%
@@ -11,7 +11,7 @@ const WAVE_DUTY: [[f32; 8]; 4] = [
     [0.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 0.0], // 75%
 ];
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct SquareChannel1 {
     // Tells whether the channel itself it enabled.
     // This can be only affected by a trigger event
@@ -49,13 +49,17 @@ pub struct SquareChannel1 {
     // conditions
     shadow_frequency: u16,
 
+    // Whether a frequency was calculated in negate mode since the last trigger.
+    // Clearing the negate bit afterwards disables the channel
+    sweep_negated: bool,
+
     // The wave pattern duty currently in use
     duty_pattern: u8,
 
     // The sound length counter. If this is >0 and bit 6 in NR24 is set
     // then it is decremented with clocks from FS. If this then hits 0
     // the sound channel is then disabled
-    length_counter: u8,
+    length_counter: u16,
 
     // The channel frequency value. This is controlled by NR23 and NR24
     frequency: u16,
@@ -63,6 +67,10 @@ pub struct SquareChannel1 {
     // Whether the length timer is enabled or not
     length_enabled: bool,
 
+    // Set by the APU while the frame sequencer's next step doesn't clock lengths,
+    // NRx4 writes then clock the length counter early
+    pub extra_length_clock: bool,
+
     // The initial volume of the envelope function
     initial_volume: u8,
 
@@ -130,6 +138,7 @@ impl SquareChannel1 {
         let mut new_frequency = self.shadow_frequency >> self.sweep_amount;
 
         new_frequency = if self.sweep_is_decrementing {
+            self.sweep_negated = true;
             self.shadow_frequency - new_frequency
         } else {
             self.shadow_frequency + new_frequency
@@ -144,29 +153,21 @@ impl SquareChannel1 {
 }
 
 impl Channel for SquareChannel1 {
-    fn tick(&mut self) {
-        // If the frequency timer decrement to 0, it is reloaded with the formula
-        // `(2048 - frequency) * 4` and wave position is advanced by one
-        if self.frequency_timer == 0 {
-            self.frequency_timer = (2048 - self.frequency) * 4;
-
-            // Wave position is wrapped, so when the position is >8 it's
-            // wrapped back to 0
-            self.wave_position = (self.wave_position + 1) % 8;
-        }
-
-        self.frequency_timer -= 1;
+    fn tick(&mut self, cycles: usize) {
+        // Every time the frequency timer runs out, it is reloaded with `(2048 - frequency) * 4`
+        // and the wave position is advanced by one, wrapping around after 8
+        let period = (2048 - self.frequency as usize) * 4;
+        let steps = advance_timer(&mut self.frequency_timer, period, cycles);
+        self.wave_position = (self.wave_position + steps) % 8;
     }
 
-    // Get the current amplitude of the channel.
-    // The only possible values of this are 0 or 1
-    fn get_amplitude(&self) -> f32 {
-        if self.dac_enabled && self.channel_enabled {
-            let input = WAVE_DUTY[self.duty_pattern as usize][self.wave_position] as f32 * self.current_volume as f32;
-
-            (input / 7.5) - 1.0
+    // The DAC input, 0-15: the duty step (0 or 1) times the current volume. PCM12 reads it and the DAC
+    // turns it into the amplitude.
+    fn digital_output(&self) -> u8 {
+        if self.channel_enabled {
+            WAVE_DUTY[self.duty_pattern as usize][self.wave_position] as u8 * self.current_volume
         } else {
-            0.0
+            0
         }
     }
 
@@ -180,9 +181,34 @@ impl Channel for SquareChannel1 {
             }
         }
     }
+
+    fn is_enabled(&self) -> bool {
+        self.channel_enabled
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // One duty cycle is 8 steps of `(2048 - frequency) * 4` T-cycles
+    fn frequency(&self) -> f32 {
+        CPU_CLOCK as f32 / ((2048 - self.frequency as u32) * 32) as f32
+    }
+
+    fn volume(&self) -> u8 {
+        self.current_volume
+    }
+
+    fn envelope(&self) -> Option<Envelope> {
+        Some(Envelope {
+            initial_volume: self.initial_volume,
+            incrementing: self.is_incrementing,
+            period: self.period,
+        })
+    }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct SquareChannel2 {
     // Whether the channel itself is enabled.
     // This can be only affected by a trigger event
@@ -206,7 +232,7 @@ pub struct SquareChannel2 {
     // The sound length counter. If this is >0 and bit 6 in NR24 is set
     // then it is decremented with clocks from FS. If this then hits 0
     // the sound channel is then disabled
-    length_counter: u8,
+    length_counter: u16,
 
     // The channel frequency value. This is controlled by NR23 and NR24
     frequency: u16,
@@ -214,6 +240,10 @@ pub struct SquareChannel2 {
     // Whether the length timer is enabled or not
     length_enabled: bool,
 
+    // Set by the APU while the frame sequencer's next step doesn't clock lengths,
+    // NRx4 writes then clock the length counter early
+    pub extra_length_clock: bool,
+
     // The initial volume of the envelope function
     initial_volume: u8,
 
@@ -258,29 +288,21 @@ impl SquareChannel2 {
 }
 
 impl Channel for SquareChannel2 {
-    fn tick(&mut self) {
-        // If the frequency timer decrement to 0, it is reloaded with the formula
-        // `(2048 - frequency) * 4` and wave position is advanced by one
-        if self.frequency_timer == 0 {
-            self.frequency_timer = (2048 - self.frequency) * 4;
-
-            // Wave position is wrapped, so when the position is >8 it's
-            // wrapped back to 0
-            self.wave_position = (self.wave_position + 1) & 7;
-        }
-
-        self.frequency_timer -= 1;
+    fn tick(&mut self, cycles: usize) {
+        // Every time the frequency timer runs out, it is reloaded with `(2048 - frequency) * 4`
+        // and the wave position is advanced by one, wrapping around after 8
+        let period = (2048 - self.frequency as usize) * 4;
+        let steps = advance_timer(&mut self.frequency_timer, period, cycles);
+        self.wave_position = (self.wave_position + steps) % 8;
     }
 
-    // Get the current amplitude of the channel.
-    // The only possible values of this are 0 or 1.
-    fn get_amplitude(&self) -> f32 {
-        if self.dac_enabled && self.channel_enabled {
-            let input = WAVE_DUTY[self.duty_pattern as usize][self.wave_position] as f32 * self.current_volume as f32;
-
-            (input / 7.5) - 1.0
+    // The DAC input, 0-15: the duty step (0 or 1) times the current volume. PCM12 reads it and the DAC
+    // turns it into the amplitude.
+    fn digital_output(&self) -> u8 {
+        if self.channel_enabled {
+            WAVE_DUTY[self.duty_pattern as usize][self.wave_position] as u8 * self.current_volume
         } else {
-            0.0
+            0
         }
     }
 
@@ -294,6 +316,31 @@ impl Channel for SquareChannel2 {
             }
         }
     }
+
+    fn is_enabled(&self) -> bool {
+        self.channel_enabled
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // One duty cycle is 8 steps of `(2048 - frequency) * 4` T-cycles
+    fn frequency(&self) -> f32 {
+        CPU_CLOCK as f32 / ((2048 - self.frequency as u32) * 32) as f32
+    }
+
+    fn volume(&self) -> u8 {
+        self.current_volume
+    }
+
+    fn envelope(&self) -> Option<Envelope> {
+        Some(Envelope {
+            initial_volume: self.initial_volume,
+            incrementing: self.is_incrementing,
+            period: self.period,
+        })
+    }
 }
 
 impl Addressable for SquareChannel1 {
@@ -320,8 +367,14 @@ impl Addressable for SquareChannel1 {
     fn write(&mut self, addr: u16, value: u8) {
         match addr {
             NR10 => {
+                // Leaving negate mode after a calculation was done in it kills the channel
+                let decrementing = (value & 0x08) != 0;
+                if self.sweep_negated && !decrementing {
+                    self.channel_enabled = false;
+                }
+
                 // Update the sweep function parameters
-                self.sweep_is_decrementing = (value & 0x08) != 0;
+                self.sweep_is_decrementing = decrementing;
                 self.sweep_period = value >> 4;
                 self.sweep_amount = value & 0x07;
             }
@@ -330,7 +383,7 @@ impl Addressable for SquareChannel1 {
 
                 // The length counter is calculated by the following formula,
                 // `Length Counter = (64 - Length Data)`
-                self.length_counter = 64 - (value & 0b0011_1111);
+                self.length_counter = 64 - (value & 0b0011_1111) as u16;
             }
             NR12 => {
                 // Update the envelope function parameters
@@ -354,11 +407,14 @@ impl Addressable for SquareChannel1 {
                 // Update frequency with the upper three bits
                 self.frequency = (self.frequency & 0xFF) | (((value & 0x07) as u16) << 8);
 
-                self.length_enabled = ((value >> 6) & 0x01) != 0;
-
-                // If length counter is zero reload it with 64
-                if self.length_counter == 0 {
-                    self.length_counter = 64;
+                if !write_length_control(
+                    &mut self.length_counter,
+                    &mut self.length_enabled,
+                    64,
+                    value,
+                    self.extra_length_clock,
+                ) {
+                    self.channel_enabled = false;
                 }
 
                 // Restart the channel iff DAC is enabled and trigger is set
@@ -373,12 +429,14 @@ impl Addressable for SquareChannel1 {
 
                     // Trigger the sweep function
                     self.shadow_frequency = self.frequency;
+                    self.sweep_negated = false;
 
                     // Sweep period of 0 is treated as 8 for some reason
                     self.sweep_period_timer = if self.sweep_period > 0 { self.sweep_period } else { 8 };
 
                     self.sweep_enabled = self.sweep_period > 0 || self.sweep_amount > 0;
 
+                    // Only the overflow check, the result isn't written back
                     if self.sweep_amount > 0 {
                         self.calculate_frequency();
                     }
@@ -411,7 +469,7 @@ impl Addressable for SquareChannel2 {
 
                 // The length counter is calculated by the following formula,
                 // `Length Counter = (64 - Length Data)`
-                self.length_counter = 64 - (value & 0b0011_1111);
+                self.length_counter = 64 - (value & 0b0011_1111) as u16;
             }
             NR22 => {
                 // Update the envelope function parameters
@@ -435,11 +493,14 @@ impl Addressable for SquareChannel2 {
                 // Update frequency with the upper three bits
                 self.frequency = (self.frequency & 0xFF) | (((value & 0x07) as u16) << 8);
 
-                self.length_enabled = ((value >> 6) & 0x01) != 0;
-
-                // If length counter is zero reload it with 64
-                if self.length_counter == 0 {
-                    self.length_counter = 64;
+                if !write_length_control(
+                    &mut self.length_counter,
+                    &mut self.length_enabled,
+                    64,
+                    value,
+                    self.extra_length_clock,
+                ) {
+                    self.channel_enabled = false;
                 }
 
                 // Restart the channel iff DAC is enabled and trigger is set
@@ -1,9 +1,10 @@
 use log::error;
 
+use crate::gameboy::Mode;
 use crate::memory::addressable::Addressable;
-use crate::sound::{NR30, NR31, NR32, NR33, NR34, WAVE_PATTERN_RAM_END, WAVE_PATTERN_RAM_START};
+use crate::sound::{CPU_CLOCK, NR30, NR31, NR32, NR33, NR34, WAVE_PATTERN_RAM_END, WAVE_PATTERN_RAM_START};
 
-use super::Channel;
+use super::{advance_timer, write_length_control, Channel};
 
 #[derive(Default, Clone)]
 pub struct WaveChannel {
@@ -42,35 +43,60 @@ pub struct WaveChannel {
     // Whether the length timer is enabled or not
     length_enabled: bool,
 
+    // Set by the APU while the frame sequencer's next step doesn't clock lengths,
+    // NRx4 writes then clock the length counter early
+    pub extra_length_clock: bool,
+
     // Arbitrary 32 4-bit samples
     wave_ram: [u8; 0x10],
+
+    // While the channel plays, the CGB redirects wave RAM accesses to the byte being played
+    cgb: bool,
 }
 
-impl Channel for WaveChannel {
-    // Tick the channel by one T-cycle
-    fn tick(&mut self) {
-        // If the frequency timer decrement to 0, it is reloaded with the formula
-        // `(2048 - frequency) * 2` and wave position is advanced by one
-        if self.frequency_timer == 0 {
-            self.frequency_timer = (2048 - self.frequency) * 2;
-
-            // Wave position is wrapped, so when the position is >32 it's
-            // wrapped back to 0
-            self.wave_position = (self.wave_position + 1) & 31;
+impl WaveChannel {
+    pub fn new(mode: Mode) -> WaveChannel {
+        WaveChannel {
+            cgb: mode == Mode::Cgb,
+            ..Default::default()
         }
+    }
 
-        self.frequency_timer -= 1;
+    // Byte of wave RAM a CPU access ends up at, None if the access fails.
+    // The DMG only gets through in the cycle the channel reads the RAM, which isn't emulated
+    fn wave_ram_index(&self, addr: u16) -> Option<usize> {
+        if !self.channel_enabled {
+            Some((addr - WAVE_PATTERN_RAM_START) as usize)
+        } else if self.cgb {
+            Some(self.wave_position / 2)
+        } else {
+            None
+        }
     }
 
-    // Get the current amplitude of the channel
-    fn get_amplitude(&self) -> f32 {
-        if self.dac_enabled {
+    // Replaces all of wave RAM, e.g. from a save state, no matter where the channel is playing
+    pub fn load_wave_ram(&mut self, wave_ram: &[u8]) {
+        self.wave_ram.copy_from_slice(wave_ram);
+    }
+}
+
+impl Channel for WaveChannel {
+    fn tick(&mut self, cycles: usize) {
+        // Every time the frequency timer runs out, it is reloaded with `(2048 - frequency) * 2`
+        // and the wave position is advanced by one, wrapping around after 32
+        let period = (2048 - self.frequency as usize) * 2;
+        let steps = advance_timer(&mut self.frequency_timer, period, cycles);
+        self.wave_position = (self.wave_position + steps) % 32;
+    }
+
+    fn digital_output(&self) -> u8 {
+        if self.channel_enabled {
             let sample =
                 ((self.wave_ram[self.wave_position / 2]) >> (if (self.wave_position & 1) != 0 { 4 } else { 0 })) & 0x0F;
 
-            (((sample >> self.volume_shift) as f32) / 7.5) - 1.0
+            sample >> self.volume_shift
         } else {
-            0.0
+            0
         }
     }
 
@@ -84,6 +110,28 @@ impl Channel for WaveChannel {
             }
         }
     }
+
+    fn is_enabled(&self) -> bool {
+        self.channel_enabled
+    }
+
+    fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // The 32 samples are stepped every `(2048 - frequency) * 2` T-cycles
+    fn frequency(&self) -> f32 {
+        CPU_CLOCK as f32 / ((2048 - self.frequency as u32) * 64) as f32
+    }
+
+    // The output level shifts samples down, 0 mutes the channel
+    fn volume(&self) -> u8 {
+        if self.output_level == 0 {
+            0
+        } else {
+            0x0f >> self.volume_shift
+        }
+    }
 }
 
 impl Addressable for WaveChannel {
@@ -93,7 +141,10 @@ impl Addressable for WaveChannel {
             NR30 => ((self.dac_enabled as u8) << 7) | 0x7F,
             NR32 => (self.output_level << 5) | 0x9F,
             NR34 => ((self.length_enabled as u8) << 6) | 0b1011_1111,
-            WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END => self.wave_ram[(addr - WAVE_PATTERN_RAM_START) as usize],
+            WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END => match self.wave_ram_index(addr) {
+                Some(index) => self.wave_ram[index],
+                None => 0xff,
+            },
             _ => {
                 error!("Unimplemented read from APU register: {:04x}", addr);
                 0
@@ -134,11 +185,14 @@ impl Addressable for WaveChannel {
                 // Update frequency with the upper three bits
                 self.frequency = (self.frequency & 0xFF) | (((value & 0x07) as u16) << 8);
 
-                self.length_enabled = ((value >> 6) & 0x01) != 0;
-
-                // If length counter is zero reload it with 6
-                if self.length_counter == 0 {
-                    self.length_counter = 256;
+                if !write_length_control(
+                    &mut self.length_counter,
+                    &mut self.length_enabled,
+                    256,
+                    value,
+                    self.extra_length_clock,
+                ) {
+                    self.channel_enabled = false;
                 }
 
                 // Restart the channel iff DAC is enabled and trigger is set
@@ -149,7 +203,9 @@ impl Addressable for WaveChannel {
                 }
             }
             WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END => {
-                self.wave_ram[(addr - WAVE_PATTERN_RAM_START) as usize] = value
+                if let Some(index) = self.wave_ram_index(addr) {
+                    self.wave_ram[index] = value;
+                }
             }
             _ => error!("Unimplemented write to APU register: {:04x}", addr),
         }
@@ -1,6 +1,11 @@
+use clap::ValueEnum;
+
 pub mod apu;
 mod channels;
-mod stereo;
+pub mod note;
+pub mod output;
+
+pub use channels::{ChannelState, Envelope};
 
 // The audio sample rate
 pub const SAMPLE_RATE: usize = 48_000;
@@ -11,6 +16,15 @@ pub const BUFFER_SIZE: usize = 1024;
 // The rate at which the CPU is ticked
 pub const CPU_CLOCK: usize = 4194304;
 
+// What paces the emulation
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum SyncMode {
+    // Waits for the audio device to need more samples, video and sound never drift apart
+    Audio,
+    // Runs frames at the hardware refresh rate, samples get dropped if the audio device falls behind
+    Timer,
+}
+
 // APU registers
 pub const NR10: u16 = 0xff10;
 pub const NR11: u16 = 0xff11;
@@ -35,3 +49,6 @@ pub const NR51: u16 = 0xff25;
 pub const NR52: u16 = 0xff26;
 pub const WAVE_PATTERN_RAM_START: u16 = 0xff30;
 pub const WAVE_PATTERN_RAM_END: u16 = 0xff3f;
+// CGB only, read-only digital outputs of channels 1+2 and 3+4
+pub const PCM12: u16 = 0xff76;
+pub const PCM34: u16 = 0xff77;
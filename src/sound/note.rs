@@ -0,0 +1,40 @@
+use std::fmt;
+
+const NOTE_NAMES: [&str; 12] = ["C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B"];
+// A4, which MIDI note 69 is tuned to
+const CONCERT_PITCH: f32 = 440.0;
+// Anything outside of this isn't played as a note
+const LOWEST_NOTE: f32 = 16.0;
+const HIGHEST_NOTE: f32 = 20_000.0;
+
+// The closest equal temperament note to a frequency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Note {
+    pub name: &'static str,
+    pub octave: i32,
+    // How far off the frequency is from the note, -50 to +50
+    pub cents: i32,
+}
+
+impl Note {
+    pub fn nearest(frequency: f32) -> Option<Note> {
+        if !(LOWEST_NOTE..=HIGHEST_NOTE).contains(&frequency) {
+            return None;
+        }
+
+        let midi = 69.0 + 12.0 * (frequency / CONCERT_PITCH).log2();
+        let note = midi.round();
+
+        Some(Note {
+            name: NOTE_NAMES[(note as i32).rem_euclid(12) as usize],
+            octave: (note as i32).div_euclid(12) - 1,
+            cents: ((midi - note) * 100.0).round() as i32,
+        })
+    }
+}
+
+impl fmt::Display for Note {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{} {:+}c", self.name, self.octave, self.cents)
+    }
+}
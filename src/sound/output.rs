@@ -0,0 +1,42 @@
+use rodio::buffer::SamplesBuffer;
+use rodio::{OutputStream, Sink};
+
+use super::SAMPLE_RATE;
+use crate::error::AyyError;
+
+// Where the APU plays its samples. The core never opens a device by itself, a frontend hands one to
+// the APU with set_audio_sink, so that any number of systems can run side by side without one.
+pub trait AudioSink {
+    // Queues a buffer of interleaved left/right samples at SAMPLE_RATE
+    fn append(&self, samples: &[f32]);
+    // Buffers queued that haven't finished playing yet
+    fn queued(&self) -> usize;
+}
+
+// The default audio device of the host
+pub struct RodioSink {
+    sink: Sink,
+    _stream: OutputStream,
+}
+
+impl RodioSink {
+    pub fn open() -> Result<RodioSink, AyyError> {
+        let (stream, stream_handle) = OutputStream::try_default().map_err(audio_error)?;
+        let sink = Sink::try_new(&stream_handle).map_err(audio_error)?;
+        Ok(RodioSink { sink, _stream: stream })
+    }
+}
+
+impl AudioSink for RodioSink {
+    fn append(&self, samples: &[f32]) {
+        self.sink.append(SamplesBuffer::new(2, SAMPLE_RATE as u32, samples));
+    }
+
+    fn queued(&self) -> usize {
+        self.sink.len()
+    }
+}
+
+fn audio_error(e: impl std::fmt::Display) -> AyyError {
+    AyyError::Audio { reason: e.to_string() }
+}
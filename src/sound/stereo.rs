@@ -1,4 +0,0 @@
-pub enum StereoSide {
-    Left,
-    Right,
-}
@@ -0,0 +1,184 @@
+use crate::error::AyyError;
+use crate::gameboy::Mode;
+use crate::memory::mapper::rtc::RtcMode;
+use crate::memory::peek::MemorySnapshot;
+use crate::memory::ram_decay::RamDecay;
+use crate::serial::printer::Printout;
+use crate::sound::SyncMode;
+use crate::video::compat::CompatPalette;
+use crate::video::palette::Color;
+use crate::video::{Accuracy, ColorCorrection, Layers};
+use clap::ValueEnum;
+use sha1::{Digest, Sha1};
+use std::any::Any;
+use std::fmt;
+use std::time::Duration;
+
+// Buttons the frontend maps its input onto
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    A,
+    B,
+    Start,
+    Select,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+        Button::A,
+        Button::B,
+        Button::Start,
+        Button::Select,
+    ];
+
+    // Bit of the button in an input bitmask, as passed to update_buttons
+    pub const fn mask(&self) -> u8 {
+        1 << *self as u8
+    }
+}
+
+// SHA-1 of a ROM as it was loaded (patches included), tells revisions and hacks of a game apart
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RomHash(pub [u8; 20]);
+
+impl RomHash {
+    pub fn of(rom: &[u8]) -> RomHash {
+        RomHash(Sha1::digest(rom).into())
+    }
+
+    pub fn parse(hex: &str) -> Option<RomHash> {
+        let hex = hex.trim();
+        if hex.len() != 40 {
+            return None;
+        }
+
+        let mut hash = [0; 20];
+        for (i, byte) in hash.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+        }
+        Some(RomHash(hash))
+    }
+}
+
+impl fmt::Display for RomHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+// Snapshot of a running system, only the system that created it knows how to load it
+pub struct SaveState {
+    state: Box<dyn Any>,
+    rom_hash: RomHash,
+}
+
+impl SaveState {
+    pub fn new<T: Any>(state: T, rom_hash: RomHash) -> SaveState {
+        SaveState {
+            state: Box::new(state),
+            rom_hash,
+        }
+    }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.state.downcast_ref::<T>()
+    }
+
+    // The ROM that was running when the state was made
+    #[inline]
+    pub fn rom_hash(&self) -> RomHash {
+        self.rom_hash
+    }
+}
+
+// Everything the frontend needs from an emulated console, so that it doesn't have to know which one it is running
+pub trait System {
+    fn run_frame(&mut self);
+    fn screen_size(&self) -> (usize, usize);
+    // How long a frame lasts on the real hardware
+    fn frame_duration(&self) -> Duration;
+    // RGB pixels of the last finished frame, row by row
+    fn framebuffer(&self) -> Vec<Color>;
+    fn update_button(&mut self, button: Button, pressed: bool);
+    // How many times faster than the hardware to run, 1 is the native speed. Sound keeps its pitch.
+    fn set_speed(&mut self, multiplier: usize);
+    // Muted systems don't output sound or wait for the audio device
+    fn set_muted(&mut self, muted: bool);
+    // Frames emulated with rendering disabled keep their timing and interrupts, only the picture isn't drawn
+    fn set_rendering(&mut self, enabled: bool);
+    // Trades rendering speed for mid-frame and mid-scanline effects, emulation timing is the same either way
+    fn set_accuracy(&mut self, accuracy: Accuracy);
+    // Hides parts of the picture for debugging game visuals or capturing them, the game runs the same
+    fn set_layers(&mut self, layers: Layers);
+    // How CGB colors are shown, DMG shades aren't affected
+    fn set_color_correction(&mut self, correction: ColorCorrection);
+    // Colors for games without any of their own, ignored by systems (or games) that have them
+    fn set_compat_palette(&mut self, palette: CompatPalette);
+    fn set_sync_mode(&mut self, mode: SyncMode);
+    // Adapts the audio buffering to the latency of the device and returns it, None without a device
+    fn calibrate_audio(&mut self) -> Option<Duration>;
+    // Emulates the given number of frames past the current one and returns the last of them,
+    // without affecting the running system (or its audio). Used to hide input lag.
+    fn run_ahead(&mut self, frames: usize) -> Vec<Color>;
+    fn save_state(&self) -> SaveState;
+    // Refuses states made with a different ROM, they would pick up where that ROM left off
+    fn load_state(&mut self, state: &SaveState) -> Result<(), AyyError> {
+        if state.rom_hash() != self.rom_hash() {
+            return Err(AyyError::SaveStateRomMismatch {
+                expected: self.rom_hash().to_string(),
+                found: state.rom_hash().to_string(),
+            });
+        }
+        self.force_load_state(state)
+    }
+    // Loads a state no matter which ROM it was made with
+    fn force_load_state(&mut self, state: &SaveState) -> Result<(), AyyError>;
+    fn rom_hash(&self) -> RomHash;
+    // Title from the cartridge header, and the hardware it runs on (e.g. "CGB"), for display only
+    fn title(&self) -> String;
+    fn model(&self) -> &'static str;
+    // What the game runs as, e.g. whether it has colors of its own
+    fn mode(&self) -> Mode;
+
+    // Battery backed cartridge data
+    fn dump_ram(&self) -> Vec<u8>;
+    fn load_ram(&mut self, ram: Vec<u8>);
+    fn dump_rtc(&self) -> Option<Vec<u8>>;
+    fn load_rtc(&mut self, rtc: Vec<u8>, mode: RtcMode);
+    // The battery backed data made it to disk, changes from here on are unsaved again
+    fn mark_saved(&mut self);
+    fn swap_cartridge(&mut self, rom: Vec<u8>) -> Result<(), AyyError>;
+    // Power cycle that keeps the cartridge, and with it the battery backed RAM
+    fn reset(&mut self);
+    // Power cycle where the cartridge RAM decays as given, as if it had no battery
+    fn power_cycle(&mut self, decay: Option<&RamDecay>);
+    // Whether the cartridge ran its rumble motor since the last call, for forwarding it to a gamepad
+    fn take_rumble(&mut self) -> bool;
+    // Pages a connected printer finished since the last call
+    fn take_printouts(&mut self) -> Vec<Printout>;
+    // Side-effect free copy of the memory, for frontends evaluating achievements after each frame
+    fn memory_snapshot(&self) -> MemorySnapshot;
+
+    fn update_buttons(&mut self, input: u8) {
+        for button in Button::ALL {
+            self.update_button(button, input & button.mask() != 0);
+        }
+    }
+
+    // Changes the buttons partway into the next frame (0.0 to 1.0), systems that can't do that
+    // change them right away
+    fn queue_buttons(&mut self, _frame_offset: f64, input: u8) {
+        self.update_buttons(input);
+    }
+
+    // System specific tooling (e.g. the debugger) downcasts to the concrete type
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
@@ -1,12 +1,84 @@
 #[cfg(test)]
 mod tests {
-    use crate::gameboy::Mode;
+    use crate::clock::ClockDivider;
+    use crate::error::AyyError;
+    use crate::frontend::archive::{load_rom, ExtractionGuard};
+    use crate::frontend::capture::{FrameSink, FrameStream};
+    use crate::frontend::cheat_search::{CheatSearch, Filter};
+    use crate::frontend::frame_rng::FrameRng;
+    use crate::frontend::frame_trace;
+    use crate::frontend::gamepad::{RumbleEnvelope, RUMBLE_UPDATE_INTERVAL};
+    use crate::frontend::library;
+    use crate::frontend::log_file::{LogConfig, LogFile, LogRing, RotatingFile};
+    use crate::frontend::monkey;
+    use crate::frontend::netplay::{Netplay, SessionInfo};
+    use crate::frontend::overlay::ChangeOverlay;
+    use crate::frontend::presence::Presence;
+    use crate::frontend::rom_settings::{PlayStats, RomSettings};
+    use crate::frontend::save_info::SaveInfo;
+    use crate::frontend::screenshot::{load_png, save_png};
+    use crate::frontend::settings::BackgroundPolicy;
+    use crate::frontend::state::{FrontendState, Hotkey, StateEvent, BACKGROUND_SLOWDOWN, FAST_FORWARD_SPEED};
+    use crate::frontend::state_diff::{self, Subsystem};
+    use crate::frontend::test_suite::{run_test, screen_hash, Outcome, Suite};
+    use crate::frontend::turbo::Turbo;
+    use crate::frontend::vram_export;
+    use crate::frontend::watch::{load_watches, save_watches, Watch, WatchType};
+    use crate::gameboy::{GameBoy, Mode};
+    use crate::lr35902::asm::Assembler;
     use crate::lr35902::cpu::*;
+    use crate::lr35902::diagnostics::StackFault;
+    use crate::lr35902::disasm::disassemble;
+    use crate::lr35902::expr::{BreakCondition, Expr};
     use crate::lr35902::sm83::*;
     use crate::lr35902::timer::Timer;
+    use crate::memory::addressable::Addressable;
+    use crate::memory::banked::BankedAddress;
+    use crate::memory::heatmap::PAGE_COUNT;
+    use crate::memory::io_dispatch::{register_doc, register_docs};
+    use crate::memory::io_registers::{register_address, register_name, Access, IoBreakpoint};
+    use crate::memory::mapper::mbc1::Mbc1;
+    use crate::memory::mapper::mbc3::Mbc3;
+    use crate::memory::mapper::mbc5::Mbc5;
     use crate::memory::mapper::rom::Rom;
+    use crate::memory::mapper::{header_title, Mapper};
     use crate::memory::mmu::*;
+    use crate::memory::patch::{apply_patch, crc32};
+    use crate::memory::ram_decay::RamDecay;
+    use crate::memory::ram_init::{RamInit, XorShift};
+    use crate::memory::registers::{InterruptFlags, LcdControl};
+    use crate::memory::{INTERRUPT_ENABLE_REGISTER, INTERRUPT_FLAGS_REGISTER};
+    use crate::scheduler::{Event, Scheduler};
+    use crate::serial::printer::{Printer, PRINTER_WIDTH};
+    use crate::serial::{LinkDevice, SerialDevice, SERIAL_CONTROL_REGISTER, SERIAL_DATA_REGISTER};
+    use crate::sound::apu::Apu;
+    use crate::sound::note::Note;
+    use crate::sound::output::AudioSink;
+    use crate::sound::{
+        Envelope, BUFFER_SIZE, CPU_CLOCK, NR10, NR11, NR12, NR13, NR14, NR21, NR22, NR23, NR24, NR30, NR32, NR33, NR34,
+        NR42, NR43, NR44, NR50, NR51, NR52, PCM12, PCM34, SAMPLE_RATE, WAVE_PATTERN_RAM_END, WAVE_PATTERN_RAM_START,
+    };
+    use crate::system::{Button, RomHash, System};
+    use crate::video::bus::LatchedLine;
+    use crate::video::compat::CompatPalette;
+    use crate::video::oam::Oam;
+    use crate::video::palette::{Color, Palette};
+    use crate::video::ppu::{tilemap_index, FrameEvents, Ppu, WindowLine};
+    use crate::video::priority::{priority_resolver, CgbPriority, DmgPriority, PriorityResolver};
+    use crate::video::sprite::{Sprite, SpriteAttributes};
+    use crate::video::state::State;
+    use crate::video::tile::{Tile, TileAttributes};
+    use crate::video::{
+        Accuracy, ColorCorrection, Layers, BACKGROUND_MAP_SIZE, BG_PALETTE_REGISTER, DRAWING_CYCLES,
+        LCD_CONTROL_REGISTER, LCD_STATUS_REGISTER, OBJ1_PALETTE_REGISTER, SCANLINE_Y_COMPARE_REGISTER,
+        SCANLINE_Y_REGISTER, SCREEN_HEIGHT, SCREEN_WIDTH, WINDOW_X_REGISTER, WINDOW_Y_REGISTER,
+    };
+    use log::Level;
     use serde_json::Value;
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashSet;
+    use std::rc::Rc;
+    use std::time::{Duration, Instant};
 
     fn is_ignore(_path: &std::path::Path) -> bool {
         false
@@ -17,6 +89,8 @@ mod tests {
     })]
     fn test_cpu(input: &str) {
         let tests: Value = serde_json::from_str(&input).unwrap();
+        // Timing mismatches are collected, so a run lists every test of the opcode that is off
+        let mut timing_errors = Vec::new();
 
         for test in tests.as_array().unwrap() {
             let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0xffff])), Mode::Dmg);
@@ -24,7 +98,6 @@ mod tests {
             mmu.resize_memory(0xffff * 4);
             let mut sm83 = Sm83::new();
             let mut cpu = Cpu::new();
-            let mut timer = Timer::new();
 
             let test = test.as_object().unwrap();
             let name = test.get("name").unwrap().as_str().unwrap();
@@ -55,16 +128,17 @@ mod tests {
                 mmu.write_unchecked(addr, value);
             }
 
-            if let Ok(instruction) = sm83.decode(&mut mmu, cpu.read_register16(&Register::PC)) {
+            if let Ok(instruction) = sm83.decode(&mmu, cpu.read_register16(&Register::PC)) {
                 println!("{} | {:?}", instruction, instruction);
             } else {
                 panic!("Failed to decode instruction");
             };
 
-            match cpu.tick(&mut mmu, &mut timer) {
-                Ok(_) => {}
+            let _ = mmu.take_write_log();
+            let cycles = match cpu.tick(&mut mmu) {
+                Ok(cycles) => cycles,
                 Err(e) => panic!("{}", e),
-            }
+            };
 
             assert_eq!(
                 cpu.read_register(&Register::A),
@@ -145,6 +219,3545 @@ mod tests {
                     name
                 );
             }
+
+            // One entry per M-cycle, idle ones are null
+            if let Some(bus) = test.get("cycles").and_then(|cycles| cycles.as_array()) {
+                if cycles != bus.len() * 4 {
+                    timing_errors.push(format!("{}: took {} cycles instead of {}", name, cycles, bus.len() * 4));
+                }
+
+                // Only writes are compared, the decoder fetches all operands up front so reads are out of order
+                let expected_writes = bus
+                    .iter()
+                    .filter_map(|cycle| {
+                        let cycle = cycle.as_array()?;
+                        match cycle.get(2)?.as_str()?.contains('w') {
+                            true => Some((cycle[0].as_u64()? as u16, cycle[1].as_u64()? as u8)),
+                            false => None,
+                        }
+                    })
+                    .collect::<Vec<(u16, u8)>>();
+                let writes = mmu.take_write_log();
+                if writes != expected_writes {
+                    let format = |writes: &[(u16, u8)]| {
+                        writes
+                            .iter()
+                            .map(|(addr, data)| format!("{:04x}={:02x}", addr, data))
+                            .collect::<Vec<String>>()
+                            .join(" ")
+                    };
+                    timing_errors.push(format!(
+                        "{}: wrote [{}] instead of [{}]",
+                        name,
+                        format(&writes),
+                        format(&expected_writes)
+                    ));
+                }
+            }
+        }
+
+        assert!(
+            timing_errors.is_empty(),
+            "Timing mismatches:\n{}",
+            timing_errors.join("\n")
+        );
+    }
+
+    // Throws random instructions with random register contents at the decoder and handlers
+    #[test]
+    fn test_fuzz_instructions() {
+        const ILLEGAL_OPCODES: [u8; 11] = [0xd3, 0xdb, 0xdd, 0xe3, 0xe4, 0xeb, 0xec, 0xed, 0xf4, 0xfc, 0xfd];
+        let registers = [
+            Register::A,
+            Register::F,
+            Register::B,
+            Register::C,
+            Register::D,
+            Register::E,
+            Register::H,
+            Register::L,
+        ];
+
+        let mut rng = XorShift::new(0x5eed);
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0xffff])), Mode::Dmg);
+        mmu.unmap_bootrom();
+        mmu.resize_memory(0x10000);
+        for addr in 0..=0xffff {
+            mmu.write_unchecked(addr, rng.next_u64() as u8);
+        }
+        let mut sm83 = Sm83::new();
+        let mut cpu = Cpu::new();
+
+        for _ in 0..200_000 {
+            for register in &registers {
+                cpu.write_register(register, rng.next_u64() as u8);
+            }
+            // Wrap-around is where handlers tend to trip up, so pointers often sit right at the edges
+            for register in [Register::SP, Register::HL] {
+                let value = match rng.next_u64() % 4 {
+                    0 => [0x0000, 0x0001, 0xfffe, 0xffff][rng.next_u64() as usize % 4],
+                    _ => rng.next_u64() as u16,
+                };
+                cpu.write_register16(&register, value);
+            }
+            let pc = rng.next_u64() as u16 % 0xfff0;
+            cpu.write_register16(&Register::PC, pc);
+            for offset in 0..3 {
+                mmu.write_unchecked(pc + offset, rng.next_u64() as u8);
+            }
+            // Keep interrupts out of it, they move PC on their own
+            mmu.write_unchecked(INTERRUPT_ENABLE_REGISTER, 0);
+            cpu.disable_interrupts();
+            cpu.halted = false;
+
+            let bytes = (0..3)
+                .map(|offset| mmu.read_unchecked(pc + offset))
+                .collect::<Vec<u8>>();
+            let flags = cpu.read_register(&Register::F);
+            let instruction = match sm83.decode(&mmu, pc) {
+                Ok(instruction) => instruction,
+                Err(_) => {
+                    assert!(ILLEGAL_OPCODES.contains(&bytes[0]), "Failed to decode {:02x?}", bytes);
+                    continue;
+                }
+            };
+
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| cpu.tick(&mut mmu)));
+            match result {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) => panic!("{} ({:02x?}) failed: {}", instruction, bytes, e),
+                Err(_) => panic!("{} ({:02x?}) panicked", instruction, bytes),
+            }
+
+            // Anything that doesn't jump moves on to the next instruction
+            let jumps = matches!(
+                instruction.opcode,
+                Opcode::Jp
+                    | Opcode::Jr
+                    | Opcode::Call
+                    | Opcode::Ret
+                    | Opcode::Reti
+                    | Opcode::Rst
+                    | Opcode::Halt
+                    | Opcode::Stop
+            );
+            if !jumps {
+                assert_eq!(
+                    cpu.read_register16(&Register::PC),
+                    pc.wrapping_add(instruction.length as u16),
+                    "{} ({:02x?}) did not advance PC",
+                    instruction,
+                    bytes
+                );
+            }
+
+            // Loads (except LD HL, SP+e8), stack pushes, jumps and bit set/reset leave the flags alone
+            let keeps_flags = matches!(
+                instruction.opcode,
+                Opcode::Ld
+                    | Opcode::Ldh
+                    | Opcode::Push
+                    | Opcode::Jp
+                    | Opcode::Jr
+                    | Opcode::Call
+                    | Opcode::Ret
+                    | Opcode::Reti
+                    | Opcode::Rst
+                    | Opcode::Nop
+                    | Opcode::Di
+                    | Opcode::Ei
+                    | Opcode::Set
+                    | Opcode::Res
+            ) && bytes[0] != 0xf8;
+            if keeps_flags {
+                assert_eq!(
+                    cpu.read_register(&Register::F),
+                    flags,
+                    "{} ({:02x?}) changed the flags",
+                    instruction,
+                    bytes
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tilemap_index_bounds() {
+        for y in 0..512 {
+            for x in 0..512 {
+                assert!(
+                    (tilemap_index(x, y) as usize) < BACKGROUND_MAP_SIZE,
+                    "Tilemap index out of bounds for ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tilemap_index_wraps_at_256() {
+        for y in 0..256 {
+            for x in 0..256 {
+                let index = tilemap_index(x, y);
+                assert_eq!(index, tilemap_index(x + 256, y), "X did not wrap for ({}, {})", x, y);
+                assert_eq!(index, tilemap_index(x, y + 256), "Y did not wrap for ({}, {})", x, y);
+                assert_eq!(
+                    index,
+                    tilemap_index(x + 256, y + 256),
+                    "XY did not wrap for ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tilemap_index_scroll() {
+        // Every visible pixel for every scroll value has to land on the same tile as with 8-bit wrapping math
+        for scroll in 0..=255u8 {
+            for x in 0..SCREEN_WIDTH {
+                let expected = ((x as u8).wrapping_add(scroll) / 8) as u16;
+                assert_eq!(
+                    tilemap_index(x + scroll as usize, 0),
+                    expected,
+                    "SCX={} X={}",
+                    scroll,
+                    x
+                );
+            }
+
+            for y in 0..SCREEN_HEIGHT {
+                let expected = ((y as u8).wrapping_add(scroll) / 8) as u16 * 32;
+                assert_eq!(
+                    tilemap_index(0, y + scroll as usize),
+                    expected,
+                    "SCY={} Y={}",
+                    scroll,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_tilemap_index_window() {
+        // WX=0xa6 only leaves the very last column on screen, which shows the first window tile
+        let wx = 0xa6;
+        assert_eq!(tilemap_index(SCREEN_WIDTH - 1 + 7 - wx, 0), 0);
+
+        // WX=7 lines the window up with the screen, so the window spans the first 20 columns
+        let wx = 7;
+        for x in 0..SCREEN_WIDTH {
+            assert_eq!(tilemap_index(x + 7 - wx, 0), (x / 8) as u16);
+        }
+
+        // The internal line counter never leaves the first 18 rows
+        for line in 0..SCREEN_HEIGHT {
+            assert_eq!(tilemap_index(0, line), (line / 8) as u16 * 32);
+        }
+    }
+
+    #[test]
+    fn test_window_edge_cases() {
+        let mut gb = GameBoy::new(None, vec![0u8; 0x8000]);
+        // LCD, window with the $9c00 map, tile data at $8000 and the background on
+        gb.mmu.write_unchecked(LCD_CONTROL_REGISTER, 0xf1);
+        gb.mmu.write_unchecked(WINDOW_Y_REGISTER, 10);
+        gb.mmu.write_unchecked(WINDOW_X_REGISTER, 7);
+
+        let mut lines = Vec::new();
+        for scanline in 0..SCREEN_HEIGHT as u8 {
+            match scanline {
+                // Moving WY past LY doesn't trigger the window again or reset its counter
+                20 => gb.mmu.write_unchecked(WINDOW_Y_REGISTER, 30),
+                // Lines without the window don't count
+                50 => gb.mmu.write_unchecked(LCD_CONTROL_REGISTER, 0xd1),
+                60 => gb.mmu.write_unchecked(LCD_CONTROL_REGISTER, 0xf1),
+                _ => {}
+            }
+            gb.ppu.handle_window_line_counter(&LatchedLine::new(&gb.mmu, scanline));
+            lines.push(gb.ppu.window_line().map(|line| line.line));
+        }
+        assert!(lines[..10].iter().all(Option::is_none));
+        assert_eq!(lines[10], Some(0));
+        assert_eq!(lines[30], Some(20));
+        assert!(lines[50..60].iter().all(Option::is_none));
+        assert_eq!(lines[60], Some(40));
+        assert_eq!(lines[143], Some(123));
+
+        // WY moving above LY before the lines meet leaves the window off for the whole frame
+        gb.mmu.write_unchecked(WINDOW_Y_REGISTER, 5);
+        for scanline in 0..SCREEN_HEIGHT as u8 {
+            if scanline == 3 {
+                gb.mmu.write_unchecked(WINDOW_Y_REGISTER, 2);
+            }
+            gb.ppu.handle_window_line_counter(&LatchedLine::new(&gb.mmu, scanline));
+            assert_eq!(gb.ppu.window_line(), None, "LY={}", scanline);
+        }
+
+        // A black window over a white background: WX=166 shows the last column, then all of the next line
+        for addr in 0x8010..0x8020 {
+            gb.mmu.write_unchecked(addr, 0xff);
+        }
+        for addr in 0x9c00..0xa000 {
+            gb.mmu.write_unchecked(addr, 0x01);
+        }
+        gb.mmu.write_unchecked(0xff47, 0xe4);
+        gb.mmu.write_unchecked(WINDOW_Y_REGISTER, 0);
+        gb.ppu.update_tile_cache(&mut gb.mmu);
+        for (scanline, wx) in [(0, 166), (1, 200), (2, 200), (3, 3)] {
+            gb.mmu.write_unchecked(WINDOW_X_REGISTER, wx);
+            let line = LatchedLine::new(&gb.mmu, scanline);
+            gb.ppu.handle_window_line_counter(&line);
+            gb.ppu.render_scanline(&line);
+        }
+        let black = gb
+            .framebuffer()
+            .chunks(SCREEN_WIDTH)
+            .take(4)
+            .map(|line| line.iter().filter(|color| **color == [0, 0, 0]).count())
+            .collect::<Vec<usize>>();
+        assert_eq!(black, vec![1, SCREEN_WIDTH, 0, SCREEN_WIDTH]);
+        assert_eq!(
+            gb.ppu.window_line(),
+            Some(WindowLine {
+                line: 2,
+                full_width: false
+            })
+        );
+    }
+
+    // Runs a test ROM below external/roms/tests the way the test-suite command does, see frontend::test_suite
+    fn run_test_rom(rom: &str) -> Outcome {
+        let root = std::path::Path::new("./external/roms/tests");
+        run_test(root, &root.join(rom)).outcome
+    }
+
+    // Panics on lines the assembler doesn't take, naming the line
+    fn assemble(program: &[&str]) -> Vec<u8> {
+        let assembler = Assembler::new();
+        program
+            .iter()
+            .flat_map(|line| assembler.assemble(line).unwrap_or_else(|e| panic!("{}: {}", line, e)))
+            .collect()
+    }
+
+    // A boot ROM that does nothing but unmap itself, the cartridge takes over at $0004
+    fn skip_bootrom() -> Vec<u8> {
+        let mut bootrom = vec![0u8; 0x100];
+        let code = assemble(&["ld a, $11", "ldh ($50), a"]);
+        bootrom[..code.len()].copy_from_slice(&code);
+        bootrom
+    }
+
+    // The boot ROM above, and a cartridge that runs the program from there on
+    fn rom_with_code(program: &[&str]) -> (Vec<u8>, Vec<u8>) {
+        let mut rom = vec![0u8; 0x8000];
+        let code = assemble(program);
+        rom[4..4 + code.len()].copy_from_slice(&code);
+        (skip_bootrom(), rom)
+    }
+
+    #[test]
+    fn test_dmg_acid2() {
+        assert_eq!(run_test_rom("dmg-acid2.gb"), Outcome::Passed);
+    }
+
+    #[test]
+    fn test_cgb_acid2() {
+        assert_eq!(run_test_rom("cgb-acid2.gbc"), Outcome::Passed);
+    }
+
+    #[test]
+    fn test_color_correction() {
+        // Raw keeps the plain scaling the golden images were made with
+        assert_eq!(
+            Palette::rgb555_to_rgb888(0x7fff, ColorCorrection::Raw),
+            [0xf8, 0xf8, 0xf8]
+        );
+        assert_eq!(
+            Palette::rgb555_to_rgb888(0x001f, ColorCorrection::Raw),
+            [0xf8, 0x00, 0x00]
+        );
+
+        // The CGB's LCD mixes some red into blue and doesn't reach full white
+        assert_eq!(Palette::rgb555_to_rgb888(0x001f, ColorCorrection::Cgb), [201, 0, 46]);
+        assert_eq!(Palette::rgb555_to_rgb888(0x7fff, ColorCorrection::Cgb), [240, 240, 240]);
+
+        // The GBA's gamma darkens the midtones
+        let gray = Palette::rgb555_to_rgb888(0x4210, ColorCorrection::Gba);
+        assert!(gray.iter().all(|channel| *channel < 0x60), "{:?}", gray);
+        assert_eq!(Palette::rgb555_to_rgb888(0x0000, ColorCorrection::Gba), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_frame_events() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Dmg);
+        mmu.unmap_bootrom();
+        mmu.write_unchecked(LCD_CONTROL_REGISTER, 0x91);
+        let mut ppu = Ppu::new(Mode::Dmg);
+
+        // Mode 0 starts once per visible line, the modes don't end the frame
+        assert_eq!(ppu.tick_state(&mut mmu, 80), FrameEvents::default());
+        assert_eq!(ppu.tick_state(&mut mmu, DRAWING_CYCLES).hblank_count, 1);
+        assert_eq!(ppu.tick_state(&mut mmu, 204), FrameEvents::default());
+        assert_eq!(ppu.tick(&mut mmu), FrameEvents::default());
+
+        // The last visible line leads into VBlank
+        mmu.write_unchecked(SCANLINE_Y_REGISTER, 143);
+        ppu.tick(&mut mmu);
+        let mut events = ppu.tick_state(&mut mmu, 80);
+        events += ppu.tick_state(&mut mmu, DRAWING_CYCLES);
+        events += ppu.tick_state(&mut mmu, 204);
+        assert!(events.entered_vblank && !events.frame_complete);
+        assert_eq!(events.hblank_count, 1);
+
+        // ... and the frame is done once LY wraps around
+        mmu.write_unchecked(SCANLINE_Y_REGISTER, 153);
+        assert!(ppu.tick(&mut mmu).frame_complete);
+    }
+
+    #[test]
+    fn test_priority_resolver() {
+        let sprite = |x, attributes| Sprite {
+            x,
+            y: 16,
+            tile_index: 0,
+            attributes,
+        };
+        let behind = sprite(8, SpriteAttributes::PRIORITY);
+        let above = sprite(8, SpriteAttributes::empty());
+        let lcdc = LcdControl::LCD_DISPLAY | LcdControl::BG_AND_WIN_DISPLAY;
+        let master_off = LcdControl::LCD_DISPLAY;
+        let none = TileAttributes::empty();
+
+        // LCDC bit 0 hides the background on DMG, on CGB it only takes away its priority
+        assert!(!DmgPriority.background_enabled(&master_off));
+        assert!(CgbPriority.background_enabled(&master_off));
+        assert!(CgbPriority.sprite_on_top(
+            &master_off,
+            &behind,
+            &Palette::Color(2, 0, 0, 0),
+            &TileAttributes::PRIORITY
+        ));
+
+        // Color 0 never covers a sprite, other colors do when the sprite is set to go behind them
+        for resolver in [&DmgPriority as &dyn PriorityResolver, &CgbPriority] {
+            assert!(resolver.sprite_on_top(&lcdc, &behind, &Palette::White(0), &none));
+            assert!(!resolver.sprite_on_top(&lcdc, &behind, &Palette::Black(3), &none));
+            assert!(resolver.sprite_on_top(&lcdc, &above, &Palette::Black(3), &none));
+        }
+        // Only the CGB has a priority bit per background tile
+        assert!(!CgbPriority.sprite_on_top(&lcdc, &above, &Palette::Color(1, 0, 0, 0), &TileAttributes::PRIORITY));
+        assert!(DmgPriority.sprite_on_top(&lcdc, &above, &Palette::Black(1), &TileAttributes::PRIORITY));
+
+        // DMG draws the leftmost sprite on top with ties going by OAM order, CGB only goes by OAM order
+        let oams = [(30, 0), (10, 1), (20, 2), (10, 3)].map(|(x, tile_index)| {
+            Some(Oam {
+                sprite: Sprite {
+                    tile_index,
+                    ..sprite(x, SpriteAttributes::empty())
+                },
+                tile1: Tile::default(),
+                tile2: None,
+            })
+        });
+        let order = |resolver: &dyn PriorityResolver| {
+            let mut oams = oams.clone();
+            resolver.order_sprites(&mut oams);
+            oams.map(|oam| oam.unwrap().sprite.tile_index)
+        };
+        assert_eq!(order(priority_resolver(&Mode::Dmg)), [1, 3, 2, 0]);
+        assert_eq!(order(priority_resolver(&Mode::Cgb)), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_compat_palette() {
+        // Right on the CGB boot screen, the same colors for the background and both object palettes
+        let mut gb = GameBoy::new(None, std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap());
+        gb.set_compat_palette(CompatPalette::Green);
+        // Frames are cut short while the LCD is off, so go by emulated time instead
+        while gb.emulated_time().as_secs() < 2 {
+            System::run_frame(&mut gb);
+        }
+        let green = [
+            [0xf8, 0xf8, 0xf8],
+            [0x50, 0xf8, 0x00],
+            [0xf8, 0x40, 0x00],
+            [0x00, 0x00, 0x00],
+        ];
+        let frame = gb.framebuffer();
+        assert!(frame.iter().all(|color| green.contains(color)));
+        assert!(frame.contains(&green[1]) && frame.contains(&green[2]));
+
+        // They are in palette RAM where the boot ROM leaves them, and survive a reset
+        gb.set_compat_palette(CompatPalette::Red);
+        gb.reset();
+        assert_eq!(gb.mmu.cgb_cram.compat_palette(), CompatPalette::Red);
+        assert_eq!(gb.mmu.cgb_cram.fetch_bg(0, 2), 0x421f);
+        assert_eq!(gb.mmu.cgb_cram.fetch_obj(1, 4), 0x7c00);
+
+        // CGB games have their own colors
+        let mut gb = GameBoy::new(None, std::fs::read("./external/roms/tests/cgb-acid2.gbc").unwrap());
+        gb.set_compat_palette(CompatPalette::Green);
+        assert_eq!(gb.mmu.cgb_cram.compat_palette(), CompatPalette::Off);
+    }
+
+    #[test]
+    fn test_mooneye_halt_ime0() {
+        // halt_ime0_nointr_timing needs interrupts raised with cycle accuracy, which the PPU doesn't do yet
+        assert_eq!(run_test_rom("mooneye-acceptance/halt_ime0_ei.gb"), Outcome::Passed);
+    }
+
+    #[test]
+    fn test_mooneye_ei_di() {
+        assert_eq!(run_test_rom("mooneye-acceptance/ei_sequence.gb"), Outcome::Passed);
+        assert_eq!(run_test_rom("mooneye-acceptance/ei_timing.gb"), Outcome::Passed);
+        assert_eq!(run_test_rom("mooneye-acceptance/rapid_di_ei.gb"), Outcome::Passed);
+    }
+
+    #[test]
+    fn test_test_suite() {
+        let root = std::path::Path::new("./external/roms/tests");
+        let mooneye = run_test(root, &root.join("mooneye-acceptance/ei_sequence.gb"));
+        assert_eq!(mooneye.suite, Suite::Mooneye);
+        assert_eq!(mooneye.name, "mooneye-acceptance/ei_sequence.gb");
+
+        // acid2 has a golden screenshot to compare against
+        let acid2 = run_test(root, &root.join("dmg-acid2.gb"));
+        assert_eq!(acid2.suite, Suite::Screen);
+
+        assert_eq!(screen_hash(&[]), "da39a3ee");
+    }
+
+    #[test]
+    fn test_frame_rng() {
+        let rng = FrameRng::new(42);
+        let numbers = |rng: &FrameRng, frame| rng.frame(frame).next_u64();
+
+        // Keyed to the frame, not to how many numbers were taken before
+        assert_eq!(numbers(&rng, 100), numbers(&FrameRng::new(42), 100));
+        assert_ne!(numbers(&rng, 100), numbers(&rng, 101));
+        assert_ne!(numbers(&rng, 100), numbers(&FrameRng::new(43), 100));
+
+        let opposite = [
+            Button::Up.mask() | Button::Down.mask(),
+            Button::Left.mask() | Button::Right.mask(),
+        ];
+        let inputs = (0..1000).map(|frame| rng.buttons(frame)).collect::<Vec<_>>();
+        assert!(inputs
+            .iter()
+            .all(|input| opposite.iter().all(|both| input & both != *both)));
+        assert!(Button::ALL
+            .iter()
+            .all(|button| inputs.iter().any(|input| input & button.mask() != 0)));
+
+        // Mashing with the same seed plays out the same
+        let rom = std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap();
+        let mash = |seed| {
+            let mut gb = GameBoy::new(None, rom.clone());
+            let rng = FrameRng::new(seed);
+            for frame in 0..30 {
+                gb.update_buttons(rng.buttons(frame));
+                gb.run_frame();
+            }
+            (gb.cycle_count(), gb.mmu.dump_wram())
+        };
+        assert_eq!(mash(7), mash(7));
+    }
+
+    #[test]
+    fn test_monkey() {
+        // Runs into an opcode that can't be decoded as soon as A, B, Select or Start is pressed
+        let (bootrom, mut rom) = rom_with_code(&[
+            "ld a, $10",
+            "ldh ($00), a",
+            "ldh a, ($00)",
+            "and a, $0f",
+            "cp a, $0f",
+            "jr z, -8",
+            "jp $0100",
+        ]);
+        rom[0x0100] = 0xd3;
+
+        let mut gb = GameBoy::new(Some(bootrom.clone()), rom.clone());
+        let report = monkey::run(&mut gb, 10, &mut |_| Button::Up.mask());
+        assert_eq!(report.failure, None);
+        assert_eq!(report.inputs, vec![Button::Up.mask(); 10]);
+
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        let report = monkey::run(&mut gb, 10, &mut |frame| if frame == 3 { Button::A.mask() } else { 0 });
+        assert_eq!(report.failure.map(|failure| failure.frame), Some(3));
+        assert_eq!(report.inputs, vec![0, 0, 0, Button::A.mask()]);
+
+        // The inputs come back from a movie as they went in
+        let path = std::env::temp_dir().join("ayyboy-monkey.txt");
+        monkey::save_movie(&path, "test\nmovie", &report.inputs).unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("# test\n# movie\n"));
+        assert_eq!(monkey::load_movie(&path).unwrap(), report.inputs);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_frame_trace() {
+        // Steps BGP every VBlank, so the (blank) screen changes shade every frame
+        let (bootrom, rom) = rom_with_code(&[
+            "ld a, $91",
+            "ldh ($40), a",
+            "ldh a, ($44)",
+            "cp a, $90",
+            "jr nz, -6",
+            "ldh a, ($47)",
+            "inc a",
+            "ldh ($47), a",
+            "ldh a, ($44)",
+            "cp a, $90",
+            "jr z, -6",
+            "jr -19",
+        ]);
+        let this_dir = std::env::temp_dir().join("ayyboy-trace-this");
+        let other_dir = std::env::temp_dir().join("ayyboy-trace-other");
+
+        // The same ROM traces the same every time
+        let gb = GameBoy::new(Some(bootrom), rom);
+        let this = frame_trace::record(&mut gb.clone(), 60, &this_dir, None).unwrap();
+        let other = frame_trace::record(&mut gb.clone(), 60, &other_dir, None).unwrap();
+        assert_eq!(this.len(), 60);
+        assert_eq!(frame_trace::load_hashes(&other_dir).unwrap(), other);
+        assert_eq!(frame_trace::first_divergence(&this, &other), None);
+        assert_eq!(frame_trace::first_divergence(&this, &other[..20]), Some(20));
+
+        // Pretend the other build drew the very first frame again once the shade changed
+        let frame = this.iter().position(|hash| *hash != this[0]).unwrap();
+        let mut diverged = other.clone();
+        diverged[frame..].fill(other[0].clone());
+        assert_eq!(frame_trace::first_divergence(&this, &diverged), Some(frame));
+        let dumped = frame_trace::dump_divergence(&this_dir, &other_dir, frame, &this, &diverged).unwrap();
+        assert_eq!(dumped.len(), 2);
+        assert!(dumped.iter().all(|path| path.exists()));
+
+        std::fs::remove_dir_all(&this_dir).unwrap();
+        std::fs::remove_dir_all(&other_dir).unwrap();
+    }
+
+    #[test]
+    fn test_library() {
+        let library_path = std::env::temp_dir().join("ayyboy-library.txt");
+        let _ = std::fs::remove_file(&library_path);
+
+        // Most recent first, playing a game again moves it back to the top
+        for game in ["a.gb", "b.gb", "a.gb", "c.gb"] {
+            library::record_played(&library_path, game, game).unwrap();
+        }
+        let paths = |games: Vec<(String, String)>| games.into_iter().map(|(path, _)| path).collect::<Vec<_>>();
+        assert_eq!(
+            paths(library::recent_games(&library_path)),
+            vec!["c.gb", "a.gb", "b.gb"]
+        );
+
+        // Zipped games are opened from the zip, their files are named after the ROM inside
+        library::record_played(&library_path, "d.zip", "d.gb").unwrap();
+        assert_eq!(
+            library::recent_games(&library_path)[0],
+            (String::from("d.zip"), String::from("d.gb"))
+        );
+        for game in 0..30 {
+            let game = format!("{}.gb", game);
+            library::record_played(&library_path, &game, &game).unwrap();
+        }
+        assert_eq!(library::recent_games(&library_path).len(), 20);
+        std::fs::remove_file(&library_path).unwrap();
+
+        assert_eq!(library::format_play_time(Duration::from_secs(750)), "12m 30s");
+        assert_eq!(library::format_play_time(Duration::from_secs(7500)), "2h 05m");
+        assert_eq!(library::format_last_played(0, 1000), "never");
+        assert_eq!(library::format_last_played(1, 1 + 3 * 86400), "3 days ago");
+
+        // The last frame comes back as it was saved
+        let frame = (0..SCREEN_WIDTH * SCREEN_HEIGHT)
+            .map(|idx| [idx as u8, (idx >> 8) as u8, 0x55])
+            .collect::<Vec<Color>>();
+        let path = std::env::temp_dir().join("ayyboy-screenshot.png");
+        save_png(&path, SCREEN_WIDTH, &frame).unwrap();
+        let (width, height, pixels) = load_png(&path).unwrap();
+        assert_eq!((width, height), (SCREEN_WIDTH, SCREEN_HEIGHT));
+        assert_eq!(pixels, frame.as_flattened());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_log_rotation() {
+        let path = std::env::temp_dir().join("ayyboy-rotation.log");
+        let read = |index: usize| {
+            let path = match index {
+                0 => path.clone(),
+                _ => RotatingFile::rotated_path(&path, index),
+            };
+            std::fs::read_to_string(path).unwrap_or_default()
+        };
+
+        // The last run's log is the first to be rotated, the oldest falls off once there are too many
+        std::fs::write(&path, "last run\n").unwrap();
+        let mut file = RotatingFile::create(&path, 8, 2).unwrap();
+        for line in ["aaa", "bbb", "ccc", "dddddddddd", "eee"] {
+            file.write_line(line).unwrap();
+        }
+        assert_eq!([read(0), read(1), read(2)], ["eee\n", "dddddddddd\n", "ccc\n"]);
+        assert!(!RotatingFile::rotated_path(&path, 3).exists());
+
+        // In a ring only the lines leading up to an error make it to the file
+        let mut ring = LogRing::new(8);
+        ["aaa", "bbb", "ccc"]
+            .iter()
+            .for_each(|line| ring.push(line.to_string()));
+        assert_eq!(ring.drain().collect::<Vec<_>>(), ["bbb", "ccc"]);
+        let config = LogConfig {
+            max_size: 0,
+            keep: 0,
+            ring: Some(8),
+        };
+        let mut log = LogFile::create(&path, &config).unwrap();
+        for line in ["aaa", "bbb", "ccc"] {
+            log.log(Level::Trace, line.to_owned()).unwrap();
+        }
+        assert_eq!(read(0), "");
+        log.log(Level::Error, String::from("err")).unwrap();
+        log.log(Level::Trace, String::from("ddd")).unwrap();
+        assert_eq!(read(0), "ccc\nerr\n");
+
+        for index in 0..=2 {
+            let _ = std::fs::remove_file(match index {
+                0 => path.clone(),
+                _ => RotatingFile::rotated_path(&path, index),
+            });
+        }
+    }
+
+    #[test]
+    fn test_frontend_state() {
+        let paused = FrontendState::Paused;
+        let running = paused.next(StateEvent::TogglePause).unwrap();
+        assert_eq!(running, FrontendState::Running);
+
+        // Fast forward only works while running, and pausing or a breakpoint ends it
+        assert_eq!(paused.next(StateEvent::FastForward), None);
+        let fast = running.next(StateEvent::FastForward).unwrap();
+        assert_eq!(fast.speed(), FAST_FORWARD_SPEED);
+        assert_eq!(fast.next(StateEvent::FastForward), None);
+        assert_eq!(fast.next(StateEvent::NormalSpeed), Some(running));
+        assert_eq!(fast.next(StateEvent::Breakpoint), Some(paused));
+        assert_eq!(running.next(StateEvent::NormalSpeed), None);
+        assert_eq!(paused.next(StateEvent::Breakpoint), None);
+        assert_eq!(
+            fast.next(StateEvent::TogglePause)
+                .unwrap()
+                .next(StateEvent::TogglePause),
+            Some(running)
+        );
+
+        assert_eq!(running.title("TETRIS", false), "ayyboyy - TETRIS");
+        assert_eq!(paused.title("TETRIS", true), "ayyboyy - TETRIS (paused) [state saved]");
+        assert_eq!(fast.title("TETRIS", false), "ayyboyy - TETRIS (fast forward)");
+
+        // Fast forward runs frames in a quarter of the time, and leaves states alone until it's over
+        let frame = Duration::from_millis(16);
+        assert_eq!(running.frame_duration(frame), frame);
+        assert_eq!(fast.frame_duration(frame), Duration::from_millis(4));
+        assert!(paused.accepts(Hotkey::LoadState) && running.accepts(Hotkey::SaveState));
+        assert!(!fast.accepts(Hotkey::SaveState) && !fast.accepts(Hotkey::LoadState));
+        assert!(fast.accepts(Hotkey::SaveCartridge));
+    }
+
+    #[test]
+    fn test_background_state() {
+        let running = FrontendState::Running;
+        let paused = FrontendState::Paused;
+        let frame = Duration::from_millis(16);
+
+        // Continue keeps running, the others go quiet until a window has focus again
+        assert_eq!(running.next(StateEvent::Background(BackgroundPolicy::Continue)), None);
+        let throttled = running
+            .next(StateEvent::Background(BackgroundPolicy::Throttle))
+            .unwrap();
+        assert!(throttled.is_running() && throttled.is_muted());
+        assert_eq!(throttled.frame_duration(frame), frame * BACKGROUND_SLOWDOWN);
+        let stopped = FrontendState::FastForward
+            .next(StateEvent::Background(BackgroundPolicy::Pause))
+            .unwrap();
+        assert!(!stopped.is_running() && stopped.is_muted());
+        assert_eq!(throttled.next(StateEvent::Foreground), Some(running));
+        assert_eq!(stopped.next(StateEvent::Foreground), Some(running));
+        assert_eq!(running.next(StateEvent::Foreground), None);
+
+        // Paused stays paused either way, and a breakpoint in the background still pauses
+        assert_eq!(paused.next(StateEvent::Background(BackgroundPolicy::Throttle)), None);
+        assert_eq!(paused.next(StateEvent::Foreground), None);
+        assert_eq!(throttled.next(StateEvent::Breakpoint), Some(paused));
+        assert_eq!(throttled.next(StateEvent::FastForward), None);
+        assert!(!running.is_muted() && !paused.is_muted());
+    }
+
+    #[test]
+    fn test_state_diff() {
+        let mut gb = GameBoy::new(None, std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap());
+        for _ in 0..30 {
+            gb.run_frame();
+        }
+        let snapshot = gb.clone();
+        assert_eq!(state_diff::diff_states(&snapshot, &gb), vec![]);
+
+        gb.cpu.write_register16(&Register::BC, 0x1234);
+        gb.mmu.write_unchecked(0xc010, 0x01);
+        gb.mmu.write_unchecked(0xc012, 0x02);
+        gb.mmu.write_unchecked(0xc100, 0x03);
+        gb.mmu.write_unchecked(0xff90, 0x04);
+        let changes = state_diff::diff_states(&snapshot, &gb);
+        let locations = changes
+            .iter()
+            .map(|change| (change.subsystem, change.location.as_str()))
+            .collect::<Vec<_>>();
+        // Nearby bytes are one range, far apart ones are not, and changes come grouped by subsystem
+        assert_eq!(
+            locations,
+            vec![
+                (Subsystem::Cpu, "BC"),
+                (Subsystem::Wram, "Bank 0 $c010-$c012"),
+                (Subsystem::Wram, "Bank 0 $c100"),
+                (Subsystem::Hram, "HRAM $ff90"),
+            ]
+        );
+        assert_eq!(changes[0].after, "1234");
+        assert_eq!(changes[1].after, format!("01 {:02x} 02", gb.mmu.peek(0xc011).unwrap()));
+
+        // Longer ranges only show their first bytes
+        let snapshot = gb.clone();
+        for addr in 0xc200..0xc210 {
+            gb.mmu.write_unchecked(addr, snapshot.mmu.peek(addr).unwrap() ^ 0xff);
+        }
+        let changes = state_diff::diff_states(&snapshot, &gb);
+        assert_eq!(changes.len(), 1);
+        assert!(changes[0].before.ends_with("(16 bytes)"));
+    }
+
+    #[test]
+    fn test_vram_export() {
+        let mut gb = GameBoy::new(None, std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap());
+        for _ in 0..30 {
+            gb.run_frame();
+        }
+
+        let tiles = vram_export::vram_tiles(&gb, 0);
+        assert_eq!(tiles.len(), 384 * 16);
+        assert_eq!(tiles[..], gb.mmu.vram(0)[..384 * 16]);
+
+        // BGP, OBP0 and OBP1 on a DMG, padded to the 256 colors of a .pal
+        gb.mmu.write_unchecked(BG_PALETTE_REGISTER, 0xe4);
+        gb.mmu.write_unchecked(OBJ1_PALETTE_REGISTER, 0x1b);
+        let palettes = vram_export::palettes(&gb);
+        assert_eq!(palettes.len(), 3);
+        assert_eq!(
+            palettes[0],
+            [
+                [0xff; 3],
+                Palette::LightGray(1).into(),
+                Palette::DarkGray(2).into(),
+                [0; 3]
+            ]
+        );
+        assert_eq!(palettes[2][0], [0; 3]);
+        let pal = vram_export::to_pal(&palettes);
+        assert_eq!(pal.len(), 256 * 3);
+        assert_eq!(pal[..3], [0xff; 3]);
+
+        // The background map is 32x32 tiles
+        let path = std::env::temp_dir().join("ayyboy-tilemap.png");
+        vram_export::save_tiles_png(&gb.dbg_render_background_tilemap(), 32, &path).unwrap();
+        let decoder = png::Decoder::new(std::fs::File::open(&path).unwrap());
+        let info = decoder.read_info().unwrap();
+        assert_eq!((info.info().width, info.info().height), (256, 256));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_layer_toggles() {
+        let mut gb = GameBoy::new(None, std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap());
+        // Frames are cut short while the LCD is off, so go by emulated time instead
+        while gb.emulated_time().as_secs() < 2 {
+            gb.run_frame();
+        }
+
+        let mut visible = gb.clone();
+        let mut hidden = gb.clone();
+        System::set_layers(
+            &mut hidden,
+            Layers {
+                sprites: false,
+                ..Layers::default()
+            },
+        );
+        visible.run_frame();
+        hidden.run_frame();
+        assert_ne!(visible.framebuffer(), hidden.framebuffer());
+
+        // Only the picture changes, the game runs the same
+        assert_eq!(
+            visible.cpu.read_register16(&Register::PC),
+            hidden.cpu.read_register16(&Register::PC)
+        );
+        assert_eq!(visible.cpu.elapsed_cycles(), hidden.cpu.elapsed_cycles());
+
+        // Nothing left to show
+        System::set_layers(
+            &mut hidden,
+            Layers {
+                background: false,
+                window: false,
+                sprites: false,
+            },
+        );
+        hidden.run_frame();
+        assert!(hidden.framebuffer().iter().all(|color| *color == [0xff; 3]));
+
+        System::set_layers(&mut hidden, Layers::default());
+        visible.run_frame();
+        hidden.run_frame();
+        assert_eq!(visible.framebuffer(), hidden.framebuffer());
+    }
+
+    #[test]
+    fn test_clock_divider() {
+        let mut clock = ClockDivider::new();
+        let normal = clock.split(8, false);
+        assert_eq!((normal.timer, normal.ppu, normal.apu, normal.cartridge), (8, 8, 8, 8));
+
+        // Double speed halves everything but the timer, odd cycles are carried over
+        let double = clock.split(8, true);
+        assert_eq!((double.timer, double.ppu, double.apu, double.cartridge), (8, 4, 4, 4));
+        assert_eq!(clock.split(3, true).ppu, 1);
+        assert_eq!(clock.split(3, true).ppu, 2);
+        assert_eq!(clock.split(1, true).ppu, 0);
+        assert_eq!(clock.split(4, false).ppu, 5);
+    }
+
+    #[test]
+    fn test_timer_double_speed() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Cgb);
+        mmu.write_unchecked(0xff07, 0b101); // enabled, every 16 cycles
+
+        // The timer counts CPU cycles, which come twice as fast in double speed
+        for double_speed in [false, true] {
+            mmu.cgb_double_speed = double_speed;
+            mmu.write_unchecked(0xff05, 0);
+            let clocks = ClockDivider::new().split(16, double_speed);
+            mmu.tick_timer(clocks.timer);
+            assert_eq!(mmu.read_unchecked(0xff05), 1);
+        }
+    }
+
+    #[test]
+    fn test_timer_falling_edges() {
+        // TIMA counts on the falling edge of the selected counter bit, bit 3 for the 16 cycle period
+        let mut timer = Timer::new();
+        timer.write(0xff07, 0b101);
+        assert!(!timer.tick(15));
+        assert_eq!(timer.read(0xff05), 0);
+        assert!(!timer.tick(1));
+        assert_eq!(timer.read(0xff05), 1);
+        assert_eq!(timer.cycles_until_tick(), Some(16));
+
+        // Batched cycles count every edge in between, and DIV is the upper byte of the same counter
+        timer.tick(0x100 - 16 + 8);
+        assert_eq!((timer.read(0xff04), timer.read(0xff05)), (1, 16));
+        assert_eq!(timer.counter(), 0x108);
+
+        // Resetting DIV while the bit is set is a falling edge as well
+        assert!(!timer.write(0xff04, 0x42));
+        assert_eq!((timer.counter(), timer.read(0xff05)), (0, 17));
+        // Not while it is clear
+        timer.write(0xff04, 0);
+        assert_eq!(timer.read(0xff05), 17);
+
+        // Disabling the timer or switching to a clear bit drops it too
+        timer.tick(8);
+        timer.write(0xff07, 0b001);
+        assert_eq!(timer.read(0xff05), 18);
+        timer.write(0xff07, 0b101);
+        timer.write(0xff07, 0b110); // bit 5 of 8 is clear
+        assert_eq!(timer.read(0xff05), 19);
+        timer.write(0xff07, 0b101);
+        assert_eq!(timer.read(0xff05), 19);
+
+        // Overflows reload from TMA and request the interrupt, even from a DIV reset
+        timer.write(0xff06, 0xab);
+        timer.write(0xff05, 0xff);
+        assert!(timer.tick(16));
+        assert_eq!(timer.read(0xff05), 0xab);
+        timer.write(0xff05, 0xff);
+        assert!(timer.reset_divider());
+        assert_eq!(timer.read(0xff05), 0xab);
+
+        // Stopped, nothing counts and nothing is scheduled
+        timer.write(0xff07, 0b001);
+        timer.tick(0x1000);
+        assert_eq!(timer.read(0xff05), 0xab);
+        assert_eq!(timer.cycles_until_tick(), None);
+    }
+
+    #[test]
+    fn test_stat_write_glitch() {
+        let stat_requested = |mmu: &mut Mmu| {
+            let requested = mmu.read_unchecked(INTERRUPT_FLAGS_REGISTER) & 0b10 != 0;
+            mmu.write_unchecked(INTERRUPT_FLAGS_REGISTER, 0);
+            requested
+        };
+
+        for mode in [Mode::Dmg, Mode::Cgb] {
+            let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), mode.clone());
+            mmu.write_unchecked(LCD_CONTROL_REGISTER, 0x80);
+            mmu.write_unchecked(SCANLINE_Y_COMPARE_REGISTER, 5);
+
+            // Any write in HBlank/VBlank raises STAT on DMG, even with every source disabled
+            mmu.cache_ppu_state(State::Drawing);
+            mmu.write_unchecked(LCD_STATUS_REGISTER, 0x00);
+            assert!(!stat_requested(&mut mmu));
+            mmu.cache_ppu_state(State::HBlank);
+            mmu.write_unchecked(LCD_STATUS_REGISTER, 0x00);
+            assert_eq!(stat_requested(&mut mmu), mode == Mode::Dmg);
+
+            // Not if the line was already high from an enabled source
+            mmu.write_unchecked(LCD_STATUS_REGISTER, 0x08);
+            stat_requested(&mut mmu);
+            mmu.write_unchecked(LCD_STATUS_REGISTER, 0x08);
+            assert!(!stat_requested(&mut mmu));
+
+            // A matching LYC raises STAT immediately and shows up in bit 2, once
+            mmu.cache_ppu_state(State::Drawing);
+            mmu.write_unchecked(LCD_STATUS_REGISTER, 0x40);
+            assert_eq!(mmu.read_unchecked(LCD_STATUS_REGISTER) & 0b111, 0b011);
+            mmu.write_unchecked(SCANLINE_Y_COMPARE_REGISTER, 0);
+            assert!(stat_requested(&mut mmu));
+            assert_eq!(mmu.read_unchecked(LCD_STATUS_REGISTER) & 0b111, 0b111);
+            mmu.write_unchecked(SCANLINE_Y_COMPARE_REGISTER, 0);
+            assert!(!stat_requested(&mut mmu));
+        }
+    }
+
+    #[test]
+    fn test_io_dispatch() {
+        // CGB registers are plain memory on the DMG
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Dmg);
+        mmu.write_unchecked(0xff4d, 0xff);
+        mmu.write_unchecked(0xff69, 0x42);
+        assert_eq!(mmu.read_unchecked(0xff4d), 0xff);
+        assert_eq!(mmu.read_unchecked(0xff69), 0x42);
+
+        // On the CGB KEY1 only holds the current speed and the queued switch
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Cgb);
+        mmu.write_unchecked(0xff4d, 0xff);
+        assert_eq!(mmu.read_unchecked(0xff4d), 0x7f);
+
+        // STAT writes only reach the interrupt sources, the PPU owns the rest
+        mmu.write_unchecked(LCD_CONTROL_REGISTER, 0x80);
+        mmu.write_unchecked(SCANLINE_Y_COMPARE_REGISTER, 5);
+        mmu.cache_ppu_state(State::Drawing);
+        mmu.write_unchecked(LCD_STATUS_REGISTER, 0xff);
+        assert_eq!(mmu.read_unchecked(LCD_STATUS_REGISTER), 0b1111_1011);
+
+        // SB lives in the serial port
+        mmu.write_unchecked(SERIAL_DATA_REGISTER, 0x5a);
+        assert_eq!(mmu.read_unchecked(SERIAL_DATA_REGISTER), 0x5a);
+    }
+
+    #[test]
+    fn test_initial_divider() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Dmg);
+
+        // DIV is the upper byte, the lower one decides when it counts up next
+        for (counter, div) in [(0x12f8, 0x12), (0x12fc, 0x13)] {
+            mmu.timer.set_counter(counter);
+            assert_eq!(mmu.read_unchecked(0xff04), 0x12);
+            mmu.tick_timer(4); // NOP
+            assert_eq!(mmu.read_unchecked(0xff04), div);
+        }
+
+        // Writes clear it, whatever the value
+        mmu.write(0xff04, 0x77).unwrap();
+        assert_eq!((mmu.read_unchecked(0xff04), mmu.timer.counter()), (0, 0));
+    }
+
+    #[test]
+    fn test_error_diagnostics() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0xffff])), Mode::Dmg);
+        mmu.unmap_bootrom();
+        mmu.resize_memory(0xffff * 4);
+        let mut cpu = Cpu::new();
+
+        cpu.write_register16(&Register::PC, 0xc000);
+        mmu.write_unchecked(0xc000, 0x00); // NOP
+        mmu.write_unchecked(0xc001, 0x3c); // INC A
+        mmu.write_unchecked(0xc002, 0xd3); // illegal
+
+        cpu.tick(&mut mmu).unwrap();
+        cpu.tick(&mut mmu).unwrap();
+        let Err(AyyError::Diagnosed { error, diagnostics }) = cpu.tick(&mut mmu) else {
+            panic!("Illegal opcode executed without diagnostics");
+        };
+
+        assert!(matches!(*error, AyyError::IllegalOpcode { opcode: 0xd3 }));
+        assert_eq!(diagnostics.pc, BankedAddress::new(0, 0xc002));
+        assert_eq!(diagnostics.history, ["00:c000  nop", "00:c001  inc a"]);
+    }
+
+    #[test]
+    fn test_lenient_opcodes() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0xffff])), Mode::Dmg);
+        mmu.unmap_bootrom();
+        mmu.resize_memory(0xffff * 4);
+        let mut cpu = Cpu::new();
+        cpu.set_lenient(true);
+
+        cpu.write_register16(&Register::PC, 0xc000);
+        mmu.write_unchecked(0xc000, 0xd3); // illegal
+        mmu.write_unchecked(0xc001, 0xfc); // illegal
+        mmu.write_unchecked(0xc002, 0x3c); // INC A
+        cpu.write_register(&Register::A, 0);
+
+        // Both run like a NOP, and the code after them still does
+        assert_eq!(cpu.tick(&mut mmu).unwrap(), 4);
+        assert_eq!(cpu.tick(&mut mmu).unwrap(), 4);
+        cpu.tick(&mut mmu).unwrap();
+        assert_eq!(cpu.read_register16(&Register::PC), 0xc003);
+        assert_eq!(cpu.read_register(&Register::A), 1);
+        assert_eq!(cpu.skipped_opcodes(), 2);
+    }
+
+    #[test]
+    fn test_cgb_compat_mode() {
+        // The boot ROM picks the mode in KEY0 and unmaps itself, the game then can't change it anymore
+        for (cgb_flag, key0, mode) in [(0x00, 0x04, Mode::Dmg), (0x80, 0x80, Mode::Cgb)] {
+            let mut bootrom = vec![0u8; 0x900];
+            let boot = assemble(&[
+                &format!("ld a, ${:02x}", key0),
+                "ldh ($4c), a",
+                "ld a, $11",
+                "ldh ($50), a",
+            ]);
+            bootrom[..boot.len()].copy_from_slice(&boot);
+            let mut rom = vec![0u8; 0x8000];
+            let code = assemble(&["ld a, $80", "ldh ($4c), a", "ld a, $91", "ldh ($40), a", "jr -2"]);
+            rom[boot.len()..boot.len() + code.len()].copy_from_slice(&code);
+            rom[0x143] = cgb_flag;
+
+            let mut gb = GameBoy::try_new_on(Some(bootrom), rom, Some(Mode::Cgb)).unwrap();
+            gb.run_frame();
+            assert_eq!(gb.mode, mode);
+            assert_eq!(gb.mmu.mode(), &mode);
+            assert_eq!(gb.model(), if mode == Mode::Dmg { "DMG" } else { "CGB" });
+            assert_eq!(gb.mmu.read_unchecked(0xff4c), key0);
+
+            // Power cycling boots the CGB again
+            gb.reset();
+            assert_eq!(gb.mode, Mode::Cgb);
+        }
+    }
+
+    #[test]
+    fn test_halt_idles() {
+        // Waits for VBlank in HALT like a game sitting in a menu, counting frames in B
+        let (bootrom, mut rom) = rom_with_code(&[
+            "ld sp, $fffe",
+            "ld a, $91",
+            "ldh ($40), a",
+            "ld a, $01",
+            "ldh ($ff), a",
+            "ei",
+            "halt",
+            "jr -3",
+        ]);
+        rom[0x40..0x42].copy_from_slice(&[0x04, 0xd9]); // INC B, RETI
+
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        for _ in 0..10 {
+            gb.run_frame();
+        }
+
+        // Nearly all the time is slept through, without missing a single VBlank
+        assert!((9..=10).contains(&gb.cpu.read_register(&Register::B)));
+        assert!(gb.idle_cycle_count() > gb.cycle_count() * 9 / 10);
+    }
+
+    #[test]
+    fn test_scheduler() {
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.cycles_until_next(), None);
+
+        scheduler.schedule(Event::Timer, Some(64));
+        scheduler.schedule(Event::PpuState, Some(80));
+        scheduler.schedule(Event::Serial, None);
+        assert_eq!(scheduler.cycles_until_next(), Some(64));
+
+        scheduler.advance(60);
+        assert!(!scheduler.due());
+        assert_eq!(scheduler.cycles_until_next(), Some(4));
+
+        // Instructions can overshoot an event, it's due until rescheduled
+        scheduler.advance(8);
+        assert!(scheduler.due());
+        scheduler.schedule(Event::Timer, None);
+        assert_eq!(scheduler.deadline(Event::PpuState), Some(80));
+        assert_eq!(scheduler.cycles_until_next(), Some(12));
+    }
+
+    #[test]
+    fn test_lazy_components_keep_timing() {
+        // Polls LY for VBlank while the timer interrupt counts up in B, both only get caught up
+        // when the CPU looks at them or something happens
+        let (bootrom, mut rom) = rom_with_code(&[
+            "ld sp, $fffe",
+            "ld a, $91",
+            "ldh ($40), a",
+            "ld a, $05", // 262144 Hz, TIMA overflows every 4096 cycles
+            "ldh ($07), a",
+            "ld a, $04",
+            "ldh ($ff), a",
+            "ei",
+            "ldh a, ($44)",
+            "cp a, $90",
+            "jr nz, -6",
+            "inc c",
+            "ldh a, ($44)",
+            "cp a, $90",
+            "jr z, -6",
+            "jr -15",
+        ]);
+        rom[0x50..0x52].copy_from_slice(&[0x04, 0xd9]); // INC B, RETI
+
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        for _ in 0..10 {
+            gb.run_frame();
+        }
+
+        // Every VBlank was seen once, and no timer interrupt was late or missed
+        assert!((9..=10).contains(&gb.cpu.read_register(&Register::C)));
+        let interrupts = gb.cpu.read_register(&Register::B) as u64;
+        let expected = gb.cycle_count() / 4096;
+        assert!(interrupts.abs_diff(expected) <= 1);
+    }
+
+    #[test]
+    fn test_halt_wakes_without_ime() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0xffff])), Mode::Dmg);
+        mmu.unmap_bootrom();
+        mmu.resize_memory(0xffff * 4);
+        let mut cpu = Cpu::new();
+
+        cpu.write_register16(&Register::PC, 0xc000);
+        cpu.write_register16(&Register::SP, 0xd000);
+        mmu.write_unchecked(0xc000, 0x76); // HALT
+        mmu.write_unchecked(0xc001, 0x3c); // INC A
+        mmu.write_unchecked(INTERRUPT_ENABLE_REGISTER, 0b0000_0100);
+
+        cpu.tick(&mut mmu).unwrap();
+        cpu.tick(&mut mmu).unwrap();
+        assert!(cpu.halted);
+
+        // The pending timer interrupt ends HALT, but without IME nothing gets dispatched
+        mmu.write_unchecked(INTERRUPT_FLAGS_REGISTER, 0b0000_0100);
+        cpu.tick(&mut mmu).unwrap();
+        assert!(!cpu.halted);
+        assert_eq!(cpu.read_register16(&Register::PC), 0xc002);
+        assert_eq!(cpu.read_register16(&Register::SP), 0xd000);
+        assert_eq!(cpu.read_register(&Register::A), 1);
+        assert_eq!(mmu.read_unchecked(INTERRUPT_FLAGS_REGISTER), 0b0000_0100);
+    }
+
+    #[test]
+    fn test_stack_check() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0xffff])), Mode::Dmg);
+        mmu.unmap_bootrom();
+        mmu.resize_memory(0xffff * 4);
+        let mut cpu = Cpu::new();
+        cpu.set_stack_check(true);
+
+        // Pops past the top of HRAM into IE, then pushes over the code running in WRAM
+        cpu.write_register16(&Register::PC, 0xc000);
+        cpu.write_register16(&Register::SP, 0xfffe);
+        mmu.write_unchecked(0xc000, 0xc1); // POP BC
+        mmu.write_unchecked(0xc001, 0x31); // LD SP, $c006
+        mmu.write_unchecked(0xc002, 0x06);
+        mmu.write_unchecked(0xc003, 0xc0);
+        mmu.write_unchecked(0xc004, 0xc5); // PUSH BC
+        for _ in 0..3 {
+            cpu.tick(&mut mmu).unwrap();
+        }
+
+        let warnings = cpu
+            .stack_warnings()
+            .iter()
+            .map(|warning| (warning.pc.addr, warning.address, warning.fault))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            [
+                (0xc000, 0xffff, StackFault::Underflow),
+                (0xc004, 0xc005, StackFault::OverlapsCode)
+            ]
+        );
+
+        // Off by default, nothing is recorded
+        let mut cpu = Cpu::new();
+        cpu.write_register16(&Register::PC, 0xc000);
+        cpu.write_register16(&Register::SP, 0xfffe);
+        cpu.tick(&mut mmu).unwrap();
+        assert!(cpu.stack_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_uninitialized_reads() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0xffff])), Mode::Dmg);
+        mmu.unmap_bootrom();
+        mmu.uninit.set_enabled(true);
+        let mut cpu = Cpu::new();
+
+        // The program itself lives in WRAM, so it has to be written through the bus like a game would copy it
+        let program = [
+            0xfa, 0x00, 0xd1, // LD A, ($d100)
+            0xea, 0x00, 0xd1, // LD ($d100), A
+            0xfa, 0x00, 0xd1, // LD A, ($d100)
+            0xf0, 0x90, // LDH A, ($90)
+            0xf0, 0x90, // LDH A, ($90)
+            0xfa, 0x00, 0xf2, // LD A, ($f200)
+            0xea, 0x00, 0xf3, // LD ($f300), A
+            0xfa, 0x00, 0xd3, // LD A, ($d300)
+        ];
+        for (offset, byte) in program.iter().enumerate() {
+            mmu.write_unchecked(0xc000 + offset as u16, *byte);
+        }
+        cpu.write_register16(&Register::PC, 0xc000);
+        for _ in 0..8 {
+            cpu.tick(&mut mmu).unwrap();
         }
+
+        // Each byte is reported once, and only reads made by instructions count. Echo RAM is WRAM.
+        mmu.read_unchecked(0xd200);
+        let reads = mmu
+            .uninit
+            .reads()
+            .iter()
+            .map(|read| (read.pc.addr, read.address))
+            .collect::<Vec<_>>();
+        assert_eq!(reads, [(0xc000, 0xd100), (0xc009, 0xff90), (0xc00d, 0xf200)]);
+
+        mmu.uninit.clear();
+        assert!(mmu.uninit.reads().is_empty());
+    }
+
+    #[test]
+    fn test_ei_delay() {
+        // Runs the program with a timer interrupt pending and returns PC after each instruction
+        let run = |program: &[u8]| {
+            let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0xffff])), Mode::Dmg);
+            mmu.unmap_bootrom();
+            mmu.resize_memory(0xffff * 4);
+            let mut cpu = Cpu::new();
+
+            cpu.write_register16(&Register::PC, 0xc000);
+            cpu.write_register16(&Register::SP, 0xd000);
+            for (i, byte) in program.iter().enumerate() {
+                mmu.write_unchecked(0xc000 + i as u16, *byte);
+            }
+            mmu.write_unchecked(INTERRUPT_ENABLE_REGISTER, 0b0000_0100);
+            mmu.write_unchecked(INTERRUPT_FLAGS_REGISTER, 0b0000_0100);
+
+            (0..program.len())
+                .map(|_| {
+                    cpu.tick(&mut mmu).unwrap();
+                    cpu.read_register16(&Register::PC)
+                })
+                .collect::<Vec<u16>>()
+        };
+
+        // EI, NOP, NOP: the instruction after EI still runs, then the interrupt is serviced
+        assert_eq!(run(&[0xfb, 0x00, 0x00]), vec![0xc001, 0xc002, 0x0051]);
+        // EI, DI, NOP: DI cancels the pending EI
+        assert_eq!(run(&[0xfb, 0xf3, 0x00]), vec![0xc001, 0xc002, 0xc003]);
+        // EI, EI, NOP: the second EI doesn't delay IME any further
+        assert_eq!(run(&[0xfb, 0xfb, 0x00]), vec![0xc001, 0xc002, 0x0051]);
+    }
+
+    #[test]
+    fn test_disassemble_bank() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x4000..0x4008].copy_from_slice(&[0x3e, 0x42, 0xea, 0x00, 0xc0, 0xcb, 0x7c, 0xd3]);
+
+        let instructions = disassemble(&rom, 1, 0x4000, 4).unwrap();
+        let lines = instructions.iter().map(|i| i.to_string()).collect::<Vec<String>>();
+
+        assert_eq!(lines[0], "01:4000  3e 42     ld a, 0x42");
+        assert_eq!(lines[1], "01:4002  ea 00 c0  ld (0xc000), a");
+        assert_eq!(lines[2], "01:4005  cb 7c     bit 7, h");
+        assert_eq!(lines[3], "01:4007  d3        db 0xd3");
+
+        // Disassembly stops at the end of the mapped bank
+        assert_eq!(disassemble(&rom, 1, 0x7ffe, 10).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_assembler_round_trip() {
+        let mut sm83 = Sm83::new();
+
+        // Every decodable opcode has to assemble back into the same bytes from its disassembly
+        let mut encodings = (0..=0xffu8)
+            .map(|opcode| vec![opcode, 0x9a, 0xbc])
+            .collect::<Vec<Vec<u8>>>();
+        encodings.extend((0..=0xffu8).map(|opcode| vec![0xcb, opcode, 0x00]));
+
+        for bytes in encodings {
+            let Ok(instruction) = Sm83::new().decode(&ByteSource::new(&bytes, 0), 0) else {
+                continue;
+            };
+
+            let text = instruction.to_string();
+            let assembled = assemble(&[&text]);
+            assert_eq!(assembled, bytes[..instruction.length], "Mismatch for {}", text);
+
+            let decoded = sm83.decode(&ByteSource::new(&assembled, 0), 0).unwrap();
+            assert_eq!(decoded.to_string(), text);
+        }
+
+        // Hand written input doesn't have to look exactly like the disassembly
+        assert_eq!(assemble(&["LD A, $3E"]), vec![0x3e, 0x3e]);
+        assert_eq!(assemble(&["ld (hli), a"]), vec![0x22]);
+        assert_eq!(assemble(&["jr nz, -2"]), vec![0x20, 0xfe]);
+        assert_eq!(assemble(&["rst $38"]), vec![0xff]);
+        assert!(Assembler::new().assemble("ld a, 0x100").is_err());
+        assert!(Assembler::new().assemble("ld q, a").is_err());
+    }
+
+    #[test]
+    fn test_apply_patch() {
+        let rom = vec![1, 2, 3, 4];
+
+        // IPS: a plain record, an RLE record past the end of the ROM and the EOF marker
+        let mut ips = b"PATCH".to_vec();
+        ips.extend([0x00, 0x00, 0x01, 0x00, 0x01, 0x07]);
+        ips.extend([0x00, 0x00, 0x05, 0x00, 0x00, 0x00, 0x02, 0x08]);
+        ips.extend(b"EOF");
+        assert_eq!(apply_patch(&rom, &ips).unwrap(), vec![1, 7, 3, 4, 0, 8, 8]);
+
+        // BPS: SourceRead 2, TargetRead 1, TargetCopy 2 (+2) and SourceCopy 1 (+3)
+        let target = vec![1, 2, 9, 9, 9, 4];
+        let mut bps = b"BPS1".to_vec();
+        bps.extend([0x84, 0x86, 0x80]);
+        bps.extend([0x84, 0x81, 0x09, 0x87, 0x84, 0x82, 0x86]);
+        bps.extend(crc32(&rom).to_le_bytes());
+        bps.extend(crc32(&target).to_le_bytes());
+        bps.extend(crc32(&bps).to_le_bytes());
+        assert_eq!(apply_patch(&rom, &bps).unwrap(), target);
+
+        // BPS patches refuse to apply to the wrong ROM
+        assert!(apply_patch(&[1, 2, 3, 5], &bps).is_err());
+        assert!(apply_patch(&rom, b"garbage").is_err());
+
+        // Sizes too large for a usize are an invalid patch, not an overflow
+        let mut bps = b"BPS1".to_vec();
+        bps.extend([0x7f; 12]);
+        bps.push(0x80);
+        bps.extend(crc32(&rom).to_le_bytes());
+        bps.extend(crc32(&target).to_le_bytes());
+        bps.extend(crc32(&bps).to_le_bytes());
+        assert!(matches!(apply_patch(&rom, &bps), Err(AyyError::InvalidPatch { .. })));
+    }
+
+    #[test]
+    fn test_zipped_rom() {
+        let dir = std::env::temp_dir().join("ayyboy-zipped-rom");
+        std::fs::create_dir_all(&dir).unwrap();
+        let zip_path = dir.join("game.zip").to_string_lossy().into_owned();
+        let rom = (0..0x8000).map(|i| i as u8).collect::<Vec<u8>>();
+
+        let mut writer = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        writer
+            .start_file("Game (USA).gb", zip::write::SimpleFileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut writer, &rom).unwrap();
+        writer.finish().unwrap();
+        std::fs::write(format!("{}.sav", zip_path), [0x42]).unwrap();
+
+        let guard = ExtractionGuard::acquire();
+        let loaded = load_rom(&zip_path).unwrap();
+        assert_eq!(loaded.rom, rom);
+
+        // Saves are named after the ROM inside, an old one named after the zip moves over
+        let rom_path = dir.join("Game (USA).gb").to_string_lossy().into_owned();
+        assert_eq!(loaded.path, rom_path);
+        assert_eq!(std::fs::read(format!("{}.sav", rom_path)).unwrap(), [0x42]);
+        assert!(!std::path::Path::new(&format!("{}.sav", zip_path)).exists());
+
+        // Unpacked to the same place every time, and cleaned up on exit
+        let extracted = std::env::temp_dir()
+            .join("ayyboy")
+            .join(format!("{:08x}", crc32(&rom)))
+            .join("Game (USA).gb");
+        assert_eq!(std::fs::read(&extracted).unwrap(), rom);
+        assert_eq!(load_rom(&zip_path).unwrap().rom, rom);
+        // Only the last guard cleans up
+        drop(ExtractionGuard::acquire());
+        assert!(extracted.exists());
+        drop(guard);
+        assert!(!extracted.exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_turbo() {
+        let frame = Duration::from_secs(1) / 60;
+        let mut turbo = Turbo::new(Button::A.mask(), 10, frame);
+        let held = Button::A.mask() | Button::B.mask();
+
+        // 10 presses a second at 60 frames a second: 3 frames pressed, 3 released, while B stays held
+        let pressed = (0..12).map(|_| turbo.apply(held)).collect::<Vec<_>>();
+        let a = Button::A.mask();
+        assert!(pressed.iter().all(|input| input & Button::B.mask() != 0));
+        assert_eq!(
+            pressed.iter().map(|input| input & a != 0).collect::<Vec<_>>(),
+            [true, true, true, false, false, false, true, true, true, false, false, false]
+        );
+
+        // Letting go starts the next press over
+        turbo.apply(held);
+        turbo.apply(held);
+        turbo.apply(held);
+        assert_eq!(turbo.apply(held) & a, 0);
+        assert_eq!(turbo.apply(0), 0);
+        assert_eq!(turbo.apply(held) & a, a);
+
+        // The fastest rate alternates every frame
+        turbo.set_rate(60, frame);
+        assert_eq!(turbo.rate(), 30);
+        turbo.apply(0);
+        assert_eq!(turbo.apply(a), a);
+        assert_eq!(turbo.apply(a), 0);
+        assert_eq!(turbo.apply(a), a);
+
+        turbo.set_button(Button::A, false);
+        assert_eq!(turbo.apply(a), a);
+        assert_eq!(turbo.apply(a), a);
+    }
+
+    #[test]
+    fn test_presence() {
+        assert_eq!(header_title(b"TETRIS\0\0\0\0\0\0\0\0\0\0"), "TETRIS");
+        assert_eq!(header_title(b"POKEMON_GLDAAUE\x80"), "POKEMON_GLD");
+
+        let mut presence = Presence::new(true);
+        assert_eq!(presence.activity(), None);
+
+        presence.set_game(String::from("TETRIS"), "DMG");
+        let paused = presence.activity().unwrap();
+        assert_eq!(
+            (paused.title.as_str(), paused.state.as_str()),
+            ("TETRIS", "Paused (DMG)")
+        );
+        assert_eq!(paused.started, None);
+
+        // Play time counts while running and carries over pauses
+        presence.set_paused(false);
+        let playing = presence.activity().unwrap();
+        assert_eq!(playing.state, "Playing on DMG");
+        assert!(playing.started.unwrap() <= std::time::SystemTime::now());
+
+        presence.set_enabled(false);
+        assert_eq!(presence.activity(), None);
+    }
+
+    #[test]
+    fn test_save_size_normalization() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02; // 8KB
+
+        // Saves are written with exactly the header size
+        let mut mbc = Mbc1::new(rom.clone());
+        assert_eq!(mbc.dump_ram().len(), 0x2000);
+
+        // Oversized saves (e.g. 32KB from older versions) are trimmed
+        let mut save = vec![0x42u8; 0x8000];
+        save[0] = 0x11;
+        mbc.load_ram(save);
+        let ram = mbc.dump_ram();
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(ram[0], 0x11);
+        assert_eq!(ram[0x1fff], 0x42);
+
+        // Short saves are padded instead of shrinking the RAM the banks index into
+        mbc.load_ram(vec![0x33u8; 0x10]);
+        let ram = mbc.dump_ram();
+        assert_eq!(ram.len(), 0x2000);
+        assert_eq!(ram[0x0f], 0x33);
+        assert_eq!(ram[0x10], 0x00);
+        mbc.write(0x0000, 0x0a).unwrap();
+        mbc.write(0xbfff, 0x99).unwrap();
+        assert_eq!(mbc.read(0xbfff).unwrap(), 0x99);
+
+        // 128KB MBC5 saves keep all 16 banks
+        rom[0x0149] = 0x04;
+        let mut mbc = Mbc5::new(rom);
+        mbc.load_ram(vec![0x55u8; 0x20000]);
+        assert_eq!(mbc.dump_ram(), vec![0x55u8; 0x20000]);
+    }
+
+    #[test]
+    fn test_ram_init() {
+        assert_eq!("zero".parse::<RamInit>(), Ok(RamInit::Zero));
+        assert_eq!("random:42".parse::<RamInit>(), Ok(RamInit::Random(42)));
+        assert!("random:x".parse::<RamInit>().is_err());
+        assert!("ones".parse::<RamInit>().is_err());
+
+        let memory = |init: RamInit| {
+            let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Dmg);
+            mmu.init_ram(&init);
+            (0xc000..=0xdfff)
+                .chain(0x8000..=0x9fff)
+                .map(|addr| mmu.peek(addr).unwrap())
+                .collect::<Vec<u8>>()
+        };
+
+        // The same seed always gives the same memory, so runs can be reproduced
+        assert_eq!(memory(RamInit::Random(1)), memory(RamInit::Random(1)));
+        assert_ne!(memory(RamInit::Random(1)), memory(RamInit::Random(2)));
+        assert!(memory(RamInit::Zero).iter().all(|byte| *byte == 0));
+        assert_eq!(
+            memory(RamInit::Pattern)[..16],
+            [0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]
+        );
+    }
+
+    #[test]
+    fn test_mbc3_rtc() {
+        let mut mbc = Mbc3::with_rtc(vec![0u8; 0x8000]);
+        mbc.write(0x0000, 0x0a).unwrap();
+        mbc.write(0x4000, 0x08).unwrap();
+        mbc.write(0xa000, 30).unwrap();
+
+        // Reads see the time as of the last latch
+        assert_eq!(mbc.read(0xa000).unwrap(), 0);
+        mbc.tick(4194304 * 2);
+        mbc.write(0x6000, 0x00).unwrap();
+        mbc.write(0x6000, 0x01).unwrap();
+        assert_eq!(mbc.read(0xa000).unwrap(), 32);
+        mbc.write(0x4000, 0x0c).unwrap();
+        assert_eq!(mbc.read(0xa000).unwrap(), 0b0011_1110);
+
+        // With the RAM disabled the clock can't be written, nor read
+        mbc.write(0x0000, 0x00).unwrap();
+        mbc.write(0x4000, 0x08).unwrap();
+        mbc.write(0xa000, 10).unwrap();
+        assert_ne!(mbc.read(0xa000).unwrap(), 32);
+        mbc.write(0x0000, 0x0a).unwrap();
+        mbc.write(0x6000, 0x00).unwrap();
+        mbc.write(0x6000, 0x01).unwrap();
+        assert_eq!(mbc.read(0xa000).unwrap(), 32);
+    }
+
+    #[test]
+    fn test_cartridge_info() {
+        let mut rom = vec![0u8; 0x10000];
+        rom[0x0147] = 0x10; // MBC3+TIMER+RAM+BATTERY
+        rom[0x0149] = 0x03; // 32KB
+        let mut mbc = Mbc3::with_rtc(rom);
+        assert_eq!((mbc.rom_size(), mbc.rom_bank_count()), (0x10000, 4));
+        assert_eq!((mbc.ram_size(), mbc.ram_bank_count()), (0x8000, 4));
+        assert!(mbc.has_battery());
+        assert!(!mbc.ram_dirty());
+
+        mbc.write(0x0000, 0x0a).unwrap();
+        mbc.write(0x2000, 0x03).unwrap();
+        mbc.write(0x4000, 0x02).unwrap();
+        assert_eq!((mbc.current_rom_bank(), mbc.current_ram_bank()), (3, 2));
+
+        // Writing the RAM leaves unsaved changes until the next save is loaded (or written)
+        mbc.write(0xa000, 0x42).unwrap();
+        assert!(mbc.ram_dirty());
+        mbc.load_ram(mbc.dump_ram());
+        assert!(!mbc.ram_dirty());
+
+        // The clock as it runs, not what was latched. It's saved along with the RAM.
+        mbc.write(0x4000, 0x0a).unwrap();
+        mbc.write(0xa000, 5).unwrap();
+        let rtc = mbc.rtc().unwrap();
+        assert_eq!((rtc.days, rtc.hours, rtc.minutes, rtc.seconds), (0, 5, 0, 0));
+        assert!(!rtc.halted && !rtc.day_carry);
+        assert!(mbc.ram_dirty());
+
+        let rom = Rom::new(vec![0u8; 0x8000]);
+        assert_eq!((rom.rom_bank_count(), rom.ram_size(), rom.ram_bank_count()), (2, 0, 0));
+        assert!(!rom.has_battery());
+        assert_eq!(rom.rtc(), None);
+        assert!(Mbc3::new(vec![0u8; 0x8000]).rtc().is_none());
+    }
+
+    #[test]
+    fn test_gamepad_rumble() {
+        // A pulse between two polls still counts, and the motor stays on until switched off
+        let mut mbc = Mbc5::with_rumble(vec![0u8; 0x8000]);
+        mbc.write(0x4000, 0x08).unwrap();
+        mbc.write(0x4000, 0x00).unwrap();
+        assert!(mbc.take_rumble());
+        assert!(!mbc.take_rumble());
+        mbc.write(0x4000, 0x0b).unwrap();
+        assert_eq!(mbc.current_ram_bank(), 3);
+        assert!(mbc.take_rumble());
+        assert!(mbc.take_rumble());
+        // The motor stops with the power
+        mbc.power_cycle(None);
+        assert!(!mbc.take_rumble());
+        let mut mbc = Mbc5::new(vec![0u8; 0x8000]);
+        mbc.write(0x4000, 0x08).unwrap();
+        assert!(!mbc.take_rumble());
+
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut envelope = RumbleEnvelope::new(50);
+        assert_eq!(envelope.update(true, at(0)), Some(0.5));
+        assert_eq!(envelope.update(true, at(10)), None);
+
+        // Decays once stopped, changes within the update interval wait for the next update
+        assert!(RUMBLE_UPDATE_INTERVAL > Duration::from_millis(12));
+        assert_eq!(envelope.update(false, at(12)), None);
+        let level = envelope.update(false, at(30)).unwrap();
+        assert!(level > 0.0 && level < 0.5, "{}", level);
+        assert_eq!(envelope.update(true, at(35)), None);
+        assert_eq!(envelope.update(true, at(60)), Some(0.5));
+        assert_eq!(envelope.update(false, at(1000)), Some(0.0));
+        assert_eq!(envelope.update(false, at(2000)), None);
+
+        let mut envelope = RumbleEnvelope::new(0);
+        assert_eq!(envelope.update(true, at(0)), None);
+        envelope.set_strength(150);
+        assert_eq!(envelope.strength(), 100);
+        assert_eq!(envelope.update(true, at(100)), Some(1.0));
+    }
+
+    #[test]
+    fn test_hot_swap_cartridge() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x01;
+        let mut gb = GameBoy::new(None, rom.clone());
+        gb.run_frame();
+
+        let pc = gb.cpu.read_register16(&Register::PC);
+        let cycles = gb.cycle_count();
+
+        // The console keeps running, only the cartridge is replaced
+        rom[0x0147] = 0x19;
+        let old = gb.hot_swap_cartridge(rom.clone()).unwrap();
+        assert_eq!(old.name(), Mbc1::new(vec![0u8; 0x8000]).name());
+        assert_eq!(gb.mmu.cartridge.name(), Mbc5::new(rom.clone()).name());
+        assert_eq!(gb.cpu.read_register16(&Register::PC), pc);
+        assert_eq!(gb.cycle_count(), cycles);
+
+        rom[0x0147] = 0xfc;
+        assert!(gb.hot_swap_cartridge(rom).is_err());
+        assert!(gb.hot_swap_cartridge(vec![0u8; 0x100]).is_err());
+    }
+
+    #[test]
+    fn test_reset() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        let mut gb = GameBoy::new(None, rom);
+        gb.load_ram(vec![0x5a; 0x2000]);
+        gb.set_accuracy(Accuracy::PerPixel);
+        gb.set_power_on_state(RamInit::Pattern, 0x1234);
+        gb.run_frame();
+        assert_ne!(gb.cpu.read_register16(&Register::PC), 0);
+
+        // Starts over in the boot ROM with the cartridge and settings untouched
+        gb.reset();
+        assert_eq!(gb.cpu.read_register16(&Register::PC), 0);
+        assert_eq!(gb.mmu.read_unchecked(0x0000), gb.mmu.bootrom()[0]);
+        assert_eq!(gb.dump_ram(), vec![0x5a; 0x2000]);
+        assert_eq!(gb.ppu.accuracy(), Accuracy::PerPixel);
+        assert_eq!(gb.mmu.timer.counter(), 0x1234);
+        assert_eq!(gb.mmu.read_unchecked(0xc008), 0xff);
+        gb.run_frame();
+    }
+
+    #[test]
+    fn test_ram_decay() {
+        assert_eq!("25:7".parse::<RamDecay>(), Ok(RamDecay::new(25, 7)));
+        assert_eq!("25".parse::<RamDecay>(), Ok(RamDecay::new(25, 0)));
+        assert!("101".parse::<RamDecay>().is_err());
+        assert!("25:x".parse::<RamDecay>().is_err());
+
+        let decay = |percent, ram: &[u8]| {
+            let mut ram = ram.to_vec();
+            RamDecay::new(percent, 7).apply(&mut ram);
+            ram
+        };
+        assert_eq!(decay(0, &[0x5a; 0x100]), vec![0x5a; 0x100]);
+        // Nothing of the old contents is left, every cell settles to its own value
+        assert_eq!(decay(100, &[0x00; 0x100]), decay(100, &[0xff; 0x100]));
+
+        // The same weak cells decay first, so a longer loss of power only adds to what decayed before
+        let partial = decay(30, &[0x5a; 0x100]);
+        assert_ne!(partial, vec![0x5a; 0x100]);
+        assert_eq!(decay(30, &partial), partial);
+        assert_eq!(decay(60, &partial), decay(60, &[0x5a; 0x100]));
+
+        // A reset keeps the RAM (there's a battery), pulling the power without one doesn't
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0147] = 0x03;
+        rom[0x0149] = 0x02;
+        let mut gb = GameBoy::new(None, rom);
+        gb.load_ram(vec![0x5a; 0x2000]);
+        gb.reset();
+        assert_eq!(gb.dump_ram(), vec![0x5a; 0x2000]);
+        gb.power_cycle(Some(&RamDecay::new(30, 7)));
+        assert_eq!(gb.dump_ram(), decay(30, &[0x5a; 0x2000]));
+        assert_eq!(gb.cpu.read_register16(&Register::PC), 0);
+    }
+
+    #[test]
+    fn test_corrupt_roms() {
+        // A tiny program without a header still runs, zero-filled to 32KB
+        let mut program = vec![0u8; 0x110];
+        program[0x0100] = 0x3c; // inc a
+        let gb = GameBoy::try_new(None, program).unwrap();
+        assert_eq!(gb.mmu.cartridge.read(0x0100).unwrap(), 0x3c);
+        assert_eq!(gb.mmu.cartridge.read(0x7fff).unwrap(), 0x00);
+
+        // A truncated dump with an intact header is padded to the size it declares
+        let mut rom = vec![0u8; 0xc000];
+        rom[0x0147] = 0x19;
+        rom[0x0148] = 0x02;
+        rom[0x014d] = rom[0x0134..=0x014c]
+            .iter()
+            .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1));
+        let mut gb = GameBoy::try_new(None, rom.clone()).unwrap();
+        gb.mmu.cartridge.write(0x2000, 0x07).unwrap();
+        assert_eq!(gb.mmu.cartridge.read(0x7fff).unwrap(), 0x00);
+
+        // With a broken header the size can't be trusted
+        rom[0x014d] ^= 0xff;
+        assert!(GameBoy::try_new(None, rom.clone()).is_err());
+
+        // Sizes the mapper can't bank in, or that don't exist at all
+        rom[0x0147] = 0x00;
+        assert!(GameBoy::try_new(None, rom.clone()).is_err());
+
+        // The odd sizes between 1MB and 2MB
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x52;
+        rom[0x014d] = rom[0x0134..=0x014c]
+            .iter()
+            .fold(0u8, |checksum, &byte| checksum.wrapping_sub(byte).wrapping_sub(1));
+        let gb = GameBoy::try_new(None, rom.clone()).unwrap();
+        assert_eq!(gb.mmu.cartridge.rom_bank_count(), 72);
+
+        rom[0x0148] = 0x42;
+        assert!(GameBoy::try_new(None, rom).is_err());
+        assert!(GameBoy::try_new(None, vec![0u8; 0x80]).is_err());
+    }
+
+    #[test]
+    fn test_save_state_round_trip() {
+        let cartridge = std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap();
+        let mut gb = GameBoy::new(None, cartridge);
+        for _ in 0..30 {
+            System::run_frame(&mut gb);
+        }
+
+        let state = gb.save_state();
+        for _ in 0..30 {
+            System::run_frame(&mut gb);
+        }
+        let expected = (
+            gb.framebuffer(),
+            gb.cycle_count(),
+            gb.cpu.read_register16(&Register::PC),
+        );
+
+        // Loading the state and running the same frames again has to end up in the exact same place
+        gb.load_state(&state).unwrap();
+        for _ in 0..30 {
+            System::run_frame(&mut gb);
+        }
+        assert_eq!(
+            (
+                gb.framebuffer(),
+                gb.cycle_count(),
+                gb.cpu.read_register16(&Register::PC)
+            ),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_cram_blocked_during_mode3() {
+        let (mut bootrom, mut rom) = rom_with_code(&[
+            "ld a, $91", // LCD on
+            "ldh ($40), a",
+            "ld a, $80", // BCPS: index 0, auto increment
+            "ldh ($68), a",
+            "ldh a, ($41)", // wait for mode 3
+            "and a, $03",
+            "cp a, $03",
+            "jr nz, -8",
+            "ld a, $1f",
+            "ldh ($69), a",
+            "ldh a, ($69)",
+            "ld ($c000), a",
+            "jr -2",
+        ]);
+        // A CGB game, with a boot ROM the size of the CGB one
+        bootrom.resize(0x900, 0);
+        rom[0x0143] = 0xc0;
+
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        gb.run_frame();
+
+        // The write got dropped and the read blocked, but the index still moved on
+        assert_eq!(gb.mmu.cgb_cram.fetch_bg(0, 0), 0);
+        assert_eq!(gb.mmu.read_unchecked(0xc000), 0xff);
+        assert_eq!(gb.mmu.read(0xff68).unwrap(), 0x81);
+    }
+
+    // Runs the bundled boot ROM until it hands over to the cartridge at $0100
+    fn boot(rom: &str) -> GameBoy {
+        let mut gb = GameBoy::new(None, std::fs::read(rom).unwrap());
+        gb.breakpoints.addresses.push(BankedAddress::new(0, 0x100));
+        for _ in 0..10000 {
+            gb.run_frame();
+            if gb.take_breakpoint_hit().is_some() {
+                assert!(
+                    !gb.mmu.is_bootrom_mapped(),
+                    "Boot ROM still mapped at $0100 for {}",
+                    rom
+                );
+                return gb;
+            }
+        }
+        panic!("Boot ROM never reached $0100 for {}", rom);
+    }
+
+    #[test]
+    fn test_boot_state() {
+        // Post-boot values from Pan Docs, DIV, STAT and LY depend on boot ROM timing and are left out
+        let expected = [
+            (
+                "./external/roms/tests/dmg-acid2.gb",
+                [0x01, 0xb0, 0x00, 0x13, 0x00, 0xd8, 0x01, 0x4d],
+            ),
+            (
+                "./external/roms/tests/cgb-acid2.gbc",
+                [0x11, 0x80, 0x00, 0x00, 0xff, 0x56, 0x00, 0x0d],
+            ),
+        ];
+        let io = [
+            (0xff40, 0x91),
+            (0xff47, 0xfc),
+            (0xff24, 0x77),
+            (0xff25, 0xf3),
+            (0xff26, 0xf1),
+            (0xffff, 0x00),
+        ];
+
+        for (rom, values) in expected {
+            let gb = boot(rom);
+            let registers = [
+                Register::A,
+                Register::F,
+                Register::B,
+                Register::C,
+                Register::D,
+                Register::E,
+                Register::H,
+                Register::L,
+            ];
+            for (register, value) in registers.iter().zip(values) {
+                assert_eq!(
+                    gb.cpu.read_register(register),
+                    value,
+                    "{} after boot for {}",
+                    register,
+                    rom
+                );
+            }
+            assert_eq!(
+                gb.cpu.read_register16(&Register::SP),
+                0xfffe,
+                "SP after boot for {}",
+                rom
+            );
+
+            for (addr, value) in io {
+                assert_eq!(
+                    gb.mmu.read_unchecked(addr),
+                    value,
+                    "${:04x} after boot for {}",
+                    addr,
+                    rom
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_banked_breakpoint() {
+        let (bootrom, mut rom) = rom_with_code(&[
+            "ld sp, $fffe",
+            "ld a, $01",
+            "ld ($2000), a",
+            "call $4000",
+            "ld a, $02",
+            "ld ($2000), a",
+            "call $4000",
+            "jr -18",
+        ]);
+
+        // Same entry point in banks 1 and 2 of an MBC1 cartridge, each just returning
+        rom.resize(0x10000, 0);
+        rom[0x0147] = 0x01;
+        rom[0x0148] = 0x01;
+        rom[0x4000] = 0xc9;
+        rom[0x8000] = 0xc9;
+
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        assert_eq!(
+            BankedAddress::parse("2:$4000", gb.mmu.cartridge.as_ref()),
+            Some(BankedAddress::new(2, 0x4000))
+        );
+        assert_eq!(
+            BankedAddress::parse("5:c000", gb.mmu.cartridge.as_ref()),
+            Some(BankedAddress::new(0, 0xc000))
+        );
+        assert_eq!(BankedAddress::new(2, 0x4000).to_string(), "02:4000");
+        gb.breakpoints.addresses.push(BankedAddress::new(2, 0x4000));
+
+        // Copies like run-ahead run through them, the running console gets them back from a loaded state
+        let state = gb.save_state();
+        let mut copy = gb.clone();
+        copy.run_frame();
+        assert_eq!(copy.take_breakpoint_hit(), None);
+        gb.load_state(&state).unwrap();
+
+        // Only stops when bank 2 is mapped, and continues past it on the next frame
+        for _ in 0..2 {
+            gb.run_frame();
+            assert_eq!(gb.take_breakpoint_hit(), Some(BankedAddress::new(2, 0x4000)));
+            assert_eq!(gb.cpu.read_register16(&Register::PC), 0x4000);
+            assert_eq!(gb.mmu.cartridge.current_rom_bank(), 2);
+        }
+
+        gb.breakpoints.addresses.clear();
+        gb.run_frame();
+        assert_eq!(gb.take_breakpoint_hit(), None);
+    }
+
+    #[test]
+    fn test_register_docs() {
+        // No two fields share a bit, and every name is taken once
+        let docs: Vec<_> = register_docs().collect();
+        assert_eq!(docs.len(), 59);
+        for (address, doc) in docs {
+            let name = doc.name;
+            assert_eq!(register_address(name), Some(address));
+            let mut used = 0u8;
+            for field in doc.fields {
+                assert_ne!(field.mask, 0, "{} {}", name, field.name);
+                assert_eq!(used & field.mask, 0, "{} {}", name, field.name);
+                assert!(
+                    field.values.is_empty()
+                        || field.values.len() == (field.mask >> field.mask.trailing_zeros()) as usize + 1
+                );
+                used |= field.mask;
+            }
+        }
+
+        let lcdc = register_doc(0xff40).unwrap().describe(0x91);
+        assert!(lcdc.starts_with("LCD control"));
+        assert!(lcdc.contains("Bit 7    LCD & PPU: 1 (on)"), "{}", lcdc);
+        assert!(lcdc.contains("Bit 4    BG & window tiles: 1 ($8000)"), "{}", lcdc);
+        assert!(lcdc.contains("Bit 1    OBJ: 0 (off)"), "{}", lcdc);
+
+        let nr52 = register_doc(0xff26).unwrap();
+        assert!(nr52.describe(0x82).contains("Bit 1    Channel 2: 1 (on)"));
+        let stat = register_doc(0xff41).unwrap();
+        assert!(stat.describe(0x03).contains("Bit 1-0  PPU mode: 3 (drawing)"));
+        assert_eq!(register_doc(0xff03).map(|doc| doc.summary), None);
+    }
+
+    #[test]
+    fn test_save_state_audio() {
+        let program = [
+            "ld a, $80", // APU on, everything to both sides at full volume
+            "ldh ($26), a",
+            "ld a, $77",
+            "ldh ($24), a",
+            "ld a, $ff",
+            "ldh ($25), a",
+            "ld a, $80", // square 2 with a 50% duty
+            "ldh ($16), a",
+            "ld a, $f0",
+            "ldh ($17), a",
+            "ld a, $87",
+            "ldh ($19), a",
+            "ld a, $f3", // noise fading out, so that the LFSR and the envelope matter
+            "ldh ($21), a",
+            "ld a, $45",
+            "ldh ($22), a",
+            "ld a, $80",
+            "ldh ($23), a",
+            "ld a, $91", // LCD on, frames are cut short without it
+            "ldh ($40), a",
+            "jr -2",
+        ];
+
+        let (bootrom, rom) = rom_with_code(&program);
+
+        let samples = Rc::new(RefCell::new(Vec::new()));
+        let run = |gb: &mut GameBoy| {
+            samples.borrow_mut().clear();
+            for _ in 0..30 {
+                gb.run_frame();
+            }
+            let bytes = samples
+                .borrow()
+                .iter()
+                .flat_map(|sample: &f32| sample.to_le_bytes())
+                .collect::<Vec<u8>>();
+            assert!(!bytes.is_empty());
+            crc32(&bytes)
+        };
+        let collected = samples.clone();
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        gb.mmu.apu.set_sample_callback(Some(Box::new(move |buffer| {
+            collected.borrow_mut().extend_from_slice(buffer)
+        })));
+
+        // Channel phases, the frame sequencer and the half filled buffer all pick up where they were
+        gb.run_frame();
+        let state = gb.save_state();
+        let expected = run(&mut gb);
+        assert_ne!(run(&mut gb), expected);
+        gb.load_state(&state).unwrap();
+        assert_eq!(run(&mut gb), expected);
+
+        // Fast forward leaves out buffers, test_audio_pitch checks that the rest keeps its pitch
+        let normal = samples.borrow().len();
+        gb.set_speed(4);
+        run(&mut gb);
+        assert!(samples.borrow().len().abs_diff(normal / 4) <= BUFFER_SIZE);
+
+        // The speed (fast forward) stays what it is now instead of what it was back then
+        let state = gb.save_state();
+        gb.set_speed(1);
+        let mut copy = state.downcast_ref::<GameBoy>().unwrap().clone();
+        copy.set_speed(1);
+        copy.mmu.apu.adopt_output(&mut gb.mmu.apu);
+        let expected = run(&mut copy);
+        gb.mmu.apu.adopt_output(&mut copy.mmu.apu);
+        gb.load_state(&state).unwrap();
+        assert_eq!(run(&mut gb), expected);
+    }
+
+    #[test]
+    fn test_queued_input() {
+        let program = [
+            "ld a, $91", // LCD on, frames are cut short without it
+            "ldh ($40), a",
+            "ld a, $10", // select the buttons
+            "ldh ($00), a",
+            "ld b, $ff",
+            "ldh a, ($00)", // collect every line that was ever low
+            "and a, b",
+            "ld b, a",
+            "ld ($c000), a",
+            "jr -9",
+        ];
+
+        let (bootrom, rom) = rom_with_code(&program);
+
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        gb.run_frame();
+        gb.run_frame();
+        assert_eq!(gb.mmu.read_unchecked(0xc000) & 0x0f, 0x0f);
+
+        // A tap shorter than a frame still reaches the game, at the point in the frame it happened
+        gb.queue_buttons(0.25, Button::A.mask());
+        gb.queue_buttons(0.5, 0);
+        assert!(!gb.mmu.joypad.a);
+        gb.run_frame();
+        assert!(!gb.mmu.joypad.a);
+        assert_eq!(gb.mmu.read_unchecked(0xc000) & 0x01, 0);
+        assert_eq!(gb.mmu.read_unchecked(0xc000) & 0x0e, 0x0e);
+        let interrupt_flags = gb.mmu.read_as_unchecked::<InterruptFlags>(INTERRUPT_FLAGS_REGISTER);
+        assert!(interrupt_flags.contains(InterruptFlags::JOYPAD));
+
+        // Changes a frame cut short by a breakpoint didn't get to don't override the buttons set after it
+        gb.breakpoints.addresses.push(BankedAddress::new(0, 0x000e));
+        gb.queue_buttons(0.9, Button::B.mask());
+        gb.run_frame();
+        assert!(gb.take_breakpoint_hit().is_some());
+        gb.breakpoints.addresses.clear();
+        gb.update_buttons(0);
+        gb.run_frame();
+        assert!(!gb.mmu.joypad.b);
+    }
+
+    #[test]
+    fn test_io_breakpoints() {
+        assert_eq!(register_name(0xff40), Some("LCDC"));
+        assert_eq!(register_address("nr52"), Some(0xff26));
+        assert_eq!(register_name(0xff03), None);
+
+        let (bootrom, rom) = rom_with_code(&[
+            "ldh a, ($40)",
+            "set 7, a",
+            "ldh ($40), a",
+            "ld hl, $ff40",
+            "res 7, (hl)",
+            "jr -4",
+        ]);
+
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        gb.breakpoints.io.push(IoBreakpoint {
+            address: 0xff40,
+            access: Access::Write,
+        });
+
+        // Stops before each write but not the read, the read-modify-write RES counts as one too
+        let mut hits = Vec::new();
+        for _ in 0..3 {
+            gb.run_frame();
+            let pc = gb.take_breakpoint_hit().map(|pc| pc.addr);
+            hits.push((pc, gb.take_io_breakpoint_hit()));
+        }
+        let write = |access| {
+            Some(IoBreakpoint {
+                address: 0xff40,
+                access,
+            })
+        };
+        assert_eq!(
+            hits,
+            [
+                (Some(0x0008), write(Access::Write)),
+                (Some(0x000d), write(Access::ReadWrite)),
+                (Some(0x000d), write(Access::ReadWrite))
+            ]
+        );
+
+        gb.breakpoints.io[0].access = Access::Read;
+        gb.run_frame();
+        assert_eq!(gb.take_breakpoint_hit().map(|pc| pc.addr), Some(0x000d));
+        assert!(gb.take_io_breakpoint_hit().is_some());
+        gb.breakpoints.io.push(IoBreakpoint {
+            address: 0xff26,
+            access: Access::ReadWrite,
+        });
+        gb.breakpoints.io.remove(0);
+        gb.run_frame();
+        assert_eq!(gb.take_breakpoint_hit(), None);
+    }
+
+    #[test]
+    fn test_debugger_expressions() {
+        let (bootrom, rom) = rom_with_code(&["ld a, $00", "inc a", "jr -3"]);
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+
+        gb.mmu.write(0xc345, 5).unwrap();
+        gb.cpu.write_register(&Register::A, 0xff);
+        gb.cpu.write_register16(&Register::HL, 0xc345);
+        gb.cpu.write_register(&Register::F, 0x80);
+        let eval = |source: &str| Expr::parse(source).unwrap().eval(&gb.cpu, &gb.mmu);
+        assert_eq!(eval("[0xC345] > 3 && A == 0xFF"), 1);
+        assert_eq!(eval("[hl] + 1"), 6);
+        assert_eq!(eval("1 + 2 * 3 == 7 || 0"), 1);
+        assert_eq!(eval("(1 << 4 | 1) - -1"), 18);
+        assert_eq!(eval("!ZF + CF + h"), 0xc3);
+        assert_eq!(eval("10 / 0"), 0);
+        for invalid in ["A ==", "[0xc345", "(1))", "foo", "0xzz", "1 # 2", ""] {
+            assert!(
+                matches!(Expr::parse(invalid), Err(AyyError::InvalidExpression { .. })),
+                "{}",
+                invalid
+            );
+        }
+
+        // Conditions are left alone while the debugger is closed
+        gb.breakpoints.conditions.push(BreakCondition::parse("A == 3").unwrap());
+        gb.run_frame();
+        assert_eq!(gb.take_breakpoint_hit(), None);
+
+        // Once attached they stop when they turn true, not again as long as they stay true. The LCD is
+        // off, so a frame is only a scanline and A takes a few of them to wrap around.
+        gb.breakpoints.debugger_attached = true;
+        gb.cpu.write_register(&Register::A, 0);
+        for _ in 0..2 {
+            let hit = (0..100).find_map(|_| {
+                gb.run_frame();
+                gb.take_breakpoint_hit()
+            });
+            assert_eq!(hit.map(|pc| pc.addr), Some(0x0007));
+            assert_eq!(gb.take_condition_hit().as_deref(), Some("A == 3"));
+            assert_eq!(gb.cpu.read_register(&Register::A), 3);
+        }
+    }
+
+    #[test]
+    fn test_io_log() {
+        let program = [
+            "ld a, $e4",
+            "ldh ($47), a",
+            "ld ($c000), a",
+            "ldh ($80), a",
+            "ld a, $80",
+            "ldh ($26), a",
+            "jr -2",
+        ];
+
+        let (bootrom, rom) = rom_with_code(&program);
+
+        // Only CPU writes to IO registers are logged, not RAM/HRAM or the hardware's own updates
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        gb.mmu.io_log.start(gb.frame_count());
+        gb.run_frame();
+        gb.mmu.io_log.stop();
+
+        let writes = gb.mmu.io_log.writes().to_vec();
+        assert_eq!(
+            writes
+                .iter()
+                .map(|write| (write.address, write.value))
+                .collect::<Vec<_>>(),
+            vec![(0xff50, 0x11), (0xff47, 0xe4), (0xff26, 0x80)]
+        );
+        assert!(writes.iter().all(|write| write.frame == 0 && write.scanline == 0));
+
+        assert!(gb
+            .mmu
+            .io_log
+            .to_csv()
+            .starts_with("frame,scanline,address,value\n0,0,ff50,11\n0,0,ff47,e4\n"));
+        let binary = gb.mmu.io_log.to_binary();
+        assert_eq!(&binary[..11], b"AYIO\x00\x00\x00\x00\x00\x50\x11");
+        assert_eq!(binary.len(), 4 + writes.len() * 7);
+
+        // Loading a state keeps the running log, the snapshot itself doesn't record
+        let state = System::save_state(&gb);
+        gb.mmu.io_log.start(gb.frame_count());
+        System::load_state(&mut gb, &state).unwrap();
+        assert!(gb.mmu.io_log.is_recording());
+        assert_eq!(gb.mmu.io_log.writes().len(), writes.len());
+    }
+
+    #[test]
+    fn test_printer() {
+        // Sends a whole packet and returns the device ID and status the printer answers with
+        fn send(printer: &mut Printer, command: u8, compression: u8, data: &[u8]) -> (u8, u8) {
+            let mut packet = vec![
+                0x88,
+                0x33,
+                command,
+                compression,
+                data.len() as u8,
+                (data.len() >> 8) as u8,
+            ];
+            packet.extend_from_slice(data);
+            let checksum = packet[2..]
+                .iter()
+                .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+            packet.extend_from_slice(&checksum.to_le_bytes());
+
+            assert!(packet.iter().all(|&byte| printer.exchange(byte) == 0x00));
+            (printer.exchange(0x00), printer.exchange(0x00))
+        }
+
+        let mut printer = Printer::new();
+        assert_eq!(send(&mut printer, 0x01, 0, &[]), (0x81, 0x00));
+
+        // Two rows of tiles, the first row of pixels is color 3 and the rest compressed zeroes
+        let mut tiles = vec![0u8; 640];
+        for tile in 0..20 {
+            tiles[tile * 16..tile * 16 + 2].copy_from_slice(&[0xff, 0xff]);
+        }
+        assert_eq!(send(&mut printer, 0x04, 0, &tiles[..320]), (0x81, 0x08));
+        assert_eq!(
+            send(&mut printer, 0x04, 1, &[0xfe, 0x00, 0xfe, 0x00, 0xbe, 0x00]),
+            (0x81, 0x08)
+        );
+
+        // Palette maps color 3 to black (3) and color 0 to white (0), a feed after ends the page
+        let (_, status) = send(&mut printer, 0x02, 0, &[0x01, 0x01, 0xe4, 0x40]);
+        assert_eq!(status & 0b0000_0010, 0b0000_0010);
+        let printouts = printer.take_printouts();
+        assert_eq!(printouts.len(), 1);
+        assert_eq!(printouts[0].height, 16);
+        assert!(printouts[0].shades[..PRINTER_WIDTH].iter().all(|&shade| shade == 3));
+        assert!(printouts[0].shades[PRINTER_WIDTH..].iter().all(|&shade| shade == 0));
+
+        let path = std::env::temp_dir().join("ayyboy-printout.png");
+        printouts[0].save_png(path.to_str().unwrap()).unwrap();
+        assert!(std::fs::metadata(&path).unwrap().len() > 0);
+
+        // A bad checksum is reported and the packet ignored
+        for byte in [0x88, 0x33, 0x04, 0x00, 0x01, 0x00, 0xaa, 0x00, 0x00] {
+            printer.exchange(byte);
+        }
+        assert_eq!(printer.exchange(0x00), 0x81);
+        assert_eq!(printer.exchange(0x00) & 0b0000_0001, 0b0000_0001);
+
+        // Through the link port: bytes go out every 8 bits of the 8192Hz clock and raise the serial interrupt
+        let mut gb = GameBoy::new(None, vec![0u8; 0x8000]);
+        gb.mmu.serial.connect(LinkDevice::Printer.create());
+        gb.mmu.write(SERIAL_DATA_REGISTER, 0x88).unwrap();
+        gb.mmu.write(SERIAL_CONTROL_REGISTER, 0x81).unwrap();
+        assert!(!gb.mmu.serial.tick(8 * 512 - 1, false));
+        assert!(gb.mmu.serial.tick(1, false));
+        assert_eq!(gb.mmu.read(SERIAL_DATA_REGISTER).unwrap(), 0x00);
+        assert_eq!(gb.mmu.read(SERIAL_CONTROL_REGISTER).unwrap(), 0x7f);
+
+        // Nothing plugged in reads $ff, and without the internal clock nothing happens
+        gb.mmu.serial.connect(LinkDevice::None.create());
+        gb.mmu.write(SERIAL_CONTROL_REGISTER, 0x80).unwrap();
+        assert!(!gb.mmu.serial.tick(0x10000, false));
+        gb.mmu.write(SERIAL_CONTROL_REGISTER, 0x81).unwrap();
+        assert!(gb.mmu.serial.tick(0x10000, false));
+        assert_eq!(gb.mmu.read(SERIAL_DATA_REGISTER).unwrap(), 0xff);
+    }
+
+    #[test]
+    fn test_link_devices() {
+        let mut gb = GameBoy::new(None, vec![0u8; 0x8000]);
+
+        // Loopback shifts the sent byte back in, one bit every 512 cycles
+        gb.mmu.serial.connect(LinkDevice::Loopback.create());
+        gb.mmu.write(SERIAL_DATA_REGISTER, 0xa5).unwrap();
+        gb.mmu.write(SERIAL_CONTROL_REGISTER, 0x81).unwrap();
+        assert!(!gb.mmu.serial.tick(511, false));
+        assert_eq!(gb.mmu.read(SERIAL_DATA_REGISTER).unwrap(), 0xa5);
+        assert!(!gb.mmu.serial.tick(1, false));
+        assert_eq!(gb.mmu.read(SERIAL_DATA_REGISTER).unwrap(), 0x4b);
+        assert!(gb.mmu.serial.tick(7 * 512, false));
+        assert_eq!(gb.mmu.read(SERIAL_DATA_REGISTER).unwrap(), 0xa5);
+
+        // Echo answers a byte late, and clocks transfers the game waits on with the external clock
+        gb.mmu.serial.connect(LinkDevice::Echo.create());
+        let mut received = Vec::new();
+        for data in [0x01, 0x02, 0x03] {
+            gb.mmu.write(SERIAL_DATA_REGISTER, data).unwrap();
+            gb.mmu.write(SERIAL_CONTROL_REGISTER, 0x80).unwrap();
+            assert!(gb.mmu.serial.tick(8 * 512, false));
+            received.push(gb.mmu.read(SERIAL_DATA_REGISTER).unwrap());
+        }
+        assert_eq!(received, vec![0xff, 0x01, 0x02]);
+
+        // A transfer started by the CPU ends in the serial interrupt
+        let (bootrom, rom) = rom_with_code(&["ld a, $81", "ldh ($02), a", "jr -2"]);
+
+        let mut gb = GameBoy::new(Some(bootrom), rom);
+        gb.mmu.serial.connect(LinkDevice::Loopback.create());
+        // With the LCD off every frame is a single line, a transfer takes about nine
+        while gb.cycle_count() < 8 * 512 + 456 {
+            gb.run_frame();
+        }
+        assert_eq!(
+            gb.mmu.read(INTERRUPT_FLAGS_REGISTER).unwrap() & 0b0000_1000,
+            0b0000_1000
+        );
+        assert_eq!(gb.mmu.read(SERIAL_CONTROL_REGISTER).unwrap() & 0x80, 0);
+
+        // Copies are unplugged, the device stays with the running console when a state is loaded
+        let state = gb.save_state();
+        let mut copy = state.downcast_ref::<GameBoy>().unwrap().clone();
+        assert!(copy.mmu.serial.device_mut().is_none());
+        gb.load_state(&state).unwrap();
+        assert!(gb.mmu.serial.device_mut().is_some());
+    }
+
+    #[test]
+    fn test_serial_clock_speeds() {
+        // CPU cycles a loopback transfer of a byte takes
+        let transfer_cycles = |gb: &mut GameBoy, control: u8| {
+            gb.mmu.write(SERIAL_DATA_REGISTER, 0xa5).unwrap();
+            gb.mmu.write(SERIAL_CONTROL_REGISTER, control).unwrap();
+            let double_speed = gb.mmu.cgb_double_speed;
+            let cycles = (1..=0x10000).find(|_| gb.mmu.serial.tick(1, double_speed));
+            assert_eq!(gb.mmu.read(SERIAL_DATA_REGISTER).unwrap(), 0xa5);
+            cycles
+        };
+
+        // The DMG only has the 8192Hz clock, the speed bit reads as 1
+        let mut gb = GameBoy::new(None, vec![0u8; 0x8000]);
+        gb.mmu.serial.connect(LinkDevice::Loopback.create());
+        assert_eq!(transfer_cycles(&mut gb, 0x81), Some(8 * 512));
+        assert_eq!(transfer_cycles(&mut gb, 0x83), Some(8 * 512));
+        assert_eq!(gb.mmu.read(SERIAL_CONTROL_REGISTER).unwrap(), 0x7f);
+
+        // The CGB's fast clock runs at 262144Hz
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0143] = 0xc0;
+        let mut gb = GameBoy::new(None, rom);
+        gb.mmu.serial.connect(LinkDevice::Loopback.create());
+        assert_eq!(transfer_cycles(&mut gb, 0x81), Some(8 * 512));
+        assert_eq!(gb.mmu.read(SERIAL_CONTROL_REGISTER).unwrap(), 0x7d);
+        assert_eq!(transfer_cycles(&mut gb, 0x83), Some(8 * 16));
+        assert_eq!(gb.mmu.read(SERIAL_CONTROL_REGISTER).unwrap(), 0x7f);
+
+        // Double speed doubles the internal clocks along with the CPU, but not a partner's clock
+        gb.mmu.cgb_double_speed = true;
+        assert_eq!(transfer_cycles(&mut gb, 0x81), Some(8 * 512));
+        assert_eq!(transfer_cycles(&mut gb, 0x83), Some(8 * 16));
+        gb.mmu.write(SERIAL_CONTROL_REGISTER, 0x82).unwrap();
+        assert_eq!(gb.mmu.serial.cycles_until_bit(true), Some(1024));
+        assert_eq!(transfer_cycles(&mut gb, 0x82), Some(8 * 1024));
+    }
+
+    #[test]
+    fn test_tile_cache_invalidation() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0143] = 0xc0;
+        let mut gb = GameBoy::new(None, rom);
+        gb.mmu.write_unchecked(0xff40, 0b1001_0000);
+        gb.mmu.write(0xff4f, 0).unwrap();
+        gb.mmu.write(0x8010, 0x00).unwrap();
+        assert!(gb.dbg_render_tileset(0)[1].pixels[0][0].is_color(0));
+
+        // Writes end up in the right bank's tile, even after it was decoded once
+        gb.mmu.write(0x8010, 0x80).unwrap();
+        gb.mmu.write(0xff4f, 1).unwrap();
+        gb.mmu.write(0x8011, 0x80).unwrap();
+        assert!(gb.dbg_render_tileset(0)[1].pixels[0][0].is_color(1));
+        assert!(gb.dbg_render_tileset(1)[1].pixels[0][0].is_color(2));
+    }
+
+    #[test]
+    fn test_vram_generation() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0143] = 0xc0;
+        let mut gb = GameBoy::new(None, rom);
+        let state = gb.save_state();
+
+        // VRAM in either bank and the palettes count, other memory doesn't
+        let mut generation = gb.mmu.vram_generation();
+        for (addr, data) in [
+            (0x9800, 0x01),
+            (0xff4f, 0x01),
+            (0x8000, 0x01),
+            (0xff69, 0x1f),
+            (0xff47, 0xe4),
+        ] {
+            gb.mmu.write(addr, data).unwrap();
+            if addr != 0xff4f {
+                assert_ne!(gb.mmu.vram_generation(), generation, "{:04x}", addr);
+                generation = gb.mmu.vram_generation();
+            }
+        }
+        assert_eq!(gb.mmu.vram_generation(), generation);
+        gb.mmu.write(0xc000, 0x01).unwrap();
+        assert_eq!(gb.mmu.vram_generation(), generation);
+
+        // OAM has a generation of its own
+        let oam_generation = gb.mmu.oam_generation();
+        gb.mmu.write(0xfe00, 0x10).unwrap();
+        assert_eq!(gb.mmu.vram_generation(), generation);
+        assert_ne!(gb.mmu.oam_generation(), oam_generation);
+        let oam_generation = gb.mmu.oam_generation();
+
+        // Loading a state with other VRAM never goes back to a generation seen before
+        gb.load_state(&state).unwrap();
+        assert!(gb.mmu.vram_generation() > generation);
+        assert!(gb.mmu.oam_generation() > oam_generation);
+    }
+
+    #[test]
+    fn test_tilemap_cgb_attributes() {
+        let mut rom = vec![0u8; 0x8000];
+        rom[0x0143] = 0xc0;
+        let mut gb = GameBoy::new(None, rom);
+        gb.mmu.write_unchecked(0xff40, 0b0001_0000);
+
+        // Tile 0 in VRAM bank 1 only has its top left pixel set
+        gb.mmu.write(0xff4f, 1).unwrap();
+        gb.mmu.write(0x8000, 0x80).unwrap();
+        gb.mmu.write(0x8001, 0x80).unwrap();
+        let attributes = TileAttributes::BANK | TileAttributes::FLIP_X | TileAttributes::FLIP_Y;
+        gb.mmu.write(0x9800, attributes.bits()).unwrap();
+        gb.mmu.write(0x9c00, attributes.bits()).unwrap();
+
+        // The debug views have to show the tile the way it ends up on screen
+        for tiles in [gb.dbg_render_background_tilemap(), gb.dbg_render_window_tilemap()] {
+            assert!(tiles[0].pixel(7, 7).is_color(3));
+            assert!(tiles[0].pixel(0, 0).is_color(0));
+        }
+    }
+
+    #[test]
+    fn test_heatmap_counts_per_frame() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Dmg);
+        mmu.cpu_write(0xc010, 0x42).unwrap();
+        mmu.cpu_write(0xc0ff, 0x42).unwrap();
+        mmu.cpu_read(0xc100).unwrap();
+        // Only what the CPU does counts, not what the emulator or debugger read and write on their own
+        mmu.read(0xc100).unwrap();
+        mmu.write(0xc010, 0x42).unwrap();
+
+        // Nothing is visible until the frame is over
+        assert_eq!(mmu.heatmap.writes(0xc0), 0);
+
+        mmu.heatmap.end_frame();
+        assert_eq!(mmu.heatmap.writes(0xc0), 2);
+        assert_eq!(mmu.heatmap.reads(0xc1), 1);
+        assert_eq!(mmu.heatmap.reads(0xc0), 0);
+
+        mmu.heatmap.end_frame();
+        assert_eq!(mmu.heatmap.writes(0xc0), 0);
+
+        // Copies don't bring the counts along, the running console keeps them across state loads
+        let mut gb = GameBoy::new(None, vec![0u8; 0x8000]);
+        let state = gb.save_state();
+        gb.mmu.cpu_write(0xc010, 0x42).unwrap();
+        gb.mmu.heatmap.end_frame();
+        assert_eq!(gb.clone().mmu.heatmap.writes(0xc0), 0);
+        gb.load_state(&state).unwrap();
+        assert_eq!(gb.mmu.heatmap.writes(0xc0), 1);
+    }
+
+    #[test]
+    fn test_ram_watch() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Dmg);
+        mmu.write(0xc000, 0x34).unwrap();
+        mmu.write(0xc001, 0x12).unwrap();
+
+        let mut watches = vec![Watch::new(0xc000, WatchType::U16), Watch::new(0xc001, WatchType::Bcd)];
+        assert_eq!(watches[0].format(&watches[0].read(&mmu)), "4660 ($1234)");
+        assert_eq!(watches[1].format(&watches[1].read(&mmu)), "12");
+
+        // A frozen value keeps getting written back
+        watches[0].freeze(&mmu);
+        mmu.write(0xc000, 0xff).unwrap();
+        watches[0].apply(&mut mmu);
+        assert_eq!(mmu.read(0xc000).unwrap(), 0x34);
+
+        let saved = save_watches(&watches);
+        assert_eq!(saved, "c000 u16 3412\nc001 bcd");
+        assert_eq!(load_watches(&saved), watches);
+        assert!(load_watches("c000 u16 12\nzzzz u8\nc000 f32").is_empty());
+    }
+
+    #[test]
+    fn test_cheat_search() {
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Dmg);
+        mmu.write(0xc010, 3).unwrap();
+        mmu.write(0xd020, 3).unwrap();
+
+        let mut search = CheatSearch::new(mmu.dump_wram());
+        assert_eq!(search.candidates().len(), 0x2000);
+        search.filter(mmu.dump_wram(), Filter::EqualTo(3));
+        assert!(search.candidates().contains(&0xc010) && search.candidates().contains(&0xd020));
+
+        // Losing a life only decrements one of them
+        mmu.write(0xc010, 2).unwrap();
+        search.filter(mmu.dump_wram(), Filter::ChangedBy(-1));
+        assert_eq!(search.candidates(), [0xc010]);
+        assert_eq!(search.value(0xc010), 2);
+
+        mmu.write(0xc010, 0).unwrap();
+        search.filter(mmu.dump_wram(), Filter::Less);
+        assert_eq!(search.candidates(), [0xc010]);
+        search.filter(mmu.dump_wram(), Filter::Changed);
+        assert!(search.candidates().is_empty());
+    }
+
+    #[test]
+    fn test_run_ahead() {
+        let cartridge = std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap();
+        let mut gb = GameBoy::new(None, cartridge);
+        while gb.emulated_time().as_millis() < 500 {
+            System::run_frame(&mut gb);
+        }
+
+        // The future frame is shown, but the system itself doesn't move
+        let cycles = gb.cycle_count();
+        let future = gb.run_ahead(2);
+        assert_eq!(gb.cycle_count(), cycles);
+
+        System::run_frame(&mut gb);
+        System::run_frame(&mut gb);
+        assert_eq!(gb.framebuffer(), future);
+    }
+
+    #[test]
+    fn test_frame_skip_keeps_timing() {
+        let cartridge = std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap();
+        let mut drawn = GameBoy::new(None, cartridge.clone());
+        let mut skipped = GameBoy::new(None, cartridge);
+        while drawn.emulated_time().as_millis() < 500 {
+            drawn.run_frame();
+            skipped.run_frame();
+        }
+
+        // Skipped frames leave the last picture on screen, but emulate exactly the same
+        let last_frame = skipped.framebuffer();
+        skipped.set_rendering(false);
+        for _ in 0..10 {
+            drawn.run_frame();
+            skipped.run_frame();
+        }
+        assert_eq!(skipped.framebuffer(), last_frame);
+        assert_eq!(skipped.cycle_count(), drawn.cycle_count());
+        assert_eq!(
+            skipped.cpu.read_register16(&Register::PC),
+            drawn.cpu.read_register16(&Register::PC)
+        );
+
+        skipped.set_rendering(true);
+        drawn.run_frame();
+        skipped.run_frame();
+        assert_eq!(skipped.framebuffer(), drawn.framebuffer());
+    }
+
+    #[test]
+    fn test_output_callbacks() {
+        let cartridge = std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap();
+        let mut gb = GameBoy::new(None, cartridge);
+
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let samples = Rc::new(Cell::new(0));
+        gb.set_frame_callback({
+            let frames = frames.clone();
+            move |frame| frames.borrow_mut().push(*frame)
+        });
+        gb.set_audio_callback({
+            let samples = samples.clone();
+            move |buffer| samples.set(samples.get() + buffer.len())
+        });
+
+        // Every frame comes in as soon as it is done
+        for _ in 0..30 {
+            gb.run_frame();
+            assert_eq!(frames.borrow().last(), Some(&gb.ppu.pull_frame()));
+        }
+        assert_eq!(frames.borrow().len(), 30);
+
+        // Sound comes in whole buffers. Frames are cut short while the LCD is off, so go by emulated time.
+        while gb.emulated_time().as_millis() < 100 {
+            gb.run_frame();
+        }
+        assert!(samples.get() > 0 && samples.get().is_multiple_of(BUFFER_SIZE));
+
+        // Run-ahead copies stay quiet, a loaded state keeps reporting
+        let count = frames.borrow().len();
+        gb.run_ahead(2);
+        assert_eq!(frames.borrow().len(), count);
+        let state = gb.save_state();
+        gb.load_state(&state).unwrap();
+        let before = samples.get();
+        gb.run_frame();
+        assert_eq!(frames.borrow().len(), count + 1);
+        assert!(samples.get() > before);
+    }
+
+    #[test]
+    fn test_memory_snapshot() {
+        let cartridge = std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap();
+        let mut gb = GameBoy::new(None, cartridge);
+
+        let peeks = Rc::new(RefCell::new(Vec::new()));
+        gb.set_memory_callback({
+            let peeks = peeks.clone();
+            move |snapshot| peeks.borrow_mut().push((snapshot.size(), snapshot.peek(0xff44)))
+        });
+        for _ in 0..3 {
+            gb.run_frame();
+        }
+
+        // Taken once per frame as the frame ends, with no CGB WRAM banks on a DMG
+        assert_eq!(*peeks.borrow(), vec![(0x10000, Some(0)); 3]);
+
+        // Peeking doesn't count as a read
+        gb.mmu.heatmap.end_frame();
+        let snapshot = gb.memory_snapshot();
+        gb.mmu.heatmap.end_frame();
+        assert!((0..PAGE_COUNT).all(|page| gb.mmu.heatmap.reads(page) == 0));
+        assert_eq!(snapshot.peek(0xc000), Some(gb.mmu.read_unchecked(0xc000)));
+        assert_eq!(snapshot.peek16(0xffff), None);
+
+        // Copies don't report frames, a loaded state does
+        gb.run_ahead(2);
+        let state = gb.save_state();
+        gb.load_state(&state).unwrap();
+        gb.run_frame();
+        assert_eq!(peeks.borrow().len(), 4);
+    }
+
+    // Counts what would have been played, and pretends to play it instantly
+    struct CountingSink(Rc<Cell<usize>>);
+
+    impl AudioSink for CountingSink {
+        fn append(&self, samples: &[f32]) {
+            self.0.set(self.0.get() + samples.len());
+        }
+
+        fn queued(&self) -> usize {
+            0
+        }
+    }
+
+    #[test]
+    fn test_audio_sink() {
+        let mut gb = GameBoy::new(None, vec![0u8; 0x8000]);
+        let played = Rc::new(Cell::new(0));
+
+        // Without a device the samples go nowhere, with one they all end up there. The LCD stays off and cuts
+        // frames short, so go by emulated time.
+        gb.run_frame();
+        gb.mmu.apu.set_audio_sink(Box::new(CountingSink(played.clone())));
+        while gb.emulated_time().as_millis() < 100 {
+            gb.run_frame();
+        }
+        assert!(played.get() > 0 && played.get().is_multiple_of(BUFFER_SIZE));
+
+        // Copies never play on the device
+        let before = played.get();
+        gb.run_ahead(2);
+        assert_eq!(played.get(), before);
+        assert!(gb.calibrate_audio().is_some());
+    }
+
+    // A minimal BESS state as SameBoy would write it: memory dumps first, then the blocks and the footer
+    fn bess_state(model: &[u8; 4], title: &[u8]) -> Vec<u8> {
+        let block = |id: &[u8; 4], body: &[u8]| {
+            let mut block = id.to_vec();
+            block.extend((body.len() as u32).to_le_bytes());
+            block.extend(body);
+            block
+        };
+
+        let mut ram = vec![0u8; 0x2000];
+        ram[0x0010] = 0x77;
+        ram[0x0123] = 0x42;
+        ram[0x1000] = 0x24;
+        let mut hram = vec![0u8; 0x7f];
+        hram[0x10] = 0x99;
+        let mut data = [ram, vec![0u8; 0x2000], vec![0u8; 0xa0], hram].concat();
+
+        let mut core = Vec::new();
+        core.extend(1u16.to_le_bytes());
+        core.extend(1u16.to_le_bytes());
+        core.extend(model);
+        for register in [0x0150u16, 0x1280, 0x0013, 0x00d8, 0x014d, 0xdffe] {
+            core.extend(register.to_le_bytes());
+        }
+        core.extend([1, 0x05, 0, 0]); // IME, IE, running
+        let mut io = vec![0u8; 0x80];
+        io[0x40] = 0x91;
+        io[0x46] = 0xc0; // would start an OAM DMA
+        io[0x26] = 0x80;
+        io[0x35] = 0x5a; // wave RAM
+        core.extend(io);
+        for (size, offset) in [
+            (0x2000, 0),
+            (0x2000, 0x2000),
+            (0, 0),
+            (0xa0, 0x4000),
+            (0x7f, 0x40a0),
+            (0, 0),
+            (0, 0),
+        ] {
+            core.extend((size as u32).to_le_bytes());
+            core.extend((offset as u32).to_le_bytes());
+        }
+
+        let mut info = title.to_vec();
+        info.resize(0x12, 0);
+
+        let first_block = data.len() as u32;
+        data.extend(block(b"NAME", b"SameBoy v0.16"));
+        data.extend(block(b"INFO", &info));
+        data.extend(block(b"CORE", &core));
+        data.extend(block(b"END ", &[]));
+        data.extend(first_block.to_le_bytes());
+        data.extend(b"BESS");
+        data
+    }
+
+    #[test]
+    fn test_import_bess_state() {
+        let mut gb = GameBoy::new(Some(skip_bootrom()), vec![0u8; 0x8000]);
+
+        gb.import_state(&bess_state(b"GD  ", b"")).unwrap();
+        assert_eq!(gb.cpu.read_register16(&Register::PC), 0x0150);
+        assert_eq!(gb.cpu.read_register(&Register::A), 0x12);
+        assert_eq!(gb.cpu.read_register16(&Register::SP), 0xdffe);
+        assert!(gb.cpu.interrupt_master_raised());
+        assert_eq!(gb.mmu.read_unchecked(0xc123), 0x42);
+        assert_eq!(gb.mmu.read_unchecked(0xd000), 0x24);
+        assert_eq!(gb.mmu.read_unchecked(0xff90), 0x99);
+        assert_eq!(gb.mmu.read_unchecked(LCD_CONTROL_REGISTER), 0x91);
+        assert_eq!(gb.mmu.read_unchecked(INTERRUPT_ENABLE_REGISTER), 0x05);
+        assert_eq!(gb.mmu.read_unchecked(WAVE_PATTERN_RAM_START + 5), 0x5a);
+        assert!(gb.mmu.oam().iter().all(|byte| *byte == 0));
+
+        // Other hardware, other games and other emulators' formats are refused without touching the console
+        for data in [
+            bess_state(b"CC  ", b""),
+            bess_state(b"GD  ", b"POKEMON RED"),
+            b"not a save state".to_vec(),
+        ] {
+            assert!(matches!(gb.import_state(&data), Err(AyyError::InvalidSaveState { .. })));
+        }
+        assert_eq!(gb.cpu.read_register16(&Register::PC), 0x0150);
+    }
+
+    #[test]
+    fn test_save_state_rom_hash() {
+        let hash = RomHash::of(b"abc");
+        assert_eq!(hash.to_string(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(RomHash::parse(&format!("{}\n", hash)), Some(hash));
+        assert_eq!(RomHash::parse("a9993e36"), None);
+        assert_eq!(RomHash::parse(&"zz".repeat(20)), None);
+
+        let rom = vec![0u8; 0x8000];
+        let mut hack = rom.clone();
+        hack[0x200] = 0x01;
+
+        let mut gb = GameBoy::new(None, rom.clone());
+        let state = gb.save_state();
+        assert_eq!(state.rom_hash(), RomHash::of(&rom));
+
+        // Another revision swapped in refuses the state, unless forced
+        gb.hot_swap_cartridge(hack.clone()).unwrap();
+        assert_eq!(gb.rom_hash(), RomHash::of(&hack));
+        assert!(matches!(
+            gb.load_state(&state),
+            Err(AyyError::SaveStateRomMismatch { .. })
+        ));
+        gb.force_load_state(&state).unwrap();
+        assert_eq!(gb.rom_hash(), RomHash::of(&rom));
+        gb.load_state(&state).unwrap();
+    }
+
+    #[test]
+    fn test_save_info() {
+        let info = SaveInfo {
+            rom_hash: Some(RomHash::of(b"abc")),
+            saved_at: 1_700_000_000,
+            play_time: Duration::from_secs(3723),
+        };
+        assert_eq!(SaveInfo::parse(&info.serialize()), info);
+        assert_eq!(
+            SaveInfo::parse(
+                "rom_sha1=bogus
+play_time=bogus
+foo=bar"
+            ),
+            SaveInfo::default()
+        );
+
+        // Saves that only came with the hash still know which ROM wrote them, until saved again
+        let save_path = std::env::temp_dir().join("ayyboy-save-info.sav");
+        let save_path = save_path.to_str().unwrap();
+        let _ = std::fs::remove_file(SaveInfo::path(save_path));
+        assert_eq!(SaveInfo::load(save_path), None);
+        std::fs::write(format!("{}.sha1", save_path), info.rom_hash.unwrap().to_string()).unwrap();
+        assert_eq!(SaveInfo::load(save_path).unwrap().rom_hash, info.rom_hash);
+
+        info.save(save_path).unwrap();
+        assert!(!std::path::Path::new(&format!("{}.sha1", save_path)).exists());
+        assert_eq!(SaveInfo::load(save_path), Some(info));
+        std::fs::remove_file(SaveInfo::path(save_path)).unwrap();
+
+        // The screenshot sits next to the info
+        assert_eq!(SaveInfo::screenshot_path("rom.gb.sav"), "rom.gb.sav.png");
+    }
+
+    #[test]
+    fn test_threaded_rendering() {
+        for path in [
+            "./external/roms/tests/dmg-acid2.gb",
+            "./external/roms/tests/cgb-acid2.gbc",
+        ] {
+            let cartridge = std::fs::read(path).unwrap();
+            let mut inline = GameBoy::new(None, cartridge.clone());
+            let mut threaded = GameBoy::new(None, cartridge);
+            threaded.ppu.set_threaded_rendering(true);
+
+            // Every frame has to come out exactly like the one drawn on the emulation thread
+            while inline.emulated_time().as_millis() < 500 {
+                inline.run_frame();
+                threaded.run_frame();
+                assert_eq!(threaded.framebuffer(), inline.framebuffer(), "{}", path);
+            }
+
+            // Loading a state keeps the render thread, which has to pick up the state's VRAM
+            let state = inline.save_state();
+            threaded.load_state(&state).unwrap();
+            assert!(threaded.ppu.threaded_rendering());
+            inline.run_frame();
+            threaded.run_frame();
+            assert_eq!(threaded.framebuffer(), inline.framebuffer(), "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_accuracy_modes() {
+        // Without raster effects all modes draw the same picture
+        for path in [
+            "./external/roms/tests/dmg-acid2.gb",
+            "./external/roms/tests/cgb-acid2.gbc",
+        ] {
+            let cartridge = std::fs::read(path).unwrap();
+            let mut balanced = GameBoy::new(None, cartridge.clone());
+            let mut others = [Accuracy::Fast, Accuracy::PerPixel]
+                .iter()
+                .map(|accuracy| {
+                    let mut gb = GameBoy::new(None, cartridge.clone());
+                    gb.set_accuracy(*accuracy);
+                    gb
+                })
+                .collect::<Vec<GameBoy>>();
+
+            while balanced.emulated_time().as_millis() < 500 {
+                balanced.run_frame();
+                for gb in others.iter_mut() {
+                    gb.run_frame();
+                    assert_eq!(gb.framebuffer(), balanced.framebuffer(), "{}", path);
+                }
+            }
+        }
+
+        // BGP turns black during mode 3 of line 72 and white again in its HBlank
+        let program = [
+            "ld a, $00",
+            "ldh ($47), a",
+            "ld a, $91",
+            "ldh ($40), a",
+            "ldh a, ($44)",
+            "cp a, $48",
+            "jr nz, -6",
+            "ldh a, ($41)",
+            "and a, $03",
+            "cp a, $03",
+            "jr nz, -8",
+            "ld a, $ff",
+            "ldh ($47), a",
+            "ldh a, ($41)",
+            "and a, $03",
+            "jr nz, -6",
+            "ld a, $00",
+            "ldh ($47), a",
+            "ldh a, ($44)",
+            "cp a, $48",
+            "jr z, -6",
+            "jr -36",
+        ];
+        let (bootrom, rom) = rom_with_code(&program);
+
+        let lines = [Accuracy::Fast, Accuracy::Balanced, Accuracy::PerPixel].map(|accuracy| {
+            let mut gb = GameBoy::new(Some(bootrom.clone()), rom.clone());
+            gb.set_accuracy(accuracy);
+            for _ in 0..3 {
+                System::run_frame(&mut gb);
+            }
+            gb.framebuffer()
+                .chunks(SCREEN_WIDTH)
+                .map(|line| line.iter().filter(|color| **color == [0, 0, 0]).count())
+                .collect::<Vec<usize>>()
+        });
+
+        // Only per-pixel mode catches the write in the middle of the line
+        assert!(lines[0].iter().all(|black| *black == 0));
+        assert!(lines[1].iter().all(|black| *black == 0));
+        assert!(lines[2][72] > 0 && lines[2][72] < SCREEN_WIDTH);
+        assert_eq!(lines[2].iter().sum::<usize>(), lines[2][72]);
+
+        // BGP turns black in the HBlank of line 72 and white again in the HBlank of line 73
+        let program = [
+            "ld a, $00",
+            "ldh ($47), a",
+            "ld a, $91",
+            "ldh ($40), a",
+            "ldh a, ($44)",
+            "cp a, $48",
+            "jr nz, -6",
+            "ldh a, ($41)",
+            "and a, $03",
+            "cp a, $03",
+            "jr nz, -8",
+            "ldh a, ($41)",
+            "and a, $03",
+            "jr nz, -6",
+            "ld a, $ff",
+            "ldh ($47), a",
+            "ldh a, ($44)",
+            "cp a, $49",
+            "jr nz, -6",
+            "ldh a, ($41)",
+            "and a, $03",
+            "cp a, $03",
+            "jr nz, -8",
+            "ldh a, ($41)",
+            "and a, $03",
+            "jr nz, -6",
+            "ld a, $00",
+            "ldh ($47), a",
+            "ldh a, ($44)",
+            "cp a, $49",
+            "jr z, -6",
+            "jr -56",
+        ];
+        let (bootrom, rom) = rom_with_code(&program);
+
+        // Balanced mode draws a line when mode 3 ends, with the registers mode 3 saw
+        for (accuracy, threaded) in [
+            (Accuracy::Balanced, false),
+            (Accuracy::Balanced, true),
+            (Accuracy::PerPixel, false),
+        ] {
+            let mut gb = GameBoy::new(Some(bootrom.clone()), rom.clone());
+            gb.set_accuracy(accuracy);
+            gb.ppu.set_threaded_rendering(threaded);
+            for _ in 0..3 {
+                System::run_frame(&mut gb);
+            }
+            let black = gb
+                .framebuffer()
+                .chunks(SCREEN_WIDTH)
+                .map(|line| line.iter().filter(|color| **color == [0, 0, 0]).count())
+                .collect::<Vec<usize>>();
+            assert_eq!(black[73], SCREEN_WIDTH, "{:?}", accuracy);
+            assert_eq!(black.iter().sum::<usize>(), SCREEN_WIDTH, "{:?}", accuracy);
+        }
+
+        // Same with the CGB palettes, the background turns black in the HBlank of line 72 and back in the next one
+        let set_color =
+            |lo: &'static str, hi: &'static str| ["ld a, $80", "ldh ($68), a", lo, "ldh ($69), a", hi, "ldh ($69), a"];
+        let wait_hblank = [
+            "ldh a, ($41)",
+            "and a, $03",
+            "cp a, $03",
+            "jr nz, -8",
+            "ldh a, ($41)",
+            "and a, $03",
+            "jr nz, -6",
+        ];
+        let program = [
+            &set_color("ld a, $ff", "ld a, $7f")[..],
+            &["ld a, $91", "ldh ($40), a"],
+            &["ldh a, ($44)", "cp a, $48", "jr nz, -6"],
+            &wait_hblank,
+            &set_color("ld a, $00", "ld a, $00"),
+            &["ldh a, ($44)", "cp a, $49", "jr nz, -6"],
+            &wait_hblank,
+            &set_color("ld a, $ff", "ld a, $7f"),
+            &["ldh a, ($44)", "cp a, $49", "jr z, -6", "jr -72"],
+        ]
+        .concat();
+        let (bootrom, mut rom) = rom_with_code(&program);
+        rom[0x0143] = 0x80;
+        for threaded in [false, true] {
+            let mut gb = GameBoy::new(Some(bootrom.clone()), rom.clone());
+            gb.ppu.set_threaded_rendering(threaded);
+            for _ in 0..3 {
+                System::run_frame(&mut gb);
+            }
+            let black = gb
+                .framebuffer()
+                .chunks(SCREEN_WIDTH)
+                .map(|line| line.iter().filter(|color| **color == [0, 0, 0]).count())
+                .collect::<Vec<usize>>();
+            assert_eq!(black[73], SCREEN_WIDTH);
+            assert_eq!(black.iter().sum::<usize>(), SCREEN_WIDTH);
+        }
+
+        // The choice is remembered per ROM
+        let settings = RomSettings {
+            accuracy: Accuracy::PerPixel,
+            color_correction: ColorCorrection::Gba,
+            compat_palette: CompatPalette::DarkBlue,
+            stats: PlayStats {
+                play_time: Duration::from_secs(3723),
+                frames: 223_380,
+                states_saved: 4,
+                states_loaded: 2,
+                last_played: 1_700_000_000,
+            },
+        };
+        assert_eq!(RomSettings::parse(&settings.serialize()), settings);
+        // Settings saved before there were stats still load
+        assert_eq!(
+            RomSettings::parse("accuracy=accurate\nframes=bogus").stats,
+            PlayStats::default()
+        );
+        // The per-pixel mode used to be called accurate
+        assert_eq!(RomSettings::parse("accuracy=accurate").accuracy, Accuracy::PerPixel);
+        assert_eq!(
+            RomSettings::parse("accuracy=bogus\nfoo=bar").accuracy,
+            Accuracy::Balanced
+        );
+    }
+
+    #[test]
+    fn test_netplay_rollback() {
+        let cartridge = std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap();
+        let session = SessionInfo {
+            rom_hash: RomHash::of(&cartridge),
+            settings_crc: 0,
+            delay: 1,
+        };
+
+        // Both sides send their half of the handshake before reading, so the client needs its own thread
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let client = std::thread::spawn(move || Netplay::handshake(&mut stream, session).map(|_| stream));
+        let mut host = Netplay::start(listener.accept().unwrap().0, session).unwrap();
+        let mut client = Netplay::new(client.join().unwrap().unwrap(), session.delay).unwrap();
+
+        let mut host_gb = GameBoy::new(None, cartridge.clone());
+        let mut client_gb = GameBoy::new(None, cartridge);
+
+        // The client only gets a turn every third iteration, so the host runs ahead and its
+        // predictions for the client input turn out wrong
+        let mut frames = (0, 0);
+        for iteration in 0.. {
+            let input = if frames.0 % 7 < 3 { Button::A.mask() } else { 0 };
+            if host.advance(&mut host_gb, input).unwrap() {
+                frames.0 += 1;
+            }
+
+            let input = if frames.1 % 5 < 2 { Button::Start.mask() } else { 0 };
+            if iteration % 3 == 0 && client.advance(&mut client_gb, input).unwrap() {
+                frames.1 += 1;
+            }
+
+            if frames.1 >= 20 {
+                break;
+            }
+        }
+
+        // Once both sides stop pressing buttons and catch up, both have to arrive at the same state
+        while frames.0 < 40 || frames.1 < 40 {
+            if frames.0 < 40 && host.advance(&mut host_gb, 0).unwrap() {
+                frames.0 += 1;
+            }
+            if frames.1 < 40 && client.advance(&mut client_gb, 0).unwrap() {
+                frames.1 += 1;
+            }
+        }
+
+        assert_eq!(host_gb.cycle_count(), client_gb.cycle_count());
+        assert_eq!(host_gb.framebuffer(), client_gb.framebuffer());
+        assert_eq!(
+            (0xc000..0xe000)
+                .map(|addr| host_gb.mmu.read_unchecked(addr))
+                .collect::<Vec<u8>>(),
+            (0xc000..0xe000)
+                .map(|addr| client_gb.mmu.read_unchecked(addr))
+                .collect::<Vec<u8>>()
+        );
+
+        let different = SessionInfo {
+            rom_hash: RomHash([0; 20]),
+            ..session
+        };
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let client = std::thread::spawn(move || Netplay::handshake(&mut stream, different).is_err());
+        assert!(Netplay::start(listener.accept().unwrap().0, session).is_err());
+        assert!(client.join().unwrap());
+    }
+
+    #[test]
+    fn test_sweep_quirks() {
+        let mut apu = Apu::new(Mode::Dmg);
+        apu.write(NR52, 0x80);
+        apu.write(NR12, 0xf0);
+
+        // The overflow check on trigger doesn't write the frequency back
+        apu.write(NR10, 0x19); // Period 1, negate, shift 1
+        apu.write(NR13, 0x00);
+        apu.write(NR14, 0x84); // Frequency $400 and trigger
+        assert!(apu.channels_enabled()[0]);
+        assert_eq!(apu.channel_states()[0].frequency, 128.0);
+
+        // Leaving negate mode after a negated calculation disables the channel
+        apu.write(NR10, 0x11);
+        assert!(!apu.channels_enabled()[0]);
+
+        // ... but not if nothing was calculated since the trigger
+        apu.write(NR10, 0x18);
+        apu.write(NR14, 0x84);
+        apu.write(NR10, 0x10);
+        assert!(apu.channels_enabled()[0]);
+
+        // Overflowing on trigger disables it right away
+        apu.write(NR10, 0x11);
+        apu.write(NR13, 0xff);
+        apu.write(NR14, 0x87);
+        assert!(!apu.channels_enabled()[0]);
+    }
+
+    #[test]
+    fn test_length_extra_clocking() {
+        let mut apu = Apu::new(Mode::Dmg);
+        apu.write(NR52, 0x80);
+        apu.write(NR12, 0xf0);
+
+        // Move the frame sequencer to step 1, the next step doesn't clock lengths
+        apu.tick(8192, false);
+
+        // Enabling length clocks it right away, running out a length of 1
+        apu.write(NR11, 0x3f);
+        apu.write(NR14, 0x80);
+        assert!(apu.channels_enabled()[0]);
+        apu.write(NR14, 0x40);
+        assert!(!apu.channels_enabled()[0]);
+
+        // Triggering with an empty counter and length enabled reloads it with 63 instead of 64
+        apu.write(NR14, 0xc0);
+        assert!(apu.channels_enabled()[0]);
+        apu.tick(62 * 16384, false);
+        assert!(apu.channels_enabled()[0]);
+        apu.tick(16384, false);
+        assert!(!apu.channels_enabled()[0]);
+    }
+
+    #[test]
+    fn test_wave_ram_while_playing() {
+        for mode in [Mode::Dmg, Mode::Cgb] {
+            let mut apu = Apu::new(mode.clone());
+            apu.write(NR52, 0x80);
+            for (i, addr) in (WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END).enumerate() {
+                apu.write(addr, i as u8);
+            }
+            assert_eq!(apu.read(WAVE_PATTERN_RAM_START + 5), 5);
+
+            // Frequency $7ff steps a sample every 2 cycles, after 9 cycles the 6th sample (byte 2) plays
+            apu.write(NR30, 0x80);
+            apu.write(NR33, 0xff);
+            apu.write(NR34, 0x87);
+            apu.tick(9, false);
+
+            if mode == Mode::Cgb {
+                assert_eq!(apu.read(WAVE_PATTERN_RAM_START + 5), 2);
+                apu.write(WAVE_PATTERN_RAM_START + 9, 0xaa);
+                assert_eq!(apu.read(WAVE_PATTERN_RAM_START), 0xaa);
+            } else {
+                assert_eq!(apu.read(WAVE_PATTERN_RAM_START + 5), 0xff);
+                apu.write(WAVE_PATTERN_RAM_START + 9, 0xaa);
+            }
+
+            // Stopping the channel makes the whole RAM accessible again
+            apu.write(NR30, 0x00);
+            assert_eq!(apu.read(WAVE_PATTERN_RAM_START + 5), 5);
+            assert_eq!(apu.read(WAVE_PATTERN_RAM_START + 9), 9);
+        }
+    }
+
+    #[test]
+    fn test_change_overlay() {
+        let mut overlay = ChangeOverlay::new();
+        let first = vec![[0x10, 0x20, 0x30]; 4];
+        assert_eq!(overlay.apply(&first), first);
+
+        let mut second = first.clone();
+        second[2] = [0x00, 0x00, 0x00];
+        let overlaid = overlay.apply(&second);
+        assert_eq!(overlaid[..2], second[..2]);
+        assert_eq!(overlaid[3], second[3]);
+        assert_ne!(overlaid[2], second[2]);
+        assert!(overlaid[2][0] > overlaid[2][1]);
+
+        // Compared against the clean previous frame, not the highlighted one
+        assert_eq!(overlay.apply(&second), second);
+    }
+
+    #[test]
+    fn test_channel_notes() {
+        assert_eq!(Note::nearest(440.0).unwrap().to_string(), "A4 +0c");
+        let middle_c = Note::nearest(263.0).unwrap();
+        assert_eq!((middle_c.name, middle_c.octave, middle_c.cents), ("C", 4, 9));
+        assert_eq!(Note::nearest(8.0), None);
+
+        let mut apu = Apu::new(Mode::Dmg);
+        apu.write(NR52, 0x80);
+        apu.write(NR12, 0xf3); // Volume 15, decreasing every 3 steps
+        apu.write(NR13, 0xd6);
+        apu.write(NR14, 0x86); // Frequency 1750 and trigger
+        apu.write(NR32, 0x40); // Half volume
+
+        let [square1, square2, wave, noise] = apu.channel_states();
+        assert!(square1.enabled);
+        assert_eq!(
+            Note::nearest(square1.frequency).map(|note| (note.name, note.octave)),
+            Some(("A", 4))
+        );
+        assert_eq!(square1.volume, 15);
+        assert_eq!(
+            square1.envelope,
+            Some(Envelope {
+                initial_volume: 15,
+                incrementing: false,
+                period: 3
+            })
+        );
+        assert!(!square2.enabled);
+        assert_eq!(wave.volume, 7);
+        assert_eq!(wave.envelope, None);
+        assert_eq!(noise.frequency, 524288.0);
+    }
+
+    #[test]
+    fn test_pcm_registers() {
+        // Plain memory on the DMG
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Dmg);
+        mmu.write_unchecked(PCM12, 0x12);
+        assert_eq!(mmu.read_unchecked(PCM12), 0x12);
+
+        let mut mmu = Mmu::new(vec![], Box::new(Rom::new(vec![0u8; 0x8000])), Mode::Cgb);
+        mmu.write_unchecked(NR52, 0x80);
+        for addr in WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END {
+            mmu.write_unchecked(addr, 0xff);
+        }
+        mmu.write_unchecked(NR11, 0x80); // 50% duty
+        mmu.write_unchecked(NR12, 0xf0); // Volume 15
+        mmu.write_unchecked(NR14, 0x80);
+        mmu.write_unchecked(NR30, 0x80);
+        mmu.write_unchecked(NR32, 0x20); // Full volume
+        mmu.write_unchecked(NR34, 0x80);
+
+        // Channel 1 goes up and down with its duty cycle, channel 2 and 4 are silent
+        let mut outputs = HashSet::new();
+        for _ in 0..256 {
+            mmu.apu.tick(256, false);
+            outputs.insert(mmu.read_unchecked(PCM12));
+            assert_eq!(mmu.read_unchecked(PCM34), 0x0f);
+        }
+        assert_eq!(outputs, HashSet::from([0x00, 0x0f]));
+
+        // Read-only
+        mmu.write_unchecked(PCM34, 0x00);
+        assert_eq!(mmu.read_unchecked(PCM34), 0x0f);
+    }
+
+    #[test]
+    fn test_apu_batched_ticks() {
+        let setup = || {
+            let mut apu = Apu::new(Mode::Dmg);
+            apu.write(NR52, 0x80);
+            apu.write(NR50, 0x77);
+            apu.write(NR51, 0xf5);
+            for addr in WAVE_PATTERN_RAM_START..=WAVE_PATTERN_RAM_END {
+                apu.write(addr, (addr as u8).wrapping_mul(0x37));
+            }
+            let registers = [
+                (NR10, 0x16), // Sweep up
+                (NR11, 0x80),
+                (NR12, 0xf3),
+                (NR13, 0x37),
+                (NR14, 0xc6), // Length enabled
+                (NR21, 0x40),
+                (NR22, 0x8b),
+                (NR23, 0x90),
+                (NR24, 0x87),
+                (NR30, 0x80),
+                (NR32, 0x20),
+                (NR33, 0xd0),
+                (NR34, 0x87),
+                (NR42, 0xf1),
+                (NR43, 0x51),
+                (NR44, 0x80),
+            ];
+            for (addr, value) in registers {
+                apu.write(addr, value);
+            }
+            apu
+        };
+
+        // Spans of all sizes, within a sample, across samples and across frame sequencer steps
+        let mut batched = setup();
+        let mut stepped = setup();
+        for span in [1, 7, 86, 87, 200, 8191, 8192, 20000, 3, 1234] {
+            batched.tick(span, false);
+            for _ in 0..span {
+                stepped.tick(1, false);
+            }
+            assert_eq!(batched.read(PCM12), stepped.read(PCM12), "{}", span);
+            assert_eq!(batched.read(PCM34), stepped.read(PCM34), "{}", span);
+            assert_eq!(batched.channel_states(), stepped.channel_states());
+        }
+        assert!(batched.buffer_position > 0);
+        assert_eq!(batched.buffer_position, stepped.buffer_position);
+        assert_eq!(batched.buffer, stepped.buffer);
+    }
+
+    #[test]
+    fn test_audio_pitch() {
+        // A 1024Hz square wave for a second of emulated time, counted in rising edges per played sample
+        let pitch = |double_speed: bool, speed: usize| {
+            let mut apu = Apu::new(Mode::Cgb);
+            let samples = Rc::new(RefCell::new(Vec::<f32>::new()));
+            let collected = samples.clone();
+            apu.set_sample_callback(Some(Box::new(move |buffer: &[f32]| {
+                collected.borrow_mut().extend(buffer.iter().step_by(2))
+            })));
+            apu.set_speed(speed);
+            for (addr, value) in [
+                (NR52, 0x80),
+                (NR50, 0x77),
+                (NR51, 0x22),
+                (NR21, 0x80),
+                (NR22, 0xf0),
+                (NR23, 0x80),
+                (NR24, 0x87),
+            ] {
+                apu.write(addr, value);
+            }
+
+            let cycles = if double_speed { 2 * CPU_CLOCK } else { CPU_CLOCK };
+            for _ in 0..cycles / 1024 {
+                apu.tick(1024, double_speed);
+            }
+            let samples = samples.borrow();
+            let edges = samples.windows(2).filter(|pair| pair[0] < pair[1]).count();
+            (edges as f64 / samples.len() as f64, samples.len())
+        };
+
+        // Double speed and fast forward play fewer or more samples, the tone stays the same
+        let (normal, played) = pitch(false, 1);
+        assert!((normal - 1024.0 / SAMPLE_RATE as f64).abs() < 0.001);
+        let (double, double_played) = pitch(true, 1);
+        assert!((double - normal).abs() < 0.001);
+        assert_eq!(double_played, played);
+        let (fast, fast_played) = pitch(false, 4);
+        assert!((fast - normal).abs() < 0.001);
+        assert!(fast_played.abs_diff(played / 4) <= BUFFER_SIZE);
+    }
+
+    #[test]
+    fn test_frame_stream() {
+        let mut gameboy = GameBoy::new(None, std::fs::read("./external/roms/tests/dmg-acid2.gb").unwrap());
+        while gameboy.emulated_time().as_millis() < 500 {
+            gameboy.run_frame();
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let mut sink = FrameStream::new(listener).unwrap();
+
+        let framebuffer = System::framebuffer(&gameboy);
+        sink.push_frame(SCREEN_WIDTH, SCREEN_HEIGHT, &framebuffer);
+
+        let mut frame = vec![0u8; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+        std::io::Read::read_exact(&mut client, &mut frame).unwrap();
+        assert_eq!(frame, framebuffer.iter().flatten().copied().collect::<Vec<u8>>());
     }
 }
@@ -0,0 +1,214 @@
+use std::sync::Arc;
+
+use crate::memory::mmu::Mmu;
+use crate::memory::registers::LcdControl;
+use crate::memory::{OAM_END, OAM_START, VRAM_START};
+use crate::video::cram::Cram;
+use crate::video::ppu::WindowLine;
+use crate::video::tile_cache::DirtyTiles;
+use crate::video::{
+    BG_PALETTE_REGISTER, LCD_CONTROL_REGISTER, OBJ0_PALETTE_REGISTER, OBJ1_PALETTE_REGISTER, SCANLINE_Y_REGISTER,
+    SCROLL_X_REGISTER, SCROLL_Y_REGISTER, WINDOW_X_REGISTER, WINDOW_Y_REGISTER,
+};
+
+// Everything the scanline renderer reads. Implemented by the MMU itself and by line snapshots,
+// so the same rendering code can run on the emulation thread or on the render worker.
+pub trait VideoBus {
+    // LCD registers ($ff40-$ff4b) and OAM
+    fn read_register(&self, addr: u16) -> u8;
+    fn read_vram(&self, addr: u16, bank: u8) -> u8;
+    fn cram(&self) -> &Cram;
+
+    #[inline]
+    fn lcd_control(&self) -> LcdControl {
+        LcdControl::from_bits_truncate(self.read_register(LCD_CONTROL_REGISTER))
+    }
+}
+
+impl VideoBus for Mmu {
+    #[inline]
+    fn read_register(&self, addr: u16) -> u8 {
+        self.read_unchecked(addr)
+    }
+
+    #[inline]
+    fn read_vram(&self, addr: u16, bank: u8) -> u8 {
+        self.read_from_vram(addr, bank)
+    }
+
+    #[inline]
+    fn cram(&self) -> &Cram {
+        &self.cgb_cram
+    }
+}
+
+// The registers a line is drawn with. The hardware picks these up as mode 3 starts, raster effects
+// change them in HBlank for the next line, which has to be ignored when drawing a line after it ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineRegisters {
+    pub lcdc: u8,
+    pub scy: u8,
+    pub scx: u8,
+    pub bgp: u8,
+    pub obp0: u8,
+    pub obp1: u8,
+    pub wy: u8,
+    pub wx: u8,
+}
+
+impl LineRegisters {
+    pub fn latch(bus: &dyn VideoBus) -> LineRegisters {
+        LineRegisters {
+            lcdc: bus.read_register(LCD_CONTROL_REGISTER),
+            scy: bus.read_register(SCROLL_Y_REGISTER),
+            scx: bus.read_register(SCROLL_X_REGISTER),
+            bgp: bus.read_register(BG_PALETTE_REGISTER),
+            obp0: bus.read_register(OBJ0_PALETTE_REGISTER),
+            obp1: bus.read_register(OBJ1_PALETTE_REGISTER),
+            wy: bus.read_register(WINDOW_Y_REGISTER),
+            wx: bus.read_register(WINDOW_X_REGISTER),
+        }
+    }
+
+    // None for registers that aren't latched
+    #[inline]
+    pub fn get(&self, addr: u16) -> Option<u8> {
+        match addr {
+            LCD_CONTROL_REGISTER => Some(self.lcdc),
+            SCROLL_Y_REGISTER => Some(self.scy),
+            SCROLL_X_REGISTER => Some(self.scx),
+            BG_PALETTE_REGISTER => Some(self.bgp),
+            OBJ0_PALETTE_REGISTER => Some(self.obp0),
+            OBJ1_PALETTE_REGISTER => Some(self.obp1),
+            WINDOW_Y_REGISTER => Some(self.wy),
+            WINDOW_X_REGISTER => Some(self.wx),
+            _ => None,
+        }
+    }
+}
+
+// Everything a line is drawn from: another bus with LY and the line's latched registers in place,
+// for drawing a line after the PPU has moved on
+pub struct LatchedLine<'a> {
+    pub bus: &'a dyn VideoBus,
+    pub scanline: u8,
+    pub registers: LineRegisters,
+}
+
+impl<'a> LatchedLine<'a> {
+    // Takes the registers as they are right now
+    pub fn new(bus: &'a dyn VideoBus, scanline: u8) -> LatchedLine<'a> {
+        LatchedLine {
+            bus,
+            scanline,
+            registers: LineRegisters::latch(bus),
+        }
+    }
+}
+
+impl VideoBus for LatchedLine<'_> {
+    #[inline]
+    fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            SCANLINE_Y_REGISTER => self.scanline,
+            _ => self.registers.get(addr).unwrap_or_else(|| self.bus.read_register(addr)),
+        }
+    }
+
+    #[inline]
+    fn read_vram(&self, addr: u16, bank: u8) -> u8 {
+        self.bus.read_vram(addr, bank)
+    }
+
+    #[inline]
+    fn cram(&self) -> &Cram {
+        self.bus.cram()
+    }
+}
+
+// VRAM, OAM and CRAM at the time a line was captured. Consecutive lines share one copy until
+// the game changes any of it, which outside of VBlank is rare.
+pub struct VideoMemory {
+    vram: [Vec<u8>; 2],
+    oam: Vec<u8>,
+    cram: Cram,
+    // The Mmu's VRAM (which covers the palettes) and OAM generations the copy was made at
+    generations: (u64, u64),
+}
+
+impl VideoMemory {
+    fn capture(mmu: &Mmu) -> VideoMemory {
+        VideoMemory {
+            vram: [mmu.vram(0).to_vec(), mmu.vram(1).to_vec()],
+            oam: mmu.oam().to_vec(),
+            cram: mmu.cgb_cram.clone(),
+            generations: VideoMemory::generations(mmu),
+        }
+    }
+
+    #[inline]
+    fn generations(mmu: &Mmu) -> (u64, u64) {
+        (mmu.vram_generation(), mmu.oam_generation())
+    }
+
+    #[inline]
+    fn matches(&self, mmu: &Mmu) -> bool {
+        self.generations == VideoMemory::generations(mmu)
+    }
+}
+
+// The state one scanline is drawn from, see RenderWorker
+pub struct LineSnapshot {
+    registers: [u8; (WINDOW_X_REGISTER - LCD_CONTROL_REGISTER) as usize + 1],
+    memory: Arc<VideoMemory>,
+    pub window_line: Option<WindowLine>,
+    // Tiles written since the previous snapshot, for the worker's tile cache
+    pub dirty_tiles: DirtyTiles,
+}
+
+impl LineSnapshot {
+    // Reuses the previous line's memory if nothing changed and remembers the new one otherwise
+    pub fn capture(
+        mmu: &Mmu, line_registers: &LineRegisters, window_line: Option<WindowLine>, dirty_tiles: DirtyTiles,
+        previous: &mut Option<Arc<VideoMemory>>,
+    ) -> LineSnapshot {
+        let memory = match previous {
+            Some(memory) if memory.matches(mmu) => Arc::clone(memory),
+            _ => Arc::clone(previous.insert(Arc::new(VideoMemory::capture(mmu)))),
+        };
+
+        let mut registers = [0; (WINDOW_X_REGISTER - LCD_CONTROL_REGISTER) as usize + 1];
+        for (offset, register) in registers.iter_mut().enumerate() {
+            let addr = LCD_CONTROL_REGISTER + offset as u16;
+            *register = line_registers.get(addr).unwrap_or_else(|| mmu.read_unchecked(addr));
+        }
+
+        LineSnapshot {
+            registers,
+            memory,
+            window_line,
+            dirty_tiles,
+        }
+    }
+}
+
+impl VideoBus for LineSnapshot {
+    #[inline]
+    fn read_register(&self, addr: u16) -> u8 {
+        match addr {
+            OAM_START..=OAM_END => self.memory.oam[(addr - OAM_START) as usize],
+            LCD_CONTROL_REGISTER..=WINDOW_X_REGISTER => self.registers[(addr - LCD_CONTROL_REGISTER) as usize],
+            _ => 0xff,
+        }
+    }
+
+    #[inline]
+    fn read_vram(&self, addr: u16, bank: u8) -> u8 {
+        self.memory.vram[bank as usize][(addr - VRAM_START) as usize]
+    }
+
+    #[inline]
+    fn cram(&self) -> &Cram {
+        &self.memory.cram
+    }
+}
@@ -0,0 +1,124 @@
+use clap::ValueEnum;
+
+// Colors a CGB puts on DMG games. The boot ROM picks them by the game's title, or lets the player pick one
+// with a button combination while the logo shows. Skipping the boot ROM skips that too, so the same
+// presets can be picked here instead. They go into CGB palette RAM like the boot ROM puts them there:
+// background palette 0, object palettes 0 and 1, looked up by the shades in BGP/OBP0/OBP1.
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum CompatPalette {
+    // The DMG's four shades of gray
+    #[default]
+    Off,
+    Brown,
+    Red,
+    DarkBrown,
+    Blue,
+    DarkBlue,
+    Gray,
+    PaleYellow,
+    Orange,
+    Yellow,
+    Green,
+    DarkGreen,
+    Inverted,
+}
+
+// Background, OBJ0 and OBJ1 from lightest to darkest, as RGB888
+type Colors = [[u32; 4]; 3];
+
+const RED: [u32; 4] = [0xffffff, 0xff8484, 0x943a3a, 0x000000];
+const GREEN: [u32; 4] = [0xffffff, 0x7bff31, 0x008400, 0x000000];
+const BROWN: [u32; 4] = [0xffffff, 0xffad63, 0x843100, 0x000000];
+const BLUE: [u32; 4] = [0xffffff, 0x63a5ff, 0x0000ff, 0x000000];
+
+impl CompatPalette {
+    pub const ALL: [CompatPalette; 13] = [
+        CompatPalette::Off,
+        CompatPalette::Brown,
+        CompatPalette::Red,
+        CompatPalette::DarkBrown,
+        CompatPalette::Blue,
+        CompatPalette::DarkBlue,
+        CompatPalette::Gray,
+        CompatPalette::PaleYellow,
+        CompatPalette::Orange,
+        CompatPalette::Yellow,
+        CompatPalette::Green,
+        CompatPalette::DarkGreen,
+        CompatPalette::Inverted,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CompatPalette::Off => "Off",
+            CompatPalette::Brown => "Brown",
+            CompatPalette::Red => "Red",
+            CompatPalette::DarkBrown => "Dark brown",
+            CompatPalette::Blue => "Blue",
+            CompatPalette::DarkBlue => "Dark blue",
+            CompatPalette::Gray => "Grayscale",
+            CompatPalette::PaleYellow => "Pale yellow",
+            CompatPalette::Orange => "Orange",
+            CompatPalette::Yellow => "Yellow",
+            CompatPalette::Green => "Green",
+            CompatPalette::DarkGreen => "Dark green",
+            CompatPalette::Inverted => "Inverted",
+        }
+    }
+
+    // Buttons held during the boot animation to get this palette on hardware
+    pub fn combo(&self) -> Option<&'static str> {
+        match self {
+            CompatPalette::Off => None,
+            CompatPalette::Brown => Some("Up"),
+            CompatPalette::Red => Some("Up+A"),
+            CompatPalette::DarkBrown => Some("Up+B"),
+            CompatPalette::Blue => Some("Left"),
+            CompatPalette::DarkBlue => Some("Left+A"),
+            CompatPalette::Gray => Some("Left+B"),
+            CompatPalette::PaleYellow => Some("Down"),
+            CompatPalette::Orange => Some("Down+A"),
+            CompatPalette::Yellow => Some("Down+B"),
+            CompatPalette::Green => Some("Right"),
+            CompatPalette::DarkGreen => Some("Right+A"),
+            CompatPalette::Inverted => Some("Right+B"),
+        }
+    }
+
+    fn colors(&self) -> Option<Colors> {
+        let colors = match self {
+            CompatPalette::Off => return None,
+            CompatPalette::Brown => [BROWN, BROWN, BROWN],
+            CompatPalette::Red => [RED, GREEN, BLUE],
+            CompatPalette::DarkBrown => [[0xffe6c5, 0xce9c84, 0x846b29, 0x5a3108], BROWN, BROWN],
+            CompatPalette::Blue => [BLUE, RED, GREEN],
+            CompatPalette::DarkBlue => [[0xffffff, 0x8c8cde, 0x52528c, 0x000000], RED, BROWN],
+            CompatPalette::Gray => [[0xffffff, 0xa5a5a5, 0x525252, 0x000000]; 3],
+            CompatPalette::PaleYellow => [[0xffffa5, 0xff9494, 0x9494ff, 0x000000]; 3],
+            CompatPalette::Orange => [[0xffffff, 0xffff00, 0xff0000, 0x000000]; 3],
+            CompatPalette::Yellow => [[0xffffff, 0xffff00, 0x7b4a00, 0x000000], BLUE, GREEN],
+            CompatPalette::Green => [[0xffffff, 0x52ff00, 0xff4200, 0x000000]; 3],
+            CompatPalette::DarkGreen => [[0xffffff, 0x7bff31, 0x0063c5, 0x000000], RED, RED],
+            CompatPalette::Inverted => [[0x000000, 0x008484, 0xffde00, 0xffffff]; 3],
+        };
+        Some(colors)
+    }
+
+    // Contents of background palette 0, object palette 0 and object palette 1 in CGB palette RAM
+    pub fn palette_ram(&self) -> Option<[[u8; 8]; 3]> {
+        self.colors().map(|colors| {
+            colors.map(|palette| {
+                let mut bytes = [0; 8];
+                for (idx, color) in palette.iter().enumerate() {
+                    bytes[idx * 2..idx * 2 + 2].copy_from_slice(&rgb888_to_rgb555(*color).to_le_bytes());
+                }
+                bytes
+            })
+        })
+    }
+}
+
+fn rgb888_to_rgb555(color: u32) -> u16 {
+    let [r, g, b] = [16, 8, 0].map(|shift| ((color >> shift) & 0xff) as u16 >> 3);
+    r | (g << 5) | (b << 10)
+}
@@ -5,13 +5,22 @@ use crate::memory::{
     BACKGROUND_PALETTE_DATA_REGISTER, BACKGROUND_PALETTE_INDEX_REGISTER, OBJECT_PALETTE_DATA_REGISTER,
     OBJECT_PALETTE_INDEX_REGISTER,
 };
+use crate::video::compat::CompatPalette;
+use crate::video::ColorCorrection;
 
+#[derive(Clone, PartialEq)]
 pub struct Cram {
     background_palette: [u8; 64],
     object_palette: [u8; 64],
     auto_increment: bool,
     obj_address: u8,
     bg_address: u8,
+    // Not part of the hardware, kept here so that lines drawn from a snapshot use it too
+    color_correction: ColorCorrection,
+    // Colors DMG games are shown in, they use the palettes above then
+    compat_palette: CompatPalette,
+    // A CGB in DMG compatibility mode shows DMG games in whatever the boot ROM left in the palettes
+    boot_colors: bool,
 }
 
 impl Cram {
@@ -22,9 +31,45 @@ impl Cram {
             auto_increment: false,
             obj_address: 0,
             bg_address: 0,
+            color_correction: ColorCorrection::default(),
+            compat_palette: CompatPalette::default(),
+            boot_colors: false,
         }
     }
 
+    pub fn color_correction(&self) -> ColorCorrection {
+        self.color_correction
+    }
+
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+    }
+
+    pub fn compat_palette(&self) -> CompatPalette {
+        self.compat_palette
+    }
+
+    // Fills the palettes DMG games use on a CGB, like the boot ROM does
+    pub fn set_compat_palette(&mut self, palette: CompatPalette) {
+        self.compat_palette = palette;
+        if let Some([background, object0, object1]) = palette.palette_ram() {
+            self.background_palette[..8].copy_from_slice(&background);
+            self.object_palette[..8].copy_from_slice(&object0);
+            self.object_palette[8..16].copy_from_slice(&object1);
+        }
+    }
+
+    // Called when the boot ROM locked the CGB into DMG compatibility mode, a picked preset still wins
+    pub fn keep_boot_colors(&mut self) {
+        self.boot_colors = true;
+        self.set_compat_palette(self.compat_palette);
+    }
+
+    // Whether DMG games are colored through the palettes above
+    pub fn colors_dmg_games(&self) -> bool {
+        self.boot_colors || self.compat_palette != CompatPalette::Off
+    }
+
     pub fn fetch_bg(&self, slot: u8, index: u8) -> u16 {
         (self.background_palette[((slot * 8) + index + 1) as usize] as u16) << 8
             | self.background_palette[((slot * 8) + index) as usize] as u16
@@ -34,6 +79,25 @@ impl Cram {
         (self.object_palette[((slot * 8) + index + 1) as usize] as u16) << 8
             | self.object_palette[((slot * 8) + index) as usize] as u16
     }
+
+    // Writes while the PPU is drawing don't reach the palette, but the index still advances
+    pub fn write_blocked(&mut self, addr: u16) {
+        if !self.auto_increment {
+            return;
+        }
+
+        match addr {
+            BACKGROUND_PALETTE_DATA_REGISTER => self.bg_address = (self.bg_address.wrapping_add(1)) & 0b0011_1111,
+            OBJECT_PALETTE_DATA_REGISTER => self.obj_address = (self.obj_address.wrapping_add(1)) & 0b0011_1111,
+            _ => {}
+        }
+    }
+}
+
+impl Default for Cram {
+    fn default() -> Cram {
+        Cram::new()
+    }
 }
 
 impl Addressable for Cram {
@@ -1,13 +1,73 @@
+use clap::ValueEnum;
+
+use crate::video::palette::Palette;
+
+pub mod bus;
+pub mod compat;
 pub mod cram;
-mod oam;
+pub mod oam;
 pub mod palette;
 pub mod ppu;
-mod sprite;
+pub mod priority;
+pub mod sprite;
 pub mod state;
 pub mod tile;
+pub mod tile_cache;
+pub mod worker;
+
+// How closely the PPU follows the hardware while drawing, faster modes lose mid-frame effects
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum Accuracy {
+    // Draws the whole frame at VBlank from the registers at that point
+    Fast,
+    // Draws one scanline at a time, changes in the middle of a line are lost
+    #[default]
+    Balanced,
+    // Draws pixels progressively as mode 3 progresses, so mid-scanline writes hit the right pixels. This
+    // is not a pixel FIFO: there is no fetcher and mode 3 always takes the same time.
+    #[value(alias = "accurate")]
+    PerPixel,
+}
+
+// Parts of the picture that get drawn. Hiding one is purely visual, the emulation doesn't notice.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Layers {
+    pub background: bool,
+    pub window: bool,
+    pub sprites: bool,
+}
+
+impl Default for Layers {
+    fn default() -> Layers {
+        Layers {
+            background: true,
+            window: true,
+            sprites: true,
+        }
+    }
+}
+
+// How CGB colors end up on screen. The raw values look oversaturated on a modern display,
+// the games were made for much duller LCDs
+#[derive(Debug, Clone, Copy, PartialEq, Default, ValueEnum)]
+pub enum ColorCorrection {
+    // Each 5-bit channel scaled up on its own
+    #[default]
+    Raw,
+    // Channels bleed into each other and white washes out like on the CGB's LCD
+    Cgb,
+    // The dark gamma of the GBA's LCD, for CGB games that were brightened up for it
+    Gba,
+}
 
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
+// A finished picture, row by row
+pub type Frame = [[Palette; SCREEN_WIDTH]; SCREEN_HEIGHT];
+// 154 scanlines of 456 T-cycles each
+pub const CYCLES_PER_FRAME: usize = 70224;
+// Length of mode 3 without any sprite or scroll penalties
+pub const DRAWING_CYCLES: usize = 172;
 
 pub const BACKGROUND_WIDTH: usize = 256;
 pub const BACKGROUND_HEIGHT: usize = 256;
@@ -23,6 +83,8 @@ pub const OAM_ADDRESS: u16 = 0xfe00;
 
 pub const BACKGROUND_MAP_SIZE: usize = 32 * 32;
 pub const TILESET_SIZE: usize = 256;
+// Tiles in $8000-$97ff of each VRAM bank
+pub const TILE_DATA_COUNT: usize = 384;
 
 pub const LCD_CONTROL_REGISTER: u16 = 0xff40;
 pub const LCD_STATUS_REGISTER: u16 = 0xff41;
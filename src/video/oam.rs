@@ -1,6 +1,7 @@
 use crate::video::sprite::Sprite;
 use crate::video::tile::Tile;
 
+#[derive(Clone)]
 pub struct Oam {
     pub sprite: Sprite,
     pub tile1: Tile,
@@ -1,7 +1,9 @@
+use std::sync::OnceLock;
+
 use crate::gameboy::Mode;
-use crate::memory::mmu::Mmu;
+use crate::video::bus::VideoBus;
 use crate::video::sprite::{Sprite, SpriteAttributes};
-use crate::video::{BG_PALETTE_REGISTER, OBJ0_PALETTE_REGISTER, OBJ1_PALETTE_REGISTER};
+use crate::video::{ColorCorrection, BG_PALETTE_REGISTER, OBJ0_PALETTE_REGISTER, OBJ1_PALETTE_REGISTER};
 
 use super::tile::TileAttributes;
 
@@ -18,9 +20,9 @@ pub enum Palette {
 }
 
 impl Palette {
-    pub fn from_background(value: u8, mmu: &Mmu, mode: &Mode, attributes: &TileAttributes) -> Palette {
+    pub fn from_background(value: u8, bus: &dyn VideoBus, mode: &Mode, attributes: &TileAttributes) -> Palette {
         if *mode == Mode::Dmg {
-            let bgp_shade = mmu.read_unchecked(BG_PALETTE_REGISTER);
+            let bgp_shade = bus.read_register(BG_PALETTE_REGISTER);
 
             let shade = match value {
                 0b00 => bgp_shade & 0b0000_0011, // shouldn't happen for window layer, only background
@@ -30,6 +32,12 @@ impl Palette {
                 _ => panic!("Invalid color value: {}", value),
             };
 
+            if bus.cram().colors_dmg_games() {
+                let [r, g, b] =
+                    Palette::rgb555_to_rgb888(bus.cram().fetch_bg(0, shade * 2), bus.cram().color_correction());
+                return Palette::Color(value, r, g, b);
+            }
+
             match shade {
                 0b00 => Palette::White(value),
                 0b01 => Palette::LightGray(value),
@@ -41,29 +49,31 @@ impl Palette {
             let palette = (attributes.bits() & TileAttributes::PALETTE.bits()) as u8;
 
             let color = match value {
-                0b00 => mmu.cgb_cram.fetch_bg(palette, 0),
-                0b01 => mmu.cgb_cram.fetch_bg(palette, 2),
-                0b10 => mmu.cgb_cram.fetch_bg(palette, 4),
-                0b11 => mmu.cgb_cram.fetch_bg(palette, 6),
+                0b00 => bus.cram().fetch_bg(palette, 0),
+                0b01 => bus.cram().fetch_bg(palette, 2),
+                0b10 => bus.cram().fetch_bg(palette, 4),
+                0b11 => bus.cram().fetch_bg(palette, 6),
                 _ => panic!("Invalid color value: {}", value),
             };
 
-            let [r, g, b] = Palette::rgb555_to_rgb888(color);
+            let [r, g, b] = Palette::rgb555_to_rgb888(color, bus.cram().color_correction());
 
             Palette::Color(value, r, g, b)
         }
     }
 
-    pub fn from_object(value: u8, mmu: &Mmu, sprite: &Sprite, allow_transparency: bool, mode: &Mode) -> Palette {
+    pub fn from_object(
+        value: u8, bus: &dyn VideoBus, sprite: &Sprite, allow_transparency: bool, mode: &Mode,
+    ) -> Palette {
         if allow_transparency && value == 0 {
             return Palette::Transparent(0);
         }
 
         if *mode == Mode::Dmg {
-            let objp_shade = if !sprite.attributes.contains(SpriteAttributes::DMG_PALETTE) {
-                mmu.read_unchecked(OBJ0_PALETTE_REGISTER)
-            } else {
-                mmu.read_unchecked(OBJ1_PALETTE_REGISTER)
+            let obp = sprite.attributes.contains(SpriteAttributes::DMG_PALETTE) as u8;
+            let objp_shade = match obp {
+                0 => bus.read_register(OBJ0_PALETTE_REGISTER),
+                _ => bus.read_register(OBJ1_PALETTE_REGISTER),
             };
 
             let shade = match value {
@@ -74,6 +84,12 @@ impl Palette {
                 _ => panic!("Invalid color value: {}", value),
             };
 
+            if bus.cram().colors_dmg_games() {
+                let [r, g, b] =
+                    Palette::rgb555_to_rgb888(bus.cram().fetch_obj(obp, shade * 2), bus.cram().color_correction());
+                return Palette::Color(value, r, g, b);
+            }
+
             match shade {
                 0b00 => Palette::White(value),
                 0b01 => Palette::LightGray(value),
@@ -85,14 +101,14 @@ impl Palette {
             let palette = (sprite.attributes.bits() & SpriteAttributes::CGB_PALETTE.bits()) as u8;
 
             let color = match value {
-                0b00 => mmu.cgb_cram.fetch_obj(palette, 0),
-                0b01 => mmu.cgb_cram.fetch_obj(palette, 2),
-                0b10 => mmu.cgb_cram.fetch_obj(palette, 4),
-                0b11 => mmu.cgb_cram.fetch_obj(palette, 6),
+                0b00 => bus.cram().fetch_obj(palette, 0),
+                0b01 => bus.cram().fetch_obj(palette, 2),
+                0b10 => bus.cram().fetch_obj(palette, 4),
+                0b11 => bus.cram().fetch_obj(palette, 6),
                 _ => panic!("Invalid color value: {}", value),
             };
 
-            let [r, g, b] = Palette::rgb555_to_rgb888(color);
+            let [r, g, b] = Palette::rgb555_to_rgb888(color, bus.cram().color_correction());
 
             Palette::Color(value, r, g, b)
         }
@@ -113,17 +129,37 @@ impl Palette {
         }
     }
 
-    fn rgb555_to_rgb888(color: u16) -> Color {
-        // Person smarter than me figured out this color correction:
-        // https://github.com/joamag/boytacean/blob/8d2d32b5fee994fdce37476995d8c29430980a6c/src/color.rs#L28-L33
-        let first = (color & 0xff) as u8;
-        let second = ((color >> 8) & 0xff) as u8;
+    pub fn rgb555_to_rgb888(color: u16, correction: ColorCorrection) -> Color {
+        let r = (color & 0x1f) as u32;
+        let g = ((color >> 5) & 0x1f) as u32;
+        let b = ((color >> 10) & 0x1f) as u32;
+
+        match correction {
+            // https://github.com/joamag/boytacean/blob/8d2d32b5fee994fdce37476995d8c29430980a6c/src/color.rs#L28-L33
+            ColorCorrection::Raw => [(r << 3) as u8, (g << 3) as u8, (b << 3) as u8],
+            // https://near.sh/articles/video/color-emulation
+            ColorCorrection::Cgb => [
+                ((r * 26 + g * 4 + b * 2).min(960) >> 2) as u8,
+                ((g * 24 + b * 8).min(960) >> 2) as u8,
+                ((r * 6 + g * 4 + b * 22).min(960) >> 2) as u8,
+            ],
+            ColorCorrection::Gba => {
+                static GBA_COLORS: OnceLock<Vec<Color>> = OnceLock::new();
+                GBA_COLORS.get_or_init(|| (0..0x8000).map(Palette::gba_color).collect())[(color & 0x7fff) as usize]
+            }
+        }
+    }
 
-        let r = (first & 0x1f) << 3;
-        let g = (((first & 0xe0) >> 5) | ((second & 0x03) << 3)) << 3;
-        let b = ((second & 0x7c) >> 2) << 3;
+    // Same source as above, mixed in linear light with the LCD's gamma of 4 and encoded back for a 2.2 display
+    fn gba_color(color: u16) -> Color {
+        let [r, g, b] = [0, 5, 10].map(|shift| (((color >> shift) & 0x1f) as f32 / 31.0).powf(4.0));
+        let encode = |value: f32| ((value / 255.0).powf(1.0 / 2.2) * 255.0 * 255.0 / 280.0).min(255.0) as u8;
 
-        [r, g, b]
+        [
+            encode(50.0 * g + 255.0 * r),
+            encode(30.0 * b + 230.0 * g + 10.0 * r),
+            encode(220.0 * b + 10.0 * g + 50.0 * r),
+        ]
     }
 }
 
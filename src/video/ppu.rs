@@ -1,6 +1,5 @@
-use std::collections::HashMap;
-
 use log::trace;
+use std::ops::{AddAssign, Range};
 
 use crate::gameboy::Mode;
 use crate::memory::mmu::Mmu;
@@ -11,21 +10,86 @@ use crate::video::palette::Palette;
 use crate::video::sprite::{Sprite, SpriteAttributes};
 use crate::video::tile::Tile;
 use crate::video::{
-    LCD_CONTROL_REGISTER, LCD_STATUS_REGISTER, SCANLINE_Y_COMPARE_REGISTER, SCANLINE_Y_REGISTER, SCREEN_HEIGHT,
-    SCREEN_WIDTH, SCROLL_X_REGISTER, SCROLL_Y_REGISTER, TILEMAP_0_ADDRESS, TILEMAP_1_ADDRESS, TILESET_0_ADDRESS,
-    TILESET_1_ADDRESS, WINDOW_X_REGISTER, WINDOW_Y_REGISTER,
+    Accuracy, Frame, Layers, DRAWING_CYCLES, LCD_CONTROL_REGISTER, LCD_STATUS_REGISTER, SCANLINE_Y_COMPARE_REGISTER,
+    SCANLINE_Y_REGISTER, SCREEN_HEIGHT, SCREEN_WIDTH, SCROLL_X_REGISTER, SCROLL_Y_REGISTER, TILEMAP_0_ADDRESS,
+    TILEMAP_1_ADDRESS, TILESET_0_ADDRESS, TILESET_1_ADDRESS, WINDOW_X_REGISTER, WINDOW_Y_REGISTER,
 };
 
+use super::bus::{LatchedLine, LineRegisters, LineSnapshot, VideoBus};
+use super::priority::{priority_resolver, PriorityResolver};
 use super::state::State;
 use super::tile::TileAttributes;
+use super::tile_cache::{DirtyTiles, TileCache, DIRTY_TILE_WORDS};
+use super::worker::RenderWorker;
 use super::{BACKGROUND_MAP_SIZE, TILESET_SIZE};
 
+// The OAM scan picks at most this many sprites per line
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// Offset into a 32x32 tilemap for the given pixel coordinates. Both axes wrap
+// around after 256 pixels, just like the hardware does for SCX/SCY.
+#[inline]
+pub fn tilemap_index(x: usize, y: usize) -> u16 {
+    let map_x = (x % 256) / 8;
+    let map_y = (y % 256) / 8;
+    (map_y * 32 + map_x) as u16
+}
+
+// How the window shows up on the line being drawn
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WindowLine {
+    // The window's own line counter, only lines that showed the window count
+    pub line: usize,
+    // WX=166 on the line before keeps the window going across this whole line
+    pub full_width: bool,
+}
+
+// What the PPU went through while it was ticked, for whatever drives it and reacts to it (ending the
+// frame, HBlank DMA, ...) instead of each of them guessing from LY or the mode
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FrameEvents {
+    // LY wrapped around to 0, or sits there with the LCD off
+    pub frame_complete: bool,
+    // Mode 1 started
+    pub entered_vblank: bool,
+    // Times mode 0 started
+    pub hblank_count: usize,
+}
+
+impl AddAssign for FrameEvents {
+    fn add_assign(&mut self, other: FrameEvents) {
+        self.frame_complete |= other.frame_complete;
+        self.entered_vblank |= other.entered_vblank;
+        self.hblank_count += other.hblank_count;
+    }
+}
+
 pub struct Ppu {
     pub state: State,
     cycles: usize,
-    emulated_frame: [[Palette; SCREEN_WIDTH]; SCREEN_HEIGHT],
-    window_line_counter: usize,
+    emulated_frame: Frame,
+    window_line: Option<WindowLine>,
+    window_lines: usize,
+    // LY matched WY at some point this frame, later WY writes can't retrigger or untrigger it
+    window_triggered: bool,
+    window_spill: bool,
     mode: Mode,
+    // Picked with the mode, decides what's drawn on top
+    priority: &'static dyn PriorityResolver,
+    // Scanlines aren't drawn while disabled, timing and interrupts are unaffected
+    rendering: bool,
+    layers: Layers,
+    tile_cache: TileCache,
+    // Tiles written since our own tile cache was last updated
+    dirty_tiles: DirtyTiles,
+    // Draws the lines on another thread if threaded rendering is enabled
+    worker: Option<RenderWorker>,
+    accuracy: Accuracy,
+    // Per-pixel mode draws the current line bit by bit, these are picked when it starts
+    line_oams: [Option<Oam>; MAX_SPRITES_PER_LINE],
+    drawn_pixels: usize,
+    // Picked up when mode 3 of the current line started, balanced mode draws the line from these when it ends
+    line_registers: Option<LineRegisters>,
 }
 
 impl Ppu {
@@ -34,23 +98,237 @@ impl Ppu {
             state: State::OamScan,
             cycles: 0,
             emulated_frame: [[Palette::default(); SCREEN_WIDTH]; SCREEN_HEIGHT],
-            window_line_counter: 0,
+            window_line: None,
+            window_lines: 0,
+            window_triggered: false,
+            window_spill: false,
+            priority: priority_resolver(&mode),
             mode,
+            rendering: true,
+            layers: Layers::default(),
+            tile_cache: TileCache::new(),
+            dirty_tiles: [0; DIRTY_TILE_WORDS],
+            worker: None,
+            accuracy: Accuracy::default(),
+            line_oams: Default::default(),
+            drawn_pixels: 0,
+            line_registers: None,
         }
     }
 
-    pub fn tick(&mut self, mmu: &mut Mmu) {
+    pub fn tick(&mut self, mmu: &mut Mmu) -> FrameEvents {
         if !mmu
             .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
             .contains(LcdControl::LCD_DISPLAY)
         {
-            return;
+            self.line_registers = None;
+            // Nothing to wait for, frames are cut short so that the game keeps running
+            return FrameEvents {
+                frame_complete: mmu.read_unchecked(SCANLINE_Y_REGISTER) == 0,
+                ..FrameEvents::default()
+            };
         }
 
-        self.handle_window_line_counter(mmu);
-        self.render_scanline(mmu);
         self.progress_scanline(mmu);
         self.handle_interrupts(mmu);
+
+        let scanline = mmu.read_unchecked(SCANLINE_Y_REGISTER);
+        if scanline as usize == SCREEN_HEIGHT {
+            match self.accuracy {
+                Accuracy::Fast if self.rendering => self.render_frame(mmu),
+                // The frame is drawn, wait for the worker to catch up
+                Accuracy::Balanced => self.collect_frame(),
+                _ => {}
+            }
+        }
+
+        FrameEvents {
+            frame_complete: scanline == 0,
+            ..FrameEvents::default()
+        }
+    }
+
+    #[inline]
+    pub fn accuracy(&self) -> Accuracy {
+        self.accuracy
+    }
+
+    // Only balanced mode draws on the render thread, anything it still has in flight is collected first
+    pub fn set_accuracy(&mut self, accuracy: Accuracy) {
+        self.collect_frame();
+        self.accuracy = accuracy;
+    }
+
+    // Draws a line captured by the emulation thread, this runs on the render worker
+    pub fn render_snapshot(&mut self, snapshot: &LineSnapshot) {
+        self.window_line = snapshot.window_line;
+        self.tile_cache.update(snapshot, snapshot.dirty_tiles);
+        self.render_scanline(snapshot);
+    }
+
+    #[inline]
+    pub fn threaded_rendering(&self) -> bool {
+        self.worker.is_some()
+    }
+
+    // A CGB running a DMG game draws like a DMG from the moment the boot ROM is done
+    pub fn set_mode(&mut self, mode: Mode) {
+        self.priority = priority_resolver(&mode);
+        self.mode = mode;
+        if self.threaded_rendering() {
+            self.collect_frame();
+            self.worker = Some(RenderWorker::new(self.mode.clone()));
+        }
+    }
+
+    pub fn set_threaded_rendering(&mut self, enabled: bool) {
+        if enabled == self.threaded_rendering() {
+            return;
+        }
+
+        match enabled {
+            true => self.worker = Some(RenderWorker::new(self.mode.clone())),
+            false => {
+                self.collect_frame();
+                self.worker = None;
+            }
+        }
+    }
+
+    // Keeps the other PPU's render thread, e.g. when loading a save state which comes without one
+    pub fn adopt_worker(&mut self, other: &mut Ppu) {
+        self.worker = other.worker.take();
+        if let Some(worker) = self.worker.as_mut() {
+            worker.invalidate();
+        }
+        // A worker draws for one mode, a CGB that went into or out of compatibility mode needs a new one
+        if self.worker.is_some() && self.mode != other.mode {
+            self.worker = Some(RenderWorker::new(self.mode.clone()));
+        }
+    }
+
+    fn collect_frame(&mut self) {
+        if let Some(frame) = self.worker.as_mut().and_then(|worker| worker.finish()) {
+            self.emulated_frame = frame;
+        }
+    }
+
+    // Tile writes are needed by our own tile cache and by the worker's
+    fn collect_dirty_tiles(&mut self, mmu: &mut Mmu) {
+        let dirty_tiles = mmu.take_dirty_tiles();
+        for (pending, dirty) in self.dirty_tiles.iter_mut().zip(&dirty_tiles) {
+            *pending |= dirty;
+        }
+        if let Some(worker) = self.worker.as_mut() {
+            worker.mark_tiles_dirty(&dirty_tiles);
+        }
+    }
+
+    #[inline]
+    pub fn rendering(&self) -> bool {
+        self.rendering
+    }
+
+    pub fn set_rendering(&mut self, enabled: bool) {
+        self.rendering = enabled;
+    }
+
+    #[inline]
+    pub fn layers(&self) -> Layers {
+        self.layers
+    }
+
+    pub fn set_layers(&mut self, layers: Layers) {
+        self.layers = layers;
+    }
+
+    // Decodes the tiles written since the last update, the debug views call this before rendering
+    pub fn update_tile_cache(&mut self, mmu: &mut Mmu) {
+        self.collect_dirty_tiles(mmu);
+        self.tile_cache.update(mmu, std::mem::take(&mut self.dirty_tiles));
+    }
+
+    // Draws all lines at once from the registers as they are at VBlank
+    fn render_frame(&mut self, mmu: &mut Mmu) {
+        self.update_tile_cache(mmu);
+        for scanline in 0..SCREEN_HEIGHT {
+            let line = LatchedLine::new(mmu, scanline as u8);
+            self.handle_window_line_counter(&line);
+            self.render_scanline(&line);
+        }
+    }
+
+    // Mode 3 of a line ended in balanced mode, the whole line is drawn at once. Writes from its HBlank (registers,
+    // palettes, HDMA) are meant for the next line and come in after this. Mid-line register writes are left out
+    // as well, mode 3 already saw the registers when it started.
+    fn draw_line(&mut self, mmu: &mut Mmu) {
+        let registers = self.line_registers.take().unwrap_or_else(|| LineRegisters::latch(mmu));
+        let scanline = mmu.read_unchecked(SCANLINE_Y_REGISTER);
+        self.handle_window_line_counter(&LatchedLine {
+            bus: mmu,
+            scanline,
+            registers,
+        });
+        if !self.rendering {
+            return;
+        }
+
+        self.collect_dirty_tiles(mmu);
+        match self.worker.as_mut() {
+            Some(worker) => {
+                if (scanline as usize) < SCREEN_HEIGHT {
+                    worker.render(mmu, &registers, self.window_line, self.layers);
+                }
+            }
+            None => {
+                self.tile_cache.update(mmu, std::mem::take(&mut self.dirty_tiles));
+                self.render_scanline(&LatchedLine {
+                    bus: mmu,
+                    scanline,
+                    registers,
+                });
+            }
+        }
+    }
+
+    // Mode 3 of a line starts in per-pixel mode: latch the sprites and draw pixels as the cycles pass
+    fn start_line(&mut self, mmu: &mut Mmu) {
+        self.drawn_pixels = 0;
+        self.handle_window_line_counter(mmu);
+        if !self.rendering {
+            return;
+        }
+
+        self.update_tile_cache(mmu);
+        let scanline = mmu.read_unchecked(SCANLINE_Y_REGISTER) as usize;
+        let sprite_height = if mmu.lcd_control().contains(LcdControl::OBJ_SIZE) {
+            16
+        } else {
+            8
+        };
+        self.line_oams = self.fetch_oams(mmu, scanline, sprite_height);
+    }
+
+    fn draw_pixels(&mut self, bus: &dyn VideoBus, until: usize) {
+        if !self.rendering || self.drawn_pixels >= until {
+            return;
+        }
+
+        let oams = std::mem::take(&mut self.line_oams);
+        self.render_pixels(bus, &oams, self.drawn_pixels..until);
+        self.line_oams = oams;
+        self.drawn_pixels = until;
+    }
+
+    // PPU cycles until the next mode change, nothing the CPU could wait for happens before that
+    pub fn cycles_until_next_state(&self) -> usize {
+        let duration = match self.state {
+            State::OamScan => 80,
+            State::Drawing => DRAWING_CYCLES,
+            State::HBlank => 204,
+            State::VBlank => 456,
+        };
+        duration.saturating_sub(self.cycles)
     }
 
     pub fn reset_state(&mut self) {
@@ -58,12 +336,13 @@ impl Ppu {
         self.cycles = 0;
     }
 
-    pub fn tick_state(&mut self, mmu: &mut Mmu, cycles: usize) {
+    pub fn tick_state(&mut self, mmu: &mut Mmu, cycles: usize) -> FrameEvents {
+        let mut events = FrameEvents::default();
         if !mmu
             .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
             .contains(LcdControl::LCD_DISPLAY)
         {
-            return;
+            return events;
         }
 
         self.cycles += cycles;
@@ -74,12 +353,23 @@ impl Ppu {
                 // TODO: Realistically, writes to the OAM should be blocked during this period
                 self.cycles -= 80;
                 self.state = State::Drawing;
+                self.line_registers = Some(LineRegisters::latch(mmu));
+                if self.accuracy == Accuracy::PerPixel {
+                    self.start_line(mmu);
+                }
             }
-            State::Drawing if self.cycles >= 172 => {
+            State::Drawing if self.cycles >= DRAWING_CYCLES => {
                 // Drawing is done, we can start the HBlank period. Just do nothing for now.
                 // TODO: Realistically, writes to the OAM should be blocked during this period
-                self.cycles -= 172;
+                // Fast mode waits for VBlank instead
+                match self.accuracy {
+                    Accuracy::PerPixel => self.draw_pixels(mmu, SCREEN_WIDTH),
+                    Accuracy::Balanced => self.draw_line(mmu),
+                    Accuracy::Fast => {}
+                }
+                self.cycles -= DRAWING_CYCLES;
                 self.state = State::HBlank;
+                events.hblank_count += 1;
 
                 let lcd_status = mmu.read_as_unchecked::<LcdStatus>(LCD_STATUS_REGISTER);
                 let interrupt_flags = mmu.read_as_unchecked::<InterruptFlags>(INTERRUPT_FLAGS_REGISTER);
@@ -96,6 +386,7 @@ impl Ppu {
                 if mmu.read_unchecked(SCANLINE_Y_REGISTER) == 144 {
                     // We finished the HBlank period of the last scanline, so we can start the VBlank period
                     self.state = State::VBlank;
+                    events.entered_vblank = true;
 
                     let lcd_status = mmu.read_as_unchecked::<LcdStatus>(LCD_STATUS_REGISTER);
                     let mut interrupt_flags = mmu.read_as_unchecked::<InterruptFlags>(INTERRUPT_FLAGS_REGISTER);
@@ -149,39 +440,62 @@ impl Ppu {
             }
             _ => {}
         }
+
+        // Spread the line evenly over the fixed length of mode 3, which is close enough for mid-line writes
+        if self.accuracy == Accuracy::PerPixel && self.state == State::Drawing {
+            self.draw_pixels(mmu, (self.cycles * SCREEN_WIDTH / DRAWING_CYCLES).min(SCREEN_WIDTH));
+        }
+        events
     }
 
-    pub fn handle_window_line_counter(&mut self, mmu: &mut Mmu) {
-        let scanline = mmu.read_unchecked(SCANLINE_Y_REGISTER);
+    #[inline]
+    pub fn window_line(&self) -> Option<WindowLine> {
+        self.window_line
+    }
+
+    pub fn handle_window_line_counter(&mut self, bus: &dyn VideoBus) {
+        let scanline = bus.read_register(SCANLINE_Y_REGISTER);
 
         // Reset window line counter if we start a new frame
         if scanline == 0 {
-            self.window_line_counter = 0;
+            self.window_lines = 0;
+            self.window_triggered = false;
+            self.window_spill = false;
         }
 
-        let wx = mmu.read_unchecked(WINDOW_X_REGISTER);
-        let wy = mmu.read_unchecked(WINDOW_Y_REGISTER);
-        let lcdc = mmu.read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER);
+        let wx = bus.read_register(WINDOW_X_REGISTER);
+        let wy = bus.read_register(WINDOW_Y_REGISTER);
+        let lcdc = bus.lcd_control();
 
-        // Check if the window is enabled and the scanline is within valid range
-        if lcdc.contains(LcdControl::WINDOW_DISPLAY) && wx <= 166 && wy <= 143 {
-            if scanline == wy {
-                // Reset window line counter if scanline matches WY
-                self.window_line_counter = 0;
-            } else if scanline > wy {
-                // Increment window line counter if scanline is greater than WY
-                self.window_line_counter += 1;
-            }
+        // WY is only compared when a line starts, moving it afterwards doesn't matter until the next frame
+        if scanline == wy {
+            self.window_triggered = true;
+        }
+
+        let spill = std::mem::take(&mut self.window_spill);
+        self.window_line = None;
+        if !self.window_triggered || !lcdc.contains(LcdControl::WINDOW_DISPLAY) || scanline as usize >= SCREEN_HEIGHT {
+            return;
+        }
+
+        // The counter only advances on lines that actually showed the window
+        if spill || wx <= 166 {
+            self.window_line = Some(WindowLine {
+                line: self.window_lines,
+                full_width: spill,
+            });
+            self.window_lines += 1;
+            self.window_spill = wx == 166;
         }
     }
 
-    pub fn render_scanline(&mut self, mmu: &Mmu) {
-        let scanline = mmu.read_unchecked(SCANLINE_Y_REGISTER) as usize;
+    pub fn render_scanline(&mut self, bus: &dyn VideoBus) {
+        let scanline = bus.read_register(SCANLINE_Y_REGISTER) as usize;
         if scanline >= SCREEN_HEIGHT {
             return;
         }
 
-        let lcdc = mmu.read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER);
+        let lcdc = bus.lcd_control();
         if !lcdc.contains(LcdControl::LCD_DISPLAY) {
             for x in 0..SCREEN_WIDTH {
                 self.emulated_frame[scanline][x] = Palette::White(0);
@@ -190,68 +504,56 @@ impl Ppu {
         }
 
         let sprite_height = if lcdc.contains(LcdControl::OBJ_SIZE) { 16 } else { 8 };
-        let oams = self.fetch_oams(mmu, sprite_height);
+        let oams = self.fetch_oams(bus, scanline, sprite_height);
+        self.render_pixels(bus, &oams, 0..SCREEN_WIDTH);
+    }
 
-        // Track visited OAMs for current scanline
-        // Key: sprite address (as OAM identifier), Value: (x coordinate, pixel color)
-        let mut visited_oams: HashMap<u16, Vec<(usize, Palette)>> = HashMap::new();
+    fn render_pixels(&mut self, bus: &dyn VideoBus, oams: &[Option<Oam>], pixels: Range<usize>) {
+        let scanline = bus.read_register(SCANLINE_Y_REGISTER) as usize;
+        if scanline >= SCREEN_HEIGHT {
+            return;
+        }
 
-        for x in 0..SCREEN_WIDTH {
-            let (background_color, bg_tile) = self.fetch_background_pixel(mmu, x, scanline);
-            self.emulated_frame[scanline][x] = background_color;
+        let lcdc = bus.lcd_control();
+        let sprite_height = if lcdc.contains(LcdControl::OBJ_SIZE) { 16 } else { 8 };
 
-            let (window_color, win_tile) = self.fetch_window_pixel(mmu, x, scanline);
-            if !window_color.is_transparent() {
-                self.emulated_frame[scanline][x] = window_color;
-            }
+        for x in pixels {
+            // The window, if present, replaces the background pixel entirely
+            let (window_color, win_tile) = self.fetch_window_pixel(bus, x);
+            let covered_by_window = !window_color.is_transparent();
+            let (bg_color, bg_tile) = if covered_by_window {
+                (window_color, win_tile)
+            } else {
+                self.fetch_background_pixel(bus, x, scanline)
+            };
+            // Hidden layers only change what ends up on screen, sprite priority still sees them
+            self.emulated_frame[scanline][x] = match covered_by_window {
+                true if self.layers.window => bg_color,
+                true if self.layers.background => self.fetch_background_pixel(bus, x, scanline).0,
+                false if self.layers.background => bg_color,
+                _ => Palette::White(0),
+            };
 
-            if visited_oams.len() <= 10
-                && mmu
-                    .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
-                    .contains(LcdControl::OBJ_DISPLAY)
-                && let Some((sprite, sprite_color)) = self.fetch_sprite_pixel(&oams, x, scanline, sprite_height)
+            if self.layers.sprites
+                && lcdc.contains(LcdControl::OBJ_DISPLAY)
+                && let Some((sprite, sprite_color)) = self.fetch_sprite_pixel(oams, x, scanline, sprite_height)
+                && self
+                    .priority
+                    .sprite_on_top(&lcdc, sprite, &bg_color, &bg_tile.attributes)
             {
-                let is_bg_visible = !background_color.is_color(0);
-                let is_win_visible = !window_color.is_color(0) && !window_color.is_transparent();
-
-                if sprite.attributes.contains(SpriteAttributes::PRIORITY) && (is_bg_visible || is_win_visible) {
-                    continue;
-                }
-
-                // Are background and window tiles deprioritized?
-                let cgb_sprite_prio = self.mode == Mode::Cgb && !lcdc.contains(LcdControl::BG_AND_WIN_DISPLAY);
-
-                // Do the background or window tiles have priority while being visible?
-                let cgb_master_prio = self.mode == Mode::Cgb
-                    && ((bg_tile.attributes.contains(TileAttributes::PRIORITY) && is_bg_visible)
-                        || (win_tile.attributes.contains(TileAttributes::PRIORITY) && is_win_visible));
-
-                if !cgb_sprite_prio && cgb_master_prio {
-                    continue;
-                }
-
-                visited_oams
-                    .entry(sprite.oam_addr)
-                    .or_insert_with(Vec::new)
-                    .push((x, sprite_color));
-            }
-        }
-
-        for (_, oam) in visited_oams {
-            for (x, color) in oam {
-                self.emulated_frame[scanline][x] = color;
+                self.emulated_frame[scanline][x] = sprite_color;
             }
         }
     }
 
-    pub fn pull_frame(&self) -> [[Palette; SCREEN_WIDTH]; SCREEN_HEIGHT] {
+    pub fn pull_frame(&self) -> Frame {
         self.emulated_frame
     }
 
-    pub fn render_tileset(&mut self, mmu: &Mmu, vram_source: u8) -> Vec<Tile> {
+    pub fn render_tileset(&mut self, bus: &dyn VideoBus, vram_source: u8) -> Vec<Tile> {
         let mut tiles: Vec<Tile> = Vec::new();
 
-        let tileset_addr = self.get_tileset_address(mmu);
+        let tileset_addr = self.get_tileset_address(bus);
 
         for tile_nr in 0..TILESET_SIZE {
             let addr = tileset_addr + (tile_nr as u16 * 16);
@@ -260,53 +562,29 @@ impl Ppu {
             let mut attributes = TileAttributes::empty();
             attributes.set(TileAttributes::BANK, vram_source == 1);
 
-            let tile = Tile::from(mmu, addr, &self.mode, attributes);
+            let tile = Tile::from(bus, self.tile_cache.get(addr, vram_source), &self.mode, attributes);
             tiles.push(tile);
         }
 
         tiles
     }
 
-    pub fn render_background_tilemap(&mut self, mmu: &Mmu) -> Vec<Tile> {
-        let mut tiles: Vec<Tile> = Vec::new();
-
-        let tileset_addr = self.get_tileset_address(mmu);
-        let tilemap_addr = self.get_background_tilemap_address(mmu);
-
-        for idx in 0..BACKGROUND_MAP_SIZE {
-            let tile_nr = mmu.read_from_vram(tilemap_addr + idx as u16, 0);
-            let addr = if tileset_addr == TILESET_0_ADDRESS {
-                tileset_addr + ((tile_nr as u16) * 16)
-            } else {
-                tileset_addr.wrapping_add_signed((tile_nr as i8 as i16 + 128) * 16)
-            };
-            let attributes = if self.mode == Mode::Cgb {
-                TileAttributes::from_bits_truncate(mmu.read_from_vram(tilemap_addr + idx as u16, 1))
-            } else {
-                TileAttributes::empty()
-            };
-            let tile = Tile::from(mmu, addr, &self.mode, attributes);
-            tiles.push(tile);
-        }
-
-        tiles
+    pub fn render_background_tilemap(&mut self, bus: &dyn VideoBus) -> Vec<Tile> {
+        let tilemap_addr = self.get_background_tilemap_address(bus);
+        self.render_tilemap(bus, tilemap_addr)
     }
 
-    pub fn render_window_tilemap(&mut self, mmu: &Mmu) -> Vec<Tile> {
-        let mut tiles: Vec<Tile> = Vec::new();
+    pub fn render_window_tilemap(&mut self, bus: &dyn VideoBus) -> Vec<Tile> {
+        let tilemap_addr = self.get_window_tilemap_address(bus);
+        self.render_tilemap(bus, tilemap_addr)
+    }
 
-        let tileset_addr = self.get_tileset_address(mmu);
-        let tilemap_addr = self.get_window_tilemap_address(mmu);
+    fn render_tilemap(&self, bus: &dyn VideoBus, tilemap_addr: u16) -> Vec<Tile> {
+        let mut tiles: Vec<Tile> = Vec::with_capacity(BACKGROUND_MAP_SIZE);
 
         for idx in 0..BACKGROUND_MAP_SIZE {
-            let tile_nr = mmu.read_from_vram(tilemap_addr + idx as u16, 0);
-            let addr = if tileset_addr == TILESET_0_ADDRESS {
-                tileset_addr + ((tile_nr as u16) * 16)
-            } else {
-                tileset_addr.wrapping_add_signed((tile_nr as i8 as i16 + 128) * 16)
-            };
-            let tile = Tile::from(mmu, addr, &self.mode, TileAttributes::empty());
-            tiles.push(tile);
+            let map_addr = tilemap_addr + tilemap_index((idx % 32) * 8, (idx / 32) * 8);
+            tiles.push(self.fetch_tile(bus, map_addr));
         }
 
         tiles
@@ -343,220 +621,143 @@ impl Ppu {
         mmu.write_unchecked(INTERRUPT_FLAGS_REGISTER, interrupt_flags.bits());
     }
 
-    fn fetch_background_pixel(&self, mmu: &Mmu, x: usize, y: usize) -> (Palette, Tile) {
+    fn fetch_background_pixel(&self, bus: &dyn VideoBus, x: usize, y: usize) -> (Palette, Tile) {
         // Handle case where background is disabled
-        if !mmu
-            .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
-            .contains(LcdControl::BG_AND_WIN_DISPLAY)
-            && self.mode == Mode::Dmg
-        {
+        if !self.priority.background_enabled(&bus.lcd_control()) {
             return (
-                Palette::from_background(0, mmu, &self.mode, &TileAttributes::empty()),
+                Palette::from_background(0, bus, &self.mode, &TileAttributes::empty()),
                 Tile::default(),
             );
         }
 
         // Read scroll values from memory
-        let scy = mmu.read_unchecked(SCROLL_Y_REGISTER);
-        let scx = mmu.read_unchecked(SCROLL_X_REGISTER);
-
-        // Read the background map and tile data addresses from memory
-        let tilemap = self.get_background_tilemap_address(mmu);
-        let tileset = self.get_tileset_address(mmu);
+        let scy = bus.read_register(SCROLL_Y_REGISTER);
+        let scx = bus.read_register(SCROLL_X_REGISTER);
 
-        // Calculate the tile coordinates in the background map
-        let bg_map_x = (((x as u8).wrapping_add(scx)) / 8) as u16;
-        let bg_map_y = (((y as u8).wrapping_add(scy)) / 8) as u16;
-        let bg_map_addr = (tilemap + (bg_map_y * 32)) + bg_map_x;
-        let tile_number = mmu.read_from_vram(bg_map_addr, 0);
+        // Position in the 256x256 background, which wraps around
+        let bg_x = x + scx as usize;
+        let bg_y = y + scy as usize;
 
-        // Calculate the address of the tile data
-        let tile_addr = if tileset == TILESET_0_ADDRESS {
-            tileset + ((tile_number as u16) * 16)
-        } else {
-            tileset.wrapping_add_signed((tile_number as i8 as i16 + 128) * 16)
-        };
+        let tilemap = self.get_background_tilemap_address(bus);
+        let tile = self.fetch_tile(bus, tilemap + tilemap_index(bg_x, bg_y));
 
-        let attributes = if self.mode == Mode::Cgb {
-            TileAttributes::from_bits_truncate(mmu.read_from_vram(bg_map_addr, 1))
-        } else {
-            TileAttributes::empty()
-        };
-        let tile = Tile::from(mmu, tile_addr, &self.mode, attributes);
+        // Get the color of the pixel, flipped if the CGB attributes say so
+        (tile.pixel(bg_x % 8, bg_y % 8), tile)
+    }
 
-        // Calculate the pixel coordinates in the tile
-        let mut tile_x = ((x as u8).wrapping_add(scx)) % 8;
-        let mut tile_y = ((y as u8).wrapping_add(scy)) % 8;
+    // OAM scan: the first sprites in OAM order that overlap the line, sorted by drawing priority
+    fn fetch_oams(
+        &self, bus: &dyn VideoBus, scanline: usize, sprite_height: usize,
+    ) -> [Option<Oam>; MAX_SPRITES_PER_LINE] {
+        let mut oams: [Option<Oam>; MAX_SPRITES_PER_LINE] = Default::default();
+        let mut count = 0;
 
-        // Flip tiles if we're in CGB mode and the tile attributes require it
-        if self.mode == Mode::Cgb {
-            if tile.attributes.contains(TileAttributes::FLIP_X) {
-                tile_x = 7 - tile_x;
+        for i in 0..40 {
+            if count == MAX_SPRITES_PER_LINE {
+                break;
             }
 
-            if tile.attributes.contains(TileAttributes::FLIP_Y) {
-                tile_y = 7 - tile_y;
+            // Only the Y coordinate decides whether a sprite is picked
+            let sprite = Sprite::from_oam(bus, i);
+            let sprite_y = sprite.y as usize;
+            if scanline + 16 < sprite_y || scanline + 16 >= sprite_y + sprite_height {
+                continue;
             }
-        }
-
-        // Get the color of the pixel
-        (tile.pixels[tile_y as usize][tile_x as usize], tile)
-    }
-
-    fn fetch_oams(&self, mmu: &Mmu, sprite_height: usize) -> Vec<Oam> {
-        let mut oams: Vec<Oam> = Vec::new();
-
-        for i in 0..40 {
-            let sprite = Sprite::from_oam(mmu, i);
 
-            if sprite_height == 16 {
+            let bank = sprite.attributes.contains(SpriteAttributes::BANK) as u8;
+            let (tile1, tile2) = if sprite_height == 16 {
                 // 16px sprite
                 let tile_index_top = sprite.tile_index & 0b1111_1110;
-                let tile_index_bot = tile_index_top + 1;
-
                 let tile_addr_top = TILESET_0_ADDRESS + (tile_index_top as u16) * 16;
-                let tile_addr_bot = TILESET_0_ADDRESS + (tile_index_bot as u16) * 16;
-
-                let tile_top = Tile::from_sprite(mmu, tile_addr_top, &sprite, &self.mode);
-                let tile_bot = Tile::from_sprite(mmu, tile_addr_bot, &sprite, &self.mode);
-
-                oams.push(Oam {
-                    sprite,
-                    tile1: tile_top,
-                    tile2: Some(tile_bot),
-                });
+                let tile_addr_bot = tile_addr_top + 16;
+                (
+                    Tile::from_sprite(bus, self.tile_cache.get(tile_addr_top, bank), &sprite, &self.mode),
+                    Some(Tile::from_sprite(
+                        bus,
+                        self.tile_cache.get(tile_addr_bot, bank),
+                        &sprite,
+                        &self.mode,
+                    )),
+                )
             } else {
                 // 8px sprite
                 let tile_addr = TILESET_0_ADDRESS + (sprite.tile_index as u16) * 16;
-                let tile = Tile::from_sprite(mmu, tile_addr, &sprite, &self.mode);
+                (
+                    Tile::from_sprite(bus, self.tile_cache.get(tile_addr, bank), &sprite, &self.mode),
+                    None,
+                )
+            };
 
-                oams.push(Oam {
-                    sprite,
-                    tile1: tile,
-                    tile2: None,
-                });
-            }
+            oams[count] = Some(Oam { sprite, tile1, tile2 });
+            count += 1;
         }
 
+        self.priority.order_sprites(&mut oams[..count]);
+
         oams
     }
 
-    fn fetch_sprite_pixel(
-        &self, oams: &Vec<Oam>, x: usize, y: usize, sprite_height: usize,
-    ) -> Option<(Sprite, Palette)> {
-        let mut sprites: Vec<(Sprite, Palette)> = Vec::new();
-
-        for oam in oams {
+    // The first opaque sprite pixel at this position, in priority order
+    fn fetch_sprite_pixel<'a>(
+        &self, oams: &'a [Option<Oam>], x: usize, y: usize, sprite_height: usize,
+    ) -> Option<(&'a Sprite, Palette)> {
+        oams.iter().flatten().find_map(|oam| {
             let sprite = &oam.sprite;
+            let tile_x = (x + 8).checked_sub(sprite.x as usize).filter(|tile_x| *tile_x < 8)?;
+            let tile_y = (y + 16)
+                .checked_sub(sprite.y as usize)
+                .filter(|tile_y| *tile_y < sprite_height)?;
+
+            let tile_x = match sprite.attributes.contains(SpriteAttributes::FLIP_X) {
+                true => 7 - tile_x,
+                false => tile_x,
+            };
+            let tile_y = match sprite.attributes.contains(SpriteAttributes::FLIP_Y) {
+                true => sprite_height - 1 - tile_y,
+                false => tile_y,
+            };
 
-            let sprite_y = sprite.y as i32 - 16;
-            let sprite_x = sprite.x as i32 - 8;
-
-            if x >= sprite_x.max(0) as usize
-                && x < (sprite_x + 8).min(SCREEN_WIDTH as i32) as usize
-                && y >= sprite_y.max(0) as usize
-                && y < (sprite_y + sprite_height as i32).min(SCREEN_HEIGHT as i32) as usize
-            {
-                if sprite_height == 16 {
-                    // 16px sprite
-                    let tile_top = &oam.tile1;
-                    let tile_bot = oam.tile2.as_ref().unwrap();
-
-                    let mut tile_x = (x as i32 - sprite_x).max(0) as u8; // Ensure we don't go below zero
-                    let mut tile_y = (y as i32 - sprite_y).max(0) as u8; // Ensure we don't go below zero
-
-                    if sprite.attributes.contains(SpriteAttributes::FLIP_X) {
-                        tile_x = 7u8.saturating_sub(tile_x);
-                    }
-
-                    if sprite.attributes.contains(SpriteAttributes::FLIP_Y) {
-                        tile_y = 15u8.saturating_sub(tile_y);
-                    }
-
-                    // Ensure tile_x and tile_y are within bounds
-                    if tile_x < 8 && tile_y < 16 {
-                        let color = if tile_y < 8 {
-                            tile_top.pixels[tile_y as usize][tile_x as usize]
-                        } else {
-                            tile_bot.pixels[(tile_y - 8) as usize][tile_x as usize]
-                        };
-
-                        if !color.is_transparent() {
-                            sprites.push((sprite.clone(), color));
-                        }
-                    }
-                } else {
-                    // 8px sprite
-                    let tile = &oam.tile1;
-
-                    let mut tile_x = (x as i32 - sprite_x).max(0) as u8; // Ensure we don't go below zero
-                    let mut tile_y = (y as i32 - sprite_y).max(0) as u8; // Ensure we don't go below zero
-
-                    if sprite.attributes.contains(SpriteAttributes::FLIP_X) {
-                        tile_x = 7u8.saturating_sub(tile_x);
-                    }
-
-                    if sprite.attributes.contains(SpriteAttributes::FLIP_Y) {
-                        tile_y = 7u8.saturating_sub(tile_y);
-                    }
-
-                    // Ensure tile_x and tile_y are within bounds
-                    if tile_x < 8 && tile_y < 8 {
-                        let color = tile.pixels[tile_y as usize][tile_x as usize];
-                        if !color.is_transparent() {
-                            sprites.push((sprite.clone(), color));
-                        }
-                    }
-                };
-            }
-        }
-
-        // Sort sprites by x coordinate if we're in DMG mode
-        if self.mode == Mode::Dmg {
-            sprites.sort_by(|a, b| a.0.x.cmp(&b.0.x));
-        }
-
-        // Return sprite pixel with highest priority
-        if let Some((sprite, color)) = sprites.first() {
-            return Some((sprite.clone(), *color));
-        }
-
-        None
+            // 16px sprites continue in the bottom tile
+            let tile = if tile_y < 8 { &oam.tile1 } else { oam.tile2.as_ref()? };
+            let color = tile.pixels[tile_y % 8][tile_x];
+            (!color.is_transparent()).then_some((sprite, color))
+        })
     }
 
-    fn fetch_window_pixel(&self, mmu: &Mmu, x: usize, y: usize) -> (Palette, Tile) {
-        if !mmu
-            .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
-            .contains(LcdControl::BG_AND_WIN_DISPLAY)
-            || !mmu
-                .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
-                .contains(LcdControl::WINDOW_DISPLAY)
-        {
+    fn fetch_window_pixel(&self, bus: &dyn VideoBus, x: usize) -> (Palette, Tile) {
+        let lcdc = bus.lcd_control();
+
+        if !self.priority.background_enabled(&lcdc) || !lcdc.contains(LcdControl::WINDOW_DISPLAY) {
             return (Palette::Transparent(0), Tile::default());
         }
 
-        // Read renderer values from memory
-        let wy = mmu.read_unchecked(WINDOW_Y_REGISTER);
-        let wx = mmu.read_unchecked(WINDOW_X_REGISTER);
-
-        // Return transparent color if window is not on screen
-        if y < wy as usize || x + 7 < wx as usize {
+        // Return transparent color if window is not on this line
+        let Some(line) = self.window_line else {
             return (Palette::Transparent(0), Tile::default());
-        }
+        };
 
         // Adjust the coordinates based on renderer position
-        let window_x = x.wrapping_add(7).wrapping_sub(wx as usize);
-        let window_y = self.window_line_counter;
+        let wx = bus.read_register(WINDOW_X_REGISTER) as usize;
+        let window_x = match line.full_width {
+            true => x,
+            // Not on screen yet
+            false if x + 7 < wx => return (Palette::Transparent(0), Tile::default()),
+            // WX=0 drops SCX's fine scroll from the window as well, WX=1..6 just cut its first columns off
+            false if wx == 0 => x + 7 + (bus.read_register(SCROLL_X_REGISTER) as usize & 7),
+            false => x + 7 - wx,
+        };
+        let window_y = line.line;
 
-        // Read the renderer map and tile data addresses from memory
-        let tilemap = self.get_window_tilemap_address(mmu);
-        let tileset = self.get_tileset_address(mmu);
+        let tilemap = self.get_window_tilemap_address(bus);
+        let tile = self.fetch_tile(bus, tilemap + tilemap_index(window_x, window_y));
 
-        // Calculate the tile coordinates in the window map
-        let win_map_x = (window_x / 8) as u16;
-        let win_map_y = (window_y / 8) as u16;
-        let win_map_addr = (tilemap + (win_map_y * 32)) + win_map_x;
-        let tile_number = mmu.read_from_vram(win_map_addr, 0);
+        // Get the color of the pixel, flipped if the CGB attributes say so
+        (tile.pixel(window_x % 8, window_y % 8), tile)
+    }
+
+    fn fetch_tile(&self, bus: &dyn VideoBus, map_addr: u16) -> Tile {
+        let tileset = self.get_tileset_address(bus);
+        let tile_number = bus.read_vram(map_addr, 0);
 
         // Calculate the address of the tile data
         let tile_addr = if tileset == TILESET_0_ADDRESS {
@@ -565,62 +766,67 @@ impl Ppu {
             tileset.wrapping_add_signed((tile_number as i8 as i16 + 128) * 16)
         };
 
-        let attributes = if self.mode == Mode::Cgb {
-            TileAttributes::from_bits_truncate(mmu.read_from_vram(win_map_addr, 1))
-        } else {
-            TileAttributes::empty()
-        };
-        let tile = Tile::from(mmu, tile_addr, &self.mode, attributes);
-
-        // Calculate the pixel coordinates in the tile
-        let mut tile_x = window_x % 8;
-        let mut tile_y = window_y % 8;
-
-        // Flip tiles if we're in CGB mode and the tile attributes require it
-        if self.mode == Mode::Cgb {
-            if tile.attributes.contains(TileAttributes::FLIP_X) {
-                tile_x = 7 - tile_x;
-            }
+        let attributes = self.fetch_tile_attributes(bus, map_addr);
+        let bank = attributes.contains(TileAttributes::BANK) as u8;
+        Tile::from(bus, self.tile_cache.get(tile_addr, bank), &self.mode, attributes)
+    }
 
-            if tile.attributes.contains(TileAttributes::FLIP_Y) {
-                tile_y = 7 - tile_y;
-            }
+    // Background, window and the debug views all get their attributes from here
+    fn fetch_tile_attributes(&self, bus: &dyn VideoBus, map_addr: u16) -> TileAttributes {
+        // CGB stores the tile attributes at the same position in VRAM bank 1
+        match self.mode {
+            Mode::Cgb => TileAttributes::from_bits_truncate(bus.read_vram(map_addr, 1)),
+            Mode::Dmg => TileAttributes::empty(),
         }
-
-        // Get the color of the pixel
-        (tile.pixels[tile_y as usize][tile_x as usize], tile)
     }
 
-    fn get_background_tilemap_address(&self, mmu: &Mmu) -> u16 {
-        if !mmu
-            .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
-            .contains(LcdControl::BG_TILE_MAP)
-        {
+    fn get_background_tilemap_address(&self, bus: &dyn VideoBus) -> u16 {
+        if !bus.lcd_control().contains(LcdControl::BG_TILE_MAP) {
             TILEMAP_0_ADDRESS
         } else {
             TILEMAP_1_ADDRESS
         }
     }
 
-    fn get_window_tilemap_address(&self, mmu: &Mmu) -> u16 {
-        if !mmu
-            .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
-            .contains(LcdControl::WINDOW_TILE_MAP)
-        {
+    fn get_window_tilemap_address(&self, bus: &dyn VideoBus) -> u16 {
+        if !bus.lcd_control().contains(LcdControl::WINDOW_TILE_MAP) {
             TILEMAP_0_ADDRESS
         } else {
             TILEMAP_1_ADDRESS
         }
     }
 
-    fn get_tileset_address(&self, mmu: &Mmu) -> u16 {
-        if !mmu
-            .read_as_unchecked::<LcdControl>(LCD_CONTROL_REGISTER)
-            .contains(LcdControl::BG_AND_WIN_TILE_DATA)
-        {
+    fn get_tileset_address(&self, bus: &dyn VideoBus) -> u16 {
+        if !bus.lcd_control().contains(LcdControl::BG_AND_WIN_TILE_DATA) {
             TILESET_1_ADDRESS
         } else {
             TILESET_0_ADDRESS
         }
     }
 }
+
+// Copies (save states, run-ahead) draw on the emulation thread, the render thread stays with the original
+impl Clone for Ppu {
+    fn clone(&self) -> Ppu {
+        Ppu {
+            state: self.state,
+            cycles: self.cycles,
+            emulated_frame: self.emulated_frame,
+            window_line: self.window_line,
+            window_lines: self.window_lines,
+            window_triggered: self.window_triggered,
+            window_spill: self.window_spill,
+            mode: self.mode.clone(),
+            priority: self.priority,
+            rendering: self.rendering,
+            layers: self.layers,
+            tile_cache: self.tile_cache.clone(),
+            dirty_tiles: self.dirty_tiles,
+            worker: None,
+            accuracy: self.accuracy,
+            line_oams: self.line_oams.clone(),
+            drawn_pixels: self.drawn_pixels,
+            line_registers: self.line_registers,
+        }
+    }
+}
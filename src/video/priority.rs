@@ -0,0 +1,73 @@
+use crate::gameboy::Mode;
+use crate::memory::registers::LcdControl;
+use crate::video::oam::Oam;
+use crate::video::palette::Palette;
+use crate::video::sprite::{Sprite, SpriteAttributes};
+use crate::video::tile::TileAttributes;
+
+// How the PPU decides what ends up on top: whether LCDC bit 0 hides the background, which sprite wins where
+// they overlap and whether a sprite is drawn over the background. DMG and CGB disagree on all of it, the
+// PPU picks the rules for its mode up front instead of checking the mode for every pixel.
+pub trait PriorityResolver: Sync {
+    // Whether LCDC bit 0 lets the background and window show up at all
+    fn background_enabled(&self, lcdc: &LcdControl) -> bool;
+
+    // Puts the sprites the OAM scan picked into drawing order, the first opaque pixel wins
+    fn order_sprites(&self, oams: &mut [Option<Oam>]);
+
+    // Whether the winning sprite pixel is drawn over the background/window pixel below it
+    fn sprite_on_top(
+        &self, lcdc: &LcdControl, sprite: &Sprite, bg_color: &Palette, bg_attributes: &TileAttributes,
+    ) -> bool;
+}
+
+pub struct DmgPriority;
+
+impl PriorityResolver for DmgPriority {
+    fn background_enabled(&self, lcdc: &LcdControl) -> bool {
+        lcdc.contains(LcdControl::BG_AND_WIN_DISPLAY)
+    }
+
+    // The leftmost sprite wins and ties go to the one first in OAM, the sort is stable
+    fn order_sprites(&self, oams: &mut [Option<Oam>]) {
+        oams.sort_by_key(|oam| oam.as_ref().map(|oam| oam.sprite.x));
+    }
+
+    fn sprite_on_top(
+        &self, _lcdc: &LcdControl, sprite: &Sprite, bg_color: &Palette, _bg_attributes: &TileAttributes,
+    ) -> bool {
+        // Sprites are always drawn over color 0
+        bg_color.is_color(0) || !sprite.attributes.contains(SpriteAttributes::PRIORITY)
+    }
+}
+
+pub struct CgbPriority;
+
+impl PriorityResolver for CgbPriority {
+    // LCDC bit 0 only decides priority on CGB
+    fn background_enabled(&self, _lcdc: &LcdControl) -> bool {
+        true
+    }
+
+    // Simply goes by OAM order
+    fn order_sprites(&self, _oams: &mut [Option<Oam>]) {}
+
+    fn sprite_on_top(
+        &self, lcdc: &LcdControl, sprite: &Sprite, bg_color: &Palette, bg_attributes: &TileAttributes,
+    ) -> bool {
+        // LCDC bit 0 is the master priority, clearing it puts all sprites on top
+        if bg_color.is_color(0) || !lcdc.contains(LcdControl::BG_AND_WIN_DISPLAY) {
+            return true;
+        }
+
+        // The BG attribute priority bit overrules the sprite's own priority bit
+        !bg_attributes.contains(TileAttributes::PRIORITY) && !sprite.attributes.contains(SpriteAttributes::PRIORITY)
+    }
+}
+
+pub fn priority_resolver(mode: &Mode) -> &'static dyn PriorityResolver {
+    match mode {
+        Mode::Dmg => &DmgPriority,
+        Mode::Cgb => &CgbPriority,
+    }
+}
@@ -1,4 +1,4 @@
-use crate::memory::mmu::Mmu;
+use crate::video::bus::VideoBus;
 use crate::video::OAM_ADDRESS;
 use bitflags::bitflags;
 
@@ -20,19 +20,17 @@ pub struct Sprite {
     pub y: u8,
     pub tile_index: u8,
     pub attributes: SpriteAttributes,
-    pub oam_addr: u16,
 }
 
 impl Sprite {
-    pub fn from_oam(mmu: &Mmu, index: u16) -> Self {
+    pub fn from_oam(bus: &dyn VideoBus, index: u16) -> Self {
         let sprite_addr = OAM_ADDRESS + (index * 4);
 
         Sprite {
-            y: mmu.read_unchecked(sprite_addr),
-            x: mmu.read_unchecked(sprite_addr + 1),
-            tile_index: mmu.read_unchecked(sprite_addr + 2),
-            attributes: SpriteAttributes::from_bits_truncate(mmu.read_unchecked(sprite_addr + 3)),
-            oam_addr: sprite_addr,
+            y: bus.read_register(sprite_addr),
+            x: bus.read_register(sprite_addr + 1),
+            tile_index: bus.read_register(sprite_addr + 2),
+            attributes: SpriteAttributes::from_bits_truncate(bus.read_register(sprite_addr + 3)),
         }
     }
 }
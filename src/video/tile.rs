@@ -1,10 +1,10 @@
 use crate::gameboy::Mode;
-use crate::memory::mmu::Mmu;
+use crate::video::bus::VideoBus;
 use crate::video::palette::Palette;
 use crate::video::sprite::Sprite;
 use bitflags::bitflags;
 
-use super::sprite::SpriteAttributes;
+use super::tile_cache::TileData;
 
 bitflags! {
     #[derive(Clone)]
@@ -24,58 +24,23 @@ pub struct Tile {
 }
 
 impl Tile {
-    pub fn from(mmu: &Mmu, address: u16, mode: &Mode, attributes: TileAttributes) -> Tile {
+    // Applies the background palette to the cached color indices
+    pub fn from(bus: &dyn VideoBus, data: &TileData, mode: &Mode, attributes: TileAttributes) -> Tile {
         let mut pixels = [[Palette::default(); 8]; 8];
-
-        // This is a closure that reads from VRAM, taking into account
-        // which bank to read from based on the tile map attributes
-        let read_from_vram = |addr: u16| -> u8 {
-            if attributes.contains(TileAttributes::BANK) {
-                mmu.read_from_vram(addr, 1)
-            } else {
-                mmu.read_from_vram(addr, 0)
-            }
-        };
-
-        for y in 0..8 {
-            let lsb = read_from_vram(address + (y * 2));
-            let msb = read_from_vram((address + (y * 2)) + 1);
-
-            for x in 0..8 {
-                let lsb_bit = (lsb >> (7 - x)) & 0b0000_0001;
-                let msb_bit = (msb >> (7 - x)) & 0b0000_0001;
-                let color = (msb_bit << 1) | lsb_bit;
-
-                pixels[y as usize][x as usize] = Palette::from_background(color, mmu, mode, &attributes);
+        for (y, row) in data.iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                pixels[y][x] = Palette::from_background(*color, bus, mode, &attributes);
             }
         }
 
         Tile { pixels, attributes }
     }
 
-    pub fn from_sprite(mmu: &Mmu, address: u16, sprite: &Sprite, mode: &Mode) -> Tile {
+    pub fn from_sprite(bus: &dyn VideoBus, data: &TileData, sprite: &Sprite, mode: &Mode) -> Tile {
         let mut pixels = [[Palette::default(); 8]; 8];
-
-        // This is a closure that reads from VRAM, taking into account
-        // which bank to read from based on the tile map attributes
-        let read_from_vram = |addr: u16| -> u8 {
-            if sprite.attributes.contains(SpriteAttributes::BANK) {
-                mmu.read_from_vram(addr, 1)
-            } else {
-                mmu.read_from_vram(addr, 0)
-            }
-        };
-
-        for y in 0..8 {
-            let lsb = read_from_vram(address + (y * 2));
-            let msb = read_from_vram((address + (y * 2)) + 1);
-
-            for x in 0..8 {
-                let lsb_bit = (lsb >> (7 - x)) & 0b0000_0001;
-                let msb_bit = (msb >> (7 - x)) & 0b0000_0001;
-                let color = (msb_bit << 1) | lsb_bit;
-
-                pixels[y as usize][x as usize] = Palette::from_object(color, mmu, sprite, true, mode);
+        for (y, row) in data.iter().enumerate() {
+            for (x, color) in row.iter().enumerate() {
+                pixels[y][x] = Palette::from_object(*color, bus, sprite, true, mode);
             }
         }
 
@@ -84,6 +49,21 @@ impl Tile {
             attributes: TileAttributes::empty(),
         }
     }
+
+    // Pixel as it appears on screen, with the CGB flip attributes applied
+    pub fn pixel(&self, x: usize, y: usize) -> Palette {
+        let x = if self.attributes.contains(TileAttributes::FLIP_X) {
+            7 - x
+        } else {
+            x
+        };
+        let y = if self.attributes.contains(TileAttributes::FLIP_Y) {
+            7 - y
+        } else {
+            y
+        };
+        self.pixels[y][x]
+    }
 }
 
 impl Default for Tile {
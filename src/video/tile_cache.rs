@@ -0,0 +1,65 @@
+use crate::video::bus::VideoBus;
+use crate::video::{TILESET_0_ADDRESS, TILE_DATA_COUNT};
+
+// One bit per tile in both VRAM banks, set by the MMU whenever tile data is written
+pub const DIRTY_TILE_WORDS: usize = TILE_DATA_COUNT * 2 / 64;
+pub type DirtyTiles = [u64; DIRTY_TILE_WORDS];
+
+// Color indices of a tile before any palette is applied
+pub type TileData = [[u8; 8]; 8];
+
+// Decoded tile data of both VRAM banks, so that fetching a tile doesn't go through the 2bpp bytes every time.
+// Only tiles the MMU flagged as written get decoded again.
+#[derive(Clone)]
+pub struct TileCache {
+    tiles: Vec<TileData>,
+}
+
+impl TileCache {
+    pub fn new() -> TileCache {
+        TileCache {
+            tiles: vec![[[0; 8]; 8]; TILE_DATA_COUNT * 2],
+        }
+    }
+
+    pub fn update(&mut self, bus: &dyn VideoBus, dirty_tiles: DirtyTiles) {
+        for (word, mut dirty) in dirty_tiles.into_iter().enumerate() {
+            while dirty != 0 {
+                let tile = word * 64 + dirty.trailing_zeros() as usize;
+                dirty &= dirty - 1;
+
+                let bank = (tile / TILE_DATA_COUNT) as u8;
+                let address = TILESET_0_ADDRESS + (tile % TILE_DATA_COUNT) as u16 * 16;
+                self.tiles[tile] = TileCache::decode(bus, address, bank);
+            }
+        }
+    }
+
+    #[inline]
+    pub fn get(&self, address: u16, bank: u8) -> &TileData {
+        &self.tiles[bank as usize * TILE_DATA_COUNT + (address - TILESET_0_ADDRESS) as usize / 16]
+    }
+
+    fn decode(bus: &dyn VideoBus, address: u16, bank: u8) -> TileData {
+        let mut data = [[0; 8]; 8];
+
+        for (y, row) in data.iter_mut().enumerate() {
+            let lsb = bus.read_vram(address + (y as u16 * 2), bank);
+            let msb = bus.read_vram((address + (y as u16 * 2)) + 1, bank);
+
+            for (x, color) in row.iter_mut().enumerate() {
+                let lsb_bit = (lsb >> (7 - x)) & 0b0000_0001;
+                let msb_bit = (msb >> (7 - x)) & 0b0000_0001;
+                *color = (msb_bit << 1) | lsb_bit;
+            }
+        }
+
+        data
+    }
+}
+
+impl Default for TileCache {
+    fn default() -> TileCache {
+        TileCache::new()
+    }
+}
@@ -0,0 +1,110 @@
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use crate::gameboy::Mode;
+use crate::memory::mmu::Mmu;
+use crate::video::bus::{LineRegisters, LineSnapshot, VideoMemory};
+use crate::video::ppu::{Ppu, WindowLine};
+use crate::video::tile_cache::{DirtyTiles, DIRTY_TILE_WORDS};
+use crate::video::{Frame, Layers};
+
+enum Job {
+    // Hidden layers go along, they can change between any two lines
+    Line(Box<LineSnapshot>, Layers),
+    // Sent at VBlank, answered with the finished frame
+    Finish,
+}
+
+// Draws scanlines on a separate thread while the CPU carries on with the next line.
+// Each line is drawn from a snapshot taken when the PPU reached it, so the result is the same
+// as drawing it right away. The PPU waits for the frame once it enters VBlank.
+pub struct RenderWorker {
+    jobs: Option<Sender<Job>>,
+    frames: Receiver<Box<Frame>>,
+    thread: Option<JoinHandle<()>>,
+    memory: Option<Arc<VideoMemory>>,
+    // Tiles written since the last line was sent
+    dirty_tiles: DirtyTiles,
+    // Whether lines were sent since the last frame was collected
+    pending: bool,
+}
+
+impl RenderWorker {
+    pub fn new(mode: Mode) -> RenderWorker {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (frame_sender, frames) = mpsc::channel();
+
+        let thread = thread::spawn(move || {
+            let mut ppu = Ppu::new(mode);
+            for job in job_receiver {
+                match job {
+                    Job::Line(snapshot, layers) => {
+                        ppu.set_layers(layers);
+                        ppu.render_snapshot(&snapshot);
+                    }
+                    Job::Finish => {
+                        if frame_sender.send(Box::new(ppu.pull_frame())).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+        });
+
+        RenderWorker {
+            jobs: Some(jobs),
+            frames,
+            thread: Some(thread),
+            memory: None,
+            // The worker's tile cache starts out empty
+            dirty_tiles: [u64::MAX; DIRTY_TILE_WORDS],
+            pending: false,
+        }
+    }
+
+    pub fn mark_tiles_dirty(&mut self, dirty_tiles: &DirtyTiles) {
+        for (pending, dirty) in self.dirty_tiles.iter_mut().zip(dirty_tiles) {
+            *pending |= dirty;
+        }
+    }
+
+    // Everything has to be decoded again, e.g. after loading a state the worker hasn't seen
+    pub fn invalidate(&mut self) {
+        self.dirty_tiles = [u64::MAX; DIRTY_TILE_WORDS];
+        self.memory = None;
+    }
+
+    pub fn render(&mut self, mmu: &Mmu, registers: &LineRegisters, window_line: Option<WindowLine>, layers: Layers) {
+        let dirty_tiles = std::mem::replace(&mut self.dirty_tiles, [0; DIRTY_TILE_WORDS]);
+        let snapshot = LineSnapshot::capture(mmu, registers, window_line, dirty_tiles, &mut self.memory);
+        self.send(Job::Line(Box::new(snapshot), layers));
+        self.pending = true;
+    }
+
+    // Blocks until all lines sent so far are drawn, None if nothing was sent since the last frame
+    pub fn finish(&mut self) -> Option<Frame> {
+        if !std::mem::take(&mut self.pending) {
+            return None;
+        }
+
+        self.send(Job::Finish);
+        Some(*self.frames.recv().expect("Render thread died"))
+    }
+
+    fn send(&self, job: Job) {
+        if let Some(jobs) = &self.jobs {
+            jobs.send(job).expect("Render thread died");
+        }
+    }
+}
+
+impl Drop for RenderWorker {
+    fn drop(&mut self) {
+        // Closing the channel ends the thread's loop
+        self.jobs = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}